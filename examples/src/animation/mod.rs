@@ -74,6 +74,12 @@ impl rend3_framework::App for AnimationExample {
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
             resolution: 2048,
+            cascade_count: 1,
+            cascade_split_lambda: 0.5,
+            shadow_bias: 0.0,
+            shadow_normal_offset: 0.0,
+            shadow_filter: rend3::types::ShadowFilter::Pcf,
+            baked: false,
         });
 
         self._directional_light_handle = Some(directional_light_handle);
@@ -138,7 +144,12 @@ impl rend3_framework::App for AnimationExample {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &pbr_routine,
                     skybox: None,
+                    atmosphere: None,
+                    ibl: None,
+                    reflection_probes: None,
                     tonemapping: &tonemapping_routine,
+                    fxaa: None,
+                    smaa: None,
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -149,6 +160,7 @@ impl rend3_framework::App for AnimationExample {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::new(0.10, 0.05, 0.10, 1.0), // Nice scene-referred purple
+                ..Default::default()
             },
         );
 
@@ -89,6 +89,8 @@ impl rend3_framework::App for CubeExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh_handle),
             material: material_handle,
             transform: glam::Mat4::IDENTITY,
+            cast_shadows: true,
+            receive_shadows: true,
         };
         // Creating an object will hold onto both the mesh and the material
         // even if they are deleted.
@@ -116,6 +118,12 @@ impl rend3_framework::App for CubeExample {
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 400.0,
             resolution: 2048,
+            cascade_count: 1,
+            cascade_split_lambda: 0.5,
+            shadow_bias: 0.0,
+            shadow_normal_offset: 0.0,
+            shadow_filter: rend3::types::ShadowFilter::Pcf,
+            baked: false,
         }));
 
         let lights = [
@@ -130,6 +138,8 @@ impl rend3_framework::App for CubeExample {
                 color,
                 radius: 2.0,
                 intensity: 4.0,
+                shadow_resolution: Some(256),
+                baked: false,
             }));
         }
     }
@@ -162,7 +172,12 @@ impl rend3_framework::App for CubeExample {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &pbr_routine,
                     skybox: None,
+                    atmosphere: None,
+                    ibl: None,
+                    reflection_probes: None,
                     tonemapping: &tonemapping_routine,
+                    fxaa: None,
+                    smaa: None,
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -173,6 +188,7 @@ impl rend3_framework::App for CubeExample {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::new(0.10, 0.05, 0.10, 1.0), // Nice scene-referred purple
+                ..Default::default()
             },
         );
 
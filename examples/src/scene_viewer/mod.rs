@@ -6,16 +6,28 @@ use flume::Receiver;
 use glam::{DVec2, Mat3A, Mat4, UVec2, Vec3, Vec3A};
 use pico_args::Arguments;
 use rend3::{
+    graph::RendererStatistics,
     types::{
-        Backend, Camera, CameraProjection, DirectionalLight, DirectionalLightHandle, SampleCount, Texture,
-        TextureFormat,
+        Backend, Camera, CameraProjection, DirectionalLight, DirectionalLightHandle, SampleCount, ShadowFilter,
+        Texture, TextureFormat,
     },
-    util::typedefs::{FastHashMap, RendererStatistics},
+    util::typedefs::FastHashMap,
     Renderer, RendererProfile,
 };
 use rend3_framework::{lock, AssetPath, Mutex};
 use rend3_gltf::{GltfLoadSettings, GltfSceneInstance, LoadedGltfScene};
-use rend3_routine::{pbr::NormalTextureYDirection, skybox::SkyboxRoutine};
+use rend3_routine::{
+    base::PostProcessAntiAliasing,
+    bloom::BloomSettings,
+    fog::FogSettings,
+    ibl::IblRoutine,
+    pbr::NormalTextureYDirection,
+    skybox::SkyboxRoutine,
+    ssao::SsaoSettings,
+    ssr::SsrSettings,
+    taa::{TaaRoutine, TaaSettings},
+    tonemapping::{AutoExposure, Exposure, TonemappingOperator, TonemappingSettings},
+};
 use web_time::Instant;
 use winit::{
     event::{DeviceEvent, ElementState, Event, KeyEvent, MouseButton, WindowEvent},
@@ -35,6 +47,7 @@ async fn load_skybox(
     renderer: &Arc<Renderer>,
     loader: &rend3_framework::AssetLoader,
     skybox_routine: &Mutex<SkyboxRoutine>,
+    ibl_routine: &Mutex<IblRoutine>,
 ) -> anyhow::Result<()> {
     let mut data = Vec::new();
     load_skybox_image(loader, &mut data, "skybox/right.jpg").await;
@@ -52,6 +65,7 @@ async fn load_skybox(
         mip_count: rend3::types::MipmapCount::ONE,
         mip_source: rend3::types::MipmapSource::Uploaded,
     })?;
+    lock(ibl_routine).set_environment(Some(handle.clone()));
     lock(skybox_routine).set_background_texture(Some(handle));
     Ok(())
 }
@@ -156,6 +170,36 @@ fn extract_vsync(value: &str) -> Result<rend3::types::PresentMode, &'static str>
     })
 }
 
+fn extract_tonemapping(value: &str) -> Result<TonemappingOperator, &'static str> {
+    Ok(match value.to_lowercase().as_str() {
+        "none" => TonemappingOperator::None,
+        "reinhard" => TonemappingOperator::Reinhard,
+        "uncharted2" => TonemappingOperator::Uncharted2,
+        "aces" => TonemappingOperator::Aces,
+        "agx" => TonemappingOperator::AgX,
+        _ => return Err("unknown tonemapping operator"),
+    })
+}
+
+fn extract_post_aa(value: &str) -> Result<PostProcessAntiAliasing, &'static str> {
+    Ok(match value.to_lowercase().as_str() {
+        "none" => PostProcessAntiAliasing::None,
+        "fxaa" => PostProcessAntiAliasing::Fxaa,
+        "smaa" => PostProcessAntiAliasing::Smaa,
+        _ => return Err("unknown post-process anti-aliasing"),
+    })
+}
+
+fn extract_shadow_filter(value: &str) -> Result<ShadowFilter, &'static str> {
+    Ok(match value.to_lowercase().as_str() {
+        "hard" => ShadowFilter::Hard,
+        "pcf" => ShadowFilter::Pcf,
+        "pcss" => ShadowFilter::Pcss { angular_diameter: 0.5 },
+        "evsm" => ShadowFilter::Evsm,
+        _ => return Err("unknown shadow filter"),
+    })
+}
+
 fn extract_array<const N: usize>(value: &str, default: [f32; N]) -> Result<[f32; N], &'static str> {
     let mut res = default;
     let split: Vec<_> = value.split(',').enumerate().collect();
@@ -242,6 +286,17 @@ Rendering:
   -p --profile                 Choose rendering profile to use ('cpu', 'gpu').
   -v --vsync                   Choose vsync mode ('immediate' [no-vsync], 'fifo' [vsync], 'fifo_relaxed' [adaptive vsync], 'mailbox' [fast vsync])
   --msaa <level>               Level of antialiasing (either 1 or 4). Default 1.
+  --tonemapping <operator>     Choose tonemapping operator ('none', 'reinhard', 'uncharted2', 'aces', 'agx'). Default 'none'.
+  --auto-exposure              Measure the exposure from the scene and adapt to it over time.
+  --exposure-compensation <ev> Brighten or darken the image by this many stops. Default 0.
+  --bloom                      Spread the light of bright areas over their surroundings.
+  --ssao                       Darken the ambient light of creases and surfaces close to other geometry.
+  --ssr                        Reflect what is on screen in smooth surfaces.
+  --fog                        Add exponential height fog.
+  --depth-prepass              Render the depth of opaque geometry first, so every pixel is only shaded once.
+  --oit                        Blend transparent geometry without sorting it, so intersecting objects look right.
+  --taa                        Smooth edges and specular highlights over multiple frames. Replaces --msaa.
+  --post-aa <method>           Smooth edges of the final image ('none', 'fxaa', 'smaa'). Default 'none'.
 
 Windowing:
   --absolute-mouse             Interpret the relative mouse coordinates as absolute. Useful when using things like VNC.
@@ -256,6 +311,10 @@ Assets:
   --scale <scale>                        Scale all objects loaded by this factor. Defaults to 1.0.
   --shadow-distance <value>              Distance from the camera there will be directional shadows. Lower values means higher quality shadows. Defaults to 100.
   --shadow-resolution <value>            Resolution of the shadow map. Higher values mean higher quality shadows with high performance cost. Defaults to 2048.
+  --shadow-cascades <value>              Amount of shadow cascades to split the shadow distance into (1 to 4). Defaults to 4.
+  --shadow-filter <filter>               Filter of the shadow of the --directional-light ('hard', 'pcf', 'pcss', 'evsm'). Defaults to 'pcf'.
+  --shadow-bias <value>                  Distance in world units the shadow of the --directional-light is pushed away from receivers. Defaults to 0.
+  --shadow-normal-offset <value>         Distance in shadow map texels receivers of the --directional-light are moved along their normal. Defaults to 0.
 
 Controls:
   --walk <speed>               Walk speed (speed without holding shift) in units/second (typically meters). Default 10.
@@ -277,10 +336,24 @@ pub struct SceneViewer {
     gltf_settings: rend3_gltf::GltfLoadSettings,
     directional_light_direction: Option<Vec3>,
     directional_light_intensity: f32,
+    directional_light_shadow_filter: ShadowFilter,
+    directional_light_shadow_bias: f32,
+    directional_light_shadow_normal_offset: f32,
     directional_light: Option<DirectionalLightHandle>,
     ambient_light_level: f32,
     present_mode: rend3::types::PresentMode,
     samples: SampleCount,
+    tonemapping: TonemappingSettings,
+    bloom: Option<BloomSettings>,
+    ssao: Option<SsaoSettings>,
+    ssr: Option<SsrSettings>,
+    fog: Option<FogSettings>,
+    depth_prepass: bool,
+    order_independent_transparency: bool,
+    taa: Option<TaaSettings>,
+    post_process_anti_aliasing: PostProcessAntiAliasing,
+    /// Frames rendered so far, selects the jitter of temporal anti-aliasing.
+    frame: u32,
 
     fullscreen: bool,
     wait_for_load: bool,
@@ -311,10 +384,23 @@ impl Default for SceneViewer {
             gltf_settings: GltfLoadSettings::default(),
             directional_light_direction: None,
             directional_light_intensity: 1.0,
+            directional_light_shadow_filter: ShadowFilter::Pcf,
+            directional_light_shadow_bias: 0.0,
+            directional_light_shadow_normal_offset: 0.0,
             directional_light: None,
             ambient_light_level: 0.1,
             present_mode: wgpu::PresentMode::Fifo,
             samples: SampleCount::One,
+            tonemapping: TonemappingSettings::default(),
+            bloom: None,
+            ssao: None,
+            ssr: None,
+            fog: None,
+            depth_prepass: false,
+            order_independent_transparency: false,
+            taa: None,
+            post_process_anti_aliasing: PostProcessAntiAliasing::None,
+            frame: 0,
             fullscreen: false,
             wait_for_load: false,
             loading_reciever: None,
@@ -357,6 +443,35 @@ impl SceneViewer {
         if let Some(present_mode) = option_arg(args.opt_value_from_fn(["-v", "--vsync"], extract_vsync)) {
             app.present_mode = present_mode;
         }
+        if let Some(operator) = option_arg(args.opt_value_from_fn("--tonemapping", extract_tonemapping)) {
+            app.tonemapping.operator = operator;
+        }
+        if args.contains("--auto-exposure") {
+            app.tonemapping.exposure = Exposure::Automatic(AutoExposure::default());
+        }
+        if let Some(exposure_compensation) = option_arg(args.opt_value_from_str("--exposure-compensation")) {
+            app.tonemapping.exposure_compensation = exposure_compensation;
+        }
+        if args.contains("--bloom") {
+            app.bloom = Some(BloomSettings::default());
+        }
+        if args.contains("--ssao") {
+            app.ssao = Some(SsaoSettings::default());
+        }
+        if args.contains("--ssr") {
+            app.ssr = Some(SsrSettings::default());
+        }
+        if args.contains("--fog") {
+            app.fog = Some(FogSettings::default());
+        }
+        app.depth_prepass = args.contains("--depth-prepass");
+        app.order_independent_transparency = args.contains("--oit");
+        if args.contains("--taa") {
+            app.taa = Some(TaaSettings::default());
+        }
+        if let Some(post_process_anti_aliasing) = option_arg(args.opt_value_from_fn("--post-aa", extract_post_aa)) {
+            app.post_process_anti_aliasing = post_process_anti_aliasing;
+        }
 
         // Windowing
         app.absolute_mouse = args.contains("--absolute-mouse");
@@ -372,6 +487,15 @@ impl SceneViewer {
         {
             app.directional_light_intensity = directional_light_intensity;
         }
+        if let Some(shadow_filter) = option_arg(args.opt_value_from_fn("--shadow-filter", extract_shadow_filter)) {
+            app.directional_light_shadow_filter = shadow_filter;
+        }
+        if let Some(shadow_bias) = option_arg(args.opt_value_from_str("--shadow-bias")) {
+            app.directional_light_shadow_bias = shadow_bias;
+        }
+        if let Some(shadow_normal_offset) = option_arg(args.opt_value_from_str("--shadow-normal-offset")) {
+            app.directional_light_shadow_normal_offset = shadow_normal_offset;
+        }
         if let Some(ambient_light_level) = option_arg(args.opt_value_from_str("--ambient")) {
             app.ambient_light_level = ambient_light_level;
         }
@@ -384,6 +508,9 @@ impl SceneViewer {
         if let Some(shadow_resolution) = option_arg(args.opt_value_from_str("--shadow-resolution")) {
             app.gltf_settings.directional_light_resolution = shadow_resolution;
         }
+        if let Some(shadow_cascades) = option_arg(args.opt_value_from_str("--shadow-cascades")) {
+            app.gltf_settings.directional_light_cascade_count = shadow_cascades;
+        }
         app.gltf_settings.enable_directional = !args.contains("--gltf-disable-directional-lights");
 
         // Controls
@@ -469,6 +596,12 @@ impl rend3_framework::App for SceneViewer {
                 direction,
                 distance: self.gltf_settings.directional_light_shadow_distance,
                 resolution: 2048,
+                cascade_count: self.gltf_settings.directional_light_cascade_count,
+                cascade_split_lambda: 0.5,
+                shadow_bias: self.directional_light_shadow_bias,
+                shadow_normal_offset: self.directional_light_shadow_normal_offset,
+                shadow_filter: self.directional_light_shadow_filter,
+                baked: false,
             }));
         }
 
@@ -486,7 +619,7 @@ impl rend3_framework::App for SceneViewer {
                 "",
                 "http://localhost:8000/resources/",
             );
-            if let Err(e) = load_skybox(&renderer, &loader, &routines.skybox).await {
+            if let Err(e) = load_skybox(&renderer, &loader, &routines.skybox, &routines.ibl).await {
                 println!("Failed to load skybox {}", e)
             };
             let loaded = load_gltf(
@@ -629,9 +762,9 @@ impl rend3_framework::App for SceneViewer {
 
         if button_pressed(&self.scancode_status, KeyCode::KeyP) {
             // write out gpu side performance info into a trace readable by chrome://tracing
-            if let Some(ref stats) = self.previous_profiling_stats {
+            if let Some(timers) = self.previous_profiling_stats.as_ref().and_then(|stats| stats.gpu_timers.as_ref()) {
                 println!("Outputing gpu timing chrome trace to profile.json");
-                wgpu_profiler::chrometrace::write_chrometrace(Path::new("profile.json"), stats).unwrap();
+                wgpu_profiler::chrometrace::write_chrometrace(Path::new("profile.json"), timers).unwrap();
             } else {
                 println!("No gpu timing trace available, either timestamp queries are unsupported or not enough frames have elapsed yet!");
             }
@@ -643,11 +776,22 @@ impl rend3_framework::App for SceneViewer {
         context
             .renderer
             .set_camera_data(Camera { projection: CameraProjection::Perspective { vfov: 60.0, near: 0.1 }, view });
+        if self.taa.is_some() {
+            context.renderer.set_camera_jitter(TaaRoutine::jitter(self.frame, context.resolution));
+            self.frame = self.frame.wrapping_add(1);
+        }
+
+        if let Exposure::Automatic(ref mut auto_exposure) = self.tonemapping.exposure {
+            auto_exposure.delta_time = context.delta_t_seconds;
+        }
 
         // Lock all the routines
         let pbr_routine = lock(&context.routines.pbr);
         let mut skybox_routine = lock(&context.routines.skybox);
+        let mut ibl_routine = lock(&context.routines.ibl);
         let tonemapping_routine = lock(&context.routines.tonemapping);
+        let fxaa_routine = lock(&context.routines.fxaa);
+        let smaa_routine = lock(&context.routines.smaa);
 
         // Swap the instruction buffers so that our frame's changes can be processed.
         context.renderer.swap_instruction_buffers();
@@ -655,6 +799,7 @@ impl rend3_framework::App for SceneViewer {
         let mut eval_output = context.renderer.evaluate_instructions();
         // Evaluate changes to routines.
         skybox_routine.evaluate(context.renderer);
+        ibl_routine.evaluate(context.renderer);
 
         // Build a rendergraph
         let mut graph = rend3::graph::RenderGraph::new();
@@ -673,7 +818,12 @@ impl rend3_framework::App for SceneViewer {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &pbr_routine,
                     skybox: Some(&skybox_routine),
+                    atmosphere: None,
+                    ibl: Some(&ibl_routine),
+                    reflection_probes: None,
                     tonemapping: &tonemapping_routine,
+                    fxaa: Some(&fxaa_routine),
+                    smaa: Some(&smaa_routine),
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -684,11 +834,20 @@ impl rend3_framework::App for SceneViewer {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: Vec3::splat(self.ambient_light_level).extend(1.0),
                 clear_color: glam::Vec4::new(0.0, 0.0, 0.0, 1.0),
+                tonemapping: self.tonemapping,
+                bloom: self.bloom,
+                ssao: self.ssao,
+                ssr: self.ssr,
+                fog: self.fog,
+                depth_prepass: self.depth_prepass,
+                order_independent_transparency: self.order_independent_transparency,
+                taa: self.taa,
+                post_process_anti_aliasing: self.post_process_anti_aliasing,
             },
         );
 
         // Dispatch a render using the built up rendergraph!
-        self.previous_profiling_stats = graph.execute(context.renderer, &mut eval_output);
+        self.previous_profiling_stats = Some(graph.execute(context.renderer, &mut eval_output));
 
         // mark the end of the frame for tracy/other profilers
         profiling::finish_frame!();
@@ -67,6 +67,8 @@ impl rend3_framework::App for StaticGltfExample {
             mesh_kind: rend3::types::ObjectMeshKind::Static(mesh),
             material,
             transform: glam::Mat4::from_scale(glam::Vec3::new(1.0, 1.0, -1.0)),
+            cast_shadows: true,
+            receive_shadows: true,
         };
         // We need to keep the object alive.
         self.object_handle = Some(context.renderer.add_object(object));
@@ -91,6 +93,12 @@ impl rend3_framework::App for StaticGltfExample {
             direction: glam::Vec3::new(-1.0, -4.0, 2.0),
             distance: 20.0,
             resolution: 2048,
+            cascade_count: 1,
+            cascade_split_lambda: 0.5,
+            shadow_bias: 0.0,
+            shadow_normal_offset: 0.0,
+            shadow_filter: rend3::types::ShadowFilter::Pcf,
+            baked: false,
         }));
     }
 
@@ -122,7 +130,12 @@ impl rend3_framework::App for StaticGltfExample {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &pbr_routine,
                     skybox: None,
+                    atmosphere: None,
+                    ibl: None,
+                    reflection_probes: None,
                     tonemapping: &tonemapping_routine,
+                    fxaa: None,
+                    smaa: None,
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -133,6 +146,7 @@ impl rend3_framework::App for StaticGltfExample {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::new(0.10, 0.05, 0.10, 1.0), // Nice scene-referred purple
+                ..Default::default()
             },
         );
         // Dispatch a render using the built up rendergraph!
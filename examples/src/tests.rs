@@ -36,12 +36,15 @@ pub async fn test_app<A: App<T>, T: 'static>(mut config: TestConfiguration<A>) -
             &base_rendergraph.interfaces,
         )),
         skybox: Mutex::new(rend3_routine::skybox::SkyboxRoutine::new(&renderer, &spp, &base_rendergraph.interfaces)),
+        ibl: Mutex::new(rend3_routine::ibl::IblRoutine::new(&renderer, &spp)),
         tonemapping: Mutex::new(rend3_routine::tonemapping::TonemappingRoutine::new(
             &renderer,
             &spp,
             &base_rendergraph.interfaces,
             wgpu::TextureFormat::Rgba8UnormSrgb,
         )),
+        fxaa: Mutex::new(rend3_routine::fxaa::FxaaRoutine::new(&renderer, &spp, wgpu::TextureFormat::Rgba8UnormSrgb)),
+        smaa: Mutex::new(rend3_routine::smaa::SmaaRoutine::new(&renderer, &spp, wgpu::TextureFormat::Rgba8UnormSrgb)),
     });
     drop(data_core);
 
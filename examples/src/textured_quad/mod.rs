@@ -87,6 +87,8 @@ impl rend3_framework::App for TexturedQuadExample {
                 glam::Quat::from_euler(glam::EulerRot::XYZ, 0.0, 0.0, 0.0),
                 glam::Vec3::new(0.0, 0.0, 0.0),
             ),
+            cast_shadows: true,
+            receive_shadows: true,
         };
 
         // Creating an object will hold onto both the mesh and the material
@@ -151,7 +153,12 @@ impl rend3_framework::App for TexturedQuadExample {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &pbr_routine,
                     skybox: None,
+                    atmosphere: None,
+                    ibl: None,
+                    reflection_probes: None,
                     tonemapping: &tonemapping_routine,
+                    fxaa: None,
+                    smaa: None,
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -162,6 +169,7 @@ impl rend3_framework::App for TexturedQuadExample {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::new(0.10, 0.05, 0.10, 1.0), // Nice scene-referred purple
+                ..Default::default()
             },
         );
 
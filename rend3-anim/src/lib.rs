@@ -6,7 +6,8 @@
 //! In order to play animations, you need to:
 //! - Create an [`AnimationData`] once when spawning your scene and store it.
 //! - Each simulation frame, use [`pose_animation_frame`] to set the mesh's
-//!   joints to a specific animation at a specific time.
+//!   joints and morph target weights to a specific animation at a specific
+//!   time.
 //!
 //! For now, this library aims to be a simple utility abstraction. Updating the
 //! current state of the animation by changing the currently played animation or
@@ -160,21 +161,37 @@ impl Lerp for Quat {
     }
 }
 
+/// Finds the two closest keyframes to the given time, returning their indices
+/// and the interpolation factor between them.
+fn keyframes_at_time(times: &[f32], current_time: f32) -> (usize, usize, f32) {
+    let next_idx = times.iter().position(|time| *time > current_time).unwrap_or(times.len() - 1);
+    let prev_idx = next_idx.saturating_sub(1);
+
+    let interp_factor = f32::clamp((current_time - times[prev_idx]) / (times[next_idx] - times[prev_idx]), 0.0, 1.0);
+
+    (prev_idx, next_idx, interp_factor)
+}
+
 /// Samples the data value for an animation channel at a given time. Will
 /// interpolate between the two closest keyframes.
 fn sample_at_time<T: Lerp + Copy>(channel: &AnimationChannel<T>, current_time: f32) -> T {
-    let next_idx = channel.times.iter().position(|time| *time > current_time).unwrap_or(channel.times.len() - 1);
-    let prev_idx = next_idx.saturating_sub(1);
-
-    let interp_factor = f32::clamp(
-        (current_time - channel.times[prev_idx]) / (channel.times[next_idx] - channel.times[prev_idx]),
-        0.0,
-        1.0,
-    );
+    let (prev_idx, next_idx, interp_factor) = keyframes_at_time(&channel.times, current_time);
 
     channel.values[prev_idx].lerp(channel.values[next_idx], interp_factor)
 }
 
+/// Samples the morph target weights of an animation channel at a given time.
+/// Will interpolate each weight between the two closest keyframes.
+fn sample_weights_at_time(channel: &AnimationChannel<Vec<f32>>, current_time: f32) -> Vec<f32> {
+    let (prev_idx, next_idx, interp_factor) = keyframes_at_time(&channel.times, current_time);
+
+    channel.values[prev_idx]
+        .iter()
+        .zip(&channel.values[next_idx])
+        .map(|(prev, next)| prev + (next - prev) * interp_factor)
+        .collect()
+}
+
 /// Sets the pose of the meshes at the given scene by using the animation at
 /// index `animation_index` at a given `time`. The provided time gets clamped to
 /// the valid range of times for the selected animation.
@@ -190,6 +207,18 @@ pub fn pose_animation_frame(
     let time = time.clamp(0.0, animation.inner.duration);
 
     for (&node_idx, channels) in &animation.inner.channels {
+        if let (Some(weights), Some(object)) = (&channels.morph_weights, &instance.nodes[node_idx].inner.object) {
+            let weights = sample_weights_at_time(weights, time);
+            for skeleton in &object.inner.skeletons {
+                renderer.set_skeleton_morph_weights(skeleton, weights.clone());
+            }
+        }
+
+        // Channels which only animate morph target weights leave the node where it is.
+        if channels.translation.is_none() && channels.rotation.is_none() && channels.scale.is_none() {
+            continue;
+        }
+
         let local_transform = instance.nodes[node_idx].inner.local_transform;
         let (bind_scale, bind_rotation, bind_translation) = local_transform.to_scale_rotation_translation();
 
@@ -222,6 +251,12 @@ pub fn pose_animation_frame(
 
         // Compute each bone's local transformation
         for (&node_idx, channels) in &animation.inner.channels {
+            // Animations may also target nodes which aren't joints of this skin, like
+            // the morph target weights of a mesh.
+            let Some(&joint_idx) = node_to_joint_idx.get(&NodeIndex(node_idx)) else {
+                continue;
+            };
+
             // NOTE: If a channel's property is not present, we need to set the
             // joint at its bind pose for that individual property
             let local_transform = instance.nodes[node_idx].inner.local_transform;
@@ -233,7 +268,6 @@ pub fn pose_animation_frame(
             let scale = channels.scale.as_ref().map(|sca| sample_at_time(sca, time)).unwrap_or(bind_scale);
 
             let matrix = Mat4::from_scale_rotation_translation(scale, rotation, translation);
-            let joint_idx = node_to_joint_idx[&NodeIndex(node_idx)];
             joint_local_matrices[joint_idx.0] = matrix;
         }
 
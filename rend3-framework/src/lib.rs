@@ -171,7 +171,10 @@ pub fn lock<T>(lock: &parking_lot::Mutex<T>) -> parking_lot::MutexGuard<'_, T> {
 pub struct DefaultRoutines {
     pub pbr: Mutex<rend3_routine::pbr::PbrRoutine>,
     pub skybox: Mutex<rend3_routine::skybox::SkyboxRoutine>,
+    pub ibl: Mutex<rend3_routine::ibl::IblRoutine>,
     pub tonemapping: Mutex<rend3_routine::tonemapping::TonemappingRoutine>,
+    pub fxaa: Mutex<rend3_routine::fxaa::FxaaRoutine>,
+    pub smaa: Mutex<rend3_routine::smaa::SmaaRoutine>,
 }
 
 pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_builder: WindowBuilder) {
@@ -233,12 +236,15 @@ pub async fn async_start<A: App<T> + 'static, T: 'static>(mut app: A, window_bui
             &base_rendergraph.interfaces,
         )),
         skybox: Mutex::new(rend3_routine::skybox::SkyboxRoutine::new(&renderer, &spp, &base_rendergraph.interfaces)),
+        ibl: Mutex::new(rend3_routine::ibl::IblRoutine::new(&renderer, &spp)),
         tonemapping: Mutex::new(rend3_routine::tonemapping::TonemappingRoutine::new(
             &renderer,
             &spp,
             &base_rendergraph.interfaces,
             format,
         )),
+        fxaa: Mutex::new(rend3_routine::fxaa::FxaaRoutine::new(&renderer, &spp, format)),
+        smaa: Mutex::new(rend3_routine::smaa::SmaaRoutine::new(&renderer, &spp, format)),
     });
     drop(data_core);
 
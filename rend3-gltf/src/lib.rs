@@ -15,6 +15,40 @@
 //! - `KHR_texture_transform`
 //! - `KHR_material_unlit`
 //!
+//! # Extras
+//!
+//! Baked lighting is read from the `extras` of materials and lights, as glTF
+//! has no extension for it.
+//!
+//! A material with a lightmap has a `lightmap` object in its extras:
+//!
+//! ```json
+//! "extras": {
+//!     "lightmap": {
+//!         "index": 3,
+//!         "directionIndex": 4,
+//!         "directionEncoding": "directional",
+//!         "scaleOffset": [0.5, 0.5, 0.0, 0.5]
+//!     }
+//! }
+//! ```
+//!
+//! - `index`: index of the texture with the irradiance. Required. The
+//!   irradiance is linear, so it should be stored in a linear or HDR format.
+//! - `directionIndex`: index of the texture with the direction of the
+//!   irradiance. Optional.
+//! - `directionEncoding`: either `"directional"` or `"monoSH"`, see
+//!   [`pbr::LightmapDirectionEncoding`]. Defaults to `"directional"`.
+//! - `scaleOffset`: scale (xy) and offset (zw) of `TEXCOORD_1` into the
+//!   lightmap, for lightmaps shared between materials. Defaults to the whole
+//!   texture.
+//!
+//! Lightmaps are always sampled with `TEXCOORD_1`, and only rendered if
+//! `ForwardFeatures::lightmaps` of rend3-routine is set, which is the default
+//! on the GpuDriven profile. A `KHR_lights_punctual` light with
+//! `"baked": true` in its extras is already part of the lightmaps, see
+//! [`types::DirectionalLight::baked`].
+//!
 //! # Known Limitations
 //! - Only the albedo texture's transform from `KHR_texture_transform` will be
 //!   used.
@@ -67,6 +101,9 @@ pub struct MeshPrimitive {
 #[derive(Debug)]
 pub struct Mesh {
     pub primitives: Vec<MeshPrimitive>,
+    /// Default weight of each morph target. Empty if the mesh has no morph
+    /// targets.
+    pub morph_weights: Vec<f32>,
 }
 
 /// A set of [`SkeletonHandle`]s, one per mesh in the wrapping object, plus the
@@ -92,6 +129,9 @@ pub struct Armature {
 pub struct Object {
     pub primitives: Vec<ObjectHandle>,
     pub armature: Option<Armature>,
+    /// One skeleton per primitive if the mesh is skinned or has morph targets,
+    /// empty otherwise. Morph target weights are set through these.
+    pub skeletons: Vec<SkeletonHandle>,
 }
 
 /// Node in the gltf scene tree
@@ -142,19 +182,21 @@ pub struct AnimationChannel<T> {
     pub times: Vec<f32>,
 }
 
-/// Animation data for a single joint, with translation, rotation and scale
-/// channels.
+/// Animation data for a single node, with translation, rotation and scale
+/// channels, as well as morph target weights for the node's mesh.
 #[derive(Debug)]
 pub struct PosRotScale {
     pub node_idx: u32,
     pub translation: Option<AnimationChannel<Vec3>>,
     pub rotation: Option<AnimationChannel<Quat>>,
     pub scale: Option<AnimationChannel<Vec3>>,
+    /// One weight per morph target for each keyframe.
+    pub morph_weights: Option<AnimationChannel<Vec<f32>>>,
 }
 
 impl PosRotScale {
     pub fn new(node_idx: u32) -> Self {
-        Self { node_idx, translation: None, rotation: None, scale: None }
+        Self { node_idx, translation: None, rotation: None, scale: None, morph_weights: None }
     }
 }
 
@@ -227,6 +269,8 @@ pub enum GltfLoadError<E: std::error::Error + 'static> {
     MissingSkin(usize),
     #[error("Gltf file references material {0} but material does not exist")]
     MissingMaterial(usize),
+    #[error("Gltf file references texture {0} but texture does not exist")]
+    MissingTexture(usize),
     #[error("Mesh {0} primitive {1} uses unsupported mode {2:?}. Only triangles are supported")]
     UnsupportedPrimitiveMode(usize, usize, gltf::mesh::Mode),
     #[error("Mesh {0} failed validation")]
@@ -291,6 +335,8 @@ pub struct GltfLoadSettings {
     pub directional_light_shadow_distance: f32,
     /// Resolution of the shadow map (default: 2048)
     pub directional_light_resolution: u16,
+    /// Amount of shadow cascades for directional lights (default: 4)
+    pub directional_light_cascade_count: u8,
     /// Coordinate space normal maps should use (default Up)
     pub normal_direction: pbr::NormalTextureYDirection,
     /// Enable built-in directional lights (default true)
@@ -303,6 +349,7 @@ impl Default for GltfLoadSettings {
             scale: 1.0,
             directional_light_shadow_distance: 100.0,
             directional_light_resolution: 2048,
+            directional_light_cascade_count: 4,
             normal_direction: pbr::NormalTextureYDirection::Up,
             enable_directional: true,
         }
@@ -397,7 +444,8 @@ where
     let default_material = load_default_material(renderer);
     let meshes = load_meshes(renderer, file.meshes(), &buffers)?;
     let (materials, images) =
-        load_materials_and_textures(renderer, file.materials(), &buffers, settings, &mut io_func).await?;
+        load_materials_and_textures(renderer, file.materials(), &file.document, &buffers, settings, &mut io_func)
+            .await?;
     let skins = load_skins(file.skins(), &buffers)?;
     let animations = load_animations(file.animations(), &buffers)?;
 
@@ -434,11 +482,14 @@ pub fn add_mesh_by_index<E: std::error::Error + 'static>(
             .map_or_else(|| Some(&loaded.default_material), |mat_idx| loaded.materials.get(mat_idx).map(|m| &m.inner))
             .ok_or_else(|| GltfLoadError::MissingMaterial(mat_idx.expect("Could not find default material")))?;
 
-        let mesh_kind = if let Some(skin) = skin {
+        let mesh_kind = if skin.is_some() || !mesh_handle.inner.morph_weights.is_empty() {
             let skeleton = renderer.add_skeleton(Skeleton {
                 // We don't need to use the inverse bind matrices. At rest pose, every
                 // joint matrix is inv_bind_pose * bind_pose, thus the identity matrix.
-                joint_matrices: vec![Mat4::IDENTITY; skin.inner.inverse_bind_matrices.len()],
+                joint_matrices: skin
+                    .map(|skin| vec![Mat4::IDENTITY; skin.inner.inverse_bind_matrices.len()])
+                    .unwrap_or_default(),
+                morph_weights: mesh_handle.inner.morph_weights.clone(),
                 mesh: prim.handle.clone(),
             })?;
             skeletons.push(skeleton.clone());
@@ -447,11 +498,21 @@ pub fn add_mesh_by_index<E: std::error::Error + 'static>(
             ObjectMeshKind::Static(prim.handle.clone())
         };
 
-        primitives.push(renderer.add_object(types::Object { mesh_kind, material: mat.clone(), transform }));
+        primitives.push(renderer.add_object(types::Object {
+            mesh_kind,
+            material: mat.clone(),
+            transform,
+            cast_shadows: true,
+            receive_shadows: true,
+        }));
     }
 
     Ok(Labeled::new(
-        Object { primitives, armature: skin_index.map(|skin_index| Armature { skeletons, skin_index }) },
+        Object {
+            primitives,
+            armature: skin_index.map(|skin_index| Armature { skeletons: skeletons.clone(), skin_index }),
+            skeletons,
+        },
         name,
     ))
 }
@@ -515,14 +576,23 @@ pub fn instance_loaded_scene<E: std::error::Error + 'static>(
         node_transforms[*node_idx] = transform;
 
         let object = if let Some(mesh) = node.mesh() {
-            Some(add_mesh_by_index(
+            let object = add_mesh_by_index(
                 renderer,
                 loaded,
                 mesh.index(),
                 mesh.name(),
                 node.skin().map(|s| s.index()),
                 transform,
-            )?)
+            )?;
+
+            // Nodes may override the default morph target weights of their mesh.
+            if let Some(weights) = node.weights() {
+                for skeleton in &object.inner.skeletons {
+                    renderer.set_skeleton_morph_weights(skeleton, weights.to_vec());
+                }
+            }
+
+            Some(object)
         } else {
             None
         };
@@ -537,6 +607,12 @@ pub fn instance_loaded_scene<E: std::error::Error + 'static>(
                         direction,
                         distance: settings.directional_light_shadow_distance,
                         resolution: settings.directional_light_resolution,
+                        cascade_count: settings.directional_light_cascade_count,
+                        cascade_split_lambda: 0.5,
+                        shadow_bias: 0.0,
+                        shadow_normal_offset: 0.0,
+                        shadow_filter: types::ShadowFilter::Pcf,
+                        baked: light_is_baked(&light),
                     }))
                 }
                 _ => None,
@@ -666,13 +742,28 @@ pub fn load_meshes<'a, E: std::error::Error + 'static>(
                     builder = builder.with_vertex_joint_weights(joint_weights.into_f32().map(Vec4::from).collect())
                 }
 
+                for (positions, normals, tangents) in reader.read_morph_targets() {
+                    builder = builder.with_morph_target(types::MorphTarget {
+                        position_offsets: positions.map(|positions| positions.map(Vec3::from).collect()),
+                        normal_offsets: normals.map(|normals| normals.map(Vec3::from).collect()),
+                        tangent_offsets: tangents.map(|tangents| tangents.map(Vec3::from).collect()),
+                    });
+                }
+
                 let mesh = builder.build().map_err(|valid| GltfLoadError::MeshValidationError(mesh.index(), valid))?;
 
                 let handle = renderer.add_mesh(mesh)?;
 
                 res_prims.push(MeshPrimitive { handle, material: prim.material().index() })
             }
-            Ok(Labeled::new(Mesh { primitives: res_prims }, mesh.name()))
+            // All primitives of a mesh have the same amount of morph targets.
+            let morph_target_count = mesh.primitives().next().map_or(0, |prim| prim.morph_targets().len());
+            let morph_weights = match mesh.weights() {
+                Some(weights) => weights.to_vec(),
+                None => vec![0.0; morph_target_count],
+            };
+
+            Ok(Labeled::new(Mesh { primitives: res_prims, morph_weights }, mesh.name()))
         })
         .collect()
 }
@@ -713,7 +804,8 @@ fn compute_animation_duration(channels: &HashMap<usize, PosRotScale>) -> f32 {
             let m1 = ch.translation.as_ref().map(channel_duration).unwrap_or(0.0);
             let m2 = ch.rotation.as_ref().map(channel_duration).unwrap_or(0.0);
             let m3 = ch.scale.as_ref().map(channel_duration).unwrap_or(0.0);
-            m1.max(m2).max(m3)
+            let m4 = ch.morph_weights.as_ref().map(channel_duration).unwrap_or(0.0);
+            m1.max(m2).max(m3).max(m4)
         })
         .map(float_ord::FloatOrd)
         .max()
@@ -740,7 +832,7 @@ fn load_animations<E: std::error::Error + 'static>(
             let reader = ch.reader(|b| Some(&buffers[b.index()][..b.length()]));
 
             // In gltf, 'inputs' refers to the keyframe times
-            let times = reader
+            let times: Vec<f32> = reader
                 .read_inputs()
                 .ok_or_else(|| GltfLoadError::MissingKeyframeTimes(anim.index(), ch_idx))?
                 .collect();
@@ -758,8 +850,12 @@ fn load_animations<E: std::error::Error + 'static>(
                 gltf::animation::util::ReadOutputs::Scales(scls) => {
                     chs.scale = Some(AnimationChannel { values: scls.map(Vec3::from).collect(), times });
                 }
-                gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => {
-                    // TODO
+                gltf::animation::util::ReadOutputs::MorphTargetWeights(weights) => {
+                    // Weights come as one flat list, with one weight per morph target for each keyframe.
+                    let weights: Vec<f32> = weights.into_f32().collect();
+                    let weight_count = weights.len() / times.len().max(1);
+                    let values = weights.chunks_exact(weight_count.max(1)).map(<[f32]>::to_vec).collect();
+                    chs.morph_weights = Some(AnimationChannel { values, times });
                 }
             }
         }
@@ -792,6 +888,7 @@ pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle
         anisotropy: pbr::MaterialComponent::None,
         uv_transform0: Mat3::IDENTITY,
         uv_transform1: Mat3::IDENTITY,
+        lightmap: pbr::LightmapTexture::None,
         unlit: false,
         sample_type: pbr::SampleType::Linear,
     })
@@ -800,12 +897,14 @@ pub fn load_default_material(renderer: &Arc<Renderer>) -> types::MaterialHandle
 /// Loads materials and textures from a [`gltf::Material`] iterator.
 ///
 /// All binary data buffers must be provided. Call this with
-/// [`gltf::Document::materials`] as the materials argument.
+/// [`gltf::Document::materials`] as the materials argument. The document is
+/// used to find the lightmaps referenced by the extras of the materials.
 ///
 /// io_func determines how URIs are resolved into their underlying data.
 pub async fn load_materials_and_textures<F, Fut, E>(
     renderer: &Arc<Renderer>,
     materials: impl ExactSizeIterator<Item = gltf::Material<'_>>,
+    document: &gltf::Document,
     buffers: &[Vec<u8>],
     settings: &GltfLoadSettings,
     io_func: &mut F,
@@ -876,6 +975,36 @@ where
         )
         .await?;
 
+        let lightmap_extras = lightmap_extras(&material);
+        let lightmap = match lightmap_extras {
+            Some(ref extras) => {
+                let irradiance =
+                    document.textures().nth(extras.index).ok_or(GltfLoadError::MissingTexture(extras.index))?;
+                // The irradiance is light, not a color, so it is read without sRGB decoding.
+                let irradiance =
+                    load_image_cached(renderer, &mut images, irradiance.source(), false, buffers, io_func).await?;
+                match extras.direction {
+                    Some((index, encoding)) => {
+                        let direction = document.textures().nth(index).ok_or(GltfLoadError::MissingTexture(index))?;
+                        let direction =
+                            load_image_cached(renderer, &mut images, direction.source(), false, buffers, io_func)
+                                .await?;
+                        pbr::LightmapTexture::Directional {
+                            irradiance: irradiance.inner.handle,
+                            direction: direction.inner.handle,
+                            encoding,
+                        }
+                    }
+                    None => pbr::LightmapTexture::Irradiance(irradiance.inner.handle),
+                }
+            }
+            None => pbr::LightmapTexture::None,
+        };
+        let lightmap_transform = lightmap_extras.map(|extras| {
+            let [scale_x, scale_y, offset_x, offset_y] = extras.scale_offset;
+            Mat3::from_scale_angle_translation(Vec2::new(scale_x, scale_y), 0.0, Vec2::new(offset_x, offset_y))
+        });
+
         let handle = renderer.add_material(pbr::PbrMaterial {
             albedo: match albedo_tex {
                 Some(tex) => pbr::AlbedoComponent::TextureVertexValue {
@@ -928,7 +1057,8 @@ where
                 None => pbr::MaterialComponent::Value(Vec3::from(emissive_factor)),
             },
             uv_transform0: uv_transform,
-            uv_transform1: uv_transform,
+            uv_transform1: lightmap_transform.unwrap_or(uv_transform),
+            lightmap,
             unlit: material.unlit(),
             sample_type: nearest,
             ..pbr::PbrMaterial::default()
@@ -940,6 +1070,64 @@ where
     Ok((result, images))
 }
 
+/// Lightmap of a material, see the crate documentation for the format of the
+/// extras it is read from.
+struct LightmapExtras {
+    index: usize,
+    direction: Option<(usize, pbr::LightmapDirectionEncoding)>,
+    scale_offset: [f32; 4],
+}
+
+fn parse_extras(extras: &gltf::json::Extras) -> Option<gltf::json::Value> {
+    gltf::json::deserialize::from_str(extras.as_ref()?.get()).ok()
+}
+
+/// Reads the lightmap from the extras of the material. Extras which don't
+/// follow the format are ignored, as they might be meant for something else.
+fn lightmap_extras(material: &gltf::Material<'_>) -> Option<LightmapExtras> {
+    let extras = parse_extras(material.extras())?;
+    let parsed = parse_lightmap(extras.get("lightmap")?);
+    if parsed.is_none() {
+        log::warn!("Ignoring malformed lightmap in the extras of material {:?}", material.name());
+    }
+    parsed
+}
+
+fn parse_lightmap(lightmap: &gltf::json::Value) -> Option<LightmapExtras> {
+    let index = usize::try_from(lightmap.get("index")?.as_u64()?).ok()?;
+    let direction = match lightmap.get("directionIndex") {
+        Some(direction) => {
+            let encoding = match lightmap.get("directionEncoding").map(|e| e.as_str()) {
+                None | Some(Some("directional")) => pbr::LightmapDirectionEncoding::Directional,
+                Some(Some("monoSH")) => pbr::LightmapDirectionEncoding::MonoSh,
+                Some(_) => return None,
+            };
+            Some((usize::try_from(direction.as_u64()?).ok()?, encoding))
+        }
+        None => None,
+    };
+    let scale_offset = match lightmap.get("scaleOffset") {
+        Some(scale_offset) => {
+            let values = scale_offset.as_array()?;
+            if values.len() != 4 {
+                return None;
+            }
+            let mut scale_offset = [0.0; 4];
+            for (dst, src) in scale_offset.iter_mut().zip(values) {
+                *dst = src.as_f64()? as f32;
+            }
+            scale_offset
+        }
+        None => [1.0, 1.0, 0.0, 0.0],
+    };
+    Some(LightmapExtras { index, direction, scale_offset })
+}
+
+/// Whether the light has `"baked": true` in its extras.
+fn light_is_baked(light: &gltf::khr_lights_punctual::Light<'_>) -> bool {
+    parse_extras(light.extras()).and_then(|extras| extras.get("baked")?.as_bool()).unwrap_or(false)
+}
+
 /// Loads a single image from a [`gltf::Image`], with caching.
 ///
 /// Uses the given ImageMap as a cache.
@@ -1629,3 +1817,89 @@ pub mod util {
         Some(tex.components())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rend3_routine::pbr::LightmapDirectionEncoding;
+
+    use super::{light_is_baked, parse_lightmap, LightmapExtras};
+
+    fn parse(json: &str) -> Option<LightmapExtras> {
+        parse_lightmap(&gltf::json::deserialize::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn lightmap_defaults() {
+        let extras = parse(r#"{ "index": 3 }"#).unwrap();
+        assert_eq!(extras.index, 3);
+        assert!(extras.direction.is_none());
+        assert_eq!(extras.scale_offset, [1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn lightmap_full() {
+        let extras = parse(
+            r#"{ "index": 3, "directionIndex": 4, "directionEncoding": "monoSH", "scaleOffset": [0.5, 0.25, 0.0, 0.5] }"#,
+        )
+        .unwrap();
+        assert_eq!(extras.index, 3);
+        assert_eq!(extras.direction, Some((4, LightmapDirectionEncoding::MonoSh)));
+        assert_eq!(extras.scale_offset, [0.5, 0.25, 0.0, 0.5]);
+
+        let extras = parse(r#"{ "index": 0, "directionIndex": 1 }"#).unwrap();
+        assert_eq!(extras.direction, Some((1, LightmapDirectionEncoding::Directional)));
+    }
+
+    #[test]
+    fn lightmap_missing_index() {
+        assert!(parse(r#"{}"#).is_none());
+        assert!(parse(r#"{ "directionIndex": 4 }"#).is_none());
+    }
+
+    #[test]
+    fn lightmap_malformed() {
+        assert!(parse(r#"3"#).is_none());
+        assert!(parse(r#"{ "index": "3" }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "directionIndex": null }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "directionIndex": 4, "directionEncoding": "rnm" }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "directionIndex": 4, "directionEncoding": 1 }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "scaleOffset": "1 1 0 0" }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "scaleOffset": [1.0, 1.0, "0", 0.0] }"#).is_none());
+    }
+
+    #[test]
+    fn lightmap_out_of_range() {
+        assert!(parse(r#"{ "index": -1 }"#).is_none());
+        assert!(parse(r#"{ "index": 1.5 }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "directionIndex": -4 }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "scaleOffset": [1.0, 1.0, 0.0] }"#).is_none());
+        assert!(parse(r#"{ "index": 3, "scaleOffset": [1.0, 1.0, 0.0, 0.0, 0.0] }"#).is_none());
+    }
+
+    #[test]
+    fn baked_lights() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "extensionsUsed": ["KHR_lights_punctual"],
+                "extensions": {
+                    "KHR_lights_punctual": {
+                        "lights": [
+                            { "type": "point", "extras": { "baked": true } },
+                            { "type": "point", "extras": { "baked": false } },
+                            { "type": "point" },
+                            { "type": "point", "extras": { "intensity": 2.0 } },
+                            { "type": "point", "extras": { "baked": "true" } },
+                            { "type": "point", "extras": { "baked": 1 } },
+                            { "type": "point", "extras": [true] }
+                        ]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let baked: Vec<_> = gltf.document.lights().unwrap().map(|light| light_is_baked(&light)).collect();
+        assert_eq!(baked, [true, false, false, false, false, false, false]);
+    }
+}
@@ -0,0 +1,195 @@
+{{include "rend3-routine/math/consts.wgsl"}}
+
+// Distances are in kilometers. The planet is centered below the origin and up is +Y.
+const BOTTOM_RADIUS: f32 = 6360.0;
+const TOP_RADIUS: f32 = 6460.0;
+// The sky is always seen from slightly above the ground.
+const VIEW_RADIUS: f32 = 6360.2;
+
+const RAYLEIGH_SCATTERING = vec3<f32>(5.802e-3, 13.558e-3, 33.1e-3);
+const RAYLEIGH_SCALE_HEIGHT: f32 = 8.0;
+// Mie coefficients are for a turbidity of 2, see AtmosphereSettings::turbidity.
+const MIE_SCATTERING: f32 = 3.996e-3;
+const MIE_ABSORPTION: f32 = 0.444e-3;
+const MIE_SCALE_HEIGHT: f32 = 1.2;
+const MIE_G: f32 = 0.8;
+// Ozone only absorbs, in a layer centered at OZONE_CENTER with the density falling off linearly to either side.
+const OZONE_ABSORPTION = vec3<f32>(0.650e-3, 1.881e-3, 0.085e-3);
+const OZONE_CENTER: f32 = 25.0;
+const OZONE_HALF_WIDTH: f32 = 15.0;
+
+const TRANSMITTANCE_LUT_SIZE = vec2<f32>(256.0, 64.0);
+const MULTIPLE_SCATTERING_LUT_SIZE = vec2<f32>(32.0, 32.0);
+const SKY_VIEW_LUT_SIZE = vec2<f32>(192.0, 108.0);
+
+struct AtmosphereUniforms {
+    /// Direction towards the sun.
+    sun_direction: vec3<f32>,
+    /// Density of the aerosols relative to the Mie coefficients.
+    mie_scale: f32,
+    /// Color of the sun, multiplied by its intensity.
+    sun_illuminance: vec3<f32>,
+    ground_albedo: vec3<f32>,
+}
+
+struct Medium {
+    rayleigh_scattering: vec3<f32>,
+    mie_scattering: f32,
+    extinction: vec3<f32>,
+}
+
+fn sample_medium(radius: f32, mie_scale: f32) -> Medium {
+    let height = max(radius - BOTTOM_RADIUS, 0.0);
+    let rayleigh_density = exp(-height / RAYLEIGH_SCALE_HEIGHT);
+    let mie_density = exp(-height / MIE_SCALE_HEIGHT) * mie_scale;
+    let ozone_density = max(1.0 - abs(height - OZONE_CENTER) / OZONE_HALF_WIDTH, 0.0);
+
+    var medium: Medium;
+    medium.rayleigh_scattering = RAYLEIGH_SCATTERING * rayleigh_density;
+    medium.mie_scattering = MIE_SCATTERING * mie_density;
+    medium.extinction = medium.rayleigh_scattering + (MIE_SCATTERING + MIE_ABSORPTION) * mie_density + OZONE_ABSORPTION * ozone_density;
+    return medium;
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+}
+
+// Cornette-Shanks phase function.
+fn mie_phase(cos_theta: f32) -> f32 {
+    let g2 = MIE_G * MIE_G;
+    let k = 3.0 / (8.0 * PI) * (1.0 - g2) / (2.0 + g2);
+    return k * (1.0 + cos_theta * cos_theta) / pow(1.0 + g2 - 2.0 * MIE_G * cos_theta, 1.5);
+}
+
+// Distance along the ray to the sphere of the given radius around the planet center, negative if it is missed.
+fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return -1.0;
+    }
+    let root = sqrt(discriminant);
+    let near = -b - root;
+    if (near >= 0.0) {
+        return near;
+    }
+    return -b + root;
+}
+
+// Keeps lookups within the texel centers, so the edges of the tables are hit exactly.
+fn unit_to_lut_uv(unit: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    return 0.5 / size + unit * (1.0 - 1.0 / size);
+}
+
+fn lut_uv_to_unit(uv: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    return (uv - 0.5 / size) / (1.0 - 1.0 / size);
+}
+
+// Parameterization of the transmittance table from Bruneton's "Precomputed Atmospheric Scattering",
+// which spends the resolution on the rays close to the horizon. x is the cosine of the zenith angle
+// and y the radius.
+fn transmittance_lut_params(uv: vec2<f32>) -> vec2<f32> {
+    let unit = lut_uv_to_unit(uv, TRANSMITTANCE_LUT_SIZE);
+    let horizon = sqrt(TOP_RADIUS * TOP_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let rho = horizon * unit.y;
+    let radius = sqrt(rho * rho + BOTTOM_RADIUS * BOTTOM_RADIUS);
+
+    let d_min = TOP_RADIUS - radius;
+    let d_max = rho + horizon;
+    let d = d_min + unit.x * (d_max - d_min);
+    var cos_zenith = 1.0;
+    if (d > 0.0) {
+        cos_zenith = clamp((horizon * horizon - rho * rho - d * d) / (2.0 * radius * d), -1.0, 1.0);
+    }
+    return vec2<f32>(cos_zenith, radius);
+}
+
+fn transmittance_lut_uv(radius: f32, cos_zenith: f32) -> vec2<f32> {
+    let horizon = sqrt(TOP_RADIUS * TOP_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS);
+    let rho = sqrt(max(radius * radius - BOTTOM_RADIUS * BOTTOM_RADIUS, 0.0));
+
+    let discriminant = radius * radius * (cos_zenith * cos_zenith - 1.0) + TOP_RADIUS * TOP_RADIUS;
+    let d = max(-radius * cos_zenith + sqrt(max(discriminant, 0.0)), 0.0);
+    let d_min = TOP_RADIUS - radius;
+    let d_max = rho + horizon;
+    return unit_to_lut_uv(vec2<f32>((d - d_min) / (d_max - d_min), rho / horizon), TRANSMITTANCE_LUT_SIZE);
+}
+
+// Transmittance from the given point to the top of the atmosphere, ignoring the ground.
+fn sample_transmittance(lut: texture_2d<f32>, lut_sampler: sampler, radius: f32, cos_zenith: f32) -> vec3<f32> {
+    return textureSampleLevel(lut, lut_sampler, transmittance_lut_uv(radius, cos_zenith), 0.0).rgb;
+}
+
+// Transmittance towards the sun, which is zero in the shadow of the planet.
+fn sun_transmittance(lut: texture_2d<f32>, lut_sampler: sampler, position: vec3<f32>, sun_direction: vec3<f32>) -> vec3<f32> {
+    let radius = length(position);
+    let cos_zenith = dot(position / radius, sun_direction);
+    let sin_horizon = BOTTOM_RADIUS / radius;
+    if (cos_zenith < -sqrt(max(1.0 - sin_horizon * sin_horizon, 0.0))) {
+        return vec3<f32>(0.0);
+    }
+    return sample_transmittance(lut, lut_sampler, radius, cos_zenith);
+}
+
+// Light scattered more than once, for a sun of unit illuminance. x is the cosine of the sun zenith angle and y the height.
+fn sample_multiple_scattering(lut: texture_2d<f32>, lut_sampler: sampler, radius: f32, sun_cos_zenith: f32) -> vec3<f32> {
+    let unit = vec2<f32>(sun_cos_zenith * 0.5 + 0.5, (radius - BOTTOM_RADIUS) / (TOP_RADIUS - BOTTOM_RADIUS));
+    return textureSampleLevel(lut, lut_sampler, unit_to_lut_uv(saturate(unit), MULTIPLE_SCATTERING_LUT_SIZE), 0.0).rgb;
+}
+
+// Angle between the zenith and the horizon as seen from the viewer.
+fn zenith_horizon_angle() -> f32 {
+    return PI - acos(sqrt(VIEW_RADIUS * VIEW_RADIUS - BOTTOM_RADIUS * BOTTOM_RADIUS) / VIEW_RADIUS);
+}
+
+// Parameterization of the sky-view table from Hillaire's "A Scalable and Production Ready Sky and
+// Atmosphere Rendering Technique". y is the zenith angle, squeezed towards the horizon where the sky
+// changes the fastest. x is the angle between the view and sun directions around the zenith, and only
+// covers half a circle as the sky is symmetric around the sun. Returns the cosines of both angles.
+fn sky_view_lut_params(uv: vec2<f32>) -> vec2<f32> {
+    let unit = lut_uv_to_unit(uv, SKY_VIEW_LUT_SIZE);
+    let horizon_angle = zenith_horizon_angle();
+    let beta = PI - horizon_angle;
+
+    var zenith_angle: f32;
+    if (unit.y < 0.5) {
+        let coord = 1.0 - unit.y * 2.0;
+        zenith_angle = horizon_angle * (1.0 - coord * coord);
+    } else {
+        let coord = unit.y * 2.0 - 1.0;
+        zenith_angle = horizon_angle + beta * coord * coord;
+    }
+
+    let azimuth = unit.x * unit.x;
+    return vec2<f32>(-(azimuth * 2.0 - 1.0), cos(zenith_angle));
+}
+
+fn sky_view_lut_uv(cos_azimuth: f32, cos_zenith: f32) -> vec2<f32> {
+    let horizon_angle = zenith_horizon_angle();
+    let beta = PI - horizon_angle;
+    let zenith_angle = acos(clamp(cos_zenith, -1.0, 1.0));
+
+    var y: f32;
+    if (zenith_angle < horizon_angle) {
+        y = (1.0 - sqrt(1.0 - zenith_angle / horizon_angle)) * 0.5;
+    } else {
+        y = sqrt((zenith_angle - horizon_angle) / beta) * 0.5 + 0.5;
+    }
+    let x = sqrt(saturate(-cos_azimuth * 0.5 + 0.5));
+    return unit_to_lut_uv(vec2<f32>(x, y), SKY_VIEW_LUT_SIZE);
+}
+
+// Light scattered towards the viewer from the given direction, for the sun in the uniforms.
+fn sample_sky_view(lut: texture_2d<f32>, lut_sampler: sampler, direction: vec3<f32>, sun_direction: vec3<f32>) -> vec3<f32> {
+    // Cosine of the angle between the view and sun directions projected onto the ground plane.
+    let view_flat = direction.xz;
+    let sun_flat = sun_direction.xz;
+    let lengths = length(view_flat) * length(sun_flat);
+    var cos_azimuth = 1.0;
+    if (lengths > 1e-6) {
+        cos_azimuth = dot(view_flat, sun_flat) / lengths;
+    }
+    return textureSampleLevel(lut, lut_sampler, sky_view_lut_uv(cos_azimuth, direction.y), 0.0).rgb;
+}
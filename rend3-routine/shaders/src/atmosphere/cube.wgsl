@@ -0,0 +1,25 @@
+{{include "rend3-routine/atmosphere/common.wgsl"}}
+{{include "rend3-routine/ibl/common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereUniforms;
+@group(0) @binding(1)
+var lut_sampler: sampler;
+@group(0) @binding(2)
+var sky_view_lut: texture_2d<f32>;
+@group(0) @binding(4)
+var output: texture_storage_2d_array<rgba16float, write>;
+
+// Sky without the sun disk, which is left out as it is far too bright to be filtered for image based lighting.
+@compute @workgroup_size(8, 8)
+fn cs_cube(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let direction = cube_direction(gid.z, gid.xy, size);
+    let sky = sample_sky_view(sky_view_lut, lut_sampler, direction, atmosphere.sun_direction) * atmosphere.sun_illuminance;
+
+    textureStore(output, gid.xy, gid.z, vec4<f32>(sky, 1.0));
+}
@@ -0,0 +1,80 @@
+{{include "rend3-routine/atmosphere/common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereUniforms;
+@group(0) @binding(1)
+var lut_sampler: sampler;
+@group(0) @binding(2)
+var transmittance_lut: texture_2d<f32>;
+@group(0) @binding(4)
+var output: texture_storage_2d_array<rgba16float, write>;
+
+const SQRT_DIRECTION_COUNT: u32 = 8u;
+const MULTIPLE_SCATTERING_STEPS: u32 = 20u;
+
+// Light reaching a point after being scattered twice, and the fraction of light that is scattered
+// back to it, for a sun of unit illuminance. The scattering is assumed to be isotropic past the first
+// bounce, so every further order is the previous one times the same fraction and the sum of all of
+// them is a geometric series.
+@compute @workgroup_size(8, 8)
+fn cs_multiple_scattering(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let unit = lut_uv_to_unit((vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size), MULTIPLE_SCATTERING_LUT_SIZE);
+    let sun_cos_zenith = unit.x * 2.0 - 1.0;
+    let sun_direction = vec3<f32>(sqrt(max(1.0 - sun_cos_zenith * sun_cos_zenith, 0.0)), sun_cos_zenith, 0.0);
+    let origin = vec3<f32>(0.0, mix(BOTTOM_RADIUS + 0.01, TOP_RADIUS - 0.01, unit.y), 0.0);
+    let isotropic_phase = 1.0 / (4.0 * PI);
+
+    var second_order = vec3<f32>(0.0);
+    var transfer = vec3<f32>(0.0);
+    for (var i = 0u; i < SQRT_DIRECTION_COUNT * SQRT_DIRECTION_COUNT; i++) {
+        // Uniformly distributed over the sphere.
+        let cell = (vec2<f32>(f32(i % SQRT_DIRECTION_COUNT), f32(i / SQRT_DIRECTION_COUNT)) + 0.5) / f32(SQRT_DIRECTION_COUNT);
+        let phi = 2.0 * PI * cell.x;
+        let cos_theta = 1.0 - 2.0 * cell.y;
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        let direction = vec3<f32>(cos(phi) * sin_theta, cos_theta, sin(phi) * sin_theta);
+
+        let ground_distance = ray_sphere(origin, direction, BOTTOM_RADIUS);
+        let hits_ground = ground_distance >= 0.0;
+        var distance = max(ray_sphere(origin, direction, TOP_RADIUS), 0.0);
+        if (hits_ground) {
+            distance = ground_distance;
+        }
+        let step = distance / f32(MULTIPLE_SCATTERING_STEPS);
+
+        var throughput = vec3<f32>(1.0);
+        for (var j = 0u; j < MULTIPLE_SCATTERING_STEPS; j++) {
+            let position = origin + direction * ((f32(j) + 0.5) * step);
+            let medium = sample_medium(length(position), atmosphere.mie_scale);
+            let scattering = medium.rayleigh_scattering + medium.mie_scattering;
+            let step_transmittance = exp(-medium.extinction * step);
+            let sun = sun_transmittance(transmittance_lut, lut_sampler, position, sun_direction);
+
+            // Analytic integral over the step, which stays stable in dense media.
+            let integral = (1.0 - step_transmittance) / max(medium.extinction, vec3<f32>(1e-8));
+            second_order += throughput * scattering * sun * isotropic_phase * integral;
+            transfer += throughput * scattering * integral;
+            throughput *= step_transmittance;
+        }
+
+        if (hits_ground) {
+            let position = origin + direction * distance;
+            let normal = normalize(position);
+            let sun = sun_transmittance(transmittance_lut, lut_sampler, position, sun_direction);
+            second_order += throughput * sun * saturate(dot(normal, sun_direction)) * atmosphere.ground_albedo / PI;
+        }
+    }
+
+    // The directions each cover the same solid angle, which cancels out with the isotropic phase.
+    let direction_count = f32(SQRT_DIRECTION_COUNT * SQRT_DIRECTION_COUNT);
+    second_order /= direction_count;
+    transfer /= direction_count;
+
+    let multiple_scattering = second_order / (1.0 - transfer);
+    textureStore(output, gid.xy, 0u, vec4<f32>(multiple_scattering, 1.0));
+}
@@ -0,0 +1,65 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/fog.wgsl"}}
+{{include "rend3-routine/atmosphere/common.wgsl"}}
+
+// Cosine of the angular radius of the sun, as seen from the earth.
+const SUN_COS_RADIUS: f32 = 0.99998918;
+// Keeps the sun disk within the range of half floats.
+const MAX_SUN_LUMINANCE: f32 = 60000.0;
+
+struct VertexOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    let clip_position = vec2<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0);
+
+    return VertexOutput(vec4<f32>(clip_position, 0.0, 1.0), clip_position);
+}
+
+@group(0) @binding(3)
+var<uniform> uniforms: UniformData;
+@group(0) @binding(4)
+var<storage> directional_lights: DirectionalLightData;
+@group(1) @binding(0)
+var<uniform> atmosphere: AtmosphereUniforms;
+@group(1) @binding(1)
+var lut_sampler: sampler;
+@group(1) @binding(2)
+var sky_view_lut: texture_2d<f32>;
+@group(1) @binding(3)
+var transmittance_lut: texture_2d<f32>;
+
+@fragment
+fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
+    // Same as the skybox, the near plane keeps the direction from turning into NaNs.
+    let clip = vec4<f32>(output.clip_position, 1.0, 1.0);
+    let world_undiv = uniforms.inv_origin_view_proj * clip;
+    let world = world_undiv.xyz / world_undiv.w;
+    let world_dir = normalize(world);
+
+    var background = sample_sky_view(sky_view_lut, lut_sampler, world_dir, atmosphere.sun_direction) * atmosphere.sun_illuminance;
+
+    if (dot(world_dir, atmosphere.sun_direction) > SUN_COS_RADIUS) {
+        // The sun disk has the luminance that spreads its illuminance over its solid angle.
+        let solid_angle = 2.0 * PI * (1.0 - SUN_COS_RADIUS);
+        let transmittance = sun_transmittance(transmittance_lut, lut_sampler, vec3<f32>(0.0, VIEW_RADIUS, 0.0), world_dir);
+        background += min(atmosphere.sun_illuminance / solid_angle * transmittance, vec3<f32>(MAX_SUN_LUMINANCE));
+    }
+
+    if (!fog_enabled(uniforms.fog)) {
+        return vec4<f32>(background, 1.0);
+    }
+
+    // The sky is infinitely far away.
+    var inscattering = vec3<f32>(0.0);
+    for (var i = 0u; i < directional_lights.count; i += 1u) {
+        inscattering += fog_inscattering(uniforms.fog, world_dir, directional_lights.data[i]);
+    }
+    let camera = uniforms.inv_view[3].xyz;
+    let fogged = apply_fog(uniforms.fog, background, camera, world_dir, 1e20, inscattering);
+
+    return vec4<f32>(fogged, 1.0);
+}
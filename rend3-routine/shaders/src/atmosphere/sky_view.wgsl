@@ -0,0 +1,71 @@
+{{include "rend3-routine/atmosphere/common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereUniforms;
+@group(0) @binding(1)
+var lut_sampler: sampler;
+@group(0) @binding(2)
+var transmittance_lut: texture_2d<f32>;
+@group(0) @binding(3)
+var multiple_scattering_lut: texture_2d<f32>;
+@group(0) @binding(4)
+var output: texture_storage_2d_array<rgba16float, write>;
+
+const SKY_VIEW_STEPS: u32 = 30u;
+
+// Light scattered towards the viewer, for a sun of unit illuminance. Directions that hit the ground
+// also see the sunlit ground behind the atmosphere.
+@compute @workgroup_size(8, 8)
+fn cs_sky_view(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let params = sky_view_lut_params((vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size));
+    let sin_zenith = sqrt(max(1.0 - params.y * params.y, 0.0));
+    let sin_azimuth = sqrt(max(1.0 - params.x * params.x, 0.0));
+    let direction = vec3<f32>(sin_zenith * params.x, params.y, sin_zenith * sin_azimuth);
+
+    // The table is relative to the sun, so it is rotated around the zenith to lie in the xy plane.
+    let sun_cos_zenith = atmosphere.sun_direction.y;
+    let sun_direction = vec3<f32>(sqrt(max(1.0 - sun_cos_zenith * sun_cos_zenith, 0.0)), sun_cos_zenith, 0.0);
+    let cos_theta = dot(direction, sun_direction);
+    let rayleigh = rayleigh_phase(cos_theta);
+    let mie = mie_phase(cos_theta);
+
+    let origin = vec3<f32>(0.0, VIEW_RADIUS, 0.0);
+    let ground_distance = ray_sphere(origin, direction, BOTTOM_RADIUS);
+    let hits_ground = ground_distance >= 0.0;
+    var distance = max(ray_sphere(origin, direction, TOP_RADIUS), 0.0);
+    if (hits_ground) {
+        distance = ground_distance;
+    }
+    let step = distance / f32(SKY_VIEW_STEPS);
+
+    var luminance = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var i = 0u; i < SKY_VIEW_STEPS; i++) {
+        let position = origin + direction * ((f32(i) + 0.5) * step);
+        let radius = length(position);
+        let medium = sample_medium(radius, atmosphere.mie_scale);
+        let step_transmittance = exp(-medium.extinction * step);
+
+        let sun = sun_transmittance(transmittance_lut, lut_sampler, position, sun_direction);
+        let multiple = sample_multiple_scattering(multiple_scattering_lut, lut_sampler, radius, dot(position / radius, sun_direction));
+        let scattering = medium.rayleigh_scattering * rayleigh + medium.mie_scattering * mie;
+        let inscattered = scattering * sun + (medium.rayleigh_scattering + medium.mie_scattering) * multiple;
+
+        let integral = (1.0 - step_transmittance) / max(medium.extinction, vec3<f32>(1e-8));
+        luminance += throughput * inscattered * integral;
+        throughput *= step_transmittance;
+    }
+
+    if (hits_ground) {
+        let position = origin + direction * distance;
+        let sun = sun_transmittance(transmittance_lut, lut_sampler, position, sun_direction);
+        luminance += throughput * sun * saturate(dot(normalize(position), sun_direction)) * atmosphere.ground_albedo / PI;
+    }
+
+    textureStore(output, gid.xy, 0u, vec4<f32>(luminance, 1.0));
+}
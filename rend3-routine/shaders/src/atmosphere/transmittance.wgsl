@@ -0,0 +1,32 @@
+{{include "rend3-routine/atmosphere/common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereUniforms;
+@group(0) @binding(4)
+var output: texture_storage_2d_array<rgba16float, write>;
+
+const TRANSMITTANCE_STEPS: u32 = 40u;
+
+// Optical depth from a point in the atmosphere to its top, integrated along a straight line.
+@compute @workgroup_size(8, 8)
+fn cs_transmittance(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size);
+    let params = transmittance_lut_params(uv);
+    let origin = vec3<f32>(0.0, params.y, 0.0);
+    let direction = vec3<f32>(sqrt(max(1.0 - params.x * params.x, 0.0)), params.x, 0.0);
+
+    let distance = max(ray_sphere(origin, direction, TOP_RADIUS), 0.0);
+    let step = distance / f32(TRANSMITTANCE_STEPS);
+    var optical_depth = vec3<f32>(0.0);
+    for (var i = 0u; i < TRANSMITTANCE_STEPS; i++) {
+        let position = origin + direction * ((f32(i) + 0.5) * step);
+        optical_depth += sample_medium(length(position), atmosphere.mie_scale).extinction * step;
+    }
+
+    textureStore(output, gid.xy, 0u, vec4<f32>(exp(-optical_depth), 1.0));
+}
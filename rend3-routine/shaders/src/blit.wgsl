@@ -13,19 +13,144 @@ fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
     return output;
 }
 
+struct TonemappingUniform {
+    /// Exposure used when auto exposure is disabled.
+    manual_exposure: f32,
+    /// Multiplier from the exposure compensation.
+    compensation: f32,
+    /// Non-zero if the exposure should come from the adapted luminance.
+    automatic: u32,
+    /// Index of the tonemapping operator, see TonemappingOperator.
+    operator_index: u32,
+}
+
+struct ExposureState {
+    luminance: f32,
+}
+
 @group(0) @binding(0)
 var primary_sampler: sampler;
 @group(1) @binding(0)
 var source: texture_2d<f32>;
+@group(1) @binding(1)
+var<uniform> tonemapping: TonemappingUniform;
+@group(1) @binding(2)
+var<storage> exposure_state: ExposureState;
+
+// Luminance which automatic exposure maps the average of the scene to.
+const MIDDLE_GREY: f32 = 0.18;
+
+fn exposure() -> f32 {
+    var value = tonemapping.manual_exposure;
+    if (tonemapping.automatic != 0u && exposure_state.luminance > 0.0) {
+        value = MIDDLE_GREY / exposure_state.luminance;
+    }
+    return value * tonemapping.compensation;
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// John Hable's filmic curve from Uncharted 2.
+fn uncharted2_curve(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn uncharted2(color: vec3<f32>) -> vec3<f32> {
+    let exposure_bias = 2.0;
+    let white_point = 11.2;
+    return uncharted2_curve(color * exposure_bias) / uncharted2_curve(vec3<f32>(white_point));
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input_matrix = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output_matrix = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = input_matrix * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return output_matrix * (a / b);
+}
+
+// Polynomial approximation of the AgX default contrast curve.
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    v = outset * v;
+    // The curve outputs display encoded values, bring them back to linear.
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+    let exposed = color * exposure();
+    var mapped = exposed;
+    switch (tonemapping.operator_index) {
+        // Reinhard
+        case 1u: {
+            mapped = reinhard(exposed);
+        }
+        // Uncharted 2
+        case 2u: {
+            mapped = uncharted2(exposed);
+        }
+        // ACES
+        case 3u: {
+            mapped = clamp(aces(exposed), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        // AgX
+        case 4u: {
+            mapped = agx(exposed);
+        }
+        default: {}
+    }
+    return mapped;
+}
 
 @fragment
 fn fs_main_scene(vout: VertexOutput) -> @location(0) vec4<f32> {
-    var sampled = textureSample(source, primary_sampler, vout.tex_coords);
-    return sampled;
+    let sampled = textureSample(source, primary_sampler, vout.tex_coords);
+    return vec4<f32>(tonemap(sampled.rgb), sampled.a);
 }
 
 @fragment
 fn fs_main_monitor(vout: VertexOutput) -> @location(0) vec4<f32> {
-    var sampled = textureSample(source, primary_sampler, vout.tex_coords);
-    return vec4<f32>(srgb_scene_to_display(sampled.rgb), sampled.a);
-}
\ No newline at end of file
+    let sampled = textureSample(source, primary_sampler, vout.tex_coords);
+    return vec4<f32>(srgb_scene_to_display(tonemap(sampled.rgb)), sampled.a);
+}
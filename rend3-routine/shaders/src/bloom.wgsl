@@ -0,0 +1,121 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+struct BloomUniform {
+    /// Brightness above which pixels start to bloom.
+    threshold: f32,
+    /// Width of the soft transition around the threshold.
+    threshold_knee: f32,
+    /// Multiplier of the bloom when it is added to the scene.
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var bloom_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> uniforms: BloomUniform;
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source, bloom_sampler, uv, 0.0).rgb;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Weight of the Karis average, which keeps single very bright pixels from flickering.
+fn karis_weight(color: vec3<f32>) -> f32 {
+    return 1.0 / (1.0 + luminance(color));
+}
+
+// Quadratic soft threshold, so pixels fade into the bloom instead of popping.
+fn apply_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = uniforms.threshold * uniforms.threshold_knee;
+    var soft = clamp(brightness - uniforms.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    let contribution = max(soft, brightness - uniforms.threshold) / max(brightness, 0.00001);
+    return color * max(contribution, 0.0);
+}
+
+// The 13 tap downsample from "Next Generation Post Processing in Call of Duty: Advanced Warfare".
+//
+// The taps are combined as five overlapping 2x2 boxes, which keeps the filter
+// from aliasing when things move by less than a texel.
+fn downsample(uv: vec2<f32>, karis: bool) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    let a = sample_source(uv + texel * vec2<f32>(-2.0, -2.0));
+    let b = sample_source(uv + texel * vec2<f32>(0.0, -2.0));
+    let c = sample_source(uv + texel * vec2<f32>(2.0, -2.0));
+    let d = sample_source(uv + texel * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + texel * vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv + texel * vec2<f32>(-2.0, 2.0));
+    let h = sample_source(uv + texel * vec2<f32>(0.0, 2.0));
+    let i = sample_source(uv + texel * vec2<f32>(2.0, 2.0));
+    let j = sample_source(uv + texel * vec2<f32>(-1.0, -1.0));
+    let k = sample_source(uv + texel * vec2<f32>(1.0, -1.0));
+    let l = sample_source(uv + texel * vec2<f32>(-1.0, 1.0));
+    let m = sample_source(uv + texel * vec2<f32>(1.0, 1.0));
+
+    let center = (j + k + l + m) * 0.25;
+    let top_left = (a + b + d + e) * 0.25;
+    let top_right = (b + c + e + f) * 0.25;
+    let bottom_left = (d + e + g + h) * 0.25;
+    let bottom_right = (e + f + h + i) * 0.25;
+
+    var weights = vec4<f32>(0.125);
+    var center_weight = 0.5;
+    if (karis) {
+        weights *= vec4<f32>(karis_weight(top_left), karis_weight(top_right), karis_weight(bottom_left), karis_weight(bottom_right));
+        center_weight *= karis_weight(center);
+    }
+
+    let sum = center * center_weight + top_left * weights.x + top_right * weights.y + bottom_left * weights.z + bottom_right * weights.w;
+    return sum / (center_weight + weights.x + weights.y + weights.z + weights.w);
+}
+
+// 3x3 tent filter, the radius is one texel of the smaller mip.
+fn upsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    var sum = sample_source(uv) * 4.0;
+    sum += (sample_source(uv + texel * vec2<f32>(-1.0, 0.0)) + sample_source(uv + texel * vec2<f32>(1.0, 0.0))) * 2.0;
+    sum += (sample_source(uv + texel * vec2<f32>(0.0, -1.0)) + sample_source(uv + texel * vec2<f32>(0.0, 1.0))) * 2.0;
+    sum += sample_source(uv + texel * vec2<f32>(-1.0, -1.0)) + sample_source(uv + texel * vec2<f32>(1.0, -1.0));
+    sum += sample_source(uv + texel * vec2<f32>(-1.0, 1.0)) + sample_source(uv + texel * vec2<f32>(1.0, 1.0));
+    return sum / 16.0;
+}
+
+@fragment
+fn fs_prefilter(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(apply_threshold(downsample(vout.tex_coords, true)), 1.0);
+}
+
+@fragment
+fn fs_downsample(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(vout.tex_coords, false), 1.0);
+}
+
+@fragment
+fn fs_upsample(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample(vout.tex_coords), 1.0);
+}
+
+@fragment
+fn fs_composite(vout: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(upsample(vout.tex_coords) * uniforms.intensity, 0.0);
+}
@@ -0,0 +1,7 @@
+// Covers the whole viewport with a depth of 0, the far plane.
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    let clip_position = vec2<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0);
+
+    return vec4<f32>(clip_position, 0.0, 1.0);
+}
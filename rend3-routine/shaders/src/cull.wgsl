@@ -0,0 +1,93 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/structures_object.wgsl"}}
+
+struct DrawCall {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<storage> object_buffer: array<Object>;
+@group(0) @binding(1)
+var<storage> per_camera_uniform: PerCameraUniform;
+@group(0) @binding(2)
+var<storage> candidates: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> draw_calls: array<DrawCall>;
+@group(0) @binding(4)
+var<storage, read_write> draw_count: atomic<u32>;
+
+{{#if hi_z}}
+@group(1) @binding(0)
+var hi_z: texture_depth_2d;
+
+fn is_occluded(sphere: Sphere) -> bool {
+    // Project the corners of the box around the sphere to get conservative screen space bounds.
+    var min_uv = vec2<f32>(1.0);
+    var max_uv = vec2<f32>(0.0);
+    var closest_depth = 0.0;
+    for (var corner = 0u; corner < 8u; corner++) {
+        let direction = vec3<f32>(vec3<u32>(corner, corner >> 1u, corner >> 2u) & vec3<u32>(1u)) * 2.0 - 1.0;
+        let clip = per_camera_uniform.view_proj * vec4<f32>(sphere.location + direction * sphere.radius, 1.0);
+
+        // Part of the sphere is behind the camera, so we can't say anything about it.
+        if (clip.w <= 0.0) {
+            return false;
+        }
+
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        min_uv = min(min_uv, uv);
+        max_uv = max(max_uv, uv);
+        // Reverse-z, bigger is closer.
+        closest_depth = max(closest_depth, ndc.z);
+    }
+    min_uv = saturate(min_uv);
+    max_uv = saturate(max_uv);
+
+    // Choose the mip where the bounds cover at most 2x2 texels.
+    let size = (max_uv - min_uv) * vec2<f32>(textureDimensions(hi_z, 0));
+    let max_mip = i32(textureNumLevels(hi_z)) - 1;
+    let mip = clamp(i32(ceil(log2(max(max(size.x, size.y), 1.0)))), 0, max_mip);
+
+    let mip_resolution = vec2<i32>(textureDimensions(hi_z, mip));
+    let min_texel = clamp(vec2<i32>(min_uv * vec2<f32>(mip_resolution)), vec2<i32>(0), mip_resolution - 1);
+    let max_texel = clamp(vec2<i32>(max_uv * vec2<f32>(mip_resolution)), vec2<i32>(0), mip_resolution - 1);
+
+    let farthest_depth = min(
+        min(textureLoad(hi_z, min_texel, mip), textureLoad(hi_z, vec2<i32>(max_texel.x, min_texel.y), mip)),
+        min(textureLoad(hi_z, vec2<i32>(min_texel.x, max_texel.y), mip), textureLoad(hi_z, max_texel, mip)),
+    );
+
+    return closest_depth < farthest_depth;
+}
+{{/if}}
+
+@compute @workgroup_size(256)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    if (gid.x >= per_camera_uniform.object_count) {
+        return;
+    }
+
+    let object_index = candidates[gid.x];
+    let bounding_sphere = object_buffer[object_index].bounding_sphere;
+
+    // Candidates were already frustum culled on the CPU.
+    {{#if hi_z}}
+    if (is_occluded(bounding_sphere)) {
+        return;
+    }
+    {{/if}}
+
+    let slot = atomicAdd(&draw_count, 1u);
+    draw_calls[slot] = DrawCall(
+        object_buffer[object_index].index_count,
+        1u,
+        object_buffer[object_index].first_index,
+        0,
+        object_index,
+    );
+}
@@ -0,0 +1,57 @@
+{{include "rend3-routine/lighting.wgsl"}}
+{{include "rend3-routine/gbuffer.wgsl"}}
+
+// The G-buffer, see GBufferOutput for its layout.
+@group(1) @binding(0)
+var gbuffer_depth: texture_depth_2d;
+@group(1) @binding(1)
+var gbuffer_albedo: texture_2d<f32>;
+@group(1) @binding(2)
+var gbuffer_surface: texture_2d<f32>;
+@group(1) @binding(3)
+var gbuffer_material: texture_2d<f32>;
+@group(1) @binding(4)
+var gbuffer_emissive: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+    let depth = textureLoad(gbuffer_depth, coords, 0);
+
+    // Nothing was rendered here, the skybox fills it in.
+    if (depth <= 0.0) {
+        discard;
+    }
+
+    let uv = position.xy / vec2<f32>(uniforms.resolution);
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, (1.0 - uv.y) * 2.0 - 1.0, depth, 1.0);
+    let world_position = uniforms.inv_view_proj * ndc;
+    let view_position = uniforms.view * vec4<f32>(world_position.xyz / world_position.w, 1.0);
+
+    let albedo = textureLoad(gbuffer_albedo, coords, 0);
+    let surface = textureLoad(gbuffer_surface, coords, 0);
+    let material = textureLoad(gbuffer_material, coords, 0);
+    let flags = u32(round(material.b * 255.0));
+
+    var pixel: PixelData;
+    pixel.albedo = vec4<f32>(albedo.rgb, 1.0);
+    pixel.normal = oct_decode(surface.xy);
+    pixel.perceptual_roughness = surface.z;
+    pixel.roughness = perceptual_roughness_to_roughness(surface.z);
+    pixel.metallic = surface.w;
+    pixel.ambient_occlusion = material.r;
+    pixel.reflectance = material.g;
+    pixel.emissive = textureLoad(gbuffer_emissive, coords, 0).rgb;
+    // The baked lighting is already part of the emissive light, only the baked lights need to be skipped.
+    pixel.lightmapped = (flags & GBUFFER_LIGHTMAPPED) != 0u;
+    pixel.baked_irradiance = vec3<f32>(0.0);
+    pixel.diffuse_color = compute_diffuse_color(pixel.albedo.rgb, pixel.metallic);
+    pixel.f0 = compute_f0(pixel.albedo.rgb, pixel.metallic, compute_dielectric_f0(pixel.reflectance));
+
+    return shade_pixel(pixel, position.xy, view_position, (flags & GBUFFER_RECEIVE_SHADOWS) != 0u);
+}
@@ -4,6 +4,8 @@
 
 @group(0) @binding(0)
 var primary_sampler: sampler;
+@group(0) @binding(1)
+var nearest_sampler: sampler;
 @group(0) @binding(3)
 var<uniform> uniforms: UniformData;
 
@@ -39,7 +41,8 @@ var albedo_tex: texture_2d<f32>;
 }}
 
 struct VertexOutput {
-    @builtin(position) position: vec4<f32>,
+    // Invariant so the depth prepass matches the depth of the forward shaders exactly.
+    @builtin(position) @invariant position: vec4<f32>,
     @location(0) coords0: vec2<f32>,
     @location(1) alpha: f32,
     @location(2) @interpolate(flat) material: u32,
@@ -85,14 +88,23 @@ fn fs_main(vs_out: VertexOutput) {
     {{#if discard}}
     var material = materials[vs_out.material];
 
-    let coords = vs_out.coords0;
+    // Must match the alpha of get_pixel_data in opaque.wgsl, so the depth prepass discards the same fragments.
+    let coords = (material.uv_transform0 * vec3<f32>(vs_out.coords0, 1.0)).xy;
     let uvdx = dpdx(coords);
-    let uvdy = dpdx(coords);
+    let uvdy = dpdy(coords);
 
     var alpha = 1.0;
     if (extract_material_flag(material.flags, FLAGS_ALBEDO_ACTIVE)) {
         if (has_albedo_texture(&material)) {
+            {{#if (eq profile "GpuDriven")}}
+            if (extract_material_flag(material.flags, FLAGS_NEAREST)) {
+                alpha = albedo_texture(&material, nearest_sampler, coords, uvdx, uvdy).a;
+            } else {
+                alpha = albedo_texture(&material, primary_sampler, coords, uvdx, uvdy).a;
+            }
+            {{else}}
             alpha = albedo_texture(&material, primary_sampler, coords, uvdx, uvdy).a;
+            {{/if}}
         }
         if (extract_material_flag(material.flags, FLAGS_ALBEDO_BLEND)) {
             alpha *= vs_out.alpha;
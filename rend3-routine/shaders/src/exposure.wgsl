@@ -0,0 +1,86 @@
+struct ExposureParameters {
+    /// log2 of the darkest luminance that is put in the histogram.
+    min_log_luminance: f32,
+    /// Difference between the log2 of the brightest and darkest luminance of the histogram.
+    log_luminance_range: f32,
+    /// How far to move from the previous luminance to the luminance of this frame, from 0 to 1.
+    adaptation: f32,
+    /// Amount of pixels in the source texture.
+    pixel_count: u32,
+}
+
+struct ExposureState {
+    /// Adapted average luminance of the scene. Negative if there has been no frame yet.
+    luminance: f32,
+}
+
+const BIN_COUNT: u32 = 256u;
+
+@group(0) @binding(0)
+var<uniform> params: ExposureParameters;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> state: ExposureState;
+@group(0) @binding(3)
+var source: texture_2d<f32>;
+
+var<workgroup> local_histogram: array<atomic<u32>, 256>;
+
+// Bin 0 holds everything too dark to be measured, the rest are evenly spaced in log2 space.
+fn luminance_to_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+
+    if (luminance < exp2(params.min_log_luminance)) {
+        return 0u;
+    }
+
+    let log_luminance = saturate((log2(luminance) - params.min_log_luminance) / params.log_luminance_range);
+    return u32(log_luminance * f32(BIN_COUNT - 2u) + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_histogram(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(local_invocation_index) local_index: u32) {
+    atomicStore(&local_histogram[local_index], 0u);
+    workgroupBarrier();
+
+    let dimensions = textureDimensions(source);
+    if (all(gid.xy < dimensions)) {
+        let color = textureLoad(source, gid.xy, 0).rgb;
+        atomicAdd(&local_histogram[luminance_to_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_histogram[local_index]));
+}
+
+var<workgroup> weighted_counts: array<f32, 256>;
+
+@compute @workgroup_size(256)
+fn cs_average(@builtin(local_invocation_index) local_index: u32) {
+    let count = atomicLoad(&histogram[local_index]);
+    weighted_counts[local_index] = f32(count) * f32(local_index);
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride >>= 1u) {
+        if (local_index < stride) {
+            weighted_counts[local_index] += weighted_counts[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        // Pixels which are too dark are left out of the average, `count` is the size of bin 0 here.
+        let measured_pixels = max(f32(params.pixel_count) - f32(count), 1.0);
+        let average_bin = weighted_counts[0] / measured_pixels - 1.0;
+        let log_luminance = saturate(average_bin / f32(BIN_COUNT - 2u)) * params.log_luminance_range + params.min_log_luminance;
+        let luminance = exp2(log_luminance);
+
+        // The first frame snaps straight to the measured luminance.
+        if (state.luminance < 0.0) {
+            state.luminance = luminance;
+        } else {
+            state.luminance = mix(state.luminance, luminance, params.adaptation);
+        }
+    }
+}
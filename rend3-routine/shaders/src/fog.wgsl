@@ -0,0 +1,35 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+// Exponential height fog, see the fog module of rend3-routine.
+
+// Fog without density doesn't change anything, so the shaders can skip it entirely.
+fn fog_enabled(fog: Fog) -> bool {
+    return fog.density > 0.0;
+}
+
+// Density of the fog integrated along the ray starting at `origin` going `distance` into `direction`.
+fn fog_optical_depth(fog: Fog, origin: vec3<f32>, direction: vec3<f32>, distance: f32) -> f32 {
+    let ray_length = max(distance - fog.start_distance, 0.0);
+    let start_height = origin.y + direction.y * min(fog.start_distance, distance);
+    let start_density = fog.density * exp(-fog.height_falloff * (start_height - fog.height));
+
+    // Close to horizontal rays see the same density along the whole ray.
+    let falloff = fog.height_falloff * direction.y;
+    if (abs(falloff) < 0.0001) {
+        return start_density * ray_length;
+    }
+    // Clamped so rays going down towards infinity don't overflow.
+    return start_density * (1.0 - exp(min(-falloff * ray_length, 80.0))) / falloff;
+}
+
+// Light of the directional light scattered towards a viewer looking into `direction`.
+fn fog_inscattering(fog: Fog, direction: vec3<f32>, light: DirectionalLight) -> vec3<f32> {
+    let cos_angle = saturate(dot(direction, -light.direction));
+    return light.color * (fog.inscattering_intensity * pow(cos_angle, fog.inscattering_exponent));
+}
+
+// Blends `color`, seen from `origin` at `distance` into `direction`, with the fog lit by `inscattering`.
+fn apply_fog(fog: Fog, color: vec3<f32>, origin: vec3<f32>, direction: vec3<f32>, distance: f32, inscattering: vec3<f32>) -> vec3<f32> {
+    let opacity = min(1.0 - exp(-fog_optical_depth(fog, origin, direction, distance)), fog.max_opacity);
+    return mix(color, fog.color + inscattering, opacity);
+}
@@ -0,0 +1,173 @@
+{{include "rend3-routine/math/color.wgsl"}}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+    output.tex_coords = vec2<f32>(f32(id / 2u) * 2.0, 1.0 - (f32(id % 2u) * 2.0));
+    return output;
+}
+
+@group(0) @binding(0)
+var linear_sampler: sampler;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+
+// Smallest local contrast which is considered an edge, keeps dark areas from being processed.
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+// Local contrast relative to the brightest neighbor which is considered an edge.
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+// How much of the aliasing inside of a single pixel is removed.
+const SUBPIXEL_QUALITY: f32 = 0.75;
+// Amount of steps taken in each direction along an edge to find its ends.
+const SEARCH_STEPS: i32 = 12;
+
+// Distance of each step along an edge, in pixels. Steps get longer the further the end is.
+fn search_step(i: i32) -> f32 {
+    if (i < 5) {
+        return 1.0;
+    }
+    if (i == 5) {
+        return 1.5;
+    }
+    if (i < 10) {
+        return 2.0;
+    }
+    if (i == 10) {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+{{#if SRGB}}
+    // The target is decoded to linear when sampled, but edges are detected on perceived brightness.
+    let perceptual = srgb_scene_to_display(color);
+{{else}}
+    let perceptual = color;
+{{/if}}
+    return dot(perceptual, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn sample_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(source, linear_sampler, uv, 0.0).rgb);
+}
+
+// Port of the quality variant of Timothy Lottes' FXAA 3.11.
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(source));
+    let uv = vout.tex_coords;
+
+    let center = textureSampleLevel(source, linear_sampler, uv, 0.0);
+    let luma_center = luma(center.rgb);
+    let luma_n = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(0, -1)).rgb);
+    let luma_s = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(0, 1)).rgb);
+    let luma_w = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(-1, 0)).rgb);
+    let luma_e = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(1, 0)).rgb);
+
+    let luma_max = max(luma_center, max(max(luma_n, luma_s), max(luma_w, luma_e)));
+    let luma_min = min(luma_center, min(min(luma_n, luma_s), min(luma_w, luma_e)));
+    let luma_range = luma_max - luma_min;
+    if (luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX)) {
+        return center;
+    }
+
+    let luma_nw = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(-1, -1)).rgb);
+    let luma_ne = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(1, -1)).rgb);
+    let luma_sw = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(-1, 1)).rgb);
+    let luma_se = luma(textureSampleLevel(source, linear_sampler, uv, 0.0, vec2<i32>(1, 1)).rgb);
+
+    let luma_ns = luma_n + luma_s;
+    let luma_we = luma_w + luma_e;
+    let luma_west_corners = luma_nw + luma_sw;
+    let luma_east_corners = luma_ne + luma_se;
+    let luma_north_corners = luma_nw + luma_ne;
+    let luma_south_corners = luma_sw + luma_se;
+
+    // Compare the vertical and horizontal second derivatives to find the orientation of the edge.
+    let edge_horizontal = abs(luma_west_corners - 2.0 * luma_w) + abs(luma_ns - 2.0 * luma_center) * 2.0
+        + abs(luma_east_corners - 2.0 * luma_e);
+    let edge_vertical = abs(luma_north_corners - 2.0 * luma_n) + abs(luma_we - 2.0 * luma_center) * 2.0
+        + abs(luma_south_corners - 2.0 * luma_s);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // The edge lies between the center and the neighbor with the steepest gradient.
+    let luma_negative = select(luma_w, luma_n, is_horizontal);
+    let luma_positive = select(luma_e, luma_s, is_horizontal);
+    let gradient_negative = abs(luma_negative - luma_center);
+    let gradient_positive = abs(luma_positive - luma_center);
+    let negative_is_steepest = gradient_negative >= gradient_positive;
+    let gradient_scaled = 0.25 * max(gradient_negative, gradient_positive);
+
+    var step_length = select(texel_size.x, texel_size.y, is_horizontal);
+    var luma_local_average = 0.5 * (luma_positive + luma_center);
+    if (negative_is_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_negative + luma_center);
+    }
+
+    // Move onto the edge and walk along it in both directions until the contrast changes.
+    var edge_uv = uv;
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let search_offset = select(vec2<f32>(0.0, texel_size.y), vec2<f32>(texel_size.x, 0.0), is_horizontal);
+
+    var uv_negative = edge_uv - search_offset * search_step(0);
+    var uv_positive = edge_uv + search_offset * search_step(0);
+    var luma_end_negative = sample_luma(uv_negative) - luma_local_average;
+    var luma_end_positive = sample_luma(uv_positive) - luma_local_average;
+    var reached_negative = abs(luma_end_negative) >= gradient_scaled;
+    var reached_positive = abs(luma_end_positive) >= gradient_scaled;
+
+    for (var i = 1; i < SEARCH_STEPS && !(reached_negative && reached_positive); i++) {
+        if (!reached_negative) {
+            uv_negative -= search_offset * search_step(i);
+            luma_end_negative = sample_luma(uv_negative) - luma_local_average;
+            reached_negative = abs(luma_end_negative) >= gradient_scaled;
+        }
+        if (!reached_positive) {
+            uv_positive += search_offset * search_step(i);
+            luma_end_positive = sample_luma(uv_positive) - luma_local_average;
+            reached_positive = abs(luma_end_positive) >= gradient_scaled;
+        }
+    }
+
+    let distance_negative = select(uv.y - uv_negative.y, uv.x - uv_negative.x, is_horizontal);
+    let distance_positive = select(uv_positive.y - uv.y, uv_positive.x - uv.x, is_horizontal);
+    let negative_is_closest = distance_negative < distance_positive;
+    let distance_closest = min(distance_negative, distance_positive);
+    let edge_length = distance_negative + distance_positive;
+
+    // Only blend if the closest end of the edge goes the same direction as the center, otherwise
+    // the pixel is on the far side of the edge.
+    let center_is_darker = luma_center < luma_local_average;
+    let correct_variation = select(
+        (luma_end_positive < 0.0) != center_is_darker,
+        (luma_end_negative < 0.0) != center_is_darker,
+        negative_is_closest,
+    );
+    var pixel_offset = select(0.0, 0.5 - distance_closest / edge_length, correct_variation);
+
+    // Blend a little more for aliasing which is smaller than a pixel, like thin lines.
+    let luma_average = (2.0 * (luma_ns + luma_we) + luma_west_corners + luma_east_corners) / 12.0;
+    let subpixel_contrast = saturate(abs(luma_average - luma_center) / luma_range);
+    let subpixel_smoothed = (3.0 - 2.0 * subpixel_contrast) * subpixel_contrast * subpixel_contrast;
+    pixel_offset = max(pixel_offset, subpixel_smoothed * subpixel_smoothed * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y += pixel_offset * step_length;
+    } else {
+        final_uv.x += pixel_offset * step_length;
+    }
+    return textureSampleLevel(source, linear_sampler, final_uv, 0.0);
+}
@@ -0,0 +1,17 @@
+// Layout of the G-buffer, see the deferred module of rend3-routine.
+
+// Bits of the flags in the material target.
+const GBUFFER_RECEIVE_SHADOWS: u32 = 1u;
+const GBUFFER_LIGHTMAPPED: u32 = 2u;
+
+struct GBufferOutput {
+    // RGB: albedo
+    @location(0) albedo: vec4<f32>,
+    // XY: octahedral encoded view space normal, Z: perceptual roughness, W: metallic.
+    // This is the same surface the prepass routines render.
+    @location(1) surface: vec4<f32>,
+    // R: ambient occlusion, G: reflectance, B: flags
+    @location(2) material: vec4<f32>,
+    // RGB: emissive light, including the baked lighting of lightmapped surfaces
+    @location(3) emissive: vec4<f32>,
+}
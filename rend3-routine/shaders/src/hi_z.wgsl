@@ -0,0 +1,57 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+{{#if CLOSEST}}
+// Keeps the closest (biggest in reverse-z) depth.
+const EMPTY_DEPTH: f32 = 0.0;
+fn reduce_depth(a: f32, b: f32) -> f32 {
+    return max(a, b);
+}
+{{else}}
+// Keeps the farthest (smallest in reverse-z) depth.
+const EMPTY_DEPTH: f32 = 1.0;
+fn reduce_depth(a: f32, b: f32) -> f32 {
+    return min(a, b);
+}
+{{/if}}
+
+{{#if (eq SAMPLES 1)}}
+@group(0) @binding(0)
+var source: texture_depth_2d;
+
+// Writes the reduced depth of the 2x2 region of the previous mip.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let source_resolution = vec2<i32>(textureDimensions(source));
+    let base = vec2<i32>(position.xy) * 2;
+
+    // Odd sized sources have a row/column which would be skipped by a plain 2x2 reduction.
+    let extent = vec2<i32>(2) + (source_resolution & vec2<i32>(1));
+
+    var depth = EMPTY_DEPTH;
+    for (var y = 0; y < extent.y; y++) {
+        for (var x = 0; x < extent.x; x++) {
+            let coords = min(base + vec2<i32>(x, y), source_resolution - 1);
+            depth = reduce_depth(depth, textureLoad(source, coords, 0));
+        }
+    }
+    return depth;
+}
+{{else}}
+@group(0) @binding(0)
+var source: texture_depth_multisampled_2d;
+
+// Resolves the multisampled depth buffer, keeping the reduced sample.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let coords = vec2<i32>(position.xy);
+
+    var depth = EMPTY_DEPTH;
+    for (var sample_index = 0; sample_index < {{SAMPLES}}; sample_index++) {
+        depth = reduce_depth(depth, textureLoad(source, coords, sample_index));
+    }
+    return depth;
+}
+{{/if}}
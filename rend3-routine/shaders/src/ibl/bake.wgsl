@@ -0,0 +1,58 @@
+{{include "rend3-routine/ibl/prefilter.wgsl"}}
+
+struct BakeParameters {
+    /// Perceptual roughness the mip is prefiltered for.
+    perceptual_roughness: f32,
+    sample_count: u32,
+}
+
+@group(0) @binding(0)
+var environment: texture_cube<f32>;
+@group(0) @binding(1)
+var environment_sampler: sampler;
+@group(0) @binding(2)
+var output: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> params: BakeParameters;
+
+// Cosine weighted integral over the hemisphere, divided by pi so it can be directly multiplied with the diffuse color.
+@compute @workgroup_size(8, 8)
+fn cs_irradiance(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let n = cube_direction(gid.z, gid.xy, size);
+    let frame = tangent_frame(n);
+
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < params.sample_count; i++) {
+        let xi = hammersley(i, params.sample_count);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = frame * vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+        let pdf = cos_theta / PI;
+        let lod = sample_lod(environment, params.sample_count, pdf);
+        irradiance += textureSampleLevel(environment, environment_sampler, l, lod).rgb;
+    }
+    irradiance /= f32(params.sample_count);
+
+    textureStore(output, gid.xy, gid.z, vec4<f32>(irradiance, 1.0));
+}
+
+// GGX prefiltered radiance, assuming the view direction is the same as the normal.
+@compute @workgroup_size(8, 8)
+fn cs_prefilter(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let n = cube_direction(gid.z, gid.xy, size);
+    let radiance = prefilter_ggx(environment, environment_sampler, n, params.perceptual_roughness, params.sample_count, vec3<f32>(1.0));
+
+    textureStore(output, gid.xy, gid.z, vec4<f32>(radiance, 1.0));
+}
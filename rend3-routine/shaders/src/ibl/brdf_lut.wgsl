@@ -0,0 +1,45 @@
+{{include "rend3-routine/ibl/common.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+@group(0) @binding(0)
+var lut: texture_storage_2d<rgba16float, write>;
+
+const SAMPLE_COUNT: u32 = 1024u;
+
+// Split-sum scale (r) and bias (g) of f0 for the GGX specular lobe. The x axis is
+// n dot v and the y axis is the perceptual roughness.
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(lut);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let coords = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size);
+    let nov = coords.x;
+    let a = coords.y * coords.y;
+
+    // The normal is +z, the view direction is in the xz plane.
+    let v = vec3<f32>(sqrt(1.0 - nov * nov), 0.0, nov);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let h = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), a);
+        let voh = saturate(dot(v, h));
+        let l = 2.0 * voh * h - v;
+
+        let nol = saturate(l.z);
+        let noh = saturate(h.z);
+
+        if (nol > 0.0) {
+            // Visibility term weighted by the pdf of the sample.
+            let g_vis = brdf_v_smith_ggx_correlated(nov, nol, a) * 4.0 * nol * voh / noh;
+            let fc = pow(1.0 - voh, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+
+    textureStore(lut, gid.xy, vec4<f32>(scale, bias, 0.0, 1.0) / vec4<f32>(f32(SAMPLE_COUNT), f32(SAMPLE_COUNT), 1.0, 1.0));
+}
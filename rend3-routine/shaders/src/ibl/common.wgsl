@@ -0,0 +1,37 @@
+{{include "rend3-routine/math/consts.wgsl"}}
+
+// Low discrepancy sequence used to spread the samples of the integrals evenly.
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Orthonormal basis with the given vector as z.
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3<f32>(tangent, bitangent, n);
+}
+
+// Half vector in tangent space, distributed according to GGX with the given (non-perceptual) roughness.
+fn importance_sample_ggx(xi: vec2<f32>, a: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// Direction through the center of a texel of the given cube face.
+fn cube_direction(face: u32, texel: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(texel) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch (face) {
+        case 0u: { direction = vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { direction = vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { direction = vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { direction = vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { direction = vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { direction = vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(direction);
+}
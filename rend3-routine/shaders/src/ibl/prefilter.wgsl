@@ -0,0 +1,50 @@
+{{include "rend3-routine/ibl/common.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+
+// Mip of the environment where a texel covers the solid angle of a single sample. Reading
+// from it instead of the top mip removes most of the noise of a low sample count.
+fn sample_lod(environment: texture_cube<f32>, sample_count: u32, pdf: f32) -> f32 {
+    let size = f32(textureDimensions(environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_solid_angle = 1.0 / (f32(sample_count) * pdf + 0.0001);
+    return max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+}
+
+// GGX prefiltered radiance around n, assuming the view direction is the same as the normal.
+//
+// Every direction is multiplied with `direction_scale` before the environment is sampled,
+// which allows reading environments which are mirrored along an axis.
+fn prefilter_ggx(
+    environment: texture_cube<f32>,
+    environment_sampler: sampler,
+    n: vec3<f32>,
+    perceptual_roughness: f32,
+    sample_count: u32,
+    direction_scale: vec3<f32>,
+) -> vec3<f32> {
+    if (perceptual_roughness == 0.0) {
+        return textureSampleLevel(environment, environment_sampler, n * direction_scale, 0.0).rgb;
+    }
+
+    let frame = tangent_frame(n);
+    let a = perceptual_roughness * perceptual_roughness;
+
+    var radiance = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let h = frame * importance_sample_ggx(hammersley(i, sample_count), a);
+        let noh = saturate(dot(n, h));
+        let l = 2.0 * noh * h - n;
+        let nol = dot(n, l);
+
+        if (nol > 0.0) {
+            // With v == n, the pdf of l simplifies to D / 4.
+            let pdf = brdf_d_ggx(noh, a) / 4.0;
+            let lod = sample_lod(environment, sample_count, pdf);
+            radiance += textureSampleLevel(environment, environment_sampler, l * direction_scale, lod).rgb * nol;
+            total_weight += nol;
+        }
+    }
+
+    return radiance / max(total_weight, 0.0001);
+}
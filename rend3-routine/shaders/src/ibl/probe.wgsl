@@ -0,0 +1,34 @@
+{{include "rend3-routine/ibl/prefilter.wgsl"}}
+{{include "rend3-routine/math/octahedral.wgsl"}}
+
+struct ProbeBakeParameters {
+    /// Perceptual roughness the mip is prefiltered for.
+    perceptual_roughness: f32,
+    sample_count: u32,
+    /// Scale of the directions the source is sampled with, mirrors captures of right handed renderers.
+    direction_scale: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var environment: texture_cube<f32>;
+@group(0) @binding(1)
+var environment_sampler: sampler;
+// Single layer of the octahedral probe array.
+@group(0) @binding(2)
+var output: texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(3)
+var<uniform> params: ProbeBakeParameters;
+
+// GGX prefiltered radiance of a reflection probe, stored as an octahedral map.
+@compute @workgroup_size(8, 8)
+fn cs_prefilter(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(output);
+    if (any(gid.xy >= size)) {
+        return;
+    }
+
+    let n = oct_decode((vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0);
+    let radiance = prefilter_ggx(environment, environment_sampler, n, params.perceptual_roughness, params.sample_count, params.direction_scale);
+
+    textureStore(output, gid.xy, 0u, vec4<f32>(radiance, 1.0));
+}
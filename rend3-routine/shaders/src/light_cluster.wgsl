@@ -0,0 +1,88 @@
+{{include "rend3-routine/structures.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> clusters: ClusterUniform;
+@group(0) @binding(1)
+var<storage> point_lights: PointLightData;
+// Every cluster is its light count followed by MAX_LIGHTS_PER_CLUSTER light indices.
+@group(0) @binding(2)
+var<storage, read_write> cluster_lights: array<u32>;
+
+// Depth slices past the last one go on forever.
+const MAX_DEPTH: f32 = 1e9;
+
+fn slice_depth(slice: u32) -> f32 {
+    var depth = clusters.near * exp2(f32(slice) / clusters.slice_scale);
+    if (slice == 0u) {
+        depth = clusters.slice_start;
+    }
+    if (slice >= clusters.grid.z) {
+        depth = MAX_DEPTH;
+    }
+    return depth;
+}
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let view = clusters.inv_proj * vec4<f32>(ndc, 1.0);
+    return view.xyz / view.w;
+}
+
+// Point on the line through the given screen position which has the given depth.
+// Works for both perspective and orthographic projections.
+fn point_at_depth(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let a = unproject(vec3<f32>(ndc, 1.0));
+    let b = unproject(vec3<f32>(ndc, 0.5));
+    let t = (depth * clusters.depth_sign - a.z) / (b.z - a.z);
+    return mix(a, b, t);
+}
+
+fn sphere_intersects_aabb(center: vec3<f32>, radius: f32, aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+    let closest = clamp(center, aabb_min, aabb_max);
+    let delta = center - closest;
+    return dot(delta, delta) <= radius * radius;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let cluster_count = clusters.grid.x * clusters.grid.y * clusters.grid.z;
+    let index = gid.x;
+    if (index >= cluster_count) {
+        return;
+    }
+
+    let cluster = vec3<u32>(
+        index % clusters.grid.x,
+        (index / clusters.grid.x) % clusters.grid.y,
+        index / (clusters.grid.x * clusters.grid.y),
+    );
+
+    // Tiles go from the top left of the screen, like framebuffer coordinates.
+    let tile_size = 2.0 / vec2<f32>(clusters.grid.xy);
+    let ndc_min = vec2<f32>(-1.0 + f32(cluster.x) * tile_size.x, 1.0 - f32(cluster.y + 1u) * tile_size.y);
+    let ndc_max = ndc_min + tile_size;
+
+    let near_depth = slice_depth(cluster.z);
+    let far_depth = slice_depth(cluster.z + 1u);
+
+    var aabb_min = vec3<f32>(3.4e38);
+    var aabb_max = vec3<f32>(-3.4e38);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let ndc = select(ndc_min, ndc_max, vec2<bool>((corner & 1u) != 0u, (corner & 2u) != 0u));
+        let depth = select(near_depth, far_depth, (corner & 4u) != 0u);
+        let corner_point = point_at_depth(ndc, depth);
+        aabb_min = min(aabb_min, corner_point);
+        aabb_max = max(aabb_max, corner_point);
+    }
+
+    let base = index * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    var count = 0u;
+    for (var i = 0u; i < point_lights.count; i++) {
+        let light = point_lights.data[i];
+        let center = (clusters.view * light.position).xyz;
+        if (count < MAX_LIGHTS_PER_CLUSTER && sphere_intersects_aabb(center, light.radius, aabb_min, aabb_max)) {
+            cluster_lights[base + 1u + count] = i;
+            count += 1u;
+        }
+    }
+    cluster_lights[base] = count;
+}
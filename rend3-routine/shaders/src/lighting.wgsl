@@ -0,0 +1,463 @@
+{{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/math/brdf.wgsl"}}
+{{include "rend3-routine/math/octahedral.wgsl"}}
+{{include "rend3-routine/shadow/pcf.wgsl"}}
+{{include "rend3-routine/shadow/pcss.wgsl"}}
+{{include "rend3-routine/shadow/evsm.wgsl"}}
+{{include "rend3-routine/fog.wgsl"}}
+
+// Lighting of PBR surfaces with the forward uniforms, shared between the forward shaders and the
+// deferred lighting pass.
+
+@group(0) @binding(0)
+var primary_sampler: sampler;
+@group(0) @binding(1)
+var nearest_sampler: sampler;
+@group(0) @binding(2)
+var comparison_sampler: sampler_comparison; 
+@group(0) @binding(3)
+var<uniform> uniforms: UniformData;
+@group(0) @binding(4)
+var<storage> directional_lights: DirectionalLightData;
+@group(0) @binding(5)
+var<storage> point_lights: PointLightData;
+{{#if spot_lights}}
+@group(0) @binding(6)
+var<storage> spot_lights: SpotLightData;
+{{/if}}
+@group(0) @binding(7)
+var shadows: texture_depth_2d;
+@group(0) @binding(8)
+var ibl_irradiance: texture_cube<f32>;
+@group(0) @binding(9)
+var ibl_prefiltered: texture_cube<f32>;
+@group(0) @binding(10)
+var ibl_brdf_lut: texture_2d<f32>;
+{{#if light_clusters}}
+@group(0) @binding(11)
+var<uniform> clusters: ClusterUniform;
+// Every cluster is its light count followed by MAX_LIGHTS_PER_CLUSTER light indices.
+@group(0) @binding(12)
+var<storage> cluster_lights: array<u32>;
+{{/if}}
+{{#if evsm}}
+// Exponential moments of the shadow atlas, only valid for maps of lights using EVSM.
+@group(0) @binding(13)
+var shadow_moments: texture_2d<f32>;
+{{/if}}
+{{#if ssao}}
+// Screen space ambient occlusion of the viewport, white if disabled.
+@group(0) @binding(14)
+var ambient_occlusion: texture_2d<f32>;
+{{/if}}
+{{#if reflection_probes}}
+// Reflection probes, every probe has an octahedral map of its prefiltered environment in the layer of its index.
+@group(0) @binding(15)
+var<storage> reflection_probes: ReflectionProbeData;
+@group(0) @binding(16)
+var reflection_probe_maps: texture_2d_array<f32>;
+{{/if}}
+
+fn compute_diffuse_color(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
+    return base_color * (1.0 - metallic);
+}
+
+fn compute_f0(base_color: vec3<f32>, metallic: f32, reflectance: f32) -> vec3<f32> {
+    return base_color * metallic + (reflectance * (1.0 - metallic));
+}
+
+fn compute_dielectric_f0(reflectance: f32) -> f32 {
+    return 0.16 * reflectance * reflectance;
+}
+
+fn perceptual_roughness_to_roughness(perceptual_roughness: f32) -> f32 {
+    return perceptual_roughness * perceptual_roughness;
+}
+
+fn surface_shading(light_dir: vec3<f32>, intensity: vec3<f32>, pixel: PixelData, view_pos: vec3<f32>, occlusion: f32) -> vec3<f32> {
+    let n = pixel.normal;
+    let h = normalize(view_pos + light_dir);
+
+    let nov = abs(dot(n, view_pos)) + 0.00001;
+    let nol = saturate(dot(n, light_dir));
+    let noh = saturate(dot(n, h));
+    let loh = saturate(dot(light_dir, h));
+
+    let f90 = saturate(dot(pixel.f0, vec3<f32>(50.0 * 0.33)));
+
+    let d = brdf_d_ggx(noh, pixel.roughness);
+    let f = brdf_f_schlick_vec3(loh, pixel.f0, f90);
+    let v = brdf_v_smith_ggx_correlated(nov, nol, pixel.roughness);
+
+    // TODO: figure out how they generate their lut
+    let energy_comp = 1.0;
+
+    // specular
+    let fr = (d * v) * f;
+    // diffuse
+    let fd = pixel.diffuse_color * brdf_fd_lambert();
+
+    let color = fd + fr * energy_comp;
+
+    let light_attenuation = 1.0;
+
+    return (color * intensity) * (light_attenuation * nol * occlusion);
+}
+
+{{#if light_clusters}}
+// Index of the light cluster the fragment is in, see the clustering module of rend3-routine.
+fn cluster_index(frag_coord: vec2<f32>, view_position: vec3<f32>) -> u32 {
+    let tile = vec2<u32>(frag_coord / vec2<f32>(uniforms.resolution) * vec2<f32>(clusters.grid.xy));
+    let depth = view_position.z * clusters.depth_sign;
+    let slice = floor(log2(max(depth, 1e-6) / clusters.near) * clusters.slice_scale);
+    let cluster = min(vec3<u32>(tile, u32(max(slice, 0.0))), clusters.grid - 1u);
+    return cluster.x + (cluster.y + cluster.z * clusters.grid.y) * clusters.grid.x;
+}
+{{/if}}
+
+// How much the probe contributes at the given position, fading out towards the sides of its box.
+fn reflection_probe_weight(probe: ReflectionProbe, world_position: vec3<f32>) -> f32 {
+    let inside = min(world_position - probe.box_min, probe.box_max - world_position);
+    let distance = min(inside.x, min(inside.y, inside.z));
+    return saturate(distance / max(probe.blend_distance, 0.0001));
+}
+
+// Direction from the probe to the point where the reflection ray leaves its box. Looking this
+// up instead of the reflection itself makes the reflections line up with the walls of the box.
+fn box_project(probe: ReflectionProbe, world_position: vec3<f32>, reflection: vec3<f32>) -> vec3<f32> {
+    let to_max = (probe.box_max - world_position) / reflection;
+    let to_min = (probe.box_min - world_position) / reflection;
+    let exits = max(to_max, to_min);
+    let distance = min(exits.x, min(exits.y, exits.z));
+    return world_position + reflection * distance - probe.position;
+}
+
+// Split-sum image based lighting, see the ibl module of rend3-routine.
+fn ibl_lighting(pixel: PixelData, view_pos: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let inv_view_mat3 = mat3x3<f32>(uniforms.inv_view[0].xyz, uniforms.inv_view[1].xyz, uniforms.inv_view[2].xyz);
+
+    let n = pixel.normal;
+    let nov = saturate(dot(n, view_pos));
+    let world_normal = inv_view_mat3 * n;
+    let world_reflection = inv_view_mat3 * reflect(-view_pos, n);
+
+    // Lightmaps replace the ambient term of the environment.
+    var irradiance: vec3<f32>;
+    if (pixel.lightmapped) {
+        irradiance = pixel.baked_irradiance;
+    } else {
+        irradiance = textureSampleLevel(ibl_irradiance, primary_sampler, world_normal, 0.0).rgb;
+    }
+    let diffuse = irradiance * pixel.diffuse_color;
+
+    let max_lod = f32(textureNumLevels(ibl_prefiltered) - 1u);
+    let environment = textureSampleLevel(ibl_prefiltered, primary_sampler, world_reflection, pixel.perceptual_roughness * max_lod).rgb;
+
+    var prefiltered = environment;
+{{#if reflection_probes}}
+    // Blend the probes containing the fragment, the global environment fills in the weight they leave.
+    let probe_max_lod = f32(textureNumLevels(reflection_probe_maps) - 1u);
+    var probe_radiance = vec3<f32>(0.0);
+    var probe_weight = 0.0;
+    for (var i = 0u; i < reflection_probes.count; i += 1u) {
+        let probe = reflection_probes.data[i];
+        let weight = reflection_probe_weight(probe, world_position);
+        if (weight <= 0.0) {
+            continue;
+        }
+
+        let direction = normalize(box_project(probe, world_position, world_reflection));
+        let uv = oct_encode(direction) * 0.5 + 0.5;
+        let lod = pixel.perceptual_roughness * probe_max_lod;
+        probe_radiance += textureSampleLevel(reflection_probe_maps, primary_sampler, uv, i, lod).rgb * weight;
+        probe_weight += weight;
+    }
+    if (probe_weight > 1.0) {
+        probe_radiance /= probe_weight;
+        probe_weight = 1.0;
+    }
+    prefiltered = probe_radiance + environment * (1.0 - probe_weight);
+{{/if}}
+
+    // Keep the lookup within the texel centers so the edges don't bleed.
+    let lut_size = vec2<f32>(textureDimensions(ibl_brdf_lut));
+    let lut_coords = (vec2<f32>(nov, pixel.perceptual_roughness) * (lut_size - 1.0) + 0.5) / lut_size;
+    let scale_bias = textureSampleLevel(ibl_brdf_lut, primary_sampler, lut_coords, 0.0).rg;
+
+    let f90 = saturate(dot(pixel.f0, vec3<f32>(50.0 * 0.33)));
+    let specular = prefiltered * (pixel.f0 * scale_bias.x + f90 * scale_bias.y);
+
+    return (diffuse + specular) * pixel.ambient_occlusion;
+}
+
+fn sample_directional_shadow(light_idx: u32, world_position: vec4<f32>, world_normal: vec3<f32>) -> f32 {
+    let light = &directional_lights.data[light_idx];
+
+    // Cascades are ordered from smallest to largest, so the first cascade which contains
+    // the fragment is the one with the highest resolution.
+    for (var cascade_idx = 0u; cascade_idx < (*light).cascade_count; cascade_idx += 1u) {
+        let cascade = (*light).cascades[cascade_idx];
+
+        // How far the shadow map coordinates move per world unit, used to convert the
+        // world space bias and texel space normal offset.
+        let map_texels = cascade.size / (*light).inv_resolution;
+        let texels_per_unit = length(vec3<f32>(cascade.view_proj[0].x, cascade.view_proj[1].x, cascade.view_proj[2].x)) * 0.5 * map_texels.x;
+        let depth_per_unit = length(vec3<f32>(cascade.view_proj[0].z, cascade.view_proj[1].z, cascade.view_proj[2].z));
+
+        let offset_position = world_position + vec4<f32>(world_normal * ((*light).shadow_normal_offset / texels_per_unit), 0.0);
+
+        // Get the shadow ndc coordinates, then convert to texture sample coordinates
+        let shadow_ndc = (cascade.view_proj * offset_position).xyz;
+        let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+        let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
+
+        // The shadow is stored in an atlas, so we need to make sure we don't linear blend
+        // across atlasses. We move our conditional borders in a half a pixel for standard
+        // linear blending (so we're hitting texel centers on the edge). We move it an additional
+        // pixel in so that our pcf5 offsets don't move off the edge of the atlasses.
+        let shadow_border = ((*light).inv_resolution * 1.5) / cascade.size;
+
+        if (
+            all(shadow_local_coords >= shadow_border) && // XY lower
+            all(shadow_local_coords <= 1.0 - shadow_border) && // XY upper
+            shadow_ndc.z >= 0.0 && // Z lower
+            shadow_ndc.z <= 1.0 // Z upper
+        ) {
+            // Texture sample coordinates in the atlas
+            let shadow_coords = cascade.offset + cascade.size * shadow_local_coords;
+
+            // Depth grows towards the light.
+            let depth = shadow_ndc.z + (*light).shadow_bias * depth_per_unit;
+
+            // Must match the filter indices in DirectionalLightManager.
+            var shadow_value = 1.0;
+            switch ((*light).shadow_filter) {
+                // Hard
+                case 0u: {
+                    shadow_value = textureSampleCompareLevel(shadows, comparison_sampler, shadow_coords, depth);
+                }
+                // PCSS
+                case 2u: {
+                    let half_texel = (*light).inv_resolution * 0.5;
+                    let bounds = vec4<f32>(cascade.offset + half_texel, cascade.offset + cascade.size - half_texel);
+                    let penumbra_scale = (*light).light_size * texels_per_unit / depth_per_unit;
+                    shadow_value = shadow_sample_pcss(shadows, comparison_sampler, shadow_coords, depth, bounds, penumbra_scale);
+                }
+                {{#if evsm}}
+                // EVSM
+                case 3u: {
+                    let moments = evsm_load_moments(shadow_moments, shadow_coords);
+                    shadow_value = shadow_sample_evsm(moments, depth);
+                }
+                {{/if}}
+                // PCF, also used for EVSM if the moments aren't bound
+                default: {
+                    shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, depth);
+                }
+            }
+            return shadow_value;
+        }
+    }
+
+    return 1.0;
+}
+
+fn sample_perspective_shadow(shadow_map: ShadowMap, inv_resolution: vec2<f32>, world_position: vec4<f32>) -> f32 {
+    // Perspective projections need the divide done by hand.
+    let shadow_clip = shadow_map.view_proj * world_position;
+    let shadow_ndc = shadow_clip.xyz / shadow_clip.w;
+    let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
+
+    // Fragments near the edge of the map are clamped inwards, so the pcf5 offsets stay within the map.
+    let shadow_border = (inv_resolution * 1.5) / shadow_map.size;
+    let shadow_local_coords = clamp(vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y), shadow_border, 1.0 - shadow_border);
+
+    // Behind the light
+    if (shadow_clip.w <= 0.0) {
+        return 1.0;
+    }
+
+    // Texture sample coordinates in the atlas
+    let shadow_coords = shadow_map.offset + shadow_map.size * shadow_local_coords;
+
+    return shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
+}
+
+fn sample_point_shadow(light_idx: u32, world_position: vec4<f32>) -> f32 {
+    let light = &point_lights.data[light_idx];
+
+    if ((*light).shadowed == 0u) {
+        return 1.0;
+    }
+
+    // Pick the cube face by the major axis of the direction from the light to the fragment.
+    let delta = world_position.xyz - (*light).position.xyz;
+    let abs_delta = abs(delta);
+    var face: u32;
+    if (abs_delta.x >= abs_delta.y && abs_delta.x >= abs_delta.z) {
+        face = select(1u, 0u, delta.x >= 0.0);
+    } else if (abs_delta.y >= abs_delta.z) {
+        face = select(3u, 2u, delta.y >= 0.0);
+    } else {
+        face = select(5u, 4u, delta.z >= 0.0);
+    }
+
+    return sample_perspective_shadow((*light).shadow_maps[face], (*light).inv_resolution, world_position);
+}
+
+{{#if spot_lights}}
+fn sample_spot_shadow(light_idx: u32, world_position: vec4<f32>) -> f32 {
+    let light = &spot_lights.data[light_idx];
+
+    if ((*light).shadowed == 0u) {
+        return 1.0;
+    }
+
+    return sample_perspective_shadow((*light).shadow_map, (*light).inv_resolution, world_position);
+}
+{{/if}}
+
+// Blends the color of the fragment with the fog between it and the camera.
+fn fog_fragment(color: vec4<f32>, view_position: vec4<f32>) -> vec4<f32> {
+    if (!fog_enabled(uniforms.fog)) {
+        return color;
+    }
+
+    let camera = uniforms.inv_view[3].xyz;
+    let delta = (uniforms.inv_view * view_position).xyz - camera;
+    let distance = length(delta);
+    let direction = delta / max(distance, 0.0001);
+
+    var inscattering = vec3<f32>(0.0);
+    for (var i = 0u; i < directional_lights.count; i += 1u) {
+        inscattering += fog_inscattering(uniforms.fog, direction, directional_lights.data[i]);
+    }
+
+    return vec4<f32>(apply_fog(uniforms.fog, color.rgb, camera, direction, distance, inscattering), color.a);
+}
+
+// Lights the surface with the environment and all lights of the scene, then applies the fog.
+//
+// `frag_coord` is the position of the fragment in pixels. Surfaces which don't receive shadows
+// are lit as if every light was unshadowed.
+fn shade_pixel(pixel: PixelData, frag_coord: vec2<f32>, view_position: vec4<f32>, receive_shadows: bool) -> vec4<f32> {
+    // View vector
+    let v = -normalize(view_position.xyz);
+
+    // Transform vectors into view space
+    let view_mat3 = mat3x3<f32>(uniforms.view[0].xyz, uniforms.view[1].xyz, uniforms.view[2].xyz);
+
+    let world_position = uniforms.inv_view * view_position;
+    let world_normal = mat3x3<f32>(uniforms.inv_view[0].xyz, uniforms.inv_view[1].xyz, uniforms.inv_view[2].xyz) * pixel.normal;
+
+    // Screen space ambient occlusion only darkens the ambient and image based lighting.
+    {{#if ssao}}
+    let ssao_coords = frag_coord / vec2<f32>(uniforms.resolution);
+    let ssao = textureSampleLevel(ambient_occlusion, primary_sampler, ssao_coords, 0.0).r;
+    {{else}}
+    let ssao = 1.0;
+    {{/if}}
+
+    var color = pixel.emissive.rgb + ibl_lighting(pixel, v, world_position.xyz) * ssao;
+    for (var i = 0; i < i32(directional_lights.count); i += 1) {
+        let light = directional_lights.data[i];
+
+        // Baked lights are already part of the lightmap.
+        if (pixel.lightmapped && light.baked != 0u) {
+            continue;
+        }
+
+        var shadow_value = 1.0;
+        if (receive_shadows) {
+            shadow_value = sample_directional_shadow(u32(i), world_position, world_normal);
+        }
+
+        // Calculate light source vector
+        let l = normalize(view_mat3 * -light.direction);
+
+        color += surface_shading(l, light.color, pixel, v, shadow_value * pixel.ambient_occlusion);
+    }
+
+    {{#if light_clusters}}
+    let cluster_base = cluster_index(frag_coord, view_position.xyz) * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    let cluster_light_count = cluster_lights[cluster_base];
+    for (var j = 0u; j < cluster_light_count; j += 1u) {
+        let i = cluster_lights[cluster_base + 1u + j];
+    {{else}}
+    // Without clusters, every point light is visited.
+    for (var i = 0u; i < point_lights.count; i += 1u) {
+    {{/if}}
+        let light = point_lights.data[i];
+
+        if (pixel.lightmapped && light.baked != 0u) {
+            continue;
+        }
+
+        // Delta to light
+        let delta = (uniforms.view * light.position).xyz - view_position.xyz;
+
+        // Distance
+        let d = length(delta);
+
+        // Attenuate from light and cusp at radius
+        // Derivative is 0 at both d = 0 and d = radius
+        // Source: https://lisyarus.github.io/blog/graphics/2022/07/30/point-light-attenuation.html
+        let s = saturate(d / light.radius);
+        let s2 = s * s;
+        let inv_s2 = 1.0 - s2;
+        let att = inv_s2 * inv_s2 / (1.0 + s2);
+        let intensity = light.color * att;
+
+        // Calculate light source vector
+        let l = delta / d;
+
+        var shadow_value = 1.0;
+        if (receive_shadows) {
+            shadow_value = sample_point_shadow(i, world_position);
+        }
+
+        color += max(surface_shading(l, intensity, pixel, v, shadow_value * pixel.ambient_occlusion), vec3<f32>(0.0));
+    }
+
+    {{#if spot_lights}}
+    for (var i = 0; i < i32(spot_lights.count); i += 1) {
+        let light = spot_lights.data[i];
+
+        if (pixel.lightmapped && light.baked != 0u) {
+            continue;
+        }
+
+        // Delta to light
+        let delta = (uniforms.view * light.position).xyz - view_position.xyz;
+
+        // Distance
+        let d = length(delta);
+
+        // Same distance attenuation as point lights
+        let s = saturate(d / light.radius);
+        let s2 = s * s;
+        let inv_s2 = 1.0 - s2;
+        let att = inv_s2 * inv_s2 / (1.0 + s2);
+
+        // Calculate light source vector
+        let l = delta / d;
+
+        // Falloff between the inner and outer cone
+        let spot_direction = normalize(view_mat3 * light.direction);
+        let cone = smoothstep(light.cos_outer_angle, light.cos_inner_angle, dot(-l, spot_direction));
+
+        let intensity = light.color * (att * cone);
+
+        var shadow_value = 1.0;
+        if (receive_shadows) {
+            shadow_value = sample_spot_shadow(u32(i), world_position);
+        }
+
+        color += max(surface_shading(l, intensity, pixel, v, shadow_value * pixel.ambient_occlusion), vec3<f32>(0.0));
+    }
+    {{/if}}
+
+    let ambient = uniforms.ambient * pixel.albedo * vec4<f32>(vec3<f32>(ssao), 1.0);
+    let shaded = vec4<f32>(color, pixel.albedo.a);
+    return fog_fragment(max(ambient, shaded), view_position);
+}
@@ -13,6 +13,8 @@ const FLAGS_CC_GLTF_SPLIT: u32        = 0x0800u;
 const FLAGS_CC_BW_SPLIT: u32          = 0x1000u;
 const FLAGS_UNLIT: u32                = 0x2000u;
 const FLAGS_NEAREST: u32              = 0x4000u;
+const FLAGS_LIGHTMAP_DIR: u32         = 0x8000u;
+const FLAGS_LIGHTMAP_MONO_SH: u32     = 0x10000u;
 
 fn extract_material_flag(data: u32, flag: u32) -> bool {
     return bool(data & flag);
@@ -31,8 +33,13 @@ struct GpuMaterialData {
     // -- 16 --
     anisotropy_tex: u32,
     ambient_occlusion_tex: u32,
+{{#if lightmaps}}
+    lightmap_tex: u32,
+    lightmap_direction_tex: u32,
+{{else}}
     _padding0: u32,
     _padding1: u32,
+{{/if}}
     
     // -- 16 --
 
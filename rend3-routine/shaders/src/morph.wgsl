@@ -0,0 +1,80 @@
+struct MorphInput {
+    /// Byte offset into vertex buffer of position attribute of unmorphed mesh.
+    base_position_offset: u32,
+    /// Byte offset into vertex buffer of normal attribute of unmorphed mesh.
+    base_normal_offset: u32,
+    /// Byte offset into vertex buffer of tangent attribute of unmorphed mesh.
+    base_tangent_offset: u32,
+    /// Byte offset into vertex buffer of position offsets of the first morph target.
+    position_offsets_offset: u32,
+    /// Byte offset into vertex buffer of normal offsets of the first morph target.
+    normal_offsets_offset: u32,
+    /// Byte offset into vertex buffer of tangent offsets of the first morph target.
+    tangent_offsets_offset: u32,
+    /// Byte offset into vertex buffer of position attribute of morphed mesh.
+    updated_position_offset: u32,
+    /// Byte offset into vertex buffer of normal attribute of morphed mesh.
+    updated_normal_offset: u32,
+    /// Byte offset into vertex buffer of tangent attribute of morphed mesh.
+    updated_tangent_offset: u32,
+
+    /// Index into the weight buffer of the weight of the first morph target.
+    weight_base_offset: u32,
+    /// Count of morph targets in this mesh.
+    target_count: u32,
+    /// Count of vertices in this mesh.
+    vertex_count: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> vertex_buffer: array<u32>;
+@group(0) @binding(1)
+var<storage> input: MorphInput;
+@group(0) @binding(2)
+var<storage> morph_weights: array<f32>;
+
+{{include "rend3/vertex_attributes.wgsl"}}
+{{include "rend3/vertex_attributes_store.wgsl"}}
+
+// Adds the weighted offsets of every morph target to the base value of a vec3 attribute.
+fn morph_attribute(base_offset: u32, offsets_offset: u32, idx: u32) -> vec3<f32> {
+    var value = extract_attribute_vec3_f32(base_offset, idx);
+
+    if (offsets_offset == 0xFFFFFFFFu) {
+        return value;
+    }
+
+    // The offsets of each target are stored back to back.
+    let target_stride = input.vertex_count * 12u;
+    for (var target_index = 0u; target_index < input.target_count; target_index++) {
+        let weight = morph_weights[input.weight_base_offset + target_index];
+
+        if (weight != 0.0) {
+            value += extract_attribute_vec3_f32(offsets_offset + target_index * target_stride, idx) * weight;
+        }
+    }
+
+    return value;
+}
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+
+    if (idx >= input.vertex_count) {
+        return;
+    }
+
+    if (input.updated_position_offset != 0xFFFFFFFFu) {
+        let position = morph_attribute(input.base_position_offset, input.position_offsets_offset, idx);
+        store_attribute_vec3_f32(input.updated_position_offset, idx, position);
+    }
+    if (input.updated_normal_offset != 0xFFFFFFFFu) {
+        let normal = morph_attribute(input.base_normal_offset, input.normal_offsets_offset, idx);
+        store_attribute_vec3_f32(input.updated_normal_offset, idx, normalize(normal));
+    }
+    if (input.updated_tangent_offset != 0xFFFFFFFFu) {
+        let tangent = morph_attribute(input.base_tangent_offset, input.tangent_offsets_offset, idx);
+        store_attribute_vec3_f32(input.updated_tangent_offset, idx, normalize(tangent));
+    }
+}
@@ -0,0 +1,26 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var accumulation: texture_2d<f32>;
+@group(0) @binding(1)
+var revealage: texture_2d<f32>;
+
+// Outputs the average color of the transparent fragments, with how much of the background shows through them
+// in alpha, which the blending uses to mix them.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+
+    let reveal = textureLoad(revealage, coords, 0).r;
+    // Nothing transparent covers this pixel.
+    if (reveal >= 1.0) {
+        discard;
+    }
+
+    let accum = textureLoad(accumulation, coords, 0);
+    let average = accum.rgb / max(accum.a, 1e-5);
+    return vec4<f32>(average, reveal);
+}
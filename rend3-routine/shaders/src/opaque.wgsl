@@ -1,25 +1,10 @@
 {{include "rend3-routine/structures.wgsl"}}
 {{include "rend3-routine/structures_object.wgsl"}}
 {{include "rend3-routine/material.wgsl"}}
-{{include "rend3-routine/math/brdf.wgsl"}}
 {{include "rend3-routine/math/color.wgsl"}}
 {{include "rend3-routine/math/matrix.wgsl"}}
-{{include "rend3-routine/shadow/pcf.wgsl"}}
-
-@group(0) @binding(0)
-var primary_sampler: sampler;
-@group(0) @binding(1)
-var nearest_sampler: sampler;
-@group(0) @binding(2)
-var comparison_sampler: sampler_comparison; 
-@group(0) @binding(3)
-var<uniform> uniforms: UniformData;
-@group(0) @binding(4)
-var<storage> directional_lights: DirectionalLightData;
-@group(0) @binding(5)
-var<storage> point_lights: PointLightData;
-@group(0) @binding(6)
-var shadows: texture_depth_2d;
+{{include "rend3-routine/lighting.wgsl"}}
+{{include "rend3-routine/gbuffer.wgsl"}}
 
 @group(1) @binding(0)
 var<storage> object_buffer: array<Object>;
@@ -58,6 +43,12 @@ var emissive_tex: texture_2d<f32>;
 var anisotropy_tex: texture_2d<f32>;
 @group(2) @binding(9)
 var ambient_occlusion_tex: texture_2d<f32>;
+{{#if lightmaps}}
+@group(2) @binding(10)
+var lightmap_tex: texture_2d<f32>;
+@group(2) @binding(11)
+var lightmap_direction_tex: texture_2d<f32>;
+{{/if}}
 {{/if}}
 
 {{
@@ -66,6 +57,7 @@ var ambient_occlusion_tex: texture_2d<f32>;
     object_buffer
 
     position
+    previous_position
     normal
     tangent
     texture_coords_0
@@ -74,7 +66,8 @@ var ambient_occlusion_tex: texture_2d<f32>;
 }}
 
 struct VertexOutput {
-    @builtin(position) position: vec4<f32>,
+    // Invariant so the forward pass matches the depth of a depth prepass exactly.
+    @builtin(position) @invariant position: vec4<f32>,
     @location(0) view_position: vec4<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) tangent: vec3<f32>,
@@ -82,6 +75,10 @@ struct VertexOutput {
     @location(4) coords1: vec2<f32>,
     @location(6) color: vec4<f32>,
     @location(7) @interpolate(flat) material: u32,
+    @location(8) @interpolate(flat) receive_shadows: u32,
+    // Unjittered clip space positions in this and the previous frame, for motion vectors.
+    @location(9) clip_position: vec4<f32>,
+    @location(10) previous_clip_position: vec4<f32>,
 }
 
 
@@ -103,6 +100,7 @@ fn vs_main(@builtin(instance_index) instance_index: u32, @builtin(vertex_index)
 
     var vs_out: VertexOutput;
     vs_out.material = data.material_index;
+    vs_out.receive_shadows = data.receive_shadows;
     vs_out.view_position = model_view * position_vec4;
     vs_out.normal = normalize(mv_mat3 * (inv_scale_sq * vs_in.normal));
     vs_out.tangent = normalize(mv_mat3 * (inv_scale_sq * vs_in.tangent));
@@ -110,6 +108,8 @@ fn vs_main(@builtin(instance_index) instance_index: u32, @builtin(vertex_index)
     vs_out.coords0 = vs_in.texture_coords_0;
     vs_out.coords1 = vs_in.texture_coords_1;
     vs_out.position = model_view_proj * position_vec4;
+    vs_out.clip_position = uniforms.unjittered_view_proj * data.transform * position_vec4;
+    vs_out.previous_clip_position = uniforms.previous_view_proj * data.previous_transform * vec4<f32>(vs_in.previous_position, 1.0);
 
     return vs_out;
 }
@@ -127,6 +127,10 @@ fn has_clear_coat_roughness_texture(material: ptr<function, Material>) -> bool {
 fn has_emissive_texture(material: ptr<function, Material>) -> bool { return (*material).emissive_tex != 0u; }
 fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return (*material).anisotropy_tex != 0u; }
 fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return (*material).ambient_occlusion_tex != 0u; }
+{{#if lightmaps}}
+fn has_lightmap_texture(material: ptr<function, Material>) -> bool { return (*material).lightmap_tex != 0u; }
+fn has_lightmap_direction_texture(material: ptr<function, Material>) -> bool { return (*material).lightmap_direction_tex != 0u; }
+{{/if}}
 
 fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).albedo_tex - 1u], samp, coords, ddx, ddy); }
 fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).normal_tex - 1u], samp, coords, ddx, ddy); }
@@ -138,6 +142,10 @@ fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler
 fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).emissive_tex - 1u], samp, coords, ddx, ddy); }
 fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).anisotropy_tex - 1u], samp, coords, ddx, ddy); }
 fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).ambient_occlusion_tex - 1u], samp, coords, ddx, ddy); }
+{{#if lightmaps}}
+fn lightmap_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).lightmap_tex - 1u], samp, coords, ddx, ddy); }
+fn lightmap_direction_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(textures[(*material).lightmap_direction_tex - 1u], samp, coords, ddx, ddy); }
+{{/if}}
 {{else}}
 alias Material = CpuMaterialData;
 
@@ -151,6 +159,10 @@ fn has_clear_coat_roughness_texture(material: ptr<function, Material>) -> bool {
 fn has_emissive_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 7u) & 0x1u); }
 fn has_anisotropy_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 8u) & 0x1u); }
 fn has_ambient_occlusion_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 9u) & 0x1u); }
+{{#if lightmaps}}
+fn has_lightmap_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 10u) & 0x1u); }
+fn has_lightmap_direction_texture(material: ptr<function, Material>) -> bool { return bool(((*material).texture_enable >> 11u) & 0x1u); }
+{{/if}}
 
 fn albedo_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(albedo_tex, samp, coords, ddx, ddy); }
 fn normal_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(normal_tex, samp, coords, ddx, ddy); }
@@ -162,23 +174,11 @@ fn clear_coat_roughness_texture(material: ptr<function, Material>, samp: sampler
 fn emissive_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(emissive_tex, samp, coords, ddx, ddy); }
 fn anisotropy_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(anisotropy_tex, samp, coords, ddx, ddy); }
 fn ambient_occlusion_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(ambient_occlusion_tex, samp, coords, ddx, ddy); }
+{{#if lightmaps}}
+fn lightmap_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(lightmap_tex, samp, coords, ddx, ddy); }
+fn lightmap_direction_texture(material: ptr<function, Material>, samp: sampler, coords: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> { return textureSampleGrad(lightmap_direction_tex, samp, coords, ddx, ddy); }
+{{/if}}
 {{/if}}
-
-fn compute_diffuse_color(base_color: vec3<f32>, metallic: f32) -> vec3<f32> {
-    return base_color * (1.0 - metallic);
-}
-
-fn compute_f0(base_color: vec3<f32>, metallic: f32, reflectance: f32) -> vec3<f32> {
-    return base_color * metallic + (reflectance * (1.0 - metallic));
-}
-
-fn compute_dielectric_f0(reflectance: f32) -> f32 {
-    return 0.16 * reflectance * reflectance;
-}
-
-fn perceptual_roughness_to_roughness(perceptual_roughness: f32) -> f32 {
-    return perceptual_roughness * perceptual_roughness;
-}
 
 fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput) -> PixelData {
     var material = material_arg;
@@ -188,6 +188,12 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
     let uvdx = dpdx(coords);
     let uvdy = dpdy(coords);
 
+{{#if lightmaps}}
+    let lightmap_coords = (material.uv_transform1 * vec3<f32>(vs_out.coords1, 1.0)).xy;
+    let lightmap_uvdx = dpdx(lightmap_coords);
+    let lightmap_uvdy = dpdy(lightmap_coords);
+{{/if}}
+
     // --- ALBEDO ---
 
     if (extract_material_flag(material.flags, FLAGS_ALBEDO_ACTIVE)) {
@@ -385,6 +391,34 @@ fn get_pixel_data_inner(material_arg: Material, s: sampler, vs_out: VertexOutput
         pixel.anisotropy = material.anisotropy;
     }
 
+    // --- LIGHTMAP ---
+
+{{#if lightmaps}}
+    pixel.lightmapped = has_lightmap_texture(&material);
+    if (pixel.lightmapped) {
+        let irradiance = lightmap_texture(&material, s, lightmap_coords, lightmap_uvdx, lightmap_uvdy).rgb;
+        let world_normal = mat3x3<f32>(uniforms.inv_view[0].xyz, uniforms.inv_view[1].xyz, uniforms.inv_view[2].xyz) * pixel.normal;
+
+        if (has_lightmap_direction_texture(&material)) {
+            let texture_read = lightmap_direction_texture(&material, s, lightmap_coords, lightmap_uvdx, lightmap_uvdy);
+            if (extract_material_flag(material.flags, FLAGS_LIGHTMAP_MONO_SH)) {
+                // RGB: L1 band normalized by the L0 band, so the irradiance is L0 * (1 + 2 * dot(L1, n)).
+                let l1 = texture_read.rgb * 2.0 - 1.0;
+                pixel.baked_irradiance = irradiance * max(1.0 + 2.0 * dot(l1, world_normal), 0.0);
+            } else {
+                // RGB: dominant direction, A: directionality. The half lambert term is rescaled
+                // so that a surface facing the dominant direction gets the full irradiance.
+                let half_lambert = dot(world_normal, texture_read.rgb - 0.5) + 0.5;
+                pixel.baked_irradiance = irradiance * half_lambert / max(texture_read.a, 0.0001);
+            }
+        } else {
+            pixel.baked_irradiance = irradiance;
+        }
+    }
+{{else}}
+    pixel.lightmapped = false;
+{{/if}}
+
     // --- COMPUTATIONS---
 
     pixel.diffuse_color = compute_diffuse_color(pixel.albedo.xyz, pixel.metallic);
@@ -417,115 +451,121 @@ fn get_pixel_data(material: Material, vs_out: VertexOutput) -> PixelData {
 }
 {{/if}}
 
-fn surface_shading(light_dir: vec3<f32>, intensity: vec3<f32>, pixel: PixelData, view_pos: vec3<f32>, occlusion: f32) -> vec3<f32> {
-    let n = pixel.normal;
-    let h = normalize(view_pos + light_dir);
+fn shade_fragment(vs_out: VertexOutput) -> vec4<f32> {
+    let material = materials[vs_out.material];
 
-    let nov = abs(dot(n, view_pos)) + 0.00001;
-    let nol = saturate(dot(n, light_dir));
-    let noh = saturate(dot(n, h));
-    let loh = saturate(dot(light_dir, h));
+    let pixel = get_pixel_data(material, vs_out);
 
-    let f90 = saturate(dot(pixel.f0, vec3<f32>(50.0 * 0.33)));
+    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
+        return fog_fragment(pixel.albedo, vs_out.view_position);
+    }
 
-    let d = brdf_d_ggx(noh, pixel.roughness);
-    let f = brdf_f_schlick_vec3(loh, pixel.f0, f90);
-    let v = brdf_v_smith_ggx_correlated(nov, nol, pixel.roughness);
+    return shade_pixel(pixel, vs_out.position.xy, vs_out.view_position, vs_out.receive_shadows != 0u);
+}
 
-    // TODO: figure out how they generate their lut
-    let energy_comp = 1.0;
+@fragment
+fn fs_main(vs_out: VertexOutput) -> @location(0) vec4<f32> {
+    return shade_fragment(vs_out);
+}
 
-    // specular
-    let fr = (d * v) * f;
-    // diffuse
-    let fd = pixel.diffuse_color * brdf_fd_lambert();
+struct VelocityOutput {
+    @location(0) color: vec4<f32>,
+    // Offset from the fragment to where it was in the previous frame, in UV coordinates.
+    @location(1) velocity: vec2<f32>,
+}
 
-    let color = fd + fr * energy_comp;
+// Shaded fragment and its motion for temporal anti-aliasing, rendered by the velocity routines.
+@fragment
+fn fs_velocity(vs_out: VertexOutput) -> VelocityOutput {
+    let current = vs_out.clip_position.xy / vs_out.clip_position.w;
+    let previous = vs_out.previous_clip_position.xy / vs_out.previous_clip_position.w;
+
+    var out: VelocityOutput;
+    out.color = shade_fragment(vs_out);
+    // UV coordinates point down, unlike normalized device coordinates.
+    out.velocity = (previous - current) * vec2<f32>(0.5, -0.5);
+    return out;
+}
 
-    let light_attenuation = 1.0;
+struct WeightedBlendedOutput {
+    // Sum of the premultiplied colors and alphas, weighted by distance.
+    @location(0) accumulation: vec4<f32>,
+    // Multiplied into the product of one minus the alphas, which is how much of the background shows through.
+    @location(1) revealage: f32,
+}
 
-    return (color * intensity) * (light_attenuation * nol * occlusion);
+// Shaded fragment for weighted blended order-independent transparency, from McGuire and Bavoil's
+// "Weighted Blended Order-Independent Transparency".
+@fragment
+fn fs_weighted_blended(vs_out: VertexOutput) -> WeightedBlendedOutput {
+    let color = shade_fragment(vs_out);
+
+    // Closer fragments get more weight, so they dominate the average like they would when sorted. This is
+    // equation 9 of the paper, capped lower so the weighted HDR color doesn't overflow the half float target.
+    let distance = length(vs_out.view_position.xyz);
+    let weight = color.a * clamp(
+        10.0 / (1e-5 + pow(distance / 5.0, 2.0) + pow(distance / 200.0, 6.0)),
+        1e-2,
+        3e2,
+    );
+
+    var out: WeightedBlendedOutput;
+    out.accumulation = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
 }
 
+// Surface of the fragment for screen space effects, rendered by the prepass routines.
+//
+// Holds the octahedral encoded view space normal in xy, the perceptual roughness in z and
+// the metallic value in w. The depth is written by the pipeline.
 @fragment
-fn fs_main(vs_out: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_prepass(vs_out: VertexOutput) -> @location(0) vec4<f32> {
     let material = materials[vs_out.material];
 
     let pixel = get_pixel_data(material, vs_out);
 
+    // Unlit surfaces don't reflect anything.
     if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
-        return pixel.albedo;
+        return vec4<f32>(oct_encode(pixel.normal), 1.0, 0.0);
     }
 
-    // View vector
-    let v = -normalize(vs_out.view_position.xyz);
-
-    // Transform vectors into view space
-    let view_mat3 = mat3x3<f32>(uniforms.view[0].xyz, uniforms.view[1].xyz, uniforms.view[2].xyz);
-
-    var color = pixel.emissive.rgb;
-    for (var i = 0; i < i32(directional_lights.count); i += 1) {
-        let light = directional_lights.data[i];
-
-        // Get the shadow ndc coordinates, then convert to texture sample coordinates
-        let shadow_ndc = (light.view_proj * uniforms.inv_view * vs_out.view_position).xyz;
-        let shadow_flipped = (shadow_ndc.xy * 0.5) + 0.5;
-        let shadow_local_coords = vec2<f32>(shadow_flipped.x, 1.0 - shadow_flipped.y);
-
-        // Texture sample coordinates of 
-        var top_left = light.offset;
-        var top_right = top_left + light.size;
-        let shadow_coords = mix(top_left, top_right, shadow_local_coords);
-
-        // The shadow is stored in an atlas, so we need to make sure we don't linear blend
-        // across atlasses. We move our conditional borders in a half a pixel for standard
-        // linear blending (so we're hitting texel centers on the edge). We move it an additional
-        // pixel in so that our pcf5 offsets don't move off the edge of the atlasses.
-        let shadow_border = light.inv_resolution * 1.5;
-        top_left += shadow_border;
-        top_right -= shadow_border;
-
-        var shadow_value = 1.0;
-        if (
-            any(shadow_flipped >= top_left) && // XY lower
-            any(shadow_flipped <= top_right) && // XY upper
-            shadow_ndc.z >= 0.0 && // Z lower
-            shadow_ndc.z <= 1.0 // Z upper
-        ) {
-            shadow_value = shadow_sample_pcf5(shadows, comparison_sampler, shadow_coords, shadow_ndc.z);
-        }
-
-        // Calculate light source vector
-        let l = normalize(view_mat3 * -light.direction);
-
-        color += surface_shading(l, light.color, pixel, v, shadow_value * pixel.ambient_occlusion);
-    }
+    return vec4<f32>(oct_encode(pixel.normal), pixel.perceptual_roughness, pixel.metallic);
+}
 
-    for (var i = 0; i < i32(point_lights.count); i += 1) {
-        let light = point_lights.data[i];
+// Surface of the fragment for the deferred lighting pass, rendered by the G-buffer routines.
+@fragment
+fn fs_gbuffer(vs_out: VertexOutput) -> GBufferOutput {
+    let material = materials[vs_out.material];
 
-        // Delta to light
-        let delta = (uniforms.view * light.position).xyz - vs_out.view_position.xyz;
+    let pixel = get_pixel_data(material, vs_out);
 
-        // Distance
-        let d = length(delta);
+    var out: GBufferOutput;
 
-        // Attenuate from light and cusp at radius
-        // Derivative is 0 at both d = 0 and d = radius
-        // Source: https://lisyarus.github.io/blog/graphics/2022/07/30/point-light-attenuation.html
-        let s = saturate(d / light.radius);
-        let s2 = s * s;
-        let inv_s2 = 1.0 - s2;
-        let att = inv_s2 * inv_s2 / (1.0 + s2);
-        let intensity = light.color * att;
+    // Unlit surfaces only emit their albedo. Without albedo or reflectance, the lights add nothing to it.
+    if (extract_material_flag(material.flags, FLAGS_UNLIT)) {
+        out.albedo = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        out.surface = vec4<f32>(oct_encode(pixel.normal), 1.0, 0.0);
+        out.material = vec4<f32>(1.0, 0.0, 0.0, 0.0);
+        out.emissive = vec4<f32>(pixel.albedo.rgb, 1.0);
+        return out;
+    }
 
-        // Calculate light source vector
-        let l = delta / d;
+    var flags = 0u;
+    if (vs_out.receive_shadows != 0u) {
+        flags |= GBUFFER_RECEIVE_SHADOWS;
+    }
 
-        color += max(surface_shading(l, intensity, pixel, v, pixel.ambient_occlusion), vec3<f32>(0.0));
+    // The baked lighting doesn't depend on the lights of the scene, so it is added up front.
+    var emissive = pixel.emissive;
+    if (pixel.lightmapped) {
+        flags |= GBUFFER_LIGHTMAPPED;
+        emissive += pixel.baked_irradiance * pixel.diffuse_color * pixel.ambient_occlusion;
     }
 
-    let ambient = uniforms.ambient * pixel.albedo;
-    let shaded = vec4<f32>(color, pixel.albedo.a);
-    return max(ambient, shaded);
+    out.albedo = vec4<f32>(pixel.albedo.rgb, 1.0);
+    out.surface = vec4<f32>(oct_encode(pixel.normal), pixel.perceptual_roughness, pixel.metallic);
+    out.material = vec4<f32>(pixel.ambient_occlusion, pixel.reflectance, f32(flags) / 255.0, 0.0);
+    out.emissive = vec4<f32>(emissive, 1.0);
+    return out;
 }
@@ -0,0 +1,60 @@
+// Exponential variance shadow maps.
+//
+// The moments are stored in Rgba32Float, the exponents are kept low enough that the squared moments don't overflow it.
+const EVSM_POSITIVE_EXPONENT: f32 = 40.0;
+const EVSM_NEGATIVE_EXPONENT: f32 = 5.0;
+// Fraction of the probability cut off to reduce light bleeding where occluders overlap.
+const EVSM_LIGHT_BLEEDING_REDUCTION: f32 = 0.2;
+// Minimum variance relative to the warped depth, as the warped depths span very different ranges.
+const EVSM_VARIANCE_BIAS: f32 = 0.0001;
+
+// Warps the depth into both exponents. Depth grows towards the light, so it's flipped to grow away from it.
+fn evsm_warp_depth(depth: f32) -> vec2<f32> {
+    let d = (1.0 - depth) * 2.0 - 1.0;
+    return vec2<f32>(exp(EVSM_POSITIVE_EXPONENT * d), -exp(-EVSM_NEGATIVE_EXPONENT * d));
+}
+
+fn evsm_moments(depth: f32) -> vec4<f32> {
+    let warped = evsm_warp_depth(depth);
+    return vec4<f32>(warped.x, warped.x * warped.x, warped.y, warped.y * warped.y);
+}
+
+// Upper bound of the probability that the receiver is lit.
+fn evsm_chebyshev(moments: vec2<f32>, receiver: f32, exponent: f32) -> f32 {
+    let depth_scale = EVSM_VARIANCE_BIAS * exponent * receiver;
+    let variance = max(moments.y - moments.x * moments.x, depth_scale * depth_scale);
+    let delta = receiver - moments.x;
+    let p_max = variance / (variance + delta * delta);
+    let reduced = saturate((p_max - EVSM_LIGHT_BLEEDING_REDUCTION) / (1.0 - EVSM_LIGHT_BLEEDING_REDUCTION));
+    return select(reduced, 1.0, receiver <= moments.x);
+}
+
+fn shadow_sample_evsm(moments: vec4<f32>, depth: f32) -> f32 {
+    let warped = evsm_warp_depth(depth);
+    return min(
+        evsm_chebyshev(moments.xy, warped.x, EVSM_POSITIVE_EXPONENT),
+        evsm_chebyshev(moments.zw, warped.y, EVSM_NEGATIVE_EXPONENT),
+    );
+}
+
+// Rgba32Float can't be filtered by a sampler, so the moments are filtered bilinearly by hand.
+// The coordinates must be at least half a texel inside of the shadow map, so no other map is read.
+fn evsm_load_moments(moments: texture_2d<f32>, coords: vec2<f32>) -> vec4<f32> {
+    let dimensions = textureDimensions(moments);
+    let texel = coords * vec2<f32>(dimensions) - 0.5;
+    let weight = fract(texel);
+    let min_texel = vec2<u32>(texel);
+    let max_texel = min(min_texel + 1u, dimensions - 1u);
+
+    let top = mix(
+        textureLoad(moments, min_texel, 0),
+        textureLoad(moments, vec2<u32>(max_texel.x, min_texel.y), 0),
+        vec4<f32>(weight.x)
+    );
+    let bottom = mix(
+        textureLoad(moments, vec2<u32>(min_texel.x, max_texel.y), 0),
+        textureLoad(moments, max_texel, 0),
+        vec4<f32>(weight.x)
+    );
+    return mix(top, bottom, vec4<f32>(weight.y));
+}
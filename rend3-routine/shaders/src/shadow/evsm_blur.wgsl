@@ -0,0 +1,19 @@
+{{include "rend3-routine/shadow/evsm_blur_common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> blur: EvsmBlurUniform;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+
+// Blurs the moments vertically.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let center = vec2<i32>(position.xy);
+
+    var moments = vec4<f32>(0.0);
+    for (var i = -EVSM_BLUR_RADIUS; i <= EVSM_BLUR_RADIUS; i += 1) {
+        let coords = clamp(center + vec2<i32>(0, i), blur.map_min, blur.map_max);
+        moments += textureLoad(source, coords, 0) * evsm_blur_weight(i);
+    }
+    return moments;
+}
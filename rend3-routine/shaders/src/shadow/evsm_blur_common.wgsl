@@ -0,0 +1,19 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+struct EvsmBlurUniform {
+    /// First texel of the shadow map in the atlas.
+    map_min: vec2<i32>,
+    /// Last texel of the shadow map in the atlas.
+    map_max: vec2<i32>,
+}
+
+const EVSM_BLUR_RADIUS: i32 = 3;
+
+// Gaussian weights with a sigma of 1.5 texels.
+fn evsm_blur_weight(offset: i32) -> f32 {
+    var weights = array<f32, 4>(0.2707, 0.2167, 0.1113, 0.0366);
+    return weights[abs(offset)];
+}
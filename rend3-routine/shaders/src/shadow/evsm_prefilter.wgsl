@@ -0,0 +1,21 @@
+{{include "rend3-routine/shadow/evsm.wgsl"}}
+{{include "rend3-routine/shadow/evsm_blur_common.wgsl"}}
+
+@group(0) @binding(0)
+var<uniform> blur: EvsmBlurUniform;
+@group(0) @binding(1)
+var shadows: texture_depth_2d;
+
+// Converts the depth of a shadow map into moments, blurring them horizontally.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let center = vec2<i32>(position.xy);
+
+    var moments = vec4<f32>(0.0);
+    for (var i = -EVSM_BLUR_RADIUS; i <= EVSM_BLUR_RADIUS; i += 1) {
+        let coords = clamp(center + vec2<i32>(i, 0), blur.map_min, blur.map_max);
+        let depth = textureLoad(shadows, coords, 0);
+        moments += evsm_moments(depth) * evsm_blur_weight(i);
+    }
+    return moments;
+}
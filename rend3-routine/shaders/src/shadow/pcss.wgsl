@@ -0,0 +1,52 @@
+// Samples along each axis of the blocker search and of the filter are [-PCSS_KERNEL_RADIUS, PCSS_KERNEL_RADIUS].
+const PCSS_KERNEL_RADIUS: i32 = 2;
+// Largest penumbra, in texels. Keeps the filter from sampling too sparsely.
+const PCSS_MAX_PENUMBRA: f32 = 16.0;
+
+// Percentage closer soft shadows.
+//
+// Searches around the receiver for occluders, then estimates the width of the penumbra from their average
+// distance to the receiver and filters over that width.
+//
+// - bounds: [0, 1] min (xy) and max (zw) coordinates of the shadow map in the atlas. No samples are taken outside of it.
+// - penumbra_scale: Width of the penumbra in texels per unit of depth between the occluder and the receiver.
+fn shadow_sample_pcss(tex: texture_depth_2d, samp: sampler_comparison, coords: vec2<f32>, depth: f32, bounds: vec4<f32>, penumbra_scale: f32) -> f32 {
+    let dimensions = vec2<f32>(textureDimensions(tex));
+    let texel = 1.0 / dimensions;
+    let kernel_radius = f32(PCSS_KERNEL_RADIUS);
+
+    // Depth grows towards the light, so the furthest an occluder can be is at depth 1.
+    let search_radius = clamp((1.0 - depth) * penumbra_scale, 1.0, PCSS_MAX_PENUMBRA);
+
+    var blocker_depth = 0.0;
+    var blocker_count = 0.0;
+    for (var y = -PCSS_KERNEL_RADIUS; y <= PCSS_KERNEL_RADIUS; y += 1) {
+        for (var x = -PCSS_KERNEL_RADIUS; x <= PCSS_KERNEL_RADIUS; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) / kernel_radius * search_radius;
+            let sample_coords = clamp(coords + offset * texel, bounds.xy, bounds.zw);
+            let sample_depth = textureLoad(tex, vec2<i32>(sample_coords * dimensions), 0);
+            if (sample_depth > depth) {
+                blocker_depth += sample_depth;
+                blocker_count += 1.0;
+            }
+        }
+    }
+
+    var result = 1.0;
+    if (blocker_count > 0.0) {
+        let average_blocker_depth = blocker_depth / blocker_count;
+        let penumbra = clamp((average_blocker_depth - depth) * penumbra_scale, 1.0, PCSS_MAX_PENUMBRA);
+
+        var lit = 0.0;
+        for (var y = -PCSS_KERNEL_RADIUS; y <= PCSS_KERNEL_RADIUS; y += 1) {
+            for (var x = -PCSS_KERNEL_RADIUS; x <= PCSS_KERNEL_RADIUS; x += 1) {
+                let offset = vec2<f32>(f32(x), f32(y)) / kernel_radius * penumbra;
+                let sample_coords = clamp(coords + offset * texel, bounds.xy, bounds.zw);
+                lit += textureSampleCompareLevel(tex, samp, sample_coords, depth);
+            }
+        }
+        let kernel_width = f32(PCSS_KERNEL_RADIUS * 2 + 1);
+        result = lit / (kernel_width * kernel_width);
+    }
+    return result;
+}
@@ -17,9 +17,13 @@ struct SkinningInput {
     updated_normal_offset: u32,
     /// Byte offset into vertex buffer of tangent attribute of skinned mesh.
     updated_tangent_offset: u32,
+    /// Byte offset into vertex buffer of position attribute of skinned mesh in the previous frame.
+    updated_previous_position_offset: u32,
 
     /// Index into the matrix buffer that joint_indices is relative to.
     joint_matrix_base_offset: u32,
+    /// Index into the matrix buffer that joint_indices is relative to for the joint matrices of the previous frame.
+    previous_joint_matrix_base_offset: u32,
     /// Count of vertices in this mesh.
     vertex_count: u32,
 }
@@ -47,6 +51,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     // Compute the skinned position
     var pos_acc = vec3<f32>(0.0);
+    var prev_pos_acc = vec3<f32>(0.0);
     var norm_acc = vec3<f32>(0.0);
     var tang_acc = vec3<f32>(0.0);
 
@@ -71,6 +76,9 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
             let joint_matrix = joint_matrices[input.joint_matrix_base_offset + joint_index];
             let joint_matrix3 = mat3x3<f32>(joint_matrix[0].xyz, joint_matrix[1].xyz, joint_matrix[2].xyz);
             pos_acc += (joint_matrix * vec4<f32>(pos, 1.0)).xyz * weight;
+
+            let previous_joint_matrix = joint_matrices[input.previous_joint_matrix_base_offset + joint_index];
+            prev_pos_acc += (previous_joint_matrix * vec4<f32>(pos, 1.0)).xyz * weight;
             
             let inv_scale_sq = mat3_inv_scale_squared(joint_matrix3);
             norm_acc += (joint_matrix3 * (inv_scale_sq * normal)) * weight;
@@ -91,4 +99,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     if (input.updated_tangent_offset != 0xFFFFFFFFu) {
         store_attribute_vec3_f32(input.updated_tangent_offset, idx, tang_acc);
     }
+    if (input.updated_previous_position_offset != 0xFFFFFFFFu) {
+        store_attribute_vec3_f32(input.updated_previous_position_offset, idx, prev_pos_acc);
+    }
 }
\ No newline at end of file
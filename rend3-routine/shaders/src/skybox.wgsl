@@ -1,4 +1,5 @@
 {{include "rend3-routine/structures.wgsl"}}
+{{include "rend3-routine/fog.wgsl"}}
 
 struct VertexOutput {
     @builtin(position) pos: vec4<f32>,
@@ -16,6 +17,8 @@ fn vs_main(@builtin(vertex_index) id: u32) -> VertexOutput {
 var primary_sampler: sampler;
 @group(0) @binding(3)
 var<uniform> uniforms: UniformData;
+@group(0) @binding(4)
+var<storage> directional_lights: DirectionalLightData;
 @group(1) @binding(0)
 var skybox: texture_cube<f32>;
 
@@ -30,5 +33,17 @@ fn fs_main(output: VertexOutput) -> @location(0) vec4<f32> {
 
     let background = textureSample(skybox, primary_sampler, world_dir).rgb;
 
-    return vec4<f32>(background, 1.0);
+    if (!fog_enabled(uniforms.fog)) {
+        return vec4<f32>(background, 1.0);
+    }
+
+    // The sky is infinitely far away.
+    var inscattering = vec3<f32>(0.0);
+    for (var i = 0u; i < directional_lights.count; i += 1u) {
+        inscattering += fog_inscattering(uniforms.fog, world_dir, directional_lights.data[i]);
+    }
+    let camera = uniforms.inv_view[3].xyz;
+    let fogged = apply_fog(uniforms.fog, background, camera, world_dir, 1e20, inscattering);
+
+    return vec4<f32>(fogged, 1.0);
 }
@@ -0,0 +1,302 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var linear_sampler: sampler;
+@group(0) @binding(1)
+var edges_tex: texture_2d<f32>;
+@group(0) @binding(2)
+var area_tex: texture_2d<f32>;
+@group(0) @binding(3)
+var search_tex: texture_2d<f32>;
+
+// Maximum amount of steps of the searches along horizontal and vertical edges. Every step covers two pixels.
+const MAX_SEARCH_STEPS: i32 = 16;
+// Maximum amount of steps of the searches along diagonal edges.
+const MAX_SEARCH_STEPS_DIAG: i32 = 8;
+// How much sharp corners are kept, from 0 for fully rounded corners to 1 for unchanged corners.
+const CORNER_ROUNDING: f32 = 0.25;
+
+// Layout of the area texture, see generate_lookup_textures.py next to the routine.
+const AREA_TEX_MAX_DISTANCE: f32 = 16.0;
+const AREA_TEX_MAX_DISTANCE_DIAG: f32 = 20.0;
+const AREA_TEX_PIXEL_SIZE: vec2<f32> = vec2<f32>(1.0 / 160.0, 1.0 / 560.0);
+// Only the first subsample of the area texture is used, as there is no temporal or multisampled
+// variant of the routine.
+const AREA_TEX_SUBSAMPLE: f32 = 0.0;
+const AREA_TEX_SUBTEX_SIZE: f32 = 1.0 / 7.0;
+
+// Size of the search texture before it was cropped.
+const SEARCH_TEX_SIZE: vec2<f32> = vec2<f32>(66.0, 33.0);
+
+var<private> pixel_size: vec2<f32>;
+
+fn sample_edges(uv: vec2<f32>) -> vec2<f32> {
+    return textureSampleLevel(edges_tex, linear_sampler, uv, 0.0).rg;
+}
+
+// Bilinear fetches of the diagonal searches give the edge one pixel to the side a weight of 0.25, this
+// reconstructs both edges from it.
+fn decode_diag_bilinear_access_2(e: vec2<f32>) -> vec2<f32> {
+    return round(vec2<f32>(e.r * abs(5.0 * e.r - 5.0 * 0.75), e.g));
+}
+
+fn decode_diag_bilinear_access_4(e: vec4<f32>) -> vec4<f32> {
+    return round(vec4<f32>(e.r * abs(5.0 * e.r - 5.0 * 0.75), e.g, e.b * abs(5.0 * e.b - 5.0 * 0.75), e.a));
+}
+
+struct DiagSearch {
+    // Distance to the end of the line, in steps.
+    distance: f32,
+    // Average of both edges at the end, 1 if the line went on past the last step.
+    end_average: f32,
+    // Edges at the end of the line.
+    end: vec2<f32>,
+}
+
+// Searches along a diagonal line going through the left edges of the pixels.
+fn search_diag_1(uv: vec2<f32>, dir: vec2<f32>) -> DiagSearch {
+    var result = DiagSearch(-1.0, 1.0, vec2<f32>(0.0));
+    var coords = uv;
+    while (result.distance < f32(MAX_SEARCH_STEPS_DIAG - 1) && result.end_average > 0.9) {
+        coords += dir * pixel_size;
+        result.distance += 1.0;
+        result.end = sample_edges(coords);
+        result.end_average = dot(result.end, vec2<f32>(0.5));
+    }
+    return result;
+}
+
+// Searches along a diagonal line going through the top edges of the pixels. The fetch is offset by a
+// quarter pixel so a single bilinear sample covers both edges.
+fn search_diag_2(uv: vec2<f32>, dir: vec2<f32>) -> DiagSearch {
+    var result = DiagSearch(-1.0, 1.0, vec2<f32>(0.0));
+    var coords = uv;
+    coords.x += 0.25 * pixel_size.x;
+    while (result.distance < f32(MAX_SEARCH_STEPS_DIAG - 1) && result.end_average > 0.9) {
+        coords += dir * pixel_size;
+        result.distance += 1.0;
+        result.end = decode_diag_bilinear_access_2(sample_edges(coords));
+        result.end_average = dot(result.end, vec2<f32>(0.5));
+    }
+    return result;
+}
+
+fn area_diag(distance: vec2<f32>, e: vec2<f32>, subsample: f32) -> vec2<f32> {
+    var uv = AREA_TEX_MAX_DISTANCE_DIAG * e + distance;
+    uv = AREA_TEX_PIXEL_SIZE * uv + 0.5 * AREA_TEX_PIXEL_SIZE;
+    // Diagonal areas are in the right half of the texture.
+    uv.x += 0.5;
+    uv.y += AREA_TEX_SUBTEX_SIZE * subsample;
+    return textureSampleLevel(area_tex, linear_sampler, uv, 0.0).rg;
+}
+
+fn calculate_diag_weights(uv: vec2<f32>, e: vec2<f32>) -> vec2<f32> {
+    var weights = vec2<f32>(0.0);
+
+    // Diagonal lines going up to the right, built from left edges.
+    var d = vec4<f32>(0.0);
+    if (e.r > 0.0) {
+        let search = search_diag_1(uv, vec2<f32>(-1.0, 1.0));
+        d.x = search.distance + f32(search.end.y > 0.9);
+        d.z = search.end_average;
+    }
+    let search_up = search_diag_1(uv, vec2<f32>(1.0, -1.0));
+    d.y = search_up.distance;
+    d.w = search_up.end_average;
+
+    // Lines of three pixels or less are left to the orthogonal searches.
+    if (d.x + d.y > 2.0) {
+        let coords = vec4<f32>(-d.x + 0.25, d.x, d.y, -d.y - 0.25) * pixel_size.xyxy + uv.xyxy;
+        let fetched = vec4<f32>(
+            textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(-1, 0)).rg,
+            textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(1, 0)).rg,
+        );
+        let decoded = decode_diag_bilinear_access_4(fetched);
+        let c = decoded.yxwz;
+
+        // The crossing edges are unknown if the search ran out of steps.
+        let cc = select(2.0 * c.xz + c.yw, vec2<f32>(0.0), d.zw >= vec2<f32>(0.9));
+        weights += area_diag(d.xy, cc, AREA_TEX_SUBSAMPLE);
+    }
+
+    // Diagonal lines going down to the right, built from top edges.
+    let search_left = search_diag_2(uv, vec2<f32>(-1.0, -1.0));
+    d.x = search_left.distance;
+    d.z = search_left.end_average;
+    if (textureSampleLevel(edges_tex, linear_sampler, uv, 0.0, vec2<i32>(1, 0)).r > 0.0) {
+        let search = search_diag_2(uv, vec2<f32>(1.0, 1.0));
+        d.y = search.distance + f32(search.end.y > 0.9);
+        d.w = search.end_average;
+    } else {
+        d.y = 0.0;
+        d.w = 0.0;
+    }
+
+    if (d.x + d.y > 2.0) {
+        let coords = vec4<f32>(-d.x, -d.x, d.y, d.y) * pixel_size.xyxy + uv.xyxy;
+        let c = vec4<f32>(
+            textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(-1, 0)).g,
+            textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(0, -1)).r,
+            textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(1, 0)).gr,
+        );
+        let cc = select(2.0 * c.xz + c.yw, vec2<f32>(0.0), d.zw >= vec2<f32>(0.9));
+        weights += area_diag(d.xy, cc, AREA_TEX_SUBSAMPLE).yx;
+    }
+
+    return weights;
+}
+
+// Looks up how many of the last two pixels of an orthogonal search are still part of the line, from the
+// bilinear fetch of their edges.
+fn search_length(e: vec2<f32>, offset: f32) -> f32 {
+    // Every combination of edges gives a multiple of 1/32, which maps to a texel of the cropped texture.
+    let coords = vec2<f32>(32.0, -32.0) * e + vec2<f32>(SEARCH_TEX_SIZE.x * offset + 0.5, 32.5);
+    return textureLoad(search_tex, vec2<i32>(floor(coords)), 0).r;
+}
+
+fn search_x_left(start: vec2<f32>, end: f32) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    // Stop when there is no top edge anymore, or a left edge crosses the line.
+    while (uv.x > end && e.g > 0.8281 && e.r == 0.0) {
+        e = sample_edges(uv);
+        uv.x -= 2.0 * pixel_size.x;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e, 0.0) + 3.25;
+    return pixel_size.x * offset + uv.x;
+}
+
+fn search_x_right(start: vec2<f32>, end: f32) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(0.0, 1.0);
+    while (uv.x < end && e.g > 0.8281 && e.r == 0.0) {
+        e = sample_edges(uv);
+        uv.x += 2.0 * pixel_size.x;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e, 0.5) + 3.25;
+    return -pixel_size.x * offset + uv.x;
+}
+
+fn search_y_up(start: vec2<f32>, end: f32) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while (uv.y > end && e.r > 0.8281 && e.g == 0.0) {
+        e = sample_edges(uv);
+        uv.y -= 2.0 * pixel_size.y;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e.gr, 0.0) + 3.25;
+    return pixel_size.y * offset + uv.y;
+}
+
+fn search_y_down(start: vec2<f32>, end: f32) -> f32 {
+    var uv = start;
+    var e = vec2<f32>(1.0, 0.0);
+    while (uv.y < end && e.r > 0.8281 && e.g == 0.0) {
+        e = sample_edges(uv);
+        uv.y += 2.0 * pixel_size.y;
+    }
+    let offset = -(255.0 / 127.0) * search_length(e.gr, 0.5) + 3.25;
+    return -pixel_size.y * offset + uv.y;
+}
+
+// Looks up the coverage of the line from the distances to its ends and the crossing edges at them.
+fn area(distance: vec2<f32>, e1: f32, e2: f32, subsample: f32) -> vec2<f32> {
+    var uv = AREA_TEX_MAX_DISTANCE * round(4.0 * vec2<f32>(e1, e2)) + distance;
+    uv = AREA_TEX_PIXEL_SIZE * uv + 0.5 * AREA_TEX_PIXEL_SIZE;
+    uv.y += AREA_TEX_SUBTEX_SIZE * subsample;
+    return textureSampleLevel(area_tex, linear_sampler, uv, 0.0).rg;
+}
+
+// Reduces the blending of pixels at the corners of lines, so sharp corners aren't rounded off.
+fn corner_rounding(d: vec2<f32>) -> vec2<f32> {
+    let left_right = step(d.xy, d.yx);
+    // Pixels in the middle of a line are affected by both of its ends.
+    return (1.0 - CORNER_ROUNDING) * left_right / (left_right.x + left_right.y);
+}
+
+fn detect_horizontal_corner_pattern(weights: vec2<f32>, coords: vec4<f32>, d: vec2<f32>) -> vec2<f32> {
+    let rounding = corner_rounding(d);
+    var factor = vec2<f32>(1.0);
+    factor.x -= rounding.x * textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(0, 1)).r;
+    factor.x -= rounding.y * textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(1, 1)).r;
+    factor.y -= rounding.x * textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(0, -2)).r;
+    factor.y -= rounding.y * textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(1, -2)).r;
+    return weights * clamp(factor, vec2<f32>(0.0), vec2<f32>(1.0));
+}
+
+fn detect_vertical_corner_pattern(weights: vec2<f32>, coords: vec4<f32>, d: vec2<f32>) -> vec2<f32> {
+    let rounding = corner_rounding(d);
+    var factor = vec2<f32>(1.0);
+    factor.x -= rounding.x * textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(1, 0)).g;
+    factor.x -= rounding.y * textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(1, 1)).g;
+    factor.y -= rounding.x * textureSampleLevel(edges_tex, linear_sampler, coords.xy, 0.0, vec2<i32>(-2, 0)).g;
+    factor.y -= rounding.y * textureSampleLevel(edges_tex, linear_sampler, coords.zw, 0.0, vec2<i32>(-2, 1)).g;
+    return weights * clamp(factor, vec2<f32>(0.0), vec2<f32>(1.0));
+}
+
+// Finds the line each edge of the pixel belongs to and how much the pixel is covered by it. The top
+// edge is stored in red and green, the left edge in blue and alpha.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    pixel_size = 1.0 / vec2<f32>(textureDimensions(edges_tex));
+    let uv = position.xy * pixel_size;
+
+    // Starting points of the searches, offset so each bilinear fetch covers the edges of two pixels.
+    let offset_0 = vec4<f32>(-0.25, -0.125, 1.25, -0.125) * pixel_size.xyxy + uv.xyxy;
+    let offset_1 = vec4<f32>(-0.125, -0.25, -0.125, 1.25) * pixel_size.xyxy + uv.xyxy;
+    let search_ends =
+        vec4<f32>(-2.0, 2.0, -2.0, 2.0) * f32(MAX_SEARCH_STEPS) * pixel_size.xxyy + vec4<f32>(offset_0.xz, offset_1.yw);
+
+    var weights = vec4<f32>(0.0);
+    var e = sample_edges(uv);
+
+    // Edge at the top.
+    if (e.g > 0.0) {
+        let diag_weights = calculate_diag_weights(uv, e);
+        weights = vec4<f32>(diag_weights, weights.zw);
+
+        // Diagonal lines take priority over orthogonal ones.
+        if (diag_weights.x == -diag_weights.y) {
+            var coords: vec3<f32>;
+            coords.x = search_x_left(offset_0.xy, search_ends.x);
+            // Sampling a quarter pixel up tells apart crossing edges above and below the line.
+            coords.y = offset_1.y;
+            let e1 = sample_edges(coords.xy).r;
+            coords.z = search_x_right(offset_0.zw, search_ends.y);
+            let e2 = textureSampleLevel(edges_tex, linear_sampler, coords.zy, 0.0, vec2<i32>(1, 0)).r;
+
+            let d = abs(round(vec2<f32>(coords.x, coords.z) / pixel_size.x - position.xx));
+            // The area texture stores distances compressed quadratically.
+            var area_weights = area(sqrt(d), e1, e2, AREA_TEX_SUBSAMPLE);
+
+            coords.y = uv.y;
+            area_weights = detect_horizontal_corner_pattern(area_weights, coords.xyzy, d);
+            weights = vec4<f32>(area_weights, weights.zw);
+        } else {
+            // Skip the left edge, it is part of the diagonal line.
+            e.r = 0.0;
+        }
+    }
+
+    // Edge at the left.
+    if (e.r > 0.0) {
+        var coords: vec3<f32>;
+        coords.y = search_y_up(offset_1.xy, search_ends.z);
+        coords.x = offset_0.x;
+        let e1 = sample_edges(coords.xy).g;
+        coords.z = search_y_down(offset_1.zw, search_ends.w);
+        let e2 = textureSampleLevel(edges_tex, linear_sampler, coords.xz, 0.0, vec2<i32>(0, 1)).g;
+
+        let d = abs(round(vec2<f32>(coords.y, coords.z) / pixel_size.y - position.yy));
+        var area_weights = area(sqrt(d), e1, e2, AREA_TEX_SUBSAMPLE);
+
+        coords.x = uv.x;
+        area_weights = detect_vertical_corner_pattern(area_weights, coords.xyxz, d);
+        weights = vec4<f32>(weights.xy, area_weights);
+    }
+
+    return weights;
+}
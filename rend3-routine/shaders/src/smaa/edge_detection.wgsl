@@ -0,0 +1,55 @@
+{{include "rend3-routine/math/color.wgsl"}}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+// Difference of luma which is considered an edge.
+const THRESHOLD: f32 = 0.1;
+// Edges are dropped if a neighboring edge has this much more contrast, which keeps the blending from
+// being pulled towards both sides of thin features.
+const LOCAL_CONTRAST_ADAPTATION_FACTOR: f32 = 2.0;
+
+fn luma_at(coords: vec2<i32>) -> f32 {
+    let max_coords = vec2<i32>(textureDimensions(source)) - 1;
+    let color = textureLoad(source, clamp(coords, vec2<i32>(0), max_coords), 0).rgb;
+{{#if SRGB}}
+    // The target is decoded to linear when loaded, but edges are detected on perceived brightness.
+    let perceptual = srgb_scene_to_display(color);
+{{else}}
+    let perceptual = color;
+{{/if}}
+    return dot(perceptual, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Marks the left edge of the pixel in red and the top edge in green.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec2<f32> {
+    let coords = vec2<i32>(position.xy);
+
+    let luma = luma_at(coords);
+    let luma_left = luma_at(coords + vec2<i32>(-1, 0));
+    let luma_top = luma_at(coords + vec2<i32>(0, -1));
+
+    let delta_left_top = abs(luma - vec2<f32>(luma_left, luma_top));
+    var edges = step(vec2<f32>(THRESHOLD), delta_left_top);
+    if (dot(edges, vec2<f32>(1.0)) == 0.0) {
+        discard;
+    }
+
+    let luma_right = luma_at(coords + vec2<i32>(1, 0));
+    let luma_bottom = luma_at(coords + vec2<i32>(0, 1));
+    var max_delta = max(delta_left_top, abs(luma - vec2<f32>(luma_right, luma_bottom)));
+
+    let luma_left_left = luma_at(coords + vec2<i32>(-2, 0));
+    let luma_top_top = luma_at(coords + vec2<i32>(0, -2));
+    max_delta = max(max_delta, abs(vec2<f32>(luma_left, luma_top) - vec2<f32>(luma_left_left, luma_top_top)));
+
+    let final_delta = max(max_delta.x, max_delta.y);
+    edges *= step(vec2<f32>(final_delta), LOCAL_CONTRAST_ADAPTATION_FACTOR * delta_left_top);
+    return edges;
+}
@@ -0,0 +1,39 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var linear_sampler: sampler;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var weights_tex: texture_2d<f32>;
+
+// Blends each pixel with its neighbors across the edges, by the weights of the lines they're part of.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel_size = 1.0 / vec2<f32>(textureDimensions(source));
+    let uv = position.xy * pixel_size;
+
+    // The weights of the right and bottom edges are stored by the neighbors on the other side of them.
+    let weights = textureSampleLevel(weights_tex, linear_sampler, uv, 0.0);
+    let right = textureSampleLevel(weights_tex, linear_sampler, uv, 0.0, vec2<i32>(1, 0)).a;
+    let bottom = textureSampleLevel(weights_tex, linear_sampler, uv, 0.0, vec2<i32>(0, 1)).g;
+    let a = vec4<f32>(right, bottom, weights.b, weights.r);
+
+    if (dot(a, vec4<f32>(1.0)) < 1e-5) {
+        return textureSampleLevel(source, linear_sampler, uv, 0.0);
+    }
+
+    // Only blend along the strongest direction.
+    let horizontal = max(a.x, a.z) > max(a.y, a.w);
+    let blending_offset = select(vec4<f32>(0.0, a.y, 0.0, a.w), vec4<f32>(a.x, 0.0, a.z, 0.0), horizontal);
+    var blending_weight = select(a.yw, a.xz, horizontal);
+    blending_weight /= dot(blending_weight, vec2<f32>(1.0));
+
+    let blending_coords = blending_offset * vec4<f32>(pixel_size, -pixel_size) + uv.xyxy;
+    var color = blending_weight.x * textureSampleLevel(source, linear_sampler, blending_coords.xy, 0.0);
+    color += blending_weight.y * textureSampleLevel(source, linear_sampler, blending_coords.zw, 0.0);
+    return color;
+}
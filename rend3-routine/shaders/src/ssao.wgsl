@@ -0,0 +1,137 @@
+{{include "rend3-routine/math/octahedral.wgsl"}}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+struct SsaoUniform {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    resolution: vec2<u32>,
+    /// Radius of the sampled hemisphere in world units.
+    radius: f32,
+    /// Exponent applied to the visibility.
+    intensity: f32,
+}
+
+const SAMPLE_COUNT: u32 = 16u;
+const DENOISE_RADIUS: i32 = 2;
+const PI: f32 = 3.1415926535897932384626433832795;
+
+@group(0) @binding(0)
+var<uniform> uniforms: SsaoUniform;
+@group(0) @binding(1)
+var depth_tex: texture_depth_2d;
+// Surface of the prepass in the ssao pass, the raw ambient occlusion in the denoise pass.
+@group(0) @binding(2)
+var source: texture_2d<f32>;
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, (1.0 - uv.y) * 2.0 - 1.0, depth, 1.0);
+    let view = uniforms.inv_proj * ndc;
+    return view.xyz / view.w;
+}
+
+fn texel_uv(coords: vec2<i32>) -> vec2<f32> {
+    return (vec2<f32>(coords) + 0.5) / vec2<f32>(uniforms.resolution);
+}
+
+// From "Next Generation Post Processing in Call of Duty: Advanced Warfare".
+fn interleaved_gradient_noise(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Normal oriented hemisphere ambient occlusion.
+//
+// Points in a hemisphere around the normal are projected onto the depth buffer,
+// every point which ends up behind the depth buffer is occluded. The hemisphere
+// is rotated per pixel, the denoise pass then hides the resulting noise.
+@fragment
+fn fs_ssao(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+    let depth = textureLoad(depth_tex, coords, 0);
+
+    // Nothing was rendered here.
+    if (depth <= 0.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let origin = view_position(texel_uv(coords), depth);
+    let normal = oct_decode(textureLoad(source, coords, 0).xy);
+
+    // Random basis around the normal.
+    let angle = interleaved_gradient_noise(position.xy) * 2.0 * PI;
+    var random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    if (abs(dot(random, normal)) > 0.99) {
+        random = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(random - normal * dot(random, normal));
+    let bitangent = cross(normal, tangent);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        // Golden angle spiral over the hemisphere, denser close to the origin.
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let phi = f32(i) * 2.39996323;
+        let cos_theta = 1.0 - t;
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let scale = mix(0.1, 1.0, t * t) * uniforms.radius;
+        let direction = (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta + normal * cos_theta;
+        let sample_position = origin + direction * scale;
+
+        let clip = uniforms.proj * vec4<f32>(sample_position, 1.0);
+        if (clip.w <= 0.0) {
+            continue;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            continue;
+        }
+
+        let sample_coords = min(vec2<i32>(uv * vec2<f32>(uniforms.resolution)), vec2<i32>(uniforms.resolution) - 1);
+        let scene_depth = textureLoad(depth_tex, sample_coords, 0);
+        let scene_position = view_position(texel_uv(sample_coords), scene_depth);
+
+        // Reverse-z, bigger is closer. Occluders far outside the radius fade out.
+        let range = smoothstep(0.0, 1.0, uniforms.radius / max(distance(scene_position, origin), 0.0001));
+        occlusion += select(0.0, range, scene_depth > ndc.z);
+    }
+
+    let visibility = 1.0 - occlusion / f32(SAMPLE_COUNT);
+    return vec4<f32>(pow(visibility, uniforms.intensity));
+}
+
+// Depth aware blur which removes the noise of the ssao pass without bleeding over edges.
+@fragment
+fn fs_denoise(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let center = vec2<i32>(position.xy);
+    let max_coords = vec2<i32>(uniforms.resolution) - 1;
+
+    let center_ndc_depth = textureLoad(depth_tex, center, 0);
+    if (center_ndc_depth <= 0.0) {
+        return vec4<f32>(1.0);
+    }
+    let center_depth = abs(view_position(texel_uv(center), center_ndc_depth).z);
+
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var y = -DENOISE_RADIUS; y <= DENOISE_RADIUS; y += 1) {
+        for (var x = -DENOISE_RADIUS; x <= DENOISE_RADIUS; x += 1) {
+            let coords = clamp(center + vec2<i32>(x, y), vec2<i32>(0), max_coords);
+            let ndc_depth = textureLoad(depth_tex, coords, 0);
+            if (ndc_depth <= 0.0) {
+                continue;
+            }
+            let depth = abs(view_position(texel_uv(coords), ndc_depth).z);
+
+            // Samples more than a few percent away in depth are likely on another surface.
+            let weight = exp(-abs(depth - center_depth) / max(center_depth * 0.05, 0.0001));
+            sum += textureLoad(source, coords, 0).r * weight;
+            weight_sum += weight;
+        }
+    }
+
+    return vec4<f32>(sum / weight_sum);
+}
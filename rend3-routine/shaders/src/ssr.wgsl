@@ -0,0 +1,185 @@
+{{include "rend3-routine/math/octahedral.wgsl"}}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+struct SsrUniform {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    resolution: vec2<u32>,
+    /// Highest mip of the hierarchical-z buffer.
+    max_level: u32,
+    max_steps: u32,
+    /// Length of the rays in world units.
+    max_distance: f32,
+    /// Thickness of the depth buffer in world units.
+    thickness: f32,
+    /// Perceptual roughness at which the reflections are faded out.
+    max_roughness: f32,
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: SsrUniform;
+// Every mip holds the closest depth of the region it covers.
+@group(0) @binding(1)
+var hi_z: texture_depth_2d;
+@group(0) @binding(2)
+var surface_tex: texture_2d<f32>;
+@group(0) @binding(3)
+var color_tex: texture_2d<f32>;
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, (1.0 - uv.y) * 2.0 - 1.0, depth, 1.0);
+    let view = uniforms.inv_proj * ndc;
+    return view.xyz / view.w;
+}
+
+// Pixel coordinates and depth of a view space position. The depth is linear along a line in this space.
+fn screen_position(view: vec3<f32>) -> vec3<f32> {
+    let clip = uniforms.proj * vec4<f32>(view, 1.0);
+    let ndc = clip.xyz / clip.w;
+    return vec3<f32>((ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * vec2<f32>(uniforms.resolution), ndc.z);
+}
+
+// Ray parameter at which the ray leaves the cell of the given level it is in, nudged into the next cell.
+fn cell_exit(origin: vec3<f32>, direction: vec3<f32>, position: vec2<f32>, level: u32) -> f32 {
+    let cell_size = f32(1u << level);
+    let positive = direction.xy >= vec2<f32>(0.0);
+    let cell = floor(position / cell_size) + select(vec2<f32>(0.0), vec2<f32>(1.0), positive);
+    let boundary = cell * cell_size + select(vec2<f32>(-0.01), vec2<f32>(0.01), positive);
+    let t = select((boundary - origin.xy) / direction.xy, vec2<f32>(1e30), abs(direction.xy) < vec2<f32>(1e-6));
+    return min(t.x, t.y);
+}
+
+// Depth at which the ray crosses the closest depth of a cell, as a ray parameter between start and end.
+fn crossing(start_t: f32, end_t: f32, start_depth: f32, end_depth: f32, depth: f32) -> f32 {
+    return mix(start_t, end_t, (start_depth - depth) / (start_depth - end_depth));
+}
+
+// Hierarchical-z screen space ray tracing.
+//
+// The reflected ray is marched through the cells of the hierarchical-z buffer. If it passes in front of
+// everything in a cell, it skips the cell and moves to a coarser mip, otherwise it refines to a finer mip
+// until it is behind the depth buffer in a single pixel. Rays which end up further behind the depth buffer
+// than its assumed thickness pass behind the surface and keep going.
+@fragment
+fn fs_trace(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+    let depth = textureLoad(hi_z, coords, 0);
+
+    // Nothing was rendered here.
+    if (depth <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+
+    let surface = textureLoad(surface_tex, coords, 0);
+    let perceptual_roughness = surface.z;
+    let metallic = surface.w;
+    if (perceptual_roughness >= uniforms.max_roughness) {
+        return vec4<f32>(0.0);
+    }
+
+    let resolution = vec2<f32>(uniforms.resolution);
+    let view_pos = view_position(position.xy / resolution, depth);
+    let view_dir = normalize(view_pos);
+    let normal = oct_decode(surface.xy);
+    let reflected = reflect(view_dir, normal);
+
+    // Shorten rays which would end behind the camera.
+    let start_w = (uniforms.proj * vec4<f32>(view_pos, 1.0)).w;
+    var ray_length = uniforms.max_distance;
+    let end_w = (uniforms.proj * vec4<f32>(view_pos + reflected * ray_length, 1.0)).w;
+    let min_w = start_w * 0.01;
+    if (end_w < min_w) {
+        ray_length *= (start_w - min_w) / (start_w - end_w);
+    }
+
+    let origin = screen_position(view_pos);
+    let direction = screen_position(view_pos + reflected * ray_length) - origin;
+
+    // Start in the next pixel, so the ray doesn't hit the surface it starts on.
+    var t = cell_exit(origin, direction, origin.xy, 0u);
+    var level = 0u;
+    var hit = false;
+    for (var i = 0u; i < uniforms.max_steps; i += 1u) {
+        if (t >= 1.0) {
+            break;
+        }
+        let ray = origin + direction * t;
+        if (any(ray.xy < vec2<f32>(0.0)) || any(ray.xy >= resolution)) {
+            break;
+        }
+
+        let exit_t = min(cell_exit(origin, direction, ray.xy, level), 1.0);
+        let exit_depth = origin.z + direction.z * exit_t;
+        let max_cell = vec2<i32>(textureDimensions(hi_z, level)) - 1;
+        let cell = min(vec2<i32>(ray.xy) >> vec2<u32>(level), max_cell);
+        let cell_depth = textureLoad(hi_z, cell, i32(level));
+
+        // Reverse-z, bigger is closer. The ray is in front of everything in the cell, skip it.
+        if (min(ray.z, exit_depth) > cell_depth) {
+            t = exit_t;
+            level = min(level + 1u, uniforms.max_level);
+            continue;
+        }
+
+        // The ray may hit something in the cell, move up to the closest depth of the cell and refine.
+        var crossing_t = t;
+        if (ray.z > cell_depth) {
+            crossing_t = crossing(t, exit_t, ray.z, exit_depth, cell_depth);
+        }
+        if (level > 0u) {
+            t = crossing_t;
+            level -= 1u;
+            continue;
+        }
+
+        let hit_ray = origin + direction * crossing_t;
+        let hit_uv = hit_ray.xy / resolution;
+        let ray_depth = abs(view_position(hit_uv, hit_ray.z).z);
+        let scene_depth = abs(view_position(hit_uv, cell_depth).z);
+        if (ray_depth - scene_depth <= uniforms.thickness) {
+            t = crossing_t;
+            hit = true;
+            break;
+        }
+
+        // The ray passes behind the surface.
+        t = exit_t;
+    }
+
+    if (!hit) {
+        return vec4<f32>(0.0);
+    }
+
+    let hit_position = (origin + direction * t).xy;
+    let hit_coords = min(vec2<i32>(hit_position), vec2<i32>(uniforms.resolution) - 1);
+
+    // The ray hit the back of a surface, what it would see isn't on screen.
+    let hit_normal = oct_decode(textureLoad(surface_tex, hit_coords, 0).xy);
+    if (dot(hit_normal, reflected) > 0.0) {
+        return vec4<f32>(0.0);
+    }
+
+    let color = textureLoad(color_tex, hit_coords, 0).rgb;
+
+    // Fade out where a hit becomes unreliable, so the reflections blend into the regular lighting instead of
+    // ending abruptly: close to the edges of the screen, at the end of the ray, on rough surfaces and for rays
+    // pointing towards the camera.
+    let edge = abs(hit_position / resolution * 2.0 - 1.0);
+    let edge_fade = saturate((1.0 - max(edge.x, edge.y)) * 10.0);
+    let distance_fade = 1.0 - smoothstep(0.8, 1.0, t);
+    let roughness_fade = 1.0 - smoothstep(uniforms.max_roughness * 0.5, uniforms.max_roughness, perceptual_roughness);
+    let facing_fade = 1.0 - saturate(dot(reflected, -view_dir));
+
+    // Schlick fresnel with a roughness term, metals reflect at every angle.
+    let nov = saturate(dot(normal, -view_dir));
+    let f0 = mix(0.04, 1.0, metallic);
+    let fresnel = f0 + (max(1.0 - perceptual_roughness, f0) - f0) * pow(1.0 - nov, 5.0);
+
+    let weight = fresnel * edge_fade * distance_fade * roughness_fade * facing_fade * uniforms.intensity;
+    return vec4<f32>(color * weight, weight);
+}
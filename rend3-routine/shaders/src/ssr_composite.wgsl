@@ -0,0 +1,13 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+@group(0) @binding(0)
+var reflections: texture_2d<f32>;
+
+// Outputs the weighted reflections, which are added onto the HDR color.
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(reflections, vec2<i32>(position.xy), 0);
+}
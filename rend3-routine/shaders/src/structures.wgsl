@@ -25,6 +25,18 @@ struct IndirectCall {
     base_instance: u32,
 }
 
+/// Exponential height fog, see the fog module of rend3-routine.
+struct Fog {
+    color: vec3<f32>,
+    density: f32,
+    height_falloff: f32,
+    height: f32,
+    start_distance: f32,
+    max_opacity: f32,
+    inscattering_exponent: f32,
+    inscattering_intensity: f32,
+}
+
 struct UniformData {
     view: mat4x4<f32>,
     view_proj: mat4x4<f32>,
@@ -32,9 +44,14 @@ struct UniformData {
     inv_view: mat4x4<f32>,
     inv_view_proj: mat4x4<f32>,
     inv_origin_view_proj: mat4x4<f32>,
+    // View projection without the jitter of temporal anti-aliasing.
+    unjittered_view_proj: mat4x4<f32>,
+    // Unjittered view projection of the previous frame.
+    previous_view_proj: mat4x4<f32>,
     frustum: Frustum,
     ambient: vec4<f32>,
     resolution: vec2<u32>,
+    fog: Fog,
 }
 
 struct PerCameraUniform {
@@ -46,20 +63,37 @@ struct PerCameraUniform {
     object_count: u32,
 }
 
-struct DirectionalLight {
-    /// View/Projection of directional light. Shadow rendering uses viewports
+struct ShadowMap {
+    /// View/Projection of the shadow map. Shadow rendering uses viewports
     /// so this always outputs [-1, 1] no matter where in the atlast the shadow is.
     view_proj: mat4x4<f32>,
+    /// [0, 1] offset of the shadow map in the atlas.
+    offset: vec2<f32>,
+    /// [0, 1] size of the shadow map in the atlas.
+    size: vec2<f32>,
+}
+
+struct DirectionalLight {
     /// Color/intensity of the light
     color: vec3<f32>,
     /// Direction of the light
     direction: vec3<f32>,
     /// 1 / resolution of whole shadow map
     inv_resolution: vec2<f32>,
-    /// [0, 1] offset of the shadow map in the atlas.
-    offset: vec2<f32>,
-    /// [0, 1] size of the shadow map in the atlas.
-    size: vec2<f32>,
+    /// Amount of valid cascades.
+    cascade_count: u32,
+    /// Distance receivers are moved towards the light, in world units.
+    shadow_bias: f32,
+    /// Distance receivers are moved along their normal, in shadow map texels.
+    shadow_normal_offset: f32,
+    /// Filter used when sampling the shadow, see sample_directional_shadow.
+    shadow_filter: u32,
+    /// Tangent of the angular diameter of the light, used by PCSS.
+    light_size: f32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    baked: u32,
+    /// Cascades, ordered from the smallest to the largest. Must match MAX_SHADOW_CASCADES.
+    cascades: array<ShadowMap, 4>,
 }
 
 struct DirectionalLightData {
@@ -74,6 +108,14 @@ struct PointLight {
     color: vec3<f32>,
     /// The radius of the light.
     radius: f32,
+    /// 1 / resolution of whole shadow map
+    inv_resolution: vec2<f32>,
+    /// 1 if the light has a shadow, 0 otherwise.
+    shadowed: u32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    baked: u32,
+    /// Faces of the shadow cube, in the order +X, -X, +Y, -Y, +Z, -Z.
+    shadow_maps: array<ShadowMap, 6>,
 }
 
 struct PointLightData {
@@ -81,6 +123,67 @@ struct PointLightData {
     data: array<PointLight>,
 }
 
+struct SpotLight {
+    /// The position of the light in world space.
+    position: vec4<f32>,
+    /// The direction of the light in world space.
+    direction: vec3<f32>,
+    // Color/intensity of the light.
+    color: vec3<f32>,
+    /// The radius of the light.
+    radius: f32,
+    /// Cosine of the inner cone angle.
+    cos_inner_angle: f32,
+    /// Cosine of the outer cone angle.
+    cos_outer_angle: f32,
+    /// 1 / resolution of whole shadow map
+    inv_resolution: vec2<f32>,
+    /// 1 if the light has a shadow, 0 otherwise.
+    shadowed: u32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    baked: u32,
+    shadow_map: ShadowMap,
+}
+
+struct SpotLightData {
+    count: u32,
+    data: array<SpotLight>,
+}
+
+struct ReflectionProbe {
+    /// The position the environment was captured from, in world space.
+    position: vec3<f32>,
+    /// Minimum corner of the box the probe applies to, in world space.
+    box_min: vec3<f32>,
+    /// Maximum corner of the box the probe applies to, in world space.
+    box_max: vec3<f32>,
+    /// Distance over which the probe fades out at the sides of the box.
+    blend_distance: f32,
+}
+
+struct ReflectionProbeData {
+    count: u32,
+    data: array<ReflectionProbe>,
+}
+
+// Must match LightClusterRoutine::MAX_LIGHTS_PER_CLUSTER.
+const MAX_LIGHTS_PER_CLUSTER: u32 = 255u;
+
+struct ClusterUniform {
+    view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    /// Amount of clusters in each dimension.
+    grid: vec3<u32>,
+    /// Sign of the view space z axis in the direction the camera looks.
+    depth_sign: f32,
+    /// Depth the first slice starts at.
+    slice_start: f32,
+    /// Depth the exponential slices start at.
+    near: f32,
+    /// Slices per doubling of the depth.
+    slice_scale: f32,
+}
+
 struct PixelData {
     albedo: vec4<f32>,
     diffuse_color: vec3<f32>,
@@ -97,4 +200,7 @@ struct PixelData {
     anisotropy: f32,
     ambient_occlusion: f32,
     material_flags: u32,
+    /// Irradiance from the lightmap, only valid if lightmapped is set.
+    baked_irradiance: vec3<f32>,
+    lightmapped: bool,
 }
@@ -2,9 +2,11 @@
 
 struct Object {
     transform: mat4x4<f32>,
+    previous_transform: mat4x4<f32>,
     bounding_sphere: Sphere,
     first_index: u32,
     index_count: u32,
     material_index: u32,
+    receive_shadows: u32,
     vertex_attribute_start_offsets: array<u32, {{vertex_array_counts}}>,
 }
@@ -0,0 +1,127 @@
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(id / 2u) * 4.0 - 1.0, f32(id % 2u) * 4.0 - 1.0, 0.0, 1.0);
+}
+
+struct TaaUniform {
+    /// Moves a point from the unjittered clip space of this frame to the one of the previous frame.
+    reprojection: mat4x4<f32>,
+    resolution: vec2<u32>,
+    current_frame_weight: f32,
+    /// 1 if the history doesn't hold a previous frame.
+    reset: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: TaaUniform;
+@group(0) @binding(1)
+var linear_sampler: sampler;
+@group(0) @binding(2)
+var current_tex: texture_2d<f32>;
+@group(0) @binding(3)
+var history_tex: texture_2d<f32>;
+@group(0) @binding(4)
+var velocity_tex: texture_2d<f32>;
+@group(0) @binding(5)
+var depth_tex: texture_depth_2d;
+
+struct TaaOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) history: vec4<f32>,
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Blending in a tonemapped space keeps single bright pixels from dominating the result, from Karis'
+// "High Quality Temporal Supersampling".
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + luminance(color));
+}
+
+fn inverse_tonemap(color: vec3<f32>) -> vec3<f32> {
+    return color / max(1.0 - luminance(color), 0.0001);
+}
+
+// Moves the history towards the center of the box until it is inside of it, from Salvi's
+// "An Excursion in Temporal Supersampling".
+fn clip_to_box(history: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> vec3<f32> {
+    let center = (box_max + box_min) * 0.5;
+    let extents = max((box_max - box_min) * 0.5, vec3<f32>(0.0001));
+    let offset = history - center;
+    let units = abs(offset / extents);
+    let max_unit = max(units.x, max(units.y, units.z));
+    if (max_unit > 1.0) {
+        return center + offset / max_unit;
+    }
+    return history;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> TaaOutput {
+    let coords = vec2<i32>(position.xy);
+    let max_coords = vec2<i32>(uniforms.resolution) - 1;
+    let uv = position.xy / vec2<f32>(uniforms.resolution);
+
+    let current = textureLoad(current_tex, coords, 0);
+
+    // Statistics of the neighborhood, used to reject history that doesn't match the current frame anymore.
+    // The velocity is taken from the closest pixel, so edges move with the object in front.
+    var moment_1 = vec3<f32>(0.0);
+    var moment_2 = vec3<f32>(0.0);
+    var neighborhood_min = vec3<f32>(1.0e10);
+    var neighborhood_max = vec3<f32>(-1.0e10);
+    var closest_depth = 0.0;
+    var closest_coords = coords;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let sample_coords = clamp(coords + vec2<i32>(x, y), vec2<i32>(0), max_coords);
+            let color = tonemap(textureLoad(current_tex, sample_coords, 0).rgb);
+            moment_1 += color;
+            moment_2 += color * color;
+            neighborhood_min = min(neighborhood_min, color);
+            neighborhood_max = max(neighborhood_max, color);
+
+            // Depth is reversed, so the closest pixel has the largest depth.
+            let depth = textureLoad(depth_tex, sample_coords, 0);
+            if (depth > closest_depth) {
+                closest_depth = depth;
+                closest_coords = sample_coords;
+            }
+        }
+    }
+
+    var history_uv: vec2<f32>;
+    if (closest_depth == 0.0) {
+        // Nothing was rendered around the pixel, so it is at infinity and only moves with the camera.
+        let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+        let previous = uniforms.reprojection * vec4<f32>(ndc, 0.0, 1.0);
+        let previous_ndc = previous.xy / previous.w;
+        history_uv = vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+    } else {
+        history_uv = uv + textureLoad(velocity_tex, closest_coords, 0).xy;
+    }
+
+    var out: TaaOutput;
+    if (uniforms.reset != 0u || any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0))) {
+        out.color = current;
+        out.history = current;
+        return out;
+    }
+
+    // Variance clipping, bounded by the neighborhood so the box never grows past the actual colors.
+    let mean = moment_1 / 9.0;
+    let deviation = sqrt(max(moment_2 / 9.0 - mean * mean, vec3<f32>(0.0)));
+    let box_min = max(mean - deviation, neighborhood_min);
+    let box_max = min(mean + deviation, neighborhood_max);
+
+    let history = textureSampleLevel(history_tex, linear_sampler, history_uv, 0.0).rgb;
+    let clipped_history = clip_to_box(tonemap(history), box_min, box_max);
+
+    let resolved = inverse_tonemap(mix(clipped_history, tonemap(current.rgb), uniforms.current_frame_weight));
+
+    out.color = vec4<f32>(resolved, current.a);
+    out.history = out.color;
+    return out;
+}
@@ -0,0 +1,544 @@
+//! Procedural sky lit by a directional light.
+//!
+//! Implements Hillaire's "A Scalable and Production Ready Sky and Atmosphere
+//! Rendering Technique" for an earth-like planet seen from just above the
+//! ground. The atmosphere is baked into small lookup tables:
+//!
+//! - A transmittance table, holding how much light makes it from any point in
+//!   the atmosphere to its top, in any direction.
+//! - A multiple scattering table, approximating the light which bounced more
+//!   than once, from any height and for any elevation of the sun.
+//! - A sky-view table, holding the sky as seen by the viewer for the current
+//!   elevation of the sun.
+//!
+//! The transmittance and multiple scattering tables only change with the
+//! [`AtmosphereSettings`] and the sky-view table only when the sun moves, so
+//! time of day changes are cheap. The sky is drawn as a background like the
+//! [`SkyboxRoutine`](crate::skybox::SkyboxRoutine), together with the sun disk.
+//!
+//! The sun is a [`DirectionalLight`](rend3::types::DirectionalLight): the sky
+//! is lit from the opposite of its direction, with its color multiplied by its
+//! intensity as the illuminance at the top of the atmosphere. Up is assumed to
+//! be +Y and the atmosphere doesn't scale with the scene, the sky always looks
+//! the same regardless of where the camera is.
+//!
+//! The sky can also be rendered into a cube texture, which can then be used as
+//! the environment of the [`IblRoutine`](crate::ibl::IblRoutine) so the
+//! ambient lighting follows the sky. The sun disk is left out of the cube, as
+//! the light itself already lights the scene.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::Vec3;
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTargets},
+    types::{DirectionalLightHandle, SampleCount, TextureCubeHandle},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, CompareFunction, ComputePassDescriptor,
+    ComputePipeline, DepthBiasState, DepthStencilState, Device, Extent3d, Face, FilterMode, FragmentState, FrontFace,
+    ImageCopyTexture, MultisampleState, Origin3d, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, StorageTextureAccess, Texture, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::{common::WholeFrameInterfaces, ibl::create_pipeline};
+
+const LUT_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+// Must match the sizes in `atmosphere/common.wgsl`.
+const TRANSMITTANCE_LUT_SIZE: (u32, u32) = (256, 64);
+const MULTIPLE_SCATTERING_LUT_SIZE: (u32, u32) = (32, 32);
+const SKY_VIEW_LUT_SIZE: (u32, u32) = (192, 108);
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Settings of the atmosphere, see module for documentation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtmosphereSettings {
+    /// Haziness of the air, from the amount of aerosols like dust and water
+    /// droplets in it. 1 is perfectly clear air, 2 a clear day and higher
+    /// values give an increasingly hazy sky with a bright halo around the
+    /// sun.
+    pub turbidity: f32,
+    /// Albedo of the ground below the horizon, which also reflects light back
+    /// into the sky.
+    pub ground_albedo: Vec3,
+}
+
+impl Default for AtmosphereSettings {
+    fn default() -> Self {
+        Self { turbidity: 2.0, ground_albedo: Vec3::splat(0.3) }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, ShaderType)]
+struct AtmosphereUniforms {
+    sun_direction: Vec3,
+    mie_scale: f32,
+    sun_illuminance: Vec3,
+    ground_albedo: Vec3,
+}
+
+struct StoredTarget {
+    handle: Option<TextureCubeHandle>,
+    rendered: bool,
+}
+
+/// Atmospheric sky rendering routine.
+///
+/// See module for documentation.
+pub struct AtmosphereRoutine {
+    compute_bgl: BindGroupLayout,
+    transmittance_bg: BindGroup,
+    multiple_scattering_bg: BindGroup,
+    sky_view_bg: BindGroup,
+    transmittance_pipeline: ComputePipeline,
+    multiple_scattering_pipeline: ComputePipeline,
+    sky_view_pipeline: ComputePipeline,
+    cube_pipeline: ComputePipeline,
+    sky_bg: BindGroup,
+    sky_pipeline_s1: RenderPipeline,
+    sky_pipeline_s4: RenderPipeline,
+    uniform_buffer: Buffer,
+    sampler: Sampler,
+    sky_view_lut: TextureView,
+    empty: TextureView,
+    /// The target cube textures lack storage usage, so the sky is rendered in
+    /// here and copied over.
+    cube: Option<Texture>,
+    sun: Option<DirectionalLightHandle>,
+    settings: AtmosphereSettings,
+    target: StoredTarget,
+    uniforms: Option<AtmosphereUniforms>,
+}
+
+impl AtmosphereRoutine {
+    /// Create the routine.
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("AtmosphereRoutine::new");
+
+        let device = &renderer.device;
+
+        let lut_binding = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+
+        let compute_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Uniform,
+                false,
+                AtmosphereUniforms::SHADER_SIZE.get(),
+            )
+            .append(ShaderStages::COMPUTE, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(ShaderStages::COMPUTE, lut_binding, None)
+            .append(ShaderStages::COMPUTE, lut_binding, None)
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: LUT_FORMAT,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                None,
+            )
+            .build(device, Some("atmosphere bgl"));
+
+        let sky_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                AtmosphereUniforms::SHADER_SIZE.get(),
+            )
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(ShaderStages::FRAGMENT, lut_binding, None)
+            .append(ShaderStages::FRAGMENT, lut_binding, None)
+            .build(device, Some("atmosphere sky bgl"));
+
+        let transmittance_pipeline = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/atmosphere/transmittance.wgsl",
+            "cs_transmittance",
+            &compute_bgl,
+            "atmosphere transmittance",
+        );
+        let multiple_scattering_pipeline = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/atmosphere/multiple_scattering.wgsl",
+            "cs_multiple_scattering",
+            &compute_bgl,
+            "atmosphere multiple scattering",
+        );
+        let sky_view_pipeline = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/atmosphere/sky_view.wgsl",
+            "cs_sky_view",
+            &compute_bgl,
+            "atmosphere sky view",
+        );
+        let cube_pipeline = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/atmosphere/cube.wgsl",
+            "cs_cube",
+            &compute_bgl,
+            "atmosphere cube",
+        );
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("atmosphere uniform"),
+            size: AtmosphereUniforms::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("atmosphere lut sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+        let transmittance_lut = create_lut(device, "atmosphere transmittance lut", TRANSMITTANCE_LUT_SIZE, usage);
+        let multiple_scattering_lut =
+            create_lut(device, "atmosphere multiple scattering lut", MULTIPLE_SCATTERING_LUT_SIZE, usage);
+        let sky_view_lut = create_lut(device, "atmosphere sky view lut", SKY_VIEW_LUT_SIZE, usage);
+        let empty = create_lut(device, "atmosphere empty lut", (1, 1), TextureUsages::TEXTURE_BINDING);
+
+        let transmittance_view = transmittance_lut.create_view(&TextureViewDescriptor::default());
+        let multiple_scattering_view = multiple_scattering_lut.create_view(&TextureViewDescriptor::default());
+        let sky_view_view = sky_view_lut.create_view(&TextureViewDescriptor::default());
+        let empty = empty.create_view(&TextureViewDescriptor::default());
+
+        let compute_bg = |label, input_a, input_b, output: &Texture| {
+            BindGroupBuilder::new()
+                .append_buffer(&uniform_buffer)
+                .append_sampler(&sampler)
+                .append_texture_view(input_a)
+                .append_texture_view(input_b)
+                .append_texture_view(&create_storage_view(output, 0))
+                .build(device, Some(label), &compute_bgl)
+        };
+        let transmittance_bg = compute_bg("atmosphere transmittance bg", &empty, &empty, &transmittance_lut);
+        let multiple_scattering_bg =
+            compute_bg("atmosphere multiple scattering bg", &transmittance_view, &empty, &multiple_scattering_lut);
+        let sky_view_bg =
+            compute_bg("atmosphere sky view bg", &transmittance_view, &multiple_scattering_view, &sky_view_lut);
+
+        let sky_bg = BindGroupBuilder::new()
+            .append_buffer(&uniform_buffer)
+            .append_sampler(&sampler)
+            .append_texture_view(&sky_view_view)
+            .append_texture_view(&transmittance_view)
+            .build(device, Some("atmosphere sky bg"), &sky_bgl);
+
+        let sky_pipeline = |samples| create_sky_pipeline(renderer, spp, interfaces, &sky_bgl, samples);
+        let sky_pipeline_s1 = sky_pipeline(SampleCount::One);
+        let sky_pipeline_s4 = sky_pipeline(SampleCount::Four);
+
+        Self {
+            compute_bgl,
+            transmittance_bg,
+            multiple_scattering_bg,
+            sky_view_bg,
+            transmittance_pipeline,
+            multiple_scattering_pipeline,
+            sky_view_pipeline,
+            cube_pipeline,
+            sky_bg,
+            sky_pipeline_s1,
+            sky_pipeline_s4,
+            uniform_buffer,
+            sampler,
+            sky_view_lut: sky_view_view,
+            empty,
+            cube: None,
+            sun: None,
+            settings: AtmosphereSettings::default(),
+            target: StoredTarget { handle: None, rendered: false },
+            uniforms: None,
+        }
+    }
+
+    /// Set the directional light acting as the sun. Without a sun, the sky is
+    /// black.
+    pub fn set_sun(&mut self, sun: Option<DirectionalLightHandle>) {
+        self.sun = sun;
+    }
+
+    /// Set the settings of the atmosphere.
+    pub fn set_settings(&mut self, settings: AtmosphereSettings) {
+        self.settings = settings;
+    }
+
+    /// Set the cube texture to render the sky into, which has to use
+    /// [`TextureFormat::Rgba16Float`]. Every mip is rendered.
+    ///
+    /// The sky is rendered on the next call to [`Self::evaluate`] after the
+    /// texture was added, which happens when the instructions of the frame
+    /// that added it are evaluated.
+    pub fn set_target(&mut self, target: Option<TextureCubeHandle>) {
+        self.target = StoredTarget { handle: target, rendered: false };
+    }
+
+    /// Update the lookup tables and the target cube if the sun or the settings
+    /// changed since the last call.
+    ///
+    /// Returns true if the target cube was rendered, in which case it needs to
+    /// be set as the environment of the [`IblRoutine`](crate::ibl::IblRoutine)
+    /// again to update the ambient lighting. If the target cube hasn't been
+    /// added to the renderer yet, it is skipped and rendered on a later call.
+    pub fn evaluate(&mut self, renderer: &Renderer) -> bool {
+        profiling::scope!("Update Atmosphere");
+
+        let device = &renderer.device;
+        let data_core = renderer.data_core.lock();
+
+        let sun = self.sun.as_ref().and_then(|sun| data_core.directional_light_manager.get(sun.get_raw()));
+        let uniforms = AtmosphereUniforms {
+            sun_direction: sun.map_or(Vec3::Y, |sun| -sun.direction.normalize_or_zero()),
+            mie_scale: (self.settings.turbidity - 1.0).max(0.0),
+            sun_illuminance: sun.map_or(Vec3::ZERO, |sun| sun.color * sun.intensity),
+            ground_albedo: self.settings.ground_albedo,
+        };
+
+        let previous = self.uniforms.replace(uniforms);
+        let medium_changed = previous.map_or(true, |previous| {
+            previous.mie_scale != uniforms.mie_scale || previous.ground_albedo != uniforms.ground_albedo
+        });
+        // The sky-view table is relative to the sun, so it stays the same when the sun only turns around the zenith.
+        let sky_view_changed =
+            medium_changed || previous.map_or(true, |previous| previous.sun_direction.y != uniforms.sun_direction.y);
+        let sky_changed = previous != Some(uniforms);
+        let target = self
+            .target
+            .handle
+            .as_ref()
+            .and_then(|handle| data_core.d2c_texture_manager.try_get_internal(handle.get_raw()));
+        let render_cube = target.is_some() && (sky_changed || !self.target.rendered);
+        if render_cube {
+            self.target.rendered = true;
+        }
+
+        if !sky_changed && !render_cube {
+            return false;
+        }
+
+        let mut data = UniformBuffer::new(Vec::with_capacity(AtmosphereUniforms::SHADER_SIZE.get() as usize));
+        data.write(&uniforms).unwrap();
+        renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("atmosphere") });
+
+        if medium_changed {
+            dispatch(&mut encoder, &self.transmittance_pipeline, &self.transmittance_bg, TRANSMITTANCE_LUT_SIZE, 1);
+            dispatch(
+                &mut encoder,
+                &self.multiple_scattering_pipeline,
+                &self.multiple_scattering_bg,
+                MULTIPLE_SCATTERING_LUT_SIZE,
+                1,
+            );
+        }
+        if sky_view_changed {
+            dispatch(&mut encoder, &self.sky_view_pipeline, &self.sky_view_bg, SKY_VIEW_LUT_SIZE, 1);
+        }
+
+        if let Some(target) = target.filter(|_| render_cube) {
+            assert_eq!(target.desc.format, LUT_FORMAT, "atmosphere target must be an Rgba16Float cube texture");
+
+            let size = target.desc.size;
+            let mip_level_count = target.desc.mip_level_count;
+            let matches_target = matches!(
+                self.cube,
+                Some(ref cube) if cube.size() == size && cube.mip_level_count() == mip_level_count
+            );
+            if !matches_target {
+                self.cube = Some(device.create_texture(&TextureDescriptor {
+                    label: Some("atmosphere cube"),
+                    size,
+                    mip_level_count,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: LUT_FORMAT,
+                    usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                }));
+            }
+            let cube = self.cube.as_ref().unwrap();
+
+            for mip in 0..mip_level_count {
+                let bg = BindGroupBuilder::new()
+                    .append_buffer(&self.uniform_buffer)
+                    .append_sampler(&self.sampler)
+                    .append_texture_view(&self.sky_view_lut)
+                    .append_texture_view(&self.empty)
+                    .append_texture_view(&create_storage_view(cube, mip))
+                    .build(device, Some("atmosphere cube bg"), &self.compute_bgl);
+
+                let mip_size = ((size.width >> mip).max(1), (size.height >> mip).max(1));
+                dispatch(&mut encoder, &self.cube_pipeline, &bg, mip_size, 6);
+            }
+
+            for mip in 0..mip_level_count {
+                let copy = |texture| ImageCopyTexture {
+                    texture,
+                    mip_level: mip,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                };
+                encoder.copy_texture_to_texture(
+                    copy(cube),
+                    copy(&target.texture),
+                    Extent3d {
+                        width: (size.width >> mip).max(1),
+                        height: (size.height >> mip).max(1),
+                        depth_or_array_layers: 6,
+                    },
+                );
+            }
+        }
+
+        drop(data_core);
+
+        renderer.queue.submit([encoder.finish()]);
+
+        render_cube
+    }
+
+    /// Add rendering the sky to the given rendergraph.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+        samples: SampleCount,
+    ) {
+        let mut builder = graph.add_node("Atmosphere");
+
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            let pipeline = match samples {
+                SampleCount::One => &self.sky_pipeline_s1,
+                SampleCount::Four => &self.sky_pipeline_s4,
+            };
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, &self.sky_bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+fn dispatch(encoder: &mut CommandEncoder, pipeline: &ComputePipeline, bg: &BindGroup, size: (u32, u32), layers: u32) {
+    let mut cpass =
+        encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("Atmosphere"), timestamp_writes: None });
+    cpass.set_pipeline(pipeline);
+    cpass.set_bind_group(0, bg, &[]);
+    cpass.dispatch_workgroups(div_round_up(size.0, WORKGROUP_SIZE), div_round_up(size.1, WORKGROUP_SIZE), layers);
+}
+
+fn create_lut(device: &Device, label: &str, size: (u32, u32), usage: TextureUsages) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: LUT_FORMAT,
+        usage,
+        view_formats: &[],
+    })
+}
+
+fn create_storage_view(texture: &Texture, mip: u32) -> TextureView {
+    texture.create_view(&TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+fn create_sky_pipeline(
+    renderer: &Renderer,
+    spp: &ShaderPreProcessor,
+    interfaces: &WholeFrameInterfaces,
+    bgl: &BindGroupLayout,
+    samples: SampleCount,
+) -> RenderPipeline {
+    profiling::scope!("build atmosphere pipeline");
+    let sky_sm = renderer.device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("atmosphere sky"),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader("rend3-routine/atmosphere/sky.wgsl", &ShaderConfig::default(), None).unwrap(),
+        )),
+    });
+
+    let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("atmosphere sky pass"),
+        bind_group_layouts: &[&interfaces.forward_uniform_bgl, bgl],
+        push_constant_ranges: &[],
+    });
+
+    renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("atmosphere sky pass"),
+        layout: Some(&pll),
+        vertex: VertexState { module: &sky_sm, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: Some(Face::Back),
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::GreaterEqual,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState { count: samples as u32, ..Default::default() },
+        fragment: Some(FragmentState {
+            module: &sky_sm,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba16Float,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
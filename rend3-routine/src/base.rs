@@ -27,10 +27,27 @@ use rend3::{
 use wgpu::BindGroup;
 
 use crate::{
-    clear,
-    common::{self, CameraSpecifier},
+    bloom::{BloomRoutine, BloomSettings},
+    clear::{self, DepthViewportClear},
+    clustering::LightClusterRoutine,
+    common::{self, CameraSpecifier, ForwardFeatures},
+    culling::{CullingArgs, DrawCallSet},
+    evsm::EvsmRoutine,
+    fog::FogSettings,
     forward::{self, ForwardRoutineArgs},
-    skinning, uniforms,
+    fxaa::FxaaRoutine,
+    hi_z::{HiZReduction, HiZRoutine},
+    ibl::{IblRoutine, IblTextures},
+    oit::{OitRoutine, OitTargets},
+    pbr::{PbrRoutine, TransparencyType},
+    reflection_probe::{ReflectionProbeBindings, ReflectionProbeRoutine},
+    skinning,
+    smaa::SmaaRoutine,
+    ssao::{SsaoRoutine, SsaoSettings},
+    ssr::{SsrRoutine, SsrSettings},
+    taa::{TaaRoutine, TaaSettings},
+    tonemapping::TonemappingSettings,
+    uniforms,
 };
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -80,7 +97,18 @@ pub struct OutputRenderTarget {
 pub struct BaseRenderGraphRoutines<'node> {
     pub pbr: &'node crate::pbr::PbrRoutine,
     pub skybox: Option<&'node crate::skybox::SkyboxRoutine>,
+    /// Drawn instead of the skybox if set.
+    pub atmosphere: Option<&'node crate::atmosphere::AtmosphereRoutine>,
+    /// Image based lighting is only applied if this is set.
+    pub ibl: Option<&'node IblRoutine>,
+    /// Reflection probes are only applied if this is set and the graph was
+    /// created with [`ForwardFeatures::reflection_probes`].
+    pub reflection_probes: Option<&'node ReflectionProbeRoutine>,
     pub tonemapping: &'node crate::tonemapping::TonemappingRoutine,
+    /// Needed for [`PostProcessAntiAliasing::Fxaa`].
+    pub fxaa: Option<&'node FxaaRoutine>,
+    /// Needed for [`PostProcessAntiAliasing::Smaa`].
+    pub smaa: Option<&'node SmaaRoutine>,
 }
 
 pub struct BaseRenderGraphInputs<'a, 'node> {
@@ -89,10 +117,50 @@ pub struct BaseRenderGraphInputs<'a, 'node> {
     pub target: OutputRenderTarget,
 }
 
+/// Anti-aliasing applied to the tonemapped image.
+///
+/// These are much cheaper than multisampling, but only see the final image,
+/// so they can't recover detail which is smaller than a pixel.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PostProcessAntiAliasing {
+    #[default]
+    None,
+    /// Single pass, blurs some texture detail. See [`FxaaRoutine`].
+    Fxaa,
+    /// Three passes, only blends along geometric lines. See [`SmaaRoutine`].
+    Smaa,
+}
+
 #[derive(Debug, Default)]
 pub struct BaseRenderGraphSettings {
     pub ambient_color: Vec4,
     pub clear_color: Vec4,
+    pub tonemapping: TonemappingSettings,
+    /// Bloom is only rendered if this is set.
+    pub bloom: Option<BloomSettings>,
+    /// Screen space ambient occlusion is only rendered if this is set.
+    pub ssao: Option<SsaoSettings>,
+    /// Screen space reflections are only rendered if this is set.
+    pub ssr: Option<SsrSettings>,
+    /// Height fog is only applied if this is set.
+    pub fog: Option<FogSettings>,
+    /// Render the depth of the opaque and cutout geometry before shading it,
+    /// so the forward pass only shades the visible fragments. On the GpuDriven
+    /// profile, the forward pass is also occlusion culled against this depth.
+    pub depth_prepass: bool,
+    /// Render the blended PBR materials with weighted blended order
+    /// independent transparency instead of sorting them, so intersecting
+    /// transparent objects blend correctly. See [`OitRoutine`].
+    pub order_independent_transparency: bool,
+    /// Temporal anti-aliasing is only applied if this is set. It replaces
+    /// multisampling, so the output target is rendered with a single sample.
+    ///
+    /// The viewport camera needs to be jittered every frame, see
+    /// [`TaaRoutine::jitter`].
+    pub taa: Option<TaaSettings>,
+    /// Only applied if the matching routine is in the
+    /// [`BaseRenderGraphRoutines`].
+    pub post_process_anti_aliasing: PostProcessAntiAliasing,
 }
 
 /// Starter RenderGraph.
@@ -102,13 +170,33 @@ pub struct BaseRenderGraph {
     pub interfaces: common::WholeFrameInterfaces,
     pub samplers: common::Samplers,
     pub gpu_skinner: skinning::GpuSkinner,
+    pub bloom: BloomRoutine,
+    /// Image based lighting textures used when there is no [`IblRoutine`].
+    pub ibl_fallback: IblTextures,
+    /// Reflection probes used when there is no [`ReflectionProbeRoutine`].
+    pub reflection_probe_fallback: ReflectionProbeBindings,
+    pub light_clusters: LightClusterRoutine,
+    pub evsm: EvsmRoutine,
+    pub hi_z: HiZRoutine,
+    pub ssao: SsaoRoutine,
+    pub ssr: SsrRoutine,
+    pub taa: TaaRoutine,
+    pub oit: OitRoutine,
+    pub shadow_clear: DepthViewportClear,
 }
 
 impl BaseRenderGraph {
+    /// Creates the graph with the default [`ForwardFeatures`] of the profile.
     pub fn new(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor) -> Self {
+        Self::with_features(renderer, spp, ForwardFeatures::new(renderer.profile))
+    }
+
+    /// Like [`Self::new`], but the forward shaders only use the given optional
+    /// features.
+    pub fn with_features(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor, features: ForwardFeatures) -> Self {
         profiling::scope!("DefaultRenderGraphData::new");
 
-        let interfaces = common::WholeFrameInterfaces::new(&renderer.device);
+        let interfaces = common::WholeFrameInterfaces::new(&renderer.device, features);
 
         let samplers = common::Samplers::new(&renderer.device);
 
@@ -116,32 +204,97 @@ impl BaseRenderGraph {
 
         let gpu_skinner = skinning::GpuSkinner::new(&renderer.device, spp);
 
-        Self { interfaces, samplers, gpu_skinner }
+        let bloom = BloomRoutine::new(renderer, spp);
+
+        let ibl_fallback = IblTextures::empty(&renderer.device);
+
+        let reflection_probe_fallback = ReflectionProbeBindings::empty(&renderer.device);
+
+        let light_clusters = LightClusterRoutine::new(renderer, spp);
+
+        let evsm = EvsmRoutine::new(renderer, spp);
+
+        let hi_z = HiZRoutine::new(renderer, spp);
+
+        let ssao = SsaoRoutine::new(renderer, spp);
+
+        let ssr = SsrRoutine::new(renderer, spp);
+
+        let taa = TaaRoutine::new(renderer, spp);
+
+        let oit = OitRoutine::new(renderer, spp);
+
+        let shadow_clear = DepthViewportClear::new(&renderer.device, spp, INTERNAL_SHADOW_DEPTH_FORMAT);
+
+        Self {
+            interfaces,
+            samplers,
+            gpu_skinner,
+            bloom,
+            ibl_fallback,
+            reflection_probe_fallback,
+            light_clusters,
+            evsm,
+            hi_z,
+            ssao,
+            ssr,
+            taa,
+            oit,
+            shadow_clear,
+        }
     }
 
     /// Add this to the rendergraph. This is the function you should start
     /// customizing.
+    ///
+    /// The sample count of the output target is ignored if temporal
+    /// anti-aliasing is enabled.
     #[allow(clippy::too_many_arguments)]
     pub fn add_to_graph<'node>(
         &'node self,
         graph: &mut RenderGraph<'node>,
-        inputs: BaseRenderGraphInputs<'_, 'node>,
-        settings: BaseRenderGraphSettings,
+        mut inputs: BaseRenderGraphInputs<'_, 'node>,
+        mut settings: BaseRenderGraphSettings,
     ) {
+        // The motion vectors can't be multisampled.
+        if settings.taa.is_some() {
+            inputs.target.samples = SampleCount::One;
+        }
+
+        // The forward shaders can't read the ambient occlusion without the binding.
+        if !self.interfaces.features.ssao {
+            settings.ssao = None;
+        }
+
         // Create the data and handles for the graph.
         let mut state = BaseRenderGraphIntermediateState::new(graph, inputs, settings);
 
-        // Clear the shadow buffers. This, as an explicit node, must be done as a limitation of the graph dependency system.
-        state.clear_shadow_buffers();
+        // Keep the shadow maps which don't need to be rendered again from the previous frames.
+        state.preserve_shadow_atlas();
 
         // Prepare all the uniforms that all shaders need access to.
         state.create_frame_uniforms(self);
 
+        // Bin the point lights into clusters so shading only visits nearby lights.
+        state.light_clustering(self);
+
         // Perform compute based skinning.
         state.skinning(self);
 
-        // Render all the shadows to the shadow map.
-        state.pbr_shadow_rendering();
+        // Render the shadows which changed to the shadow map.
+        state.pbr_shadow_rendering(self);
+
+        // Convert the shadow maps that use EVSM filtering into blurred moments.
+        state.shadow_moments(self);
+
+        // Render the depth and surface of the opaque geometry for the screen space effects.
+        state.pbr_prepass();
+
+        // Render the ambient occlusion of the opaque geometry.
+        state.ambient_occlusion(self);
+
+        // Render the depth of the opaque geometry, so the first pass only shades what is visible.
+        state.pbr_depth_prepass(self);
 
         // Do the first pass, rendering the predicted triangles from last frame.
         state.pbr_render();
@@ -149,14 +302,26 @@ impl BaseRenderGraph {
         // Render the skybox.
         state.skybox();
 
+        // Add the reflections of the opaque geometry and skybox.
+        state.screen_space_reflections(self);
+
         // Render all transparent objects.
         //
         // This _must_ happen after culling, as all transparent objects are
         // considered "residual".
-        state.pbr_forward_rendering_transparent();
+        state.pbr_forward_rendering_transparent(self);
+
+        // Blend the frame into the history of the previous frames.
+        state.temporal_anti_aliasing(self);
+
+        // Spread the light of bright areas over their surroundings.
+        state.bloom(self);
 
         // Tonemap the HDR inner buffer to the output buffer.
         state.tonemapping();
+
+        // Smooth the edges of the tonemapped image into the output buffer.
+        state.post_process_anti_aliasing();
     }
 }
 
@@ -173,8 +338,20 @@ pub struct BaseRenderGraphIntermediateState<'a, 'node> {
     pub forward_uniform_bg: DataHandle<BindGroup>,
 
     pub shadow: RenderTargetHandle,
+    pub shadow_moments: RenderTargetHandle,
+    pub ambient_occlusion: RenderTargetHandle,
+    pub prepass_surface: RenderTargetHandle,
     pub depth: DepthTargets,
     pub primary_renderpass: RenderPassTargets,
+    /// Hierarchical-z buffer the viewport is occlusion culled against, if
+    /// there is one.
+    pub hi_z: Option<RenderTargetHandle>,
+    /// Motion vectors of the viewport, written by the forward pass if
+    /// temporal anti-aliasing is enabled.
+    pub velocity: RenderTargetHandle,
+    /// Target tonemapping renders to. This is the output target, unless
+    /// post-process anti-aliasing is enabled.
+    pub tonemapped: RenderTargetHandle,
 
     pub pre_skinning_buffers: DataHandle<skinning::PreSkinningBuffers>,
 }
@@ -189,14 +366,52 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         let shadow_uniform_bg = graph.add_data::<BindGroup>();
         let forward_uniform_bg = graph.add_data::<BindGroup>();
 
-        // Shadow render target
-        let shadow = graph.add_render_target(RenderTargetDescriptor {
-            label: Some("shadow target".into()),
-            resolution: inputs.eval_output.shadow_target_size,
+        // Shadow render target, which keeps its contents between frames.
+        let shadow = graph.add_shared_render_target(
+            Arc::clone(&inputs.eval_output.shadow_atlas),
+            0..1,
+            0..1,
+            ViewportRect::from_size(inputs.eval_output.shadow_target_size),
+        );
+
+        // Moments of the shadow maps using EVSM filtering. Only allocated at full
+        // size if there are any.
+        let shadow_moments = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("shadow moments".into()),
+            resolution: if EvsmRoutine::any_evsm(&inputs.eval_output.shadows) {
+                inputs.eval_output.shadow_target_size
+            } else {
+                UVec2::ONE
+            },
             depth: 1,
             mip_levels: Some(1),
             samples: SampleCount::One,
-            format: INTERNAL_SHADOW_DEPTH_FORMAT,
+            format: EvsmRoutine::MOMENTS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        // Ambient occlusion of the viewport. Only allocated at full size if
+        // it is enabled, otherwise it is cleared to white.
+        let ambient_occlusion = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("ambient occlusion".into()),
+            resolution: if settings.ssao.is_some() { inputs.target.resolution } else { UVec2::ONE },
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: SsaoRoutine::AO_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        // Surface of the opaque geometry for the screen space effects. Only
+        // allocated at full size and rendered if any of them are enabled.
+        let surface_prepass = settings.ssao.is_some() || settings.ssr.is_some();
+        let prepass_surface = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("prepass surface".into()),
+            resolution: if surface_prepass { inputs.target.resolution } else { UVec2::ONE },
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: PbrRoutine::PREPASS_FORMAT,
             usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
         });
 
@@ -222,6 +437,39 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             })
         });
         let depth = DepthTargets::new(graph, inputs.target.resolution, inputs.target.samples);
+
+        // Motion vectors for temporal anti-aliasing. Only allocated at full
+        // size if it is enabled.
+        let velocity = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("velocity".into()),
+            resolution: if settings.taa.is_some() { inputs.target.resolution } else { UVec2::ONE },
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: TaaRoutine::VELOCITY_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        // The anti-aliasing reads the tonemapped image, so it can't be rendered
+        // straight into the output.
+        let anti_aliasing_format = match settings.post_process_anti_aliasing {
+            PostProcessAntiAliasing::None => None,
+            PostProcessAntiAliasing::Fxaa => inputs.routines.fxaa.map(FxaaRoutine::output_format),
+            PostProcessAntiAliasing::Smaa => inputs.routines.smaa.map(SmaaRoutine::output_format),
+        };
+        let tonemapped = match anti_aliasing_format {
+            Some(format) => graph.add_render_target(RenderTargetDescriptor {
+                label: Some("tonemapped".into()),
+                resolution: inputs.target.resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            }),
+            None => inputs.target.handle,
+        };
+
         let primary_renderpass = graph::RenderPassTargets {
             targets: vec![graph::RenderPassTarget { color, resolve, clear: settings.clear_color }],
             depth_stencil: Some(graph::RenderPassDepthTarget {
@@ -242,16 +490,23 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
             forward_uniform_bg,
 
             shadow,
+            shadow_moments,
+            ambient_occlusion,
+            prepass_surface,
             depth,
             primary_renderpass,
+            hi_z: None,
+            velocity,
+            tonemapped,
 
             pre_skinning_buffers,
         }
     }
 
-    /// Clear the shadow buffers. This, as an explicit node, must be done as a limitation of the graph dependency system.
-    fn clear_shadow_buffers(&mut self) {
-        clear::add_depth_clear_to_graph(self.graph, self.shadow, 0.0);
+    /// Mark the shadow atlas as used before any shadow is rendered, so rendering a shadow doesn't
+    /// clear the whole atlas.
+    pub fn preserve_shadow_atlas(&mut self) {
+        clear::add_preserve_to_graph(self.graph, self.shadow);
     }
 
     /// Create all the uniforms all the shaders in this graph need.
@@ -259,6 +514,8 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         uniforms::add_to_graph(
             self.graph,
             self.shadow,
+            self.shadow_moments,
+            self.ambient_occlusion,
             uniforms::UniformBindingHandles {
                 interfaces: &base.interfaces,
                 shadow_uniform_bg: self.shadow_uniform_bg,
@@ -268,18 +525,66 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                 samplers: &base.samplers,
                 ambient: self.settings.ambient_color,
                 resolution: self.inputs.target.resolution,
+                fog: self.settings.fog,
+                ibl: self.inputs.routines.ibl.map_or(&base.ibl_fallback, IblRoutine::textures),
+                reflection_probes: self
+                    .inputs
+                    .routines
+                    .reflection_probes
+                    .map_or(&base.reflection_probe_fallback, ReflectionProbeRoutine::bindings),
+                light_clusters: &base.light_clusters,
             },
         );
     }
 
+    /// Bin the point lights into the clusters of the viewport camera.
+    pub fn light_clustering(&mut self, base: &'node BaseRenderGraph) {
+        if !base.interfaces.features.light_clusters {
+            return;
+        }
+        base.light_clusters.add_to_graph(self.graph);
+    }
+
     pub fn skinning(&mut self, base: &'node BaseRenderGraph) {
         skinning::add_skinning_to_graph(self.graph, &base.gpu_skinner);
     }
 
-    /// Render all shadows for the PBR materials.
-    pub fn pbr_shadow_rendering(&mut self) {
+    /// Cull the PBR objects with the given transparency on the GPU. The
+    /// viewport is occlusion culled against [`Self::hi_z`], if there is one.
+    ///
+    /// Returns `None` if the profile doesn't support GPU culling, in which case the
+    /// forward routine will cull on the CPU.
+    pub fn pbr_culling(
+        &mut self,
+        label: &str,
+        camera: CameraSpecifier,
+        transparency: TransparencyType,
+    ) -> Option<DataHandle<DrawCallSet>> {
+        let culler = self.inputs.routines.pbr.culler.as_ref()?;
+
+        Some(culler.add_culling_to_graph(CullingArgs {
+            graph: self.graph,
+            label,
+            camera,
+            material_key: transparency as u64,
+            hi_z: match camera {
+                CameraSpecifier::Viewport => self.hi_z,
+                CameraSpecifier::Shadow(_) => None,
+            },
+        }))
+    }
+
+    /// Render the dirty shadows for the PBR materials. The other shadows are
+    /// left as they were rendered on a previous frame.
+    pub fn pbr_shadow_rendering(&mut self, base: &'node BaseRenderGraph) {
         for (shadow_index, desc) in self.inputs.eval_output.shadows.iter().enumerate() {
+            if !desc.dirty {
+                continue;
+            }
+
             let target = self.shadow.set_viewport(ViewportRect::new(desc.map.offset, UVec2::splat(desc.map.size)));
+            base.shadow_clear.add_to_graph(self.graph, target);
+
             let renderpass = graph::RenderPassTargets {
                 targets: vec![],
                 depth_stencil: Some(graph::RenderPassDepthTarget {
@@ -289,17 +594,24 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                 }),
             };
 
-            let routines = [&self.inputs.routines.pbr.opaque_depth, &self.inputs.routines.pbr.cutout_depth];
-            for routine in routines {
+            let routines = [
+                (&self.inputs.routines.pbr.opaque_depth, TransparencyType::Opaque),
+                (&self.inputs.routines.pbr.cutout_depth, TransparencyType::Cutout),
+            ];
+            for (routine, transparency) in routines {
+                let camera = CameraSpecifier::Shadow(shadow_index as u32);
+                let culling_source =
+                    self.pbr_culling(&format!("pbr shadow culling S{shadow_index}"), camera, transparency);
                 routine.add_forward_to_graph(ForwardRoutineArgs {
                     graph: self.graph,
                     label: &format!("pbr shadow renderering S{shadow_index}"),
-                    camera: CameraSpecifier::Shadow(shadow_index as u32),
+                    camera,
                     binding_data: forward::ForwardRoutineBindingData {
                         whole_frame_uniform_bg: self.shadow_uniform_bg,
                         per_material_bgl: &self.inputs.routines.pbr.per_material,
                         extra_bgs: None,
                     },
+                    culling_source,
                     samples: SampleCount::One,
                     renderpass: renderpass.clone(),
                 });
@@ -307,9 +619,141 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         }
     }
 
-    /// Render the skybox.
+    /// Convert the shadow maps using EVSM filtering into blurred moments.
+    pub fn shadow_moments(&mut self, base: &'node BaseRenderGraph) {
+        if !base.interfaces.features.evsm {
+            return;
+        }
+        base.evsm.add_to_graph(
+            self.graph,
+            self.shadow,
+            self.shadow_moments,
+            &self.inputs.eval_output.shadows,
+            self.inputs.eval_output.shadow_target_size,
+        );
+    }
+
+    /// Render the depth and surface of the opaque and cutout PBR materials, if
+    /// any screen space effects are enabled in the settings.
+    ///
+    /// The main pass keeps testing against this depth if it isn't
+    /// multisampled.
+    pub fn pbr_prepass(&mut self) {
+        if self.settings.ssao.is_none() && self.settings.ssr.is_none() {
+            return;
+        }
+
+        let renderpass = graph::RenderPassTargets {
+            targets: vec![graph::RenderPassTarget { color: self.prepass_surface, resolve: None, clear: Vec4::ZERO }],
+            depth_stencil: Some(graph::RenderPassDepthTarget {
+                target: self.depth.single_sample_mipped.set_mips(0..1),
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+            }),
+        };
+
+        let routines = [
+            (&self.inputs.routines.pbr.opaque_prepass, TransparencyType::Opaque),
+            (&self.inputs.routines.pbr.cutout_prepass, TransparencyType::Cutout),
+        ];
+        for (routine, transparency) in routines {
+            let culling_source = self.pbr_culling("PBR Prepass Culling", CameraSpecifier::Viewport, transparency);
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR Prepass",
+                camera: CameraSpecifier::Viewport,
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: self.shadow_uniform_bg,
+                    per_material_bgl: &self.inputs.routines.pbr.per_material,
+                    extra_bgs: None,
+                },
+                culling_source,
+                samples: SampleCount::One,
+                renderpass: renderpass.clone(),
+            });
+        }
+    }
+
+    /// Render the ambient occlusion of the prepass, if enabled in the
+    /// settings.
+    pub fn ambient_occlusion(&mut self, base: &'node BaseRenderGraph) {
+        let Some(settings) = self.settings.ssao else {
+            clear::add_clear_to_graph(self.graph, self.ambient_occlusion, Vec4::ONE);
+            return;
+        };
+
+        base.ssao.add_to_graph(
+            self.graph,
+            self.depth.single_sample_mipped.set_mips(0..1),
+            self.prepass_surface,
+            self.ambient_occlusion,
+            self.inputs.target.resolution,
+            settings,
+        );
+    }
+
+    /// Render the depth of the opaque and cutout PBR materials into the depth
+    /// target of the primary renderpass, if enabled in the settings.
+    ///
+    /// On the GpuDriven profile, this also builds [`Self::hi_z`] out of the
+    /// depth, so the following passes over the viewport are occlusion culled.
+    pub fn pbr_depth_prepass(&mut self, base: &'node BaseRenderGraph) {
+        if !self.settings.depth_prepass {
+            return;
+        }
+
+        // The surface prepass already rendered this depth if it isn't multisampled.
+        let surface_prepass = self.settings.ssao.is_some() || self.settings.ssr.is_some();
+        if !surface_prepass || self.inputs.target.samples.needs_resolve() {
+            let renderpass = graph::RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(graph::RenderPassDepthTarget {
+                    target: self.depth.rendering_target(),
+                    depth_clear: Some(0.0),
+                    stencil_clear: None,
+                }),
+            };
+
+            let routines = [
+                (&self.inputs.routines.pbr.opaque_depth_prepass, TransparencyType::Opaque),
+                (&self.inputs.routines.pbr.cutout_depth_prepass, TransparencyType::Cutout),
+            ];
+            for (routine, transparency) in routines {
+                let culling_source =
+                    self.pbr_culling("PBR Depth Prepass Culling", CameraSpecifier::Viewport, transparency);
+                routine.add_forward_to_graph(ForwardRoutineArgs {
+                    graph: self.graph,
+                    label: "PBR Depth Prepass",
+                    camera: CameraSpecifier::Viewport,
+                    binding_data: forward::ForwardRoutineBindingData {
+                        whole_frame_uniform_bg: self.shadow_uniform_bg,
+                        per_material_bgl: &self.inputs.routines.pbr.per_material,
+                        extra_bgs: None,
+                    },
+                    culling_source,
+                    samples: self.inputs.target.samples,
+                    renderpass: renderpass.clone(),
+                });
+            }
+        }
+
+        // Only the GPU culling can make use of the hierarchical-z buffer.
+        if self.inputs.routines.pbr.culler.is_some() {
+            base.hi_z.add_hi_z_to_graph(self.graph, self.depth, self.inputs.target.resolution, HiZReduction::Farthest);
+            self.hi_z = Some(self.depth.single_sample_mipped);
+        }
+    }
+
+    /// Render the skybox, or the atmosphere if there is one.
     pub fn skybox(&mut self) {
-        if let Some(skybox) = self.inputs.routines.skybox {
+        if let Some(atmosphere) = self.inputs.routines.atmosphere {
+            atmosphere.add_to_graph(
+                self.graph,
+                self.primary_renderpass.clone(),
+                self.forward_uniform_bg,
+                self.inputs.target.samples,
+            );
+        } else if let Some(skybox) = self.inputs.routines.skybox {
             skybox.add_to_graph(
                 self.graph,
                 self.primary_renderpass.clone(),
@@ -319,10 +763,36 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
         }
     }
 
-    /// Render the PBR materials.
+    /// Render the PBR materials. If the depth prepass is enabled in the
+    /// settings, only the fragments matching its depth are shaded. If temporal
+    /// anti-aliasing is enabled, this also writes [`Self::velocity`].
     pub fn pbr_render(&mut self) {
-        let routines = [&self.inputs.routines.pbr.opaque_routine, &self.inputs.routines.pbr.cutout_routine];
-        for routine in routines {
+        let pbr = self.inputs.routines.pbr;
+        let taa = self.settings.taa.is_some();
+        let routines = match (self.settings.depth_prepass, taa) {
+            (false, false) => {
+                [(&pbr.opaque_routine, TransparencyType::Opaque), (&pbr.cutout_routine, TransparencyType::Cutout)]
+            }
+            (true, false) => [
+                (&pbr.opaque_prepassed_routine, TransparencyType::Opaque),
+                (&pbr.cutout_prepassed_routine, TransparencyType::Cutout),
+            ],
+            (false, true) => [
+                (&pbr.opaque_velocity_routine, TransparencyType::Opaque),
+                (&pbr.cutout_velocity_routine, TransparencyType::Cutout),
+            ],
+            (true, true) => [
+                (&pbr.opaque_prepassed_velocity_routine, TransparencyType::Opaque),
+                (&pbr.cutout_prepassed_velocity_routine, TransparencyType::Cutout),
+            ],
+        };
+        // Pixels without geometry are left at zero, the resolve reprojects them with the camera.
+        let mut renderpass = self.primary_renderpass.clone();
+        if taa {
+            renderpass.targets.push(graph::RenderPassTarget { color: self.velocity, resolve: None, clear: Vec4::ZERO });
+        }
+        for (routine, transparency) in routines {
+            let culling_source = self.pbr_culling("PBR Forward Culling", CameraSpecifier::Viewport, transparency);
             routine.add_forward_to_graph(ForwardRoutineArgs {
                 graph: self.graph,
                 label: "PBR Forward Pass",
@@ -332,35 +802,151 @@ impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
                     per_material_bgl: &self.inputs.routines.pbr.per_material,
                     extra_bgs: None,
                 },
+                culling_source,
                 samples: self.inputs.target.samples,
-                renderpass: self.primary_renderpass.clone(),
+                renderpass: renderpass.clone(),
             });
         }
     }
 
-    /// Render the PBR materials.
-    pub fn pbr_forward_rendering_transparent(&mut self) {
-        self.inputs.routines.pbr.blend_routine.add_forward_to_graph(ForwardRoutineArgs {
+    /// Add the reflections of everything rendered so far, if enabled in the
+    /// settings.
+    ///
+    /// This turns the mips of the single sampled depth into a hierarchical-z
+    /// buffer of the prepass.
+    pub fn screen_space_reflections(&mut self, base: &'node BaseRenderGraph) {
+        let Some(settings) = self.settings.ssr else {
+            return;
+        };
+
+        base.hi_z.add_hi_z_to_graph(self.graph, self.depth, self.inputs.target.resolution, HiZReduction::Closest);
+        base.ssr.add_to_graph(
+            self.graph,
+            self.depth.single_sample_mipped,
+            self.prepass_surface,
+            self.primary_renderpass.resolved_color(0),
+            self.primary_renderpass.clone(),
+            self.inputs.target.resolution,
+            self.inputs.target.samples,
+            settings,
+        );
+    }
+
+    /// Render the blended PBR materials. If order independent transparency is
+    /// enabled in the settings, they are accumulated into their own targets
+    /// and composited onto the primary renderpass, otherwise they are sorted
+    /// and blended straight onto it.
+    pub fn pbr_forward_rendering_transparent(&mut self, base: &'node BaseRenderGraph) {
+        let pbr = self.inputs.routines.pbr;
+        let oit_targets = self
+            .settings
+            .order_independent_transparency
+            .then(|| OitTargets::new(self.graph, self.inputs.target.resolution, self.inputs.target.samples));
+        let (routine, renderpass) = match oit_targets {
+            Some(targets) => {
+                (&pbr.blend_weighted_routine, targets.renderpass(self.primary_renderpass.depth_stencil.clone()))
+            }
+            None => (&pbr.blend_routine, self.primary_renderpass.clone()),
+        };
+
+        routine.add_forward_to_graph(ForwardRoutineArgs {
             graph: self.graph,
             label: "PBR Forward Transparent",
             camera: CameraSpecifier::Viewport,
             binding_data: forward::ForwardRoutineBindingData {
                 whole_frame_uniform_bg: self.forward_uniform_bg,
-                per_material_bgl: &self.inputs.routines.pbr.per_material,
+                per_material_bgl: &pbr.per_material,
                 extra_bgs: None,
             },
+            // Transparent objects need to be sorted, so they can't be culled on the GPU.
+            culling_source: None,
             samples: self.inputs.target.samples,
-            renderpass: self.primary_renderpass.clone(),
+            renderpass,
+        });
+
+        if let Some(targets) = oit_targets {
+            base.oit.add_to_graph(self.graph, targets, self.primary_renderpass.clone(), self.inputs.target.samples);
+        }
+    }
+
+    /// Blend the HDR color into the history of the previous frames, if
+    /// enabled in the settings.
+    ///
+    /// The following passes of the primary renderpass then render into the
+    /// anti-aliased color.
+    pub fn temporal_anti_aliasing(&mut self, base: &'node BaseRenderGraph) {
+        let Some(settings) = self.settings.taa else {
+            return;
+        };
+
+        let output = self.graph.add_render_target(RenderTargetDescriptor {
+            label: Some("taa output".into()),
+            resolution: self.inputs.target.resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: TaaRoutine::HISTORY_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
         });
+
+        base.taa.add_to_graph(
+            self.graph,
+            self.primary_renderpass.resolved_color(0),
+            self.velocity,
+            self.depth.single_sample_mipped.set_mips(0..1),
+            output,
+            self.inputs.target.resolution,
+            settings,
+        );
+
+        self.primary_renderpass.targets[0].color = output;
+        self.primary_renderpass.targets[0].resolve = None;
+    }
+
+    /// Add bloom to the resolved HDR color, if enabled in the settings.
+    pub fn bloom(&mut self, base: &'node BaseRenderGraph) {
+        if let Some(settings) = self.settings.bloom {
+            base.bloom.add_to_graph(
+                self.graph,
+                self.primary_renderpass.resolved_color(0),
+                self.inputs.target.resolution,
+                settings,
+            );
+        }
     }
 
-    /// Tonemap onto the given render target.
+    /// Tonemap onto the tonemapped target.
     pub fn tonemapping(&mut self) {
         self.inputs.routines.tonemapping.add_to_graph(
             self.graph,
             self.primary_renderpass.resolved_color(0),
-            self.inputs.target.handle,
+            self.tonemapped,
             self.forward_uniform_bg,
+            self.inputs.target.resolution,
+            self.settings.tonemapping,
         );
     }
+
+    /// Anti-alias the tonemapped target onto the output target, if enabled in
+    /// the settings and the routine was provided.
+    pub fn post_process_anti_aliasing(&mut self) {
+        match self.settings.post_process_anti_aliasing {
+            PostProcessAntiAliasing::None => {}
+            PostProcessAntiAliasing::Fxaa => {
+                if let Some(fxaa) = self.inputs.routines.fxaa {
+                    fxaa.add_to_graph(self.graph, self.tonemapped, self.inputs.target.handle);
+                }
+            }
+            PostProcessAntiAliasing::Smaa => {
+                if let Some(smaa) = self.inputs.routines.smaa {
+                    smaa.add_to_graph(
+                        self.graph,
+                        self.tonemapped,
+                        self.inputs.target.handle,
+                        self.inputs.target.resolution,
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,298 @@
+//! Bloom post-process which spreads the light of bright parts of the HDR image
+//! over their surroundings.
+//!
+//! This follows the approach of "Next Generation Post Processing in Call of
+//! Duty: Advanced Warfare". The HDR image is progressively downsampled into the
+//! mips of a half resolution render target, then the mips are upsampled and
+//! added back up the chain. Because every mip is blurred a little and the
+//! results are summed, the bloom falls off smoothly over large distances at a
+//! fraction of the cost of a wide blur. The top of the chain is then added to
+//! the HDR image, so this must run before tonemapping.
+//!
+//! By default, every pixel blooms a little, which is the physically based
+//! behavior of a lens. Raising [`BloomSettings::threshold`] limits the bloom to
+//! bright pixels, like emissive materials.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState,
+    BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, FilterMode,
+    FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType, TextureViewDimension,
+    VertexState,
+};
+
+/// Per-frame settings of the [`BloomRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+    /// Brightness above which pixels start to bloom. 0 lets every pixel
+    /// bloom.
+    pub threshold: f32,
+    /// Fraction of the threshold over which pixels fade into the bloom,
+    /// from 0 for a hard cut to 1.
+    pub threshold_knee: f32,
+    /// Multiplier of the bloom when it is added to the image.
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self { threshold: 0.0, threshold_knee: 0.5, intensity: 0.04 }
+    }
+}
+
+#[derive(ShaderType)]
+struct BloomUniform {
+    threshold: f32,
+    threshold_knee: f32,
+    intensity: f32,
+}
+
+const ADDITIVE: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    // Keep the alpha of the image we're adding onto.
+    alpha: BlendComponent {
+        src_factor: BlendFactor::Zero,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    bgl: &BindGroupLayout,
+    label: &str,
+    entry_point: &str,
+    blend: Option<BlendState>,
+) -> RenderPipeline {
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module,
+            entry_point,
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba16Float,
+                blend,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Bloom post-process routine.
+///
+/// See module for documentation.
+pub struct BloomRoutine {
+    bgl: BindGroupLayout,
+    sampler: Sampler,
+    prefilter_pipeline: RenderPipeline,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+}
+
+impl BloomRoutine {
+    /// Maximum amount of mips in the bloom chain. More mips spread the bloom
+    /// further.
+    pub const MAX_MIP_COUNT: u8 = 6;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("BloomRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, BloomUniform::SHADER_SIZE.get())
+            .build(&renderer.device, Some("bloom bgl"));
+
+        // Samples past the edges must not wrap around to the other side of the screen.
+        let sampler = renderer.device.create_sampler(&SamplerDescriptor {
+            label: Some("bloom sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("bloom"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/bloom.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let device = &renderer.device;
+        let prefilter_pipeline = create_pipeline(device, &module, &bgl, "bloom prefilter", "fs_prefilter", None);
+        let downsample_pipeline = create_pipeline(device, &module, &bgl, "bloom downsample", "fs_downsample", None);
+        let upsample_pipeline = create_pipeline(device, &module, &bgl, "bloom upsample", "fs_upsample", Some(ADDITIVE));
+        let composite_pipeline =
+            create_pipeline(device, &module, &bgl, "bloom composite", "fs_composite", Some(ADDITIVE));
+
+        Self { bgl, sampler, prefilter_pipeline, downsample_pipeline, upsample_pipeline, composite_pipeline }
+    }
+
+    /// Adds bloom to `hdr_color`, which must be a single sampled
+    /// `Rgba16Float` target of the given resolution.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        hdr_color: RenderTargetHandle,
+        resolution: UVec2,
+        settings: BloomSettings,
+    ) {
+        let chain_resolution = ((resolution + 1) / 2).max(UVec2::ONE);
+        let mip_count = (chain_resolution.min_element().ilog2() as u8 + 1).min(Self::MAX_MIP_COUNT);
+
+        let chain = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("bloom chain".into()),
+            resolution: chain_resolution,
+            depth: 1,
+            mip_levels: Some(mip_count),
+            samples: SampleCount::One,
+            format: TextureFormat::Rgba16Float,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        self.add_pass_to_graph(
+            graph,
+            "Bloom Prefilter",
+            &self.prefilter_pipeline,
+            hdr_color,
+            chain.set_mips(0..1),
+            settings,
+        );
+        for mip in 1..mip_count {
+            self.add_pass_to_graph(
+                graph,
+                &format!("Bloom Downsample Mip {mip}"),
+                &self.downsample_pipeline,
+                chain.set_mips(mip - 1..mip),
+                chain.set_mips(mip..mip + 1),
+                settings,
+            );
+        }
+        for mip in (1..mip_count).rev() {
+            self.add_pass_to_graph(
+                graph,
+                &format!("Bloom Upsample Mip {mip}"),
+                &self.upsample_pipeline,
+                chain.set_mips(mip..mip + 1),
+                chain.set_mips(mip - 1..mip),
+                settings,
+            );
+        }
+        self.add_pass_to_graph(
+            graph,
+            "Bloom Composite",
+            &self.composite_pipeline,
+            chain.set_mips(0..1),
+            hdr_color,
+            settings,
+        );
+    }
+
+    fn add_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        pipeline: &'node RenderPipeline,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        settings: BloomSettings,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            let uniform_values = BloomUniform {
+                threshold: settings.threshold,
+                threshold_knee: settings.threshold_knee,
+                intensity: settings.intensity,
+            };
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("bloom uniform"),
+                size: BloomUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform_values).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+            let uniform_buffer = ctx.temps.add(uniform_buffer);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(src_view)
+                    .append_sampler(&self.sampler)
+                    .append_buffer(uniform_buffer)
+                    .build(&ctx.renderer.device, Some("bloom bg"), &self.bgl),
+            );
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
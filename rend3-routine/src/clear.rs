@@ -1,4 +1,17 @@
-use rend3::graph::{NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTargets, RenderTargetHandle};
+use std::borrow::Cow;
+
+use glam::Vec4;
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets, RenderTargetHandle,
+    },
+    ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    CompareFunction, DepthStencilState, Device, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+    StencilState, TextureFormat, VertexState,
+};
 
 /// Due to limitations of how we auto-clear buffers, we need to explicitly clear the shadow depth buffer.
 pub fn add_depth_clear_to_graph(graph: &mut RenderGraph<'_>, depth: RenderTargetHandle, depth_clear: f32) {
@@ -18,3 +31,99 @@ pub fn add_depth_clear_to_graph(graph: &mut RenderGraph<'_>, depth: RenderTarget
 
     builder.build(|_| ())
 }
+
+/// Clears the given color target. Only clears if this is the first use of the target this frame.
+pub fn add_clear_to_graph(graph: &mut RenderGraph<'_>, color: RenderTargetHandle, clear: Vec4) {
+    let mut builder = graph.add_node("Clear");
+
+    let _rpass_handle = builder.add_renderpass(
+        RenderPassTargets { targets: vec![RenderPassTarget { color, resolve: None, clear }], depth_stencil: None },
+        NodeResourceUsage::Output,
+    );
+
+    builder.build(|_| ())
+}
+
+/// Marks the contents of the given target as used, so following render passes load it instead of
+/// clearing it. Needed for imported targets which keep their contents between frames.
+pub fn add_preserve_to_graph(graph: &mut RenderGraph<'_>, target: RenderTargetHandle) {
+    let mut builder = graph.add_node("Preserve");
+
+    let _target_handle = builder.add_render_target(target, NodeResourceUsage::InputOutput);
+
+    builder.build(|_| ())
+}
+
+/// Clears the viewport of a depth target to 0 by drawing over it, as render passes can only clear
+/// whole targets.
+pub struct DepthViewportClear {
+    pipeline: RenderPipeline,
+}
+
+impl DepthViewportClear {
+    pub fn new(device: &Device, spp: &ShaderPreProcessor, format: TextureFormat) -> Self {
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("depth viewport clear"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/clear_depth.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("depth viewport clear"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("depth viewport clear"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState::default(),
+            fragment: None,
+            multiview: None,
+        });
+
+        Self { pipeline }
+    }
+
+    /// Clear the viewport of the given depth target.
+    pub fn add_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>, depth: RenderTargetHandle) {
+        let mut builder = graph.add_node("Clear Depth Viewport");
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(RenderPassDepthTarget {
+                    target: depth,
+                    depth_clear: Some(0.0),
+                    stencil_clear: None,
+                }),
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,225 @@
+//! Clustered forward light culling.
+//!
+//! The view frustum of the viewport camera is split into a 3D grid of
+//! clusters: [`LightClusterRoutine::GRID_SIZE`] tiles across the screen, and
+//! depth slices spaced exponentially between the near plane and
+//! [`LightClusterRoutine::SLICE_FAR`]. Every frame a compute pass tests the
+//! bounding box of each cluster against the sphere of influence of every point
+//! light and stores the indices of the lights that touch it. The PBR shaders
+//! then find the cluster of the fragment they are shading and only iterate
+//! over its lights, instead of over every point light in the scene.
+//!
+//! The last depth slice extends to infinity, so lights past
+//! [`LightClusterRoutine::SLICE_FAR`] are still found, only culled less
+//! precisely. A cluster holds at most
+//! [`LightClusterRoutine::MAX_LIGHTS_PER_CLUSTER`] lights, any more are
+//! dropped.
+
+use std::{borrow::Cow, num::NonZeroU64};
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec3};
+use rend3::{
+    graph::RenderGraph,
+    managers::CameraState,
+    types::{CameraProjection, Handedness},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ComputePassDescriptor,
+    ComputePipeline, ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages,
+};
+
+/// Depth the first slice starts at when the projection doesn't have a near
+/// plane to start from.
+const FALLBACK_NEAR: f32 = 0.1;
+
+#[derive(ShaderType)]
+struct ClusterUniform {
+    view: Mat4,
+    inv_proj: Mat4,
+    grid: UVec3,
+    /// Sign of the view space z axis in the direction the camera looks.
+    depth_sign: f32,
+    /// Depth the first slice starts at.
+    slice_start: f32,
+    /// Depth the exponential slices start at.
+    near: f32,
+    /// Slices per doubling of the depth.
+    slice_scale: f32,
+}
+
+impl ClusterUniform {
+    fn new(camera: &CameraState) -> Self {
+        let (slice_start, near) = match camera.get_data().projection {
+            CameraProjection::Perspective { near, .. } => (0.0, near),
+            CameraProjection::Orthographic { size } => (-size.z * 0.5, FALLBACK_NEAR),
+            CameraProjection::Raw(_) => (0.0, FALLBACK_NEAR),
+        };
+        let far = LightClusterRoutine::SLICE_FAR.max(near * 2.0);
+
+        Self {
+            view: camera.view(),
+            inv_proj: camera.proj().inverse(),
+            grid: LightClusterRoutine::GRID_SIZE,
+            depth_sign: match camera.handedness() {
+                Handedness::Left => 1.0,
+                Handedness::Right => -1.0,
+            },
+            slice_start,
+            near,
+            slice_scale: (LightClusterRoutine::GRID_SIZE.z - 1) as f32 / (far / near).log2(),
+        }
+    }
+}
+
+/// Clustered light culling routine.
+///
+/// See module for documentation.
+pub struct LightClusterRoutine {
+    bgl: BindGroupLayout,
+    pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+    cluster_buffer: Buffer,
+}
+
+impl LightClusterRoutine {
+    /// Amount of clusters in each dimension. x and y split the screen, z
+    /// splits the depth.
+    pub const GRID_SIZE: UVec3 = UVec3::new(16, 9, 24);
+    /// Depth of the start of the last slice. Must be above the near plane.
+    pub const SLICE_FAR: f32 = 500.0;
+    /// Maximum amount of lights affecting a single cluster. Must match
+    /// MAX_LIGHTS_PER_CLUSTER in `structures.wgsl`.
+    pub const MAX_LIGHTS_PER_CLUSTER: u32 = 255;
+
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("LightClusterRoutine::new");
+
+        let device = &renderer.device;
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, false, ClusterUniform::SHADER_SIZE.get())
+            // Point lights
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                None,
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, 4)
+            .build(device, Some("light cluster bgl"));
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("light clustering"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/light_cluster.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("light clustering pass"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("light clustering pass"),
+            layout: Some(&pll),
+            module: &module,
+            entry_point: "cs_main",
+        });
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("light cluster uniform"),
+            size: ClusterUniform::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // wgpu zero initializes buffers, so every cluster starts out empty.
+        let cluster_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("light clusters"),
+            size: Self::cluster_buffer_size(),
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        Self { bgl, pipeline, uniform_buffer, cluster_buffer }
+    }
+
+    fn cluster_buffer_size() -> u64 {
+        let cluster_count = Self::GRID_SIZE.x * Self::GRID_SIZE.y * Self::GRID_SIZE.z;
+        // Every cluster stores its light count followed by the light indices.
+        cluster_count as u64 * (Self::MAX_LIGHTS_PER_CLUSTER as u64 + 1) * 4
+    }
+
+    /// Add the cluster uniform and light lists to the given bind group layout
+    /// builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append_buffer(
+            ShaderStages::FRAGMENT,
+            BufferBindingType::Uniform,
+            false,
+            ClusterUniform::SHADER_SIZE.get(),
+        )
+        .append(
+            ShaderStages::FRAGMENT,
+            BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: NonZeroU64::new(Self::cluster_buffer_size()),
+            },
+            None,
+        );
+    }
+
+    /// Add the cluster uniform and light lists to the given bind group builder.
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_buffer(&self.uniform_buffer).append_buffer(&self.cluster_buffer);
+    }
+
+    /// Bin the point lights into the clusters of the viewport camera. Must be
+    /// added before anything that reads the clusters.
+    pub fn add_to_graph<'node>(&'node self, graph: &mut RenderGraph<'node>) {
+        let mut builder = graph.add_node("Light Clustering");
+
+        // The clusters live outside of the graph.
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+
+            profiling::scope!("light clustering");
+
+            let uniform = ClusterUniform::new(&ctx.data_core.viewport_camera_state);
+            let mut data = UniformBuffer::new(Vec::with_capacity(ClusterUniform::SHADER_SIZE.get() as usize));
+            data.write(&uniform).unwrap();
+            ctx.renderer.queue.write_buffer(&self.uniform_buffer, 0, &data.into_inner());
+
+            let mut bgb = BindGroupBuilder::new();
+            bgb.append_buffer(&self.uniform_buffer);
+            ctx.data_core.point_light_manager.add_to_bg(&mut bgb);
+            bgb.append_buffer(&self.cluster_buffer);
+            let bg = bgb.build(&ctx.renderer.device, Some("light cluster bg"), &self.bgl);
+
+            let cluster_count = Self::GRID_SIZE.x * Self::GRID_SIZE.y * Self::GRID_SIZE.z;
+
+            let mut cpass = encoder
+                .begin_compute_pass(&ComputePassDescriptor { label: Some("Light Clustering"), timestamp_writes: None });
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups(div_round_up(cluster_count, Self::WORKGROUP_SIZE), 1, 1);
+        });
+    }
+}
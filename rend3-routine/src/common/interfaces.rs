@@ -3,19 +3,72 @@ use std::{marker::PhantomData, mem, num::NonZeroU64};
 use encase::ShaderType;
 use glam::{Mat4, Vec3};
 use rend3::{
-    managers::{DirectionalLightManager, PointLightManager},
+    managers::{DirectionalLightManager, PointLightManager, SpotLightManager},
     types::Material,
     util::bind_merge::BindGroupLayoutBuilder,
+    RendererProfile,
 };
+use serde::Serialize;
 use wgpu::{
     BindGroupLayout, BindingType, BufferBindingType, Device, ShaderStages, TextureSampleType, TextureViewDimension,
 };
 
 use crate::{
+    clustering::LightClusterRoutine,
     common::samplers::Samplers,
+    evsm::EvsmRoutine,
+    ibl::IblTextures,
+    reflection_probe::ReflectionProbeBindings,
+    ssao::SsaoRoutine,
     uniforms::{FrameUniforms, PerCameraUniform},
 };
 
+/// Optional parts of the forward uniforms and shaders.
+///
+/// Every feature takes bindings in the fragment stage, and all of them
+/// together need more than the [`CPU_REQUIRED_LIMITS`](rend3::CPU_REQUIRED_LIMITS)
+/// and [`GPU_REQUIRED_LIMITS`](rend3::GPU_REQUIRED_LIMITS) guarantee. Only
+/// enable more than the defaults if the limits of the device are high enough.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct ForwardFeatures {
+    /// Spot lights and their shadows. Without them, spot lights are ignored.
+    pub spot_lights: bool,
+    /// Point lights binned into clusters by [`LightClusterRoutine`]. Without
+    /// them, every fragment is lit by every point light.
+    pub light_clusters: bool,
+    /// Moments of the shadow maps using [`ShadowFilter::Evsm`](rend3::types::ShadowFilter::Evsm).
+    /// Without them, these shadow maps are filtered with PCF.
+    pub evsm: bool,
+    /// Screen space ambient occlusion. Without it,
+    /// [`BaseRenderGraphSettings::ssao`](crate::base::BaseRenderGraphSettings::ssao)
+    /// is ignored.
+    pub ssao: bool,
+    /// Reflection probes of
+    /// [`BaseRenderGraphRoutines::reflection_probes`](crate::base::BaseRenderGraphRoutines::reflection_probes).
+    pub reflection_probes: bool,
+    /// Lightmaps of [`PbrMaterial::lightmap`](crate::pbr::PbrMaterial::lightmap).
+    /// The textures are part of the material either way, on the CpuDriven
+    /// profile they take two more sampled textures than the required limits
+    /// guarantee.
+    pub lightmaps: bool,
+}
+
+impl ForwardFeatures {
+    /// Everything except the reflection probes on the GpuDriven profile,
+    /// nothing on the CpuDriven profile.
+    pub fn new(profile: RendererProfile) -> Self {
+        let gpu_driven = profile == RendererProfile::GpuDriven;
+        Self {
+            spot_lights: gpu_driven,
+            light_clusters: gpu_driven,
+            evsm: gpu_driven,
+            ssao: gpu_driven,
+            reflection_probes: false,
+            lightmaps: gpu_driven,
+        }
+    }
+}
+
 /// Interfaces which are used throughout the whole frame.
 ///
 /// Contains the samplers, per frame uniforms, and directional light
@@ -26,10 +79,13 @@ pub struct WholeFrameInterfaces {
     pub depth_uniform_bgl: BindGroupLayout,
     /// Includes everything.
     pub forward_uniform_bgl: BindGroupLayout,
+    /// Optional parts the forward uniforms include. Disabled features leave
+    /// their bindings empty, so the bindings after them don't move.
+    pub features: ForwardFeatures,
 }
 
 impl WholeFrameInterfaces {
-    pub fn new(device: &Device) -> Self {
+    pub fn new(device: &Device, features: ForwardFeatures) -> Self {
         profiling::scope!("ShaderInterfaces::new");
 
         let mut uniform_bglb = BindGroupLayoutBuilder::new();
@@ -48,6 +104,11 @@ impl WholeFrameInterfaces {
 
         DirectionalLightManager::add_to_bgl(&mut uniform_bglb);
         PointLightManager::add_to_bgl(&mut uniform_bglb);
+        if features.spot_lights {
+            SpotLightManager::add_to_bgl(&mut uniform_bglb);
+        } else {
+            uniform_bglb.skip(1);
+        }
 
         let shadow_uniform_bgl = uniform_bglb.build(device, Some("shadow uniform bgl"));
 
@@ -62,9 +123,29 @@ impl WholeFrameInterfaces {
             None,
         );
 
+        IblTextures::add_to_bgl(&mut uniform_bglb);
+        if features.light_clusters {
+            LightClusterRoutine::add_to_bgl(&mut uniform_bglb);
+        } else {
+            uniform_bglb.skip(2);
+        }
+        if features.evsm {
+            EvsmRoutine::add_to_bgl(&mut uniform_bglb);
+        } else {
+            uniform_bglb.skip(1);
+        }
+        if features.ssao {
+            SsaoRoutine::add_to_bgl(&mut uniform_bglb);
+        } else {
+            uniform_bglb.skip(1);
+        }
+        if features.reflection_probes {
+            ReflectionProbeBindings::add_to_bgl(&mut uniform_bglb);
+        }
+
         let forward_uniform_bgl = uniform_bglb.build(device, Some("forward uniform bgl"));
 
-        Self { depth_uniform_bgl: shadow_uniform_bgl, forward_uniform_bgl }
+        Self { depth_uniform_bgl: shadow_uniform_bgl, forward_uniform_bgl, features }
     }
 }
 
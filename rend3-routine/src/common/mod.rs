@@ -1,9 +1,8 @@
 //! Common utilities used throughout the crate.
 
-mod camera;
 mod interfaces;
 mod samplers;
 
-pub use camera::*;
 pub use interfaces::*;
+pub use rend3::managers::CameraSpecifier;
 pub use samplers::*;
@@ -0,0 +1,334 @@
+//! GPU-driven culling of objects into indirect draw calls.
+//!
+//! A culling pass takes every object of a material archetype that uses the
+//! requested material key and survived the CPU frustum culling of a camera
+//! and, optionally, tests it against a hierarchical-z buffer made by
+//! [`HiZRoutine`](crate::hi_z::HiZRoutine). Every surviving object gets a
+//! draw call appended to a compacted indirect buffer, which the forward
+//! routine draws with a single `multi_draw_indexed_indirect_count`. Objects
+//! which don't cast shadows are skipped when culling for a shadow camera.
+//!
+//! Only available on the GpuDriven profile, if the device supports
+//! `INDIRECT_FIRST_INSTANCE`. The order of the draw calls is not stable, so
+//! materials which need sorting must keep using the CPU path.
+
+use std::{borrow::Cow, marker::PhantomData, mem, sync::Arc};
+
+use encase::{ShaderSize, ShaderType, StorageBuffer};
+use rend3::{
+    graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderTargetHandle},
+    managers::ShaderObject,
+    types::{GraphDataHandle, Material},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+        typedefs::FastHashMap,
+    },
+    Renderer, RendererProfile, ShaderPreProcessor, ShaderVertexBufferConfig,
+};
+use serde::Serialize;
+use wgpu::{
+    util::DrawIndexedIndirectArgs, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor,
+    BufferUsages, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Features,
+    PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType,
+    TextureViewDimension,
+};
+
+use crate::{common::CameraSpecifier, uniforms::PerCameraUniform};
+
+#[derive(Serialize)]
+struct CullingPreprocessingArguments {
+    hi_z: bool,
+}
+
+/// The output of a culling pass.
+pub struct DrawCallSet {
+    /// Per-camera uniforms of the camera the objects were culled against.
+    pub per_camera_uniform: Arc<Buffer>,
+    /// Indirect draw calls, one per visible object.
+    pub draw_calls: Arc<Buffer>,
+    /// Single u32 holding the amount of valid draw calls in `draw_calls`.
+    pub draw_count: Arc<Buffer>,
+    /// Upper bound on the amount of draw calls in `draw_calls`.
+    pub max_draw_count: u32,
+}
+
+pub struct CullingArgs<'a, 'node> {
+    pub graph: &'a mut RenderGraph<'node>,
+
+    /// Also identifies the buffers of the pass, so must be unique per
+    /// camera and material key.
+    pub label: &'a str,
+
+    pub camera: CameraSpecifier,
+    /// Only objects whose material has this key are considered.
+    pub material_key: u64,
+    /// Hierarchical-z buffer to occlusion cull against. Must have been built
+    /// from the depth of the same camera.
+    pub hi_z: Option<RenderTargetHandle>,
+}
+
+/// Buffers of a single culling pass, kept between frames.
+struct CullingBuffers {
+    per_camera_uniform: Arc<Buffer>,
+    draw_calls: Arc<Buffer>,
+    draw_count: Arc<Buffer>,
+    candidates: Buffer,
+    /// Amount of candidates `candidates` and `draw_calls` have room for.
+    capacity: u32,
+}
+
+impl CullingBuffers {
+    fn new(device: &Device, capacity: u32) -> Self {
+        let per_camera_uniform = Arc::new(device.create_buffer(&BufferDescriptor {
+            label: Some("Per Camera Uniform"),
+            size: PerCameraUniform::SHADER_SIZE.get(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        let draw_calls = Arc::new(device.create_buffer(&BufferDescriptor {
+            label: Some("Culling Draw Calls"),
+            size: capacity as u64 * mem::size_of::<DrawIndexedIndirectArgs>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        }));
+        let draw_count = Arc::new(device.create_buffer(&BufferDescriptor {
+            label: Some("Culling Draw Count"),
+            size: 4,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        let candidates = device.create_buffer(&BufferDescriptor {
+            label: Some("Culling Candidates"),
+            size: capacity as u64 * 4,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { per_camera_uniform, draw_calls, draw_count, candidates, capacity }
+    }
+}
+
+const MIN_CANDIDATE_CAPACITY: u32 = 64;
+
+/// Amount of candidates to make room for, growing by powers of two so the
+/// buffers aren't recreated every time an object is added.
+fn candidate_capacity(current: u32, needed: u32) -> u32 {
+    if needed <= current {
+        current
+    } else {
+        needed.next_power_of_two().max(MIN_CANDIDATE_CAPACITY)
+    }
+}
+
+/// Compute pipelines for culling a single material archetype.
+pub struct GpuCuller<M> {
+    bgl: BindGroupLayout,
+    hi_z_bgl: BindGroupLayout,
+    pipeline: ComputePipeline,
+    occlusion_pipeline: ComputePipeline,
+    buffers: GraphDataHandle<FastHashMap<(String, u64), CullingBuffers>>,
+    _phantom: PhantomData<M>,
+}
+
+impl<M: Material> GpuCuller<M> {
+    const WORKGROUP_SIZE: u32 = 256;
+
+    /// If GPU culling can be used on the given renderer. Otherwise objects
+    /// are drawn from the CPU.
+    pub fn supported(renderer: &Renderer) -> bool {
+        renderer.profile == RendererProfile::GpuDriven && renderer.features.contains(Features::INDIRECT_FIRST_INSTANCE)
+    }
+
+    pub fn new(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("GpuCuller::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, ShaderObject::<M>::min_size().get()) // Objects
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, PerCameraUniform::min_size().get()) // Per-Camera uniforms
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, 4) // Candidates
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, mem::size_of::<DrawIndexedIndirectArgs>() as u64) // Draw calls
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, 4) // Draw count
+            .build(&renderer.device, Some("culling bgl"));
+
+        let hi_z_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("culling hi-z bgl"));
+
+        let pipeline = create_pipeline::<M>(&renderer.device, spp, &[&bgl], false);
+        let occlusion_pipeline = create_pipeline::<M>(&renderer.device, spp, &[&bgl, &hi_z_bgl], true);
+
+        // The buffers are created by the first pass using them.
+        let buffers = renderer.add_graph_data(FastHashMap::default());
+
+        Self { bgl, hi_z_bgl, pipeline, occlusion_pipeline, buffers, _phantom: PhantomData }
+    }
+
+    /// Add a culling pass to the graph, returning the handle the forward
+    /// routine can draw from.
+    pub fn add_culling_to_graph<'node>(&'node self, args: CullingArgs<'_, 'node>) -> DataHandle<DrawCallSet> {
+        let output = args.graph.add_data::<DrawCallSet>();
+
+        let mut builder = args.graph.add_node(args.label);
+
+        let output_handle = builder.add_data(output, NodeResourceUsage::Output);
+        let hi_z_handle = builder.add_optional_render_target(args.hi_z, NodeResourceUsage::Input);
+
+        let camera_specifier = args.camera;
+        let material_key = args.material_key;
+        let buffers_key = (args.label.to_owned(), material_key);
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+
+            let camera = match camera_specifier {
+                CameraSpecifier::Viewport => &ctx.data_core.viewport_camera_state,
+                CameraSpecifier::Shadow(idx) => &ctx.eval_output.shadows[idx as usize].camera,
+            };
+
+            // The frustum culling was already done on the CPU, so only the visible objects are candidates.
+            let candidates: Vec<u32> =
+                match ctx.data_core.object_manager.enumerated_visible_objects::<M>(camera_specifier) {
+                    Some(objects) => {
+                        profiling::scope!("Culling Candidate Collection");
+                        let archetype_view = ctx.data_core.material_manager.archetype_view::<M>();
+                        objects
+                            .filter(|(_, object)| {
+                                archetype_view.material(*object.material_handle).inner.key() == material_key
+                            })
+                            .map(|(handle, _)| handle.idx as u32)
+                            .collect()
+                    }
+                    None => Vec::new(),
+                };
+
+            let mut buffer_map = ctx.data_core.graph_storage.get_mut(&self.buffers);
+            let buffers = buffer_map
+                .entry(buffers_key.clone())
+                .or_insert_with(|| CullingBuffers::new(&ctx.renderer.device, MIN_CANDIDATE_CAPACITY));
+            let capacity = candidate_capacity(buffers.capacity, candidates.len() as u32);
+            if capacity != buffers.capacity {
+                *buffers = CullingBuffers::new(&ctx.renderer.device, capacity);
+            }
+
+            let per_camera_uniform_values = PerCameraUniform {
+                view: camera.view(),
+                view_proj: camera.view_proj(),
+                frustum: camera.world_frustum(),
+                object_count: candidates.len() as u32,
+            };
+            let mut data = StorageBuffer::new(Vec::with_capacity(PerCameraUniform::SHADER_SIZE.get() as usize));
+            data.write(&per_camera_uniform_values).unwrap();
+            ctx.renderer.queue.write_buffer(&buffers.per_camera_uniform, 0, &data.into_inner());
+
+            encoder.clear_buffer(&buffers.draw_count, 0, None);
+
+            // Avoid dispatching if there is nothing to cull.
+            if !candidates.is_empty() {
+                ctx.renderer.queue.write_buffer(&buffers.candidates, 0, bytemuck::cast_slice(&candidates));
+
+                let object_buffer = ctx.data_core.object_manager.buffer::<M>().unwrap();
+                let bg = BindGroupBuilder::new()
+                    .append_buffer(object_buffer)
+                    .append_buffer(&buffers.per_camera_uniform)
+                    .append_buffer(&buffers.candidates)
+                    .append_buffer(&buffers.draw_calls)
+                    .append_buffer(&buffers.draw_count)
+                    .build(&ctx.renderer.device, Some("Culling BG"), &self.bgl);
+
+                let hi_z_bg = hi_z_handle.map(|handle| {
+                    BindGroupBuilder::new().append_texture_view(ctx.graph_data.get_render_target(handle)).build(
+                        &ctx.renderer.device,
+                        Some("Culling Hi-Z BG"),
+                        &self.hi_z_bgl,
+                    )
+                });
+
+                let mut cpass = encoder
+                    .begin_compute_pass(&ComputePassDescriptor { label: Some("GPU Culling"), timestamp_writes: None });
+                match hi_z_bg {
+                    Some(ref hi_z_bg) => {
+                        cpass.set_pipeline(&self.occlusion_pipeline);
+                        cpass.set_bind_group(1, hi_z_bg, &[]);
+                    }
+                    None => cpass.set_pipeline(&self.pipeline),
+                }
+                cpass.set_bind_group(0, &bg, &[]);
+                cpass.dispatch_workgroups(div_round_up(candidates.len() as u32, Self::WORKGROUP_SIZE), 1, 1);
+            }
+
+            ctx.graph_data.set_data(
+                output_handle,
+                Some(DrawCallSet {
+                    per_camera_uniform: Arc::clone(&buffers.per_camera_uniform),
+                    draw_calls: Arc::clone(&buffers.draw_calls),
+                    draw_count: Arc::clone(&buffers.draw_count),
+                    max_draw_count: candidates.len() as u32,
+                }),
+            );
+        });
+
+        output
+    }
+}
+
+fn create_pipeline<M: Material>(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    bgls: &[&BindGroupLayout],
+    hi_z: bool,
+) -> ComputePipeline {
+    let label = if hi_z { "gpu occlusion culling" } else { "gpu culling" };
+
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader(
+                "rend3-routine/cull.wgsl",
+                &CullingPreprocessingArguments { hi_z },
+                Some(&ShaderVertexBufferConfig::from_material::<M>()),
+            )
+            .unwrap(),
+        )),
+    });
+
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: bgls,
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        module: &module,
+        entry_point: "cs_main",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::candidate_capacity;
+
+    #[test]
+    fn capacity_kept_when_large_enough() {
+        assert_eq!(candidate_capacity(64, 0), 64);
+        assert_eq!(candidate_capacity(64, 64), 64);
+        assert_eq!(candidate_capacity(256, 100), 256);
+    }
+
+    #[test]
+    fn capacity_grows_to_power_of_two() {
+        assert_eq!(candidate_capacity(64, 65), 128);
+        assert_eq!(candidate_capacity(64, 1000), 1024);
+        assert_eq!(candidate_capacity(0, 1), 64);
+    }
+}
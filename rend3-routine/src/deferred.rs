@@ -0,0 +1,400 @@
+//! Deferred shading render graph.
+//!
+//! An alternative to the forward [`BaseRenderGraph`] for scenes with many
+//! lights or a lot of overdraw. The opaque and cutout PBR objects first write
+//! their surface into a G-buffer, without any lighting. A single fullscreen
+//! pass then lights every pixel once, no matter how many objects were drawn on
+//! top of each other. Transparent objects can't be stored in the G-buffer, so
+//! they are still rendered by the forward
+//! [`PbrRoutine::blend_routine`](crate::pbr::PbrRoutine::blend_routine), or
+//! [`PbrRoutine::blend_weighted_routine`](crate::pbr::PbrRoutine::blend_weighted_routine)
+//! with order independent transparency, afterwards.
+//!
+//! The G-buffer consists of the depth and the targets of
+//! [`GBufferTargets::FORMATS`]:
+//!
+//! | Target | Format | Contents |
+//! |--------|--------|----------|
+//! | albedo | `Rgba8UnormSrgb` | RGB: albedo |
+//! | surface | `Rgba16Float` | XY: octahedral view space normal, Z: perceptual roughness, W: metallic |
+//! | material | `Rgba8Unorm` | R: ambient occlusion, G: reflectance, B: flags |
+//! | emissive | `Rgba16Float` | RGB: emissive light, including the baked lighting |
+//!
+//! The surface target has the layout of
+//! [`PbrRoutine::PREPASS_FORMAT`](crate::pbr::PbrRoutine::PREPASS_FORMAT), so
+//! the screen space effects use it directly instead of needing a prepass.
+//!
+//! The lighting pass shares its shading with the forward shaders, so both
+//! paths look the same with two exceptions: everything is rendered with a
+//! single sample, and screen space ambient occlusion doesn't darken the
+//! lighting baked into lightmaps.
+//!
+//! [`DeferredRenderGraph::add_to_graph`] takes the same inputs and settings as
+//! [`BaseRenderGraph::add_to_graph`], so apps can switch between the two.
+
+use std::{borrow::Cow, sync::Arc};
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        self, DataHandle, NodeResourceUsage, RenderGraph, RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, FragmentState, FrontFace, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    base::{BaseRenderGraph, BaseRenderGraphInputs, BaseRenderGraphIntermediateState, BaseRenderGraphSettings},
+    common::{CameraSpecifier, ForwardFeatures, WholeFrameInterfaces},
+    forward::{self, ForwardRoutineArgs},
+    pbr::{PbrRoutine, TransparencyType},
+};
+
+/// The color targets of the G-buffer. See the module for their layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GBufferTargets {
+    pub albedo: RenderTargetHandle,
+    pub surface: RenderTargetHandle,
+    pub material: RenderTargetHandle,
+    pub emissive: RenderTargetHandle,
+}
+
+impl GBufferTargets {
+    /// Formats of the albedo, surface, material and emissive targets, in the
+    /// order the G-buffer routines write them.
+    pub const FORMATS: [TextureFormat; 4] = [
+        TextureFormat::Rgba8UnormSrgb,
+        PbrRoutine::PREPASS_FORMAT,
+        TextureFormat::Rgba8Unorm,
+        TextureFormat::Rgba16Float,
+    ];
+
+    /// Create the G-buffer targets.
+    pub fn new(graph: &mut RenderGraph<'_>, resolution: UVec2) -> Self {
+        let mut add_target = |label: &str, format| {
+            graph.add_render_target(RenderTargetDescriptor {
+                label: Some(label.into()),
+                resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples: SampleCount::One,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            })
+        };
+
+        Self {
+            albedo: add_target("gbuffer albedo", Self::FORMATS[0]),
+            surface: add_target("gbuffer surface", Self::FORMATS[1]),
+            material: add_target("gbuffer material", Self::FORMATS[2]),
+            emissive: add_target("gbuffer emissive", Self::FORMATS[3]),
+        }
+    }
+
+    /// The targets in the order of [`Self::FORMATS`].
+    pub fn targets(&self) -> [RenderTargetHandle; 4] {
+        [self.albedo, self.surface, self.material, self.emissive]
+    }
+}
+
+#[derive(Serialize)]
+struct DeferredLightingArguments {
+    #[serde(flatten)]
+    features: ForwardFeatures,
+}
+
+/// Lights the G-buffer with a fullscreen pass.
+pub struct DeferredLightingRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl DeferredLightingRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, interfaces: &WholeFrameInterfaces) -> Self {
+        profiling::scope!("DeferredLightingRoutine::new");
+
+        let device = &renderer.device;
+
+        // The G-buffer is only ever loaded, so it doesn't need to be filterable.
+        let mut bglb = BindGroupLayoutBuilder::new();
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Depth,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            None,
+        );
+        for _ in GBufferTargets::FORMATS {
+            bglb.append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            );
+        }
+        let bgl = bglb.build(device, Some("deferred lighting bgl"));
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("deferred lighting"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader(
+                    "rend3-routine/deferred_lighting.wgsl",
+                    &DeferredLightingArguments { features: interfaces.features },
+                    None,
+                )
+                .unwrap(),
+            )),
+        });
+
+        let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("deferred lighting"),
+            bind_group_layouts: &[&interfaces.forward_uniform_bgl, &bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("deferred lighting"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::Rgba16Float,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { bgl, pipeline }
+    }
+
+    /// Lights the G-buffer into the first color target of `renderpass`.
+    ///
+    /// `depth` must be the single sampled depth the G-buffer was rendered
+    /// with. Pixels where nothing was rendered are left untouched.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        gbuffer: GBufferTargets,
+        depth: RenderTargetHandle,
+        renderpass: RenderPassTargets,
+        forward_uniform_bg: DataHandle<BindGroup>,
+    ) {
+        let mut builder = graph.add_node("Deferred Lighting");
+
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let gbuffer_handles =
+            gbuffer.targets().map(|target| builder.add_render_target(target, NodeResourceUsage::Input));
+
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        let forward_uniform_handle = builder.add_data(forward_uniform_bg, NodeResourceUsage::Input);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let forward_uniform_bg = ctx.graph_data.get_data(ctx.temps, forward_uniform_handle).unwrap();
+
+            let mut bgb = BindGroupBuilder::new();
+            bgb.append_texture_view(ctx.graph_data.get_render_target(depth_handle));
+            for handle in gbuffer_handles {
+                bgb.append_texture_view(ctx.graph_data.get_render_target(handle));
+            }
+            let bg = ctx.temps.add(bgb.build(&ctx.renderer.device, Some("deferred lighting bg"), &self.bgl));
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, forward_uniform_bg, &[]);
+            rpass.set_bind_group(1, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
+
+/// Deferred shading RenderGraph.
+///
+/// See module for documentation.
+pub struct DeferredRenderGraph {
+    /// Routines shared with the forward graph, like shadows, screen space
+    /// effects and post processing.
+    pub base: BaseRenderGraph,
+    pub lighting: DeferredLightingRoutine,
+}
+
+impl DeferredRenderGraph {
+    pub fn new(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor) -> Self {
+        Self::with_features(renderer, spp, ForwardFeatures::new(renderer.profile))
+    }
+
+    /// Like [`Self::new`], but lights the G-buffer with the given optional
+    /// features. See [`BaseRenderGraph::with_features`].
+    pub fn with_features(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor, features: ForwardFeatures) -> Self {
+        profiling::scope!("DeferredRenderGraph::new");
+
+        let base = BaseRenderGraph::with_features(renderer, spp, features);
+
+        let lighting = DeferredLightingRoutine::new(renderer, spp, &base.interfaces);
+
+        Self { base, lighting }
+    }
+
+    /// Add this to the rendergraph. Like [`BaseRenderGraph::add_to_graph`],
+    /// this is the function you should start customizing.
+    ///
+    /// The sample count of the output target is ignored, everything is
+    /// rendered with a single sample. Temporal anti-aliasing isn't supported
+    /// and [`BaseRenderGraphSettings::taa`] is ignored.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        mut inputs: BaseRenderGraphInputs<'_, 'node>,
+        mut settings: BaseRenderGraphSettings,
+    ) {
+        let base = &self.base;
+
+        // The G-buffer can't be multisampled.
+        inputs.target.samples = SampleCount::One;
+
+        // The G-buffer already uses all the color attachment bytes, there is no room for motion vectors.
+        settings.taa = None;
+
+        // The lighting shader can't read the ambient occlusion without the binding.
+        if !base.interfaces.features.ssao {
+            settings.ssao = None;
+        }
+
+        // Create the data and handles for the graph.
+        let mut state = BaseRenderGraphIntermediateState::new(graph, inputs, settings);
+        let gbuffer = GBufferTargets::new(state.graph, state.inputs.target.resolution);
+        // The screen space effects read the surface of the G-buffer instead of a prepass.
+        state.prepass_surface = gbuffer.surface;
+
+        // Keep the shadow maps which don't need to be rendered again from the previous frames.
+        state.preserve_shadow_atlas();
+
+        // Prepare all the uniforms that all shaders need access to.
+        state.create_frame_uniforms(base);
+
+        // Bin the point lights into clusters so shading only visits nearby lights.
+        state.light_clustering(base);
+
+        // Perform compute based skinning.
+        state.skinning(base);
+
+        // Render the shadows which changed to the shadow map.
+        state.pbr_shadow_rendering(base);
+
+        // Convert the shadow maps that use EVSM filtering into blurred moments.
+        state.shadow_moments(base);
+
+        // Render the surface of the opaque geometry.
+        state.pbr_gbuffer(gbuffer);
+
+        // Render the ambient occlusion of the G-buffer.
+        state.ambient_occlusion(base);
+
+        // Light every pixel of the G-buffer.
+        state.deferred_lighting(&self.lighting, gbuffer);
+
+        // Render the skybox.
+        state.skybox();
+
+        // Add the reflections of the opaque geometry and skybox.
+        state.screen_space_reflections(base);
+
+        // Render all transparent objects.
+        state.pbr_forward_rendering_transparent(base);
+
+        // Spread the light of bright areas over their surroundings.
+        state.bloom(base);
+
+        // Tonemap the HDR inner buffer to the output buffer.
+        state.tonemapping();
+
+        // Smooth the edges of the tonemapped image into the output buffer.
+        state.post_process_anti_aliasing();
+    }
+}
+
+impl<'a, 'node> BaseRenderGraphIntermediateState<'a, 'node> {
+    /// Render the surface of the opaque and cutout PBR materials into the
+    /// G-buffer.
+    ///
+    /// The surface target of the G-buffer doubles as the prepass surface of
+    /// the screen space effects.
+    pub fn pbr_gbuffer(&mut self, gbuffer: GBufferTargets) {
+        let renderpass = graph::RenderPassTargets {
+            targets: gbuffer
+                .targets()
+                .into_iter()
+                .map(|color| graph::RenderPassTarget { color, resolve: None, clear: Vec4::ZERO })
+                .collect(),
+            depth_stencil: Some(graph::RenderPassDepthTarget {
+                target: self.depth.single_sample_mipped.set_mips(0..1),
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+            }),
+        };
+
+        let routines = [
+            (&self.inputs.routines.pbr.opaque_gbuffer, TransparencyType::Opaque),
+            (&self.inputs.routines.pbr.cutout_gbuffer, TransparencyType::Cutout),
+        ];
+        for (routine, transparency) in routines {
+            let culling_source = self.pbr_culling("PBR G-Buffer Culling", CameraSpecifier::Viewport, transparency);
+            routine.add_forward_to_graph(ForwardRoutineArgs {
+                graph: self.graph,
+                label: "PBR G-Buffer",
+                camera: CameraSpecifier::Viewport,
+                binding_data: forward::ForwardRoutineBindingData {
+                    whole_frame_uniform_bg: self.shadow_uniform_bg,
+                    per_material_bgl: &self.inputs.routines.pbr.per_material,
+                    extra_bgs: None,
+                },
+                culling_source,
+                samples: SampleCount::One,
+                renderpass: renderpass.clone(),
+            });
+        }
+    }
+
+    /// Light the G-buffer into the color target of the primary renderpass,
+    /// clearing the pixels without geometry.
+    pub fn deferred_lighting(&mut self, lighting: &'node DeferredLightingRoutine, gbuffer: GBufferTargets) {
+        let color = &self.primary_renderpass.targets[0];
+        lighting.add_to_graph(
+            self.graph,
+            gbuffer,
+            self.depth.single_sample_mipped.set_mips(0..1),
+            graph::RenderPassTargets {
+                targets: vec![graph::RenderPassTarget { color: color.color, resolve: None, clear: color.clear }],
+                depth_stencil: None,
+            },
+            self.forward_uniform_bg,
+        );
+    }
+}
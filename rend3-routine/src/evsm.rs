@@ -0,0 +1,258 @@
+//! Exponential variance shadow maps.
+//!
+//! Shadow maps of lights using [`ShadowFilter::Evsm`] are converted into
+//! warped depth moments after they are rendered. The moments are blurred with a
+//! separable gaussian, so the shading only needs a single bilinear sample to
+//! get soft shadows. The moments are 32-bit floats to keep enough precision at
+//! high exponents, which samplers can't filter, so the shader filters them by
+//! hand. Conversion and the horizontal blur happen in one pass that
+//! reads the depth atlas, the vertical blur writes into the moments atlas,
+//! which has the same layout as the depth atlas.
+//!
+//! Every shadow map is blurred on its own and never reads past its own edges,
+//! so neighboring maps in the atlas can't bleed into each other.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{IVec2, UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor,
+        RenderTargetHandle, ViewportRect,
+    },
+    managers::ShadowDesc,
+    types::{SampleCount, ShadowFilter, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+    Device, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    TextureSampleType, TextureViewDimension, VertexState,
+};
+
+#[derive(Copy, Clone, ShaderType)]
+struct EvsmBlurUniform {
+    map_min: IVec2,
+    map_max: IVec2,
+}
+
+fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    path: &str,
+    sample_type: TextureSampleType,
+    label: &str,
+) -> (BindGroupLayout, RenderPipeline) {
+    let bgl = BindGroupLayoutBuilder::new()
+        .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, EvsmBlurUniform::SHADER_SIZE.get())
+        .append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture { sample_type, view_dimension: TextureViewDimension::D2, multisampled: false },
+            None,
+        )
+        .build(device, Some(label));
+
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_shader(path, &ShaderConfig::default(), None).unwrap())),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[&bgl],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: EvsmRoutine::MOMENTS_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    });
+
+    (bgl, pipeline)
+}
+
+/// Exponential variance shadow map routine.
+///
+/// See module for documentation.
+pub struct EvsmRoutine {
+    prefilter_bgl: BindGroupLayout,
+    prefilter_pipeline: RenderPipeline,
+    blur_bgl: BindGroupLayout,
+    blur_pipeline: RenderPipeline,
+}
+
+impl EvsmRoutine {
+    /// Format of the moments atlas.
+    pub const MOMENTS_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("EvsmRoutine::new");
+
+        let device = &renderer.device;
+        let (prefilter_bgl, prefilter_pipeline) = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/shadow/evsm_prefilter.wgsl",
+            TextureSampleType::Depth,
+            "evsm prefilter",
+        );
+        // The moments are only ever loaded, so they don't need to be filterable.
+        let (blur_bgl, blur_pipeline) = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/shadow/evsm_blur.wgsl",
+            TextureSampleType::Float { filterable: false },
+            "evsm blur",
+        );
+
+        Self { prefilter_bgl, prefilter_pipeline, blur_bgl, blur_pipeline }
+    }
+
+    /// Returns true if any of the shadow maps need moments.
+    pub fn any_evsm(shadows: &[ShadowDesc]) -> bool {
+        shadows.iter().any(|desc| desc.filter == ShadowFilter::Evsm)
+    }
+
+    /// Convert all EVSM shadow maps in the `shadows` depth atlas into blurred
+    /// moments in the `moments` atlas. Both must be of the given resolution.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        shadows: RenderTargetHandle,
+        moments: RenderTargetHandle,
+        descs: &[ShadowDesc],
+        resolution: UVec2,
+    ) {
+        if !Self::any_evsm(descs) {
+            return;
+        }
+
+        let temp = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("evsm blur temp".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: Self::MOMENTS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        for (shadow_index, desc) in descs.iter().enumerate() {
+            if desc.filter != ShadowFilter::Evsm {
+                continue;
+            }
+
+            let viewport = ViewportRect::new(desc.map.offset, UVec2::splat(desc.map.size));
+            let uniform = EvsmBlurUniform {
+                map_min: desc.map.offset.as_ivec2(),
+                map_max: (desc.map.offset + desc.map.size - 1).as_ivec2(),
+            };
+
+            self.add_pass_to_graph(
+                graph,
+                &format!("EVSM Prefilter S{shadow_index}"),
+                (&self.prefilter_bgl, &self.prefilter_pipeline),
+                shadows,
+                temp.set_viewport(viewport),
+                uniform,
+            );
+            self.add_pass_to_graph(
+                graph,
+                &format!("EVSM Blur S{shadow_index}"),
+                (&self.blur_bgl, &self.blur_pipeline),
+                temp,
+                moments.set_viewport(viewport),
+                uniform,
+            );
+        }
+    }
+
+    fn add_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        (bgl, pipeline): (&'node BindGroupLayout, &'node RenderPipeline),
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        uniform: EvsmBlurUniform,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("evsm blur uniform"),
+                size: EvsmBlurUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+            let uniform_buffer = ctx.temps.add(uniform_buffer);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new().append_buffer(uniform_buffer).append_texture_view(src_view).build(
+                    &ctx.renderer.device,
+                    Some("evsm blur bg"),
+                    bgl,
+                ),
+            );
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    /// Add the moments atlas to the given bind group layout builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            None,
+        );
+    }
+}
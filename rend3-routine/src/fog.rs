@@ -0,0 +1,70 @@
+//! Exponential height fog.
+//!
+//! The density of the fog falls off exponentially with height above
+//! [`FogSettings::height`], so valleys fill with thick fog while mountain tops
+//! stay clear. The PBR and skybox shaders integrate the density along the view
+//! ray in closed form and blend towards the fog color by the resulting
+//! opacity. Up is assumed to be +Y. The clear color is not fogged, so scenes
+//! which rely on fog to hide their far away geometry need a skybox.
+//!
+//! Directional lights brighten the fog when looking towards them, imitating
+//! the light the fog scatters towards the viewer. Shadows are not taken into
+//! account, so this doesn't produce light shafts.
+
+use encase::ShaderType;
+use glam::Vec3;
+
+/// Settings of the exponential height fog, see module for documentation.
+#[derive(Debug, Copy, Clone, PartialEq, ShaderType)]
+pub struct FogSettings {
+    /// Scene referred color of the fog.
+    pub color: Vec3,
+    /// Density of the fog at [`Self::height`], per world unit.
+    pub density: f32,
+    /// How fast the density falls off with height. The density drops by a
+    /// factor of e every `1 / height_falloff` world units. 0 gives a fog of
+    /// the same density everywhere.
+    pub height_falloff: f32,
+    /// Height at which the fog has [`Self::density`].
+    pub height: f32,
+    /// Distance from the camera at which the fog starts.
+    pub start_distance: f32,
+    /// Maximum opacity of the fog, from 0 to 1. Lower values keep the sky
+    /// visible through infinitely far fog.
+    pub max_opacity: f32,
+    /// Exponent of the lobe around every directional light in which it
+    /// brightens the fog. Larger values give a tighter lobe.
+    pub inscattering_exponent: f32,
+    /// Fraction of the color of the directional lights the fog scatters
+    /// towards the viewer. 0 disables the scattering.
+    pub inscattering_intensity: f32,
+}
+
+impl FogSettings {
+    /// Settings which don't add any fog.
+    pub const NONE: Self = Self {
+        color: Vec3::ZERO,
+        density: 0.0,
+        height_falloff: 0.0,
+        height: 0.0,
+        start_distance: 0.0,
+        max_opacity: 0.0,
+        inscattering_exponent: 0.0,
+        inscattering_intensity: 0.0,
+    };
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            color: Vec3::new(0.5, 0.6, 0.7),
+            density: 0.02,
+            height_falloff: 0.2,
+            height: 0.0,
+            start_distance: 0.0,
+            max_opacity: 1.0,
+            inscattering_exponent: 8.0,
+            inscattering_intensity: 0.1,
+        }
+    }
+}
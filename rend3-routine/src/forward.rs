@@ -16,13 +16,17 @@ use rend3::{
 };
 use serde::Serialize;
 use wgpu::{
-    BindGroup, BindGroupLayout, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState,
-    FragmentState, IndexFormat, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
-    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, StencilState, TextureFormat,
-    VertexState,
+    BindGroup, BindGroupLayout, BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState, ColorWrites,
+    CompareFunction, DepthBiasState, DepthStencilState, FragmentState, IndexFormat, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, StencilState, TextureFormat, VertexState,
 };
 
 use crate::common::{CameraSpecifier, PerMaterialArchetypeInterface, WholeFrameInterfaces};
+use crate::culling::DrawCallSet;
+use crate::deferred::GBufferTargets;
+use crate::oit::OitRoutine;
+use crate::taa::TaaRoutine;
 use crate::uniforms::PerCameraUniform;
 
 #[derive(Serialize)]
@@ -33,8 +37,31 @@ struct ForwardPreprocessingArguments {
 
 #[derive(Debug)]
 pub enum RoutineType {
+    /// Only writes depth, for rendering shadow maps.
     Depth,
+    /// Only writes depth, for the depth prepass of the viewport. Unlike
+    /// [`Self::Depth`], back faces are culled like in the forward pass.
+    DepthPrepass,
+    /// Writes depth and a single `Rgba16Float` target, without binding the
+    /// shadow maps. Used to render the surface of the geometry for screen
+    /// space effects.
+    Prepass,
+    /// Writes depth and the targets of
+    /// [`GBufferTargets::FORMATS`](crate::deferred::GBufferTargets::FORMATS),
+    /// without binding the shadow maps. Used to render the surface of the
+    /// geometry for deferred lighting.
+    GBuffer,
     Forward,
+    /// Like [`Self::Forward`], but writes the motion of every pixel into a
+    /// second target of [`TaaRoutine::VELOCITY_FORMAT`](crate::taa::TaaRoutine::VELOCITY_FORMAT),
+    /// for temporal anti-aliasing.
+    ForwardVelocity,
+    /// Shades like [`Self::Forward`], but additively accumulates the
+    /// fragments into the targets of
+    /// [`OitRoutine::ACCUMULATION_FORMAT`](crate::oit::OitRoutine::ACCUMULATION_FORMAT)
+    /// and [`OitRoutine::REVEALAGE_FORMAT`](crate::oit::OitRoutine::REVEALAGE_FORMAT),
+    /// for order independent transparency.
+    ForwardWeightedBlended,
 }
 
 pub struct ShaderModulePair<'a> {
@@ -86,6 +113,10 @@ pub struct ForwardRoutineArgs<'a, 'node, M> {
     pub binding_data: ForwardRoutineBindingData<'node, M>,
 
     /// Source of culling information, determines which triangles are rendered this pass.
+    ///
+    /// If `None`, objects are culled and sorted on the CPU and drawn one by one.
+    pub culling_source: Option<DataHandle<DrawCallSet>>,
+
     pub samples: SampleCount,
     pub renderpass: RenderPassTargets,
 }
@@ -118,8 +149,12 @@ impl<M: Material> ForwardRoutine<M> {
 
         let mut bgls: ArrayVec<&BindGroupLayout, 8> = ArrayVec::new();
         bgls.push(match args.routine_type {
-            RoutineType::Depth => &args.interfaces.depth_uniform_bgl,
-            RoutineType::Forward => &args.interfaces.forward_uniform_bgl,
+            RoutineType::Depth | RoutineType::DepthPrepass | RoutineType::Prepass | RoutineType::GBuffer => {
+                &args.interfaces.depth_uniform_bgl
+            }
+            RoutineType::Forward | RoutineType::ForwardVelocity | RoutineType::ForwardWeightedBlended => {
+                &args.interfaces.forward_uniform_bgl
+            }
         });
         bgls.push(&args.per_material.bgl);
         if args.renderer.profile == RendererProfile::GpuDriven {
@@ -151,12 +186,55 @@ impl<M: Material> ForwardRoutine<M> {
 
         let whole_frame_uniform_handle =
             builder.add_data(args.binding_data.whole_frame_uniform_bg, NodeResourceUsage::Input);
+        let culling_handle = builder.add_optional_data(args.culling_source, NodeResourceUsage::Input);
 
         builder.build(move |mut ctx| {
             let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
             let whole_frame_uniform_bg = ctx.graph_data.get_data(ctx.temps, whole_frame_uniform_handle).unwrap();
 
-            let Some(objects) = ctx.data_core.object_manager.enumerated_objects::<M>() else {
+            let pipeline = match args.samples {
+                SampleCount::One => &self.pipeline_s1,
+                SampleCount::Four => &self.pipeline_s4,
+            };
+
+            if let Some(culling_handle) = culling_handle {
+                let draw_calls = ctx.graph_data.get_data(ctx.temps, culling_handle).unwrap();
+                if draw_calls.max_draw_count == 0 {
+                    return;
+                }
+
+                let per_material_bg = ctx.temps.add(
+                    BindGroupBuilder::new()
+                        .append_buffer(ctx.data_core.object_manager.buffer::<M>().unwrap())
+                        .append_buffer(&ctx.eval_output.mesh_buffer)
+                        .append_buffer(&draw_calls.per_camera_uniform)
+                        .append_buffer(ctx.data_core.material_manager.archetype_view::<M>().buffer())
+                        .build(&ctx.renderer.device, Some("Per-Material BG"), &args.binding_data.per_material_bgl.bgl),
+                );
+
+                rpass.set_index_buffer(ctx.eval_output.mesh_buffer.slice(..), IndexFormat::Uint32);
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, whole_frame_uniform_bg, &[]);
+                rpass.set_bind_group(1, per_material_bg, &[]);
+                if let ProfileData::Gpu(ref bg) = ctx.eval_output.d2_texture.bg {
+                    rpass.set_bind_group(2, bg, &[]);
+                }
+                if let Some(v) = args.binding_data.extra_bgs {
+                    for (idx, bg) in v.iter().enumerate() {
+                        rpass.set_bind_group((idx + 3) as _, bg, &[])
+                    }
+                }
+                rpass.multi_draw_indexed_indirect_count(
+                    &draw_calls.draw_calls,
+                    0,
+                    &draw_calls.draw_count,
+                    0,
+                    draw_calls.max_draw_count,
+                );
+                return;
+            }
+
+            let Some(objects) = ctx.data_core.object_manager.enumerated_visible_objects::<M>(args.camera) else {
                 return;
             };
 
@@ -196,10 +274,6 @@ impl<M: Material> ForwardRoutine<M> {
                     .build(&ctx.renderer.device, Some("Per-Material BG"), &args.binding_data.per_material_bgl.bgl),
             );
 
-            let pipeline = match args.samples {
-                SampleCount::One => &self.pipeline_s1,
-                SampleCount::Four => &self.pipeline_s4,
-            };
             rpass.set_index_buffer(ctx.eval_output.mesh_buffer.slice(..), IndexFormat::Uint32);
             rpass.set_pipeline(pipeline);
             rpass.set_bind_group(0, whole_frame_uniform_bg, &[]);
@@ -243,11 +317,10 @@ fn sort<'a, M, I>(
 where
     M: Material,
     I: IntoIterator<Item = (RawObjectHandle, &'a InternalObject<M>)>,
-    I::IntoIter: ExactSizeIterator,
 {
     let objects = objects.into_iter();
 
-    let mut sorted_objects = Vec::with_capacity(objects.len());
+    let mut sorted_objects = Vec::with_capacity(objects.size_hint().0);
     {
         profiling::scope!("Sort Key Creation");
         for (raw_handle, object) in objects {
@@ -259,11 +332,6 @@ where
                 continue;
             }
 
-            // Frustum culling
-            if !camera.world_frustum().contains_sphere(object.inner.bounding_sphere) {
-                continue;
-            }
-
             let bind_group_index = material.bind_group_index.map_gpu(|_| TextureBindGroupIndex::DUMMY).into_common();
 
             let mut distance_sq = camera.location().distance_squared(object.location.into());
@@ -337,13 +405,50 @@ fn build_forward_pipeline_inner<M: Material>(
     args: &ForwardRoutineCreateArgs<'_, M>,
     samples: SampleCount,
 ) -> RenderPipeline {
-    let mut render_targets: ArrayVec<_, 1> = ArrayVec::new();
-    if matches!(args.routine_type, RoutineType::Forward) {
-        render_targets.push(Some(ColorTargetState {
+    let mut render_targets: ArrayVec<_, 4> = ArrayVec::new();
+    match args.routine_type {
+        RoutineType::Depth | RoutineType::DepthPrepass => {}
+        RoutineType::Prepass | RoutineType::Forward => render_targets.push(Some(ColorTargetState {
             format: TextureFormat::Rgba16Float,
             blend: None,
             write_mask: ColorWrites::all(),
-        }));
+        })),
+        RoutineType::GBuffer => render_targets.extend(
+            GBufferTargets::FORMATS
+                .map(|format| Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::all() })),
+        ),
+        RoutineType::ForwardVelocity => render_targets.extend(
+            [TextureFormat::Rgba16Float, TaaRoutine::VELOCITY_FORMAT]
+                .map(|format| Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::all() })),
+        ),
+        RoutineType::ForwardWeightedBlended => {
+            const ADDITIVE: BlendComponent = BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            };
+            render_targets.extend([
+                // Sum of the weighted premultiplied colors.
+                Some(ColorTargetState {
+                    format: OitRoutine::ACCUMULATION_FORMAT,
+                    blend: Some(BlendState { color: ADDITIVE, alpha: ADDITIVE }),
+                    write_mask: ColorWrites::all(),
+                }),
+                // Product of one minus the alphas.
+                Some(ColorTargetState {
+                    format: OitRoutine::REVEALAGE_FORMAT,
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::Zero,
+                            dst_factor: BlendFactor::OneMinusSrc,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha: BlendComponent::REPLACE,
+                    }),
+                    write_mask: ColorWrites::all(),
+                }),
+            ]);
+        }
     }
     let mut desc = RenderPipelineDescriptor {
         label: Some(args.name),
@@ -355,7 +460,12 @@ fn build_forward_pipeline_inner<M: Material>(
             front_face: args.renderer.handedness.into(),
             cull_mode: Some(match args.routine_type {
                 RoutineType::Depth => wgpu::Face::Front,
-                RoutineType::Forward => wgpu::Face::Back,
+                RoutineType::DepthPrepass
+                | RoutineType::Prepass
+                | RoutineType::GBuffer
+                | RoutineType::Forward
+                | RoutineType::ForwardVelocity
+                | RoutineType::ForwardWeightedBlended => wgpu::Face::Back,
             }),
             unclipped_depth: false,
             polygon_mode: PolygonMode::Fill,
@@ -369,7 +479,12 @@ fn build_forward_pipeline_inner<M: Material>(
             bias: match args.routine_type {
                 // TODO: figure out what to put here
                 RoutineType::Depth => DepthBiasState { constant: 0, slope_scale: 0.0, clamp: 0.0 },
-                RoutineType::Forward => DepthBiasState::default(),
+                RoutineType::DepthPrepass
+                | RoutineType::Prepass
+                | RoutineType::GBuffer
+                | RoutineType::Forward
+                | RoutineType::ForwardVelocity
+                | RoutineType::ForwardWeightedBlended => DepthBiasState::default(),
             },
         }),
         multisample: MultisampleState { count: samples as u32, ..Default::default() },
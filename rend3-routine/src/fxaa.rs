@@ -0,0 +1,169 @@
+//! Fast approximate anti-aliasing.
+//!
+//! A port of the quality variant of FXAA 3.11. Every pixel which has enough
+//! local contrast is treated as part of an edge, the edge is followed in both
+//! directions to find its ends, and the pixel is blended with its neighbor
+//! across the edge based on how far it is from them. It is a single fullscreen
+//! pass, so it is the cheapest way to smooth edges, at the cost of blurring
+//! some texture detail.
+//!
+//! Edges are detected on the displayed image, so this must run after
+//! tonemapping. As with the [`TonemappingRoutine`], each instance only has a
+//! single pipeline for the output format it was created with.
+//!
+//! [`TonemappingRoutine`]: crate::tonemapping::TonemappingRoutine
+
+use std::borrow::Cow;
+
+use glam::Vec4;
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, FilterMode, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+#[derive(Serialize)]
+struct FxaaPreprocessingArguments {
+    #[serde(rename = "SRGB")]
+    srgb: bool,
+}
+
+/// FXAA post-process routine.
+///
+/// See module for documentation.
+pub struct FxaaRoutine {
+    bgl: BindGroupLayout,
+    sampler: Sampler,
+    pipeline: RenderPipeline,
+    output_format: TextureFormat,
+}
+
+impl FxaaRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, output_format: TextureFormat) -> Self {
+        profiling::scope!("FxaaRoutine::new");
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("fxaa bgl"));
+
+        // The searches along edges must not wrap around to the other side of the screen.
+        let sampler = renderer.device.create_sampler(&SamplerDescriptor {
+            label: Some("fxaa sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("fxaa"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader(
+                    "rend3-routine/fxaa.wgsl",
+                    &FxaaPreprocessingArguments { srgb: output_format.is_srgb() },
+                    None,
+                )
+                .unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("fxaa pass"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("fxaa pass"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: output_format,
+                    blend: None,
+                    write_mask: ColorWrites::all(),
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { bgl, sampler, pipeline, output_format }
+    }
+
+    /// Format of the targets this routine reads from and renders to.
+    pub fn output_format(&self) -> TextureFormat {
+        self.output_format
+    }
+
+    /// Anti-aliases `src` into `dst`. Both must be single sampled targets of
+    /// the output format with the same resolution.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("FXAA");
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            profiling::scope!("fxaa");
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new().append_sampler(&self.sampler).append_texture_view(src_view).build(
+                    &ctx.renderer.device,
+                    Some("fxaa bg"),
+                    &self.bgl,
+                ),
+            );
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,233 @@
+//! Builds a hierarchical-z buffer out of the mip chain of
+//! [`DepthTargets::single_sample_mipped`].
+//!
+//! With [`HiZReduction::Farthest`], every mip holds the farthest depth of the
+//! 2x2 region of the previous mip, so a single sample of the right mip tells you
+//! if anything could be visible behind the already rendered geometry. This is
+//! what occlusion culling needs. With [`HiZReduction::Closest`], every mip
+//! holds the closest depth instead, so a ray which is in front of a texel of a
+//! mip can skip over the whole region, which is what screen space ray tracing
+//! needs. If the depth was rendered multisampled, it is first resolved into mip
+//! 0 with the same reduction.
+//!
+//! This must run after whatever depth is supposed to be in the buffer has been
+//! rendered, usually a depth prepass.
+
+use std::borrow::Cow;
+
+use glam::UVec2;
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTargets, RenderTargetHandle},
+    types::SampleCount,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    BindGroupLayout, BindingType, CompareFunction, DepthStencilState, Device, FragmentState, FrontFace,
+    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureFormat,
+    TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::base::DepthTargets;
+
+#[derive(Serialize)]
+struct HiZPreprocessingArguments {
+    #[serde(rename = "SAMPLES")]
+    samples: u32,
+    #[serde(rename = "CLOSEST")]
+    closest: bool,
+}
+
+/// Which depth of the region it covers every texel of the hierarchical-z
+/// buffer keeps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HiZReduction {
+    /// The farthest depth. Used for occlusion culling.
+    Farthest,
+    /// The closest depth. Used for screen space ray tracing.
+    Closest,
+}
+
+fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    samples: SampleCount,
+    reduction: HiZReduction,
+) -> (BindGroupLayout, RenderPipeline) {
+    let label = match (samples, reduction) {
+        (SampleCount::One, HiZReduction::Farthest) => "hi-z farthest downscale",
+        (SampleCount::Four, HiZReduction::Farthest) => "hi-z farthest resolve",
+        (SampleCount::One, HiZReduction::Closest) => "hi-z closest downscale",
+        (SampleCount::Four, HiZReduction::Closest) => "hi-z closest resolve",
+    };
+
+    let bgl = BindGroupLayoutBuilder::new()
+        .append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Depth,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: samples != SampleCount::One,
+            },
+            None,
+        )
+        .build(device, Some(label));
+
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader(
+                "rend3-routine/hi_z.wgsl",
+                &HiZPreprocessingArguments { samples: samples as u32, closest: reduction == HiZReduction::Closest },
+                None,
+            )
+            .unwrap(),
+        )),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[&bgl],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState::default(),
+            bias: Default::default(),
+        }),
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState { module: &module, entry_point: "fs_main", targets: &[] }),
+        multiview: None,
+    });
+
+    (bgl, pipeline)
+}
+
+struct HiZPipelines {
+    downscale_bgl: BindGroupLayout,
+    downscale_pipeline: RenderPipeline,
+    resolve_bgl: BindGroupLayout,
+    resolve_pipeline: RenderPipeline,
+}
+
+impl HiZPipelines {
+    fn new(device: &Device, spp: &ShaderPreProcessor, reduction: HiZReduction) -> Self {
+        let (downscale_bgl, downscale_pipeline) = create_pipeline(device, spp, SampleCount::One, reduction);
+        let (resolve_bgl, resolve_pipeline) = create_pipeline(device, spp, SampleCount::Four, reduction);
+
+        Self { downscale_bgl, downscale_pipeline, resolve_bgl, resolve_pipeline }
+    }
+}
+
+/// Hierarchical-z buffer builder.
+///
+/// See module for documentation.
+pub struct HiZRoutine {
+    farthest: HiZPipelines,
+    closest: HiZPipelines,
+}
+
+impl HiZRoutine {
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("HiZRoutine::new");
+
+        let farthest = HiZPipelines::new(&renderer.device, spp, HiZReduction::Farthest);
+        let closest = HiZPipelines::new(&renderer.device, spp, HiZReduction::Closest);
+
+        Self { farthest, closest }
+    }
+
+    /// Amount of mips of the hierarchical-z buffer of the given resolution.
+    pub fn mip_count(resolution: UVec2) -> u8 {
+        resolution.max_element().max(1).ilog2() as u8 + 1
+    }
+
+    /// Fills all mips of `depth.single_sample_mipped` from the depth which
+    /// has already been rendered at the given resolution.
+    pub fn add_hi_z_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: DepthTargets,
+        resolution: UVec2,
+        reduction: HiZReduction,
+    ) {
+        let pipelines = match reduction {
+            HiZReduction::Farthest => &self.farthest,
+            HiZReduction::Closest => &self.closest,
+        };
+
+        if let Some(multi_sample) = depth.multi_sample {
+            self.add_pass_to_graph(
+                graph,
+                "HiZ Resolve",
+                (&pipelines.resolve_bgl, &pipelines.resolve_pipeline),
+                multi_sample,
+                depth.single_sample_mipped.set_mips(0..1),
+            );
+        }
+
+        for mip in 1..Self::mip_count(resolution) {
+            self.add_pass_to_graph(
+                graph,
+                &format!("HiZ Mip {mip}"),
+                (&pipelines.downscale_bgl, &pipelines.downscale_pipeline),
+                depth.single_sample_mipped.set_mips(mip - 1..mip),
+                depth.single_sample_mipped.set_mips(mip..mip + 1),
+            );
+        }
+    }
+
+    fn add_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        (bgl, pipeline): (&'node BindGroupLayout, &'node RenderPipeline),
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![],
+                depth_stencil: Some(RenderPassDepthTarget { target: dst, depth_clear: Some(0.0), stencil_clear: None }),
+            },
+            NodeResourceUsage::Output,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(src_view).build(
+                &ctx.renderer.device,
+                Some("hi-z source bg"),
+                bgl,
+            ));
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,401 @@
+//! Image based lighting precomputed from an environment cube map.
+//!
+//! When an environment is set, [`IblRoutine::evaluate`] bakes it into two
+//! smaller cube maps:
+//!
+//! - A diffuse irradiance cube, holding the cosine weighted integral of the
+//!   environment over the hemisphere around every direction.
+//! - A specular cube, where every mip is the environment prefiltered with the
+//!   GGX lobe of an increasing roughness, from mirror-like in mip 0 to fully
+//!   rough in the last mip.
+//!
+//! Together with a split-sum BRDF lookup table, which only depends on the view
+//! angle and roughness and is baked once when the routine is created, this
+//! lets the PBR shaders light every surface with the whole environment in a
+//! handful of texture reads.
+//!
+//! Baking is done right away on the GPU when evaluating, so avoid changing the
+//! environment every frame.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use rend3::{
+    types::TextureCubeHandle,
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+    },
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, Extent3d,
+    FilterMode, PipelineLayoutDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StorageTextureAccess, Texture, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+const IBL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const IRRADIANCE_SAMPLE_COUNT: u32 = 512;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_COUNT: u32 = 6;
+const PREFILTERED_SAMPLE_COUNT: u32 = 512;
+const BRDF_LUT_SIZE: u32 = 128;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(ShaderType)]
+struct BakeParameters {
+    perceptual_roughness: f32,
+    sample_count: u32,
+}
+
+/// Textures the PBR shaders read image based lighting from.
+pub struct IblTextures {
+    /// Cube of the diffuse irradiance, divided by pi.
+    pub irradiance: TextureView,
+    /// Cube of the environment prefiltered for increasing roughness in
+    /// every mip.
+    pub prefiltered: TextureView,
+    /// Split-sum scale and bias of f0 in the red and green channels.
+    pub brdf_lut: TextureView,
+}
+
+impl IblTextures {
+    /// Black textures, which add no light.
+    pub fn empty(device: &Device) -> Self {
+        // wgpu zero initializes textures, so these are all black.
+        let irradiance = create_cube(device, "empty ibl irradiance", 1, 1, TextureUsages::TEXTURE_BINDING);
+        let prefiltered = create_cube(device, "empty ibl prefiltered", 1, 1, TextureUsages::TEXTURE_BINDING);
+        let brdf_lut = create_brdf_lut_texture(device, 1, TextureUsages::TEXTURE_BINDING);
+
+        Self {
+            irradiance: create_cube_view(&irradiance),
+            prefiltered: create_cube_view(&prefiltered),
+            brdf_lut: brdf_lut.create_view(&TextureViewDescriptor::default()),
+        }
+    }
+
+    /// Add the textures to the given bind group layout builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        let cube = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::Cube,
+            multisampled: false,
+        };
+        let d2 = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+
+        bglb.append(ShaderStages::FRAGMENT, cube, None).append(ShaderStages::FRAGMENT, cube, None).append(
+            ShaderStages::FRAGMENT,
+            d2,
+            None,
+        );
+    }
+
+    /// Add the textures to the given bind group builder.
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_texture_view(&self.irradiance)
+            .append_texture_view(&self.prefiltered)
+            .append_texture_view(&self.brdf_lut);
+    }
+}
+
+struct StoredEnvironment {
+    handle: Option<TextureCubeHandle>,
+    baked: bool,
+}
+
+/// Image based lighting routine.
+///
+/// See module for documentation.
+pub struct IblRoutine {
+    bake_bgl: BindGroupLayout,
+    irradiance_pipeline: ComputePipeline,
+    prefilter_pipeline: ComputePipeline,
+    sampler: Sampler,
+    brdf_lut: Texture,
+    textures: IblTextures,
+    environment: StoredEnvironment,
+}
+
+impl IblRoutine {
+    /// Create the routine, baking the BRDF lookup table.
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("IblRoutine::new");
+
+        let device = &renderer.device;
+
+        let bake_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(ShaderStages::COMPUTE, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: IBL_FORMAT,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                None,
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Uniform, false, BakeParameters::SHADER_SIZE.get())
+            .build(device, Some("ibl bake bgl"));
+
+        let lut_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: IBL_FORMAT,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                None,
+            )
+            .build(device, Some("ibl brdf lut bgl"));
+
+        let irradiance_pipeline =
+            create_pipeline(device, spp, "rend3-routine/ibl/bake.wgsl", "cs_irradiance", &bake_bgl, "ibl irradiance");
+        let prefilter_pipeline =
+            create_pipeline(device, spp, "rend3-routine/ibl/bake.wgsl", "cs_prefilter", &bake_bgl, "ibl prefilter");
+        let lut_pipeline =
+            create_pipeline(device, spp, "rend3-routine/ibl/brdf_lut.wgsl", "cs_main", &lut_bgl, "ibl brdf lut");
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("ibl bake sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let brdf_lut = create_brdf_lut_texture(
+            device,
+            BRDF_LUT_SIZE,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+        );
+        let lut_view = brdf_lut.create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("ibl brdf lut") });
+        {
+            let bg =
+                BindGroupBuilder::new().append_texture_view(&lut_view).build(device, Some("ibl brdf lut bg"), &lut_bgl);
+
+            let mut cpass = encoder
+                .begin_compute_pass(&ComputePassDescriptor { label: Some("IBL BRDF LUT"), timestamp_writes: None });
+            cpass.set_pipeline(&lut_pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            let workgroups = div_round_up(BRDF_LUT_SIZE, WORKGROUP_SIZE);
+            cpass.dispatch_workgroups(workgroups, workgroups, 1);
+        }
+        renderer.queue.submit([encoder.finish()]);
+
+        let empty = IblTextures::empty(device);
+        let textures = IblTextures { brdf_lut: lut_view, ..empty };
+
+        Self {
+            bake_bgl,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            sampler,
+            brdf_lut,
+            textures,
+            environment: StoredEnvironment { handle: None, baked: false },
+        }
+    }
+
+    /// Set the environment to light everything with. Bad things will happen
+    /// if this isn't a cube texture.
+    ///
+    /// The environment is baked on the next call to [`Self::evaluate`].
+    pub fn set_environment(&mut self, environment: Option<TextureCubeHandle>) {
+        self.environment = StoredEnvironment { handle: environment, baked: false };
+    }
+
+    /// Bake the environment if it changed since the last call.
+    pub fn evaluate(&mut self, renderer: &Renderer) {
+        if self.environment.baked {
+            return;
+        }
+        self.environment.baked = true;
+
+        profiling::scope!("Bake IBL");
+
+        let device = &renderer.device;
+
+        let Some(ref handle) = self.environment.handle else {
+            self.textures = IblTextures {
+                brdf_lut: self.brdf_lut.create_view(&TextureViewDescriptor::default()),
+                ..IblTextures::empty(device)
+            };
+            return;
+        };
+
+        let usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+        let irradiance = create_cube(device, "ibl irradiance", IRRADIANCE_SIZE, 1, usage);
+        let prefiltered = create_cube(device, "ibl prefiltered", PREFILTERED_SIZE, PREFILTERED_MIP_COUNT, usage);
+
+        let data_core = renderer.data_core.lock();
+        let environment_view = data_core.d2c_texture_manager.get_view(handle.get_raw());
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("ibl bake") });
+
+        self.bake(
+            device,
+            &mut encoder,
+            environment_view,
+            &irradiance,
+            0,
+            &self.irradiance_pipeline,
+            BakeParameters { perceptual_roughness: 1.0, sample_count: IRRADIANCE_SAMPLE_COUNT },
+        );
+        for mip in 0..PREFILTERED_MIP_COUNT {
+            self.bake(
+                device,
+                &mut encoder,
+                environment_view,
+                &prefiltered,
+                mip,
+                &self.prefilter_pipeline,
+                BakeParameters {
+                    perceptual_roughness: mip as f32 / (PREFILTERED_MIP_COUNT - 1) as f32,
+                    sample_count: PREFILTERED_SAMPLE_COUNT,
+                },
+            );
+        }
+
+        drop(data_core);
+
+        renderer.queue.submit([encoder.finish()]);
+
+        self.textures = IblTextures {
+            irradiance: create_cube_view(&irradiance),
+            prefiltered: create_cube_view(&prefiltered),
+            brdf_lut: self.brdf_lut.create_view(&TextureViewDescriptor::default()),
+        };
+    }
+
+    /// The textures of the currently baked environment.
+    pub fn textures(&self) -> &IblTextures {
+        &self.textures
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn bake(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        environment: &TextureView,
+        output: &Texture,
+        mip: u32,
+        pipeline: &ComputePipeline,
+        params: BakeParameters,
+    ) {
+        let output_view = output.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("ibl bake parameters"),
+            size: BakeParameters::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        let mut mapping = params_buffer.slice(..).get_mapped_range_mut();
+        UniformBuffer::new(&mut *mapping).write(&params).unwrap();
+        drop(mapping);
+        params_buffer.unmap();
+
+        let bg = BindGroupBuilder::new()
+            .append_texture_view(environment)
+            .append_sampler(&self.sampler)
+            .append_texture_view(&output_view)
+            .append_buffer(&params_buffer)
+            .build(device, Some("ibl bake bg"), &self.bake_bgl);
+
+        let size = (output.width() >> mip).max(1);
+        let workgroups = div_round_up(size, WORKGROUP_SIZE);
+
+        let mut cpass =
+            encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("IBL Bake"), timestamp_writes: None });
+        cpass.set_pipeline(pipeline);
+        cpass.set_bind_group(0, &bg, &[]);
+        cpass.dispatch_workgroups(workgroups, workgroups, 6);
+    }
+}
+
+pub(crate) fn create_pipeline(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    shader: &str,
+    entry_point: &str,
+    bgl: &BindGroupLayout,
+    label: &str,
+) -> ComputePipeline {
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(label),
+        source: ShaderSource::Wgsl(Cow::Owned(spp.render_shader(shader, &ShaderConfig::default(), None).unwrap())),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        module: &module,
+        entry_point,
+    })
+}
+
+fn create_cube(device: &Device, label: &str, size: u32, mip_level_count: u32, usage: TextureUsages) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d { width: size, height: size, depth_or_array_layers: 6 },
+        mip_level_count,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: IBL_FORMAT,
+        usage,
+        view_formats: &[],
+    })
+}
+
+fn create_cube_view(texture: &Texture) -> TextureView {
+    texture.create_view(&TextureViewDescriptor { dimension: Some(TextureViewDimension::Cube), ..Default::default() })
+}
+
+fn create_brdf_lut_texture(device: &Device, size: u32, usage: TextureUsages) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("ibl brdf lut"),
+        size: Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: IBL_FORMAT,
+        usage,
+        view_formats: &[],
+    })
+}
@@ -18,14 +18,30 @@
 //! abstraction is designed to be easily replaced and extended without needing
 //! too much user side boilerplate.
 
+pub mod atmosphere;
 pub mod base;
+pub mod bloom;
 pub mod clear;
+pub mod clustering;
 pub mod common;
+pub mod culling;
+pub mod deferred;
+pub mod evsm;
+pub mod fog;
 pub mod forward;
+pub mod fxaa;
+pub mod hi_z;
+pub mod ibl;
+pub mod oit;
 pub mod pbr;
+pub mod reflection_probe;
 mod shaders;
 pub mod skinning;
 pub mod skybox;
+pub mod smaa;
+pub mod ssao;
+pub mod ssr;
+pub mod taa;
 pub mod tonemapping;
 pub mod uniforms;
 
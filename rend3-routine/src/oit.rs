@@ -0,0 +1,236 @@
+//! Weighted blended order independent transparency.
+//!
+//! Sorting blended objects back to front only works between whole objects,
+//! so intersecting or self-overlapping transparent meshes, like foliage cards
+//! or glass assemblies, blend in the wrong order. This instead implements
+//! McGuire and Bavoil's weighted blended order independent transparency,
+//! which approximates the blended color with an average that doesn't depend
+//! on the order of the fragments.
+//!
+//! The blended objects are rendered with
+//! [`PbrRoutine::blend_weighted_routine`](crate::pbr::PbrRoutine::blend_weighted_routine)
+//! into the [`OitTargets`]: the accumulation target sums their premultiplied
+//! colors, weighted so that closer fragments count more, and the revealage
+//! target multiplies how much of the background shows through them. The
+//! composite pass then blends the average color over the opaque scene by the
+//! revealage.
+//!
+//! The result is exact for a single layer, and close for a few layers of
+//! similar opacity. Layers of very different opacity or brightness are
+//! averaged instead of properly occluding each other.
+
+use std::borrow::Cow;
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
+        RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, ColorTargetState,
+    ColorWrites, CompareFunction, DepthStencilState, FragmentState, FrontFace, MultisampleState,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, TextureSampleType, TextureViewDimension,
+    VertexState,
+};
+
+/// Blends the average color over the destination by the revealage in the
+/// source alpha.
+const COMPOSITE: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::OneMinusSrcAlpha,
+        dst_factor: BlendFactor::SrcAlpha,
+        operation: BlendOperation::Add,
+    },
+    // Keep the alpha of the image we're blending onto.
+    alpha: BlendComponent {
+        src_factor: BlendFactor::Zero,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+/// Targets the weighted blended routines render into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OitTargets {
+    pub accumulation: RenderTargetHandle,
+    pub accumulation_resolve: Option<RenderTargetHandle>,
+    pub revealage: RenderTargetHandle,
+    pub revealage_resolve: Option<RenderTargetHandle>,
+}
+
+impl OitTargets {
+    /// Create the targets. They are multisampled with single sampled resolve
+    /// targets if `samples` needs a resolve.
+    pub fn new(graph: &mut RenderGraph<'_>, resolution: UVec2, samples: SampleCount) -> Self {
+        let mut add_target = |label: &str, format, samples| {
+            graph.add_render_target(RenderTargetDescriptor {
+                label: Some(label.into()),
+                resolution,
+                depth: 1,
+                mip_levels: Some(1),
+                samples,
+                format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            })
+        };
+
+        let accumulation = add_target("oit accumulation", OitRoutine::ACCUMULATION_FORMAT, samples);
+        let revealage = add_target("oit revealage", OitRoutine::REVEALAGE_FORMAT, samples);
+        let (accumulation_resolve, revealage_resolve) = if samples.needs_resolve() {
+            (
+                Some(add_target("oit accumulation resolve", OitRoutine::ACCUMULATION_FORMAT, SampleCount::One)),
+                Some(add_target("oit revealage resolve", OitRoutine::REVEALAGE_FORMAT, SampleCount::One)),
+            )
+        } else {
+            (None, None)
+        };
+
+        Self { accumulation, accumulation_resolve, revealage, revealage_resolve }
+    }
+
+    /// Renderpass the weighted blended routines render into. Nothing is
+    /// accumulated and everything is revealed until they do.
+    ///
+    /// `depth_stencil` should hold the depth of the opaque geometry, so it
+    /// hides the transparent fragments behind it.
+    pub fn renderpass(&self, depth_stencil: Option<RenderPassDepthTarget>) -> RenderPassTargets {
+        RenderPassTargets {
+            targets: vec![
+                RenderPassTarget { color: self.accumulation, resolve: self.accumulation_resolve, clear: Vec4::ZERO },
+                RenderPassTarget { color: self.revealage, resolve: self.revealage_resolve, clear: Vec4::ONE },
+            ],
+            depth_stencil,
+        }
+    }
+}
+
+/// Weighted blended order independent transparency composite routine.
+///
+/// See module for documentation.
+pub struct OitRoutine {
+    bgl: BindGroupLayout,
+    pipeline_s1: RenderPipeline,
+    pipeline_s4: RenderPipeline,
+}
+
+impl OitRoutine {
+    /// Format of the sum of the weighted premultiplied colors in RGB and the
+    /// weighted alphas in A.
+    pub const ACCUMULATION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    /// Format of the product of one minus the alphas.
+    pub const REVEALAGE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("OitRoutine::new");
+
+        let texture = BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, texture, None)
+            .append(ShaderStages::FRAGMENT, texture, None)
+            .build(&renderer.device, Some("oit composite bgl"));
+
+        let module = renderer.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("oit composite"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/oit_composite.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let pll = renderer.device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("oit composite"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let inner = |samples: SampleCount| {
+            renderer.device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("oit composite"),
+                layout: Some(&pll),
+                vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Cw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                // The composite blends onto the primary renderpass, which has a depth target it must not touch.
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::Always,
+                    stencil: StencilState::default(),
+                    bias: Default::default(),
+                }),
+                multisample: MultisampleState { count: samples as u32, ..Default::default() },
+                fragment: Some(FragmentState {
+                    module: &module,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: TextureFormat::Rgba16Float,
+                        blend: Some(COMPOSITE),
+                        write_mask: ColorWrites::all(),
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self { bgl, pipeline_s1: inner(SampleCount::One), pipeline_s4: inner(SampleCount::Four) }
+    }
+
+    /// Blends the transparent objects accumulated into `targets` onto the
+    /// color of `renderpass`.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        targets: OitTargets,
+        renderpass: RenderPassTargets,
+        samples: SampleCount,
+    ) {
+        let mut builder = graph.add_node("OIT Composite");
+
+        let accumulation_handle = builder
+            .add_render_target(targets.accumulation_resolve.unwrap_or(targets.accumulation), NodeResourceUsage::Input);
+        let revealage_handle =
+            builder.add_render_target(targets.revealage_resolve.unwrap_or(targets.revealage), NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let accumulation_view = ctx.graph_data.get_render_target(accumulation_handle);
+            let revealage_view = ctx.graph_data.get_render_target(revealage_handle);
+
+            profiling::scope!("oit composite");
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(accumulation_view)
+                    .append_texture_view(revealage_view)
+                    .build(&ctx.renderer.device, Some("oit composite bg"), &self.bgl),
+            );
+
+            let pipeline = match samples {
+                SampleCount::One => &self.pipeline_s1,
+                SampleCount::Four => &self.pipeline_s4,
+            };
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
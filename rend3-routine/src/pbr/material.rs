@@ -4,7 +4,7 @@ use encase::ShaderType;
 use glam::{Mat3, Vec3, Vec4};
 use rend3::types::{
     Material, RawTexture2DHandle, Sorting, Texture2DHandle, VertexAttributeId, VERTEX_ATTRIBUTE_COLOR_0,
-    VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+    VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_PREVIOUS_POSITION, VERTEX_ATTRIBUTE_TANGENT,
     VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0, VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1,
 };
 
@@ -27,6 +27,8 @@ bitflags::bitflags! {
         const CC_BW_SPLIT =         0b0001_0000_0000_0000;
         const UNLIT =               0b0010_0000_0000_0000;
         const NEAREST =             0b0100_0000_0000_0000;
+        const LIGHTMAP_DIR =        0b1000_0000_0000_0000;
+        const LIGHTMAP_MONO_SH =    0b0001_0000_0000_0000_0000;
     }
 }
 
@@ -367,6 +369,66 @@ impl Default for ClearcoatTextures {
     }
 }
 
+/// How the direction texture of a [`LightmapTexture`] is encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightmapDirectionEncoding {
+    /// Dominant direction light arrives from in RGB, remapped from [-1, 1] to
+    /// [0, 1], with the directionality in A. This is what Unity's directional
+    /// lightmaps store.
+    Directional,
+    /// L1 band of luminance spherical harmonics in RGB, normalized by the
+    /// irradiance and remapped from [-1, 1] to [0, 1]. This is what Bakery's
+    /// MonoSH lightmaps store.
+    MonoSh,
+}
+
+/// Baked lighting of a material, sampled with the second set of texture
+/// coordinates transformed by `uv_transform1`.
+///
+/// Lightmaps replace the ambient term, which is otherwise taken from the
+/// image based lighting. Lights with `baked` set are already part of the
+/// lightmap, so they are skipped when shading lightmapped materials.
+/// Directions are in world space.
+///
+/// Lightmaps are ignored unless the graph was created with
+/// [`ForwardFeatures::lightmaps`](crate::common::ForwardFeatures::lightmaps)
+/// set, which is the default on the GpuDriven profile.
+#[derive(Debug, Default, Clone)]
+pub enum LightmapTexture {
+    /// No lightmap.
+    #[default]
+    None,
+    /// Texture with the irradiance in RGB. The surface normal is ignored.
+    Irradiance(Texture2DHandle),
+    /// Texture with the irradiance in RGB and a second texture with the
+    /// direction it arrives from, which lets normal maps shade baked lighting.
+    Directional { irradiance: Texture2DHandle, direction: Texture2DHandle, encoding: LightmapDirectionEncoding },
+}
+
+impl LightmapTexture {
+    pub fn to_irradiance_texture(&self) -> Option<&Texture2DHandle> {
+        match *self {
+            Self::Irradiance(ref texture) | Self::Directional { irradiance: ref texture, .. } => Some(texture),
+            Self::None => None,
+        }
+    }
+
+    pub fn to_direction_texture(&self) -> Option<&Texture2DHandle> {
+        match *self {
+            Self::Directional { ref direction, .. } => Some(direction),
+            Self::Irradiance(_) | Self::None => None,
+        }
+    }
+
+    pub fn to_flags(&self) -> MaterialFlags {
+        match self {
+            Self::Directional { encoding: LightmapDirectionEncoding::Directional, .. } => MaterialFlags::LIGHTMAP_DIR,
+            Self::Directional { encoding: LightmapDirectionEncoding::MonoSh, .. } => MaterialFlags::LIGHTMAP_MONO_SH,
+            Self::Irradiance(_) | Self::None => MaterialFlags::empty(),
+        }
+    }
+}
+
 /// How textures should be sampled.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SampleType {
@@ -468,6 +530,7 @@ pub struct PbrMaterial {
     pub anisotropy: MaterialComponent<f32>,
     pub uv_transform0: Mat3,
     pub uv_transform1: Mat3,
+    pub lightmap: LightmapTexture,
     // TODO: Make unlit a different shader entirely.
     pub unlit: bool,
     pub sample_type: SampleType,
@@ -475,9 +538,9 @@ pub struct PbrMaterial {
 
 impl Material for PbrMaterial {
     type DataType = ShaderMaterial;
-    type TextureArrayType = [Option<RawTexture2DHandle>; 10];
+    type TextureArrayType = [Option<RawTexture2DHandle>; 12];
     type RequiredAttributeArrayType = [&'static VertexAttributeId; 1];
-    type SupportedAttributeArrayType = [&'static VertexAttributeId; 6];
+    type SupportedAttributeArrayType = [&'static VertexAttributeId; 7];
 
     fn required_attributes() -> Self::RequiredAttributeArrayType {
         [&VERTEX_ATTRIBUTE_POSITION]
@@ -491,6 +554,7 @@ impl Material for PbrMaterial {
             &VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_0,
             &VERTEX_ATTRIBUTE_TEXTURE_COORDINATES_1,
             &VERTEX_ATTRIBUTE_COLOR_0,
+            &VERTEX_ATTRIBUTE_PREVIOUS_POSITION,
         ]
     }
 
@@ -514,6 +578,8 @@ impl Material for PbrMaterial {
             self.emissive.to_texture(),
             self.anisotropy.to_texture(),
             self.aomr_textures.to_ao_texture(),
+            self.lightmap.to_irradiance_texture(),
+            self.lightmap.to_direction_texture(),
         ]
         .map(|opt| opt.map(|r| r.get_raw()))
     }
@@ -568,6 +634,7 @@ impl ShaderMaterial {
                 flags |= material.normal.to_flags();
                 flags |= material.aomr_textures.to_flags();
                 flags |= material.clearcoat_textures.to_flags();
+                flags |= material.lightmap.to_flags();
                 flags.set(MaterialFlags::UNLIT, material.unlit);
                 flags.set(
                     MaterialFlags::NEAREST,
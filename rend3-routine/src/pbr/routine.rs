@@ -2,10 +2,11 @@ use std::{borrow::Cow, sync::Arc};
 
 use rend3::{Renderer, RendererDataCore, RendererProfile, ShaderPreProcessor, ShaderVertexBufferConfig};
 use serde::Serialize;
-use wgpu::{BlendState, ShaderModuleDescriptor, ShaderSource};
+use wgpu::{BlendState, CompareFunction, ShaderModuleDescriptor, ShaderSource, TextureFormat};
 
 use crate::{
-    common::{PerMaterialArchetypeInterface, WholeFrameInterfaces},
+    common::{ForwardFeatures, PerMaterialArchetypeInterface, WholeFrameInterfaces},
+    culling::GpuCuller,
     forward::{ForwardRoutine, ForwardRoutineCreateArgs, RoutineType, ShaderModulePair},
     pbr::{PbrMaterial, TransparencyType},
 };
@@ -14,19 +15,58 @@ use crate::{
 struct BlendModeWrapper {
     profile: RendererProfile,
     discard: bool,
+    #[serde(flatten)]
+    features: ForwardFeatures,
 }
 
 /// Render routine that renders the using PBR materials
 pub struct PbrRoutine {
     pub opaque_depth: ForwardRoutine<PbrMaterial>,
     pub cutout_depth: ForwardRoutine<PbrMaterial>,
+    pub opaque_depth_prepass: ForwardRoutine<PbrMaterial>,
+    pub cutout_depth_prepass: ForwardRoutine<PbrMaterial>,
+    pub opaque_prepass: ForwardRoutine<PbrMaterial>,
+    pub cutout_prepass: ForwardRoutine<PbrMaterial>,
+    pub opaque_gbuffer: ForwardRoutine<PbrMaterial>,
+    pub cutout_gbuffer: ForwardRoutine<PbrMaterial>,
     pub opaque_routine: ForwardRoutine<PbrMaterial>,
     pub cutout_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `opaque_routine`, but only shades the fragments which match the
+    /// depth of the depth prepass, without writing depth.
+    pub opaque_prepassed_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `cutout_routine`, but only shades the fragments which match the
+    /// depth of the depth prepass, without writing depth.
+    pub cutout_prepassed_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `opaque_routine`, but also writes the motion vectors used by
+    /// temporal anti-aliasing.
+    pub opaque_velocity_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `cutout_routine`, but also writes the motion vectors used by
+    /// temporal anti-aliasing.
+    pub cutout_velocity_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `opaque_prepassed_routine`, but also writes the motion vectors
+    /// used by temporal anti-aliasing.
+    pub opaque_prepassed_velocity_routine: ForwardRoutine<PbrMaterial>,
+    /// Same as `cutout_prepassed_routine`, but also writes the motion vectors
+    /// used by temporal anti-aliasing.
+    pub cutout_prepassed_velocity_routine: ForwardRoutine<PbrMaterial>,
     pub blend_routine: ForwardRoutine<PbrMaterial>,
+    /// Alternative to `blend_routine` which accumulates the blended objects
+    /// for weighted blended order independent transparency, so intersecting
+    /// objects don't need to be sorted. Must be composited with the
+    /// [`OitRoutine`](crate::oit::OitRoutine).
+    pub blend_weighted_routine: ForwardRoutine<PbrMaterial>,
     pub per_material: PerMaterialArchetypeInterface<PbrMaterial>,
+    /// Culls opaque and cutout objects on the GPU. Only present on the GpuDriven
+    /// profile, if the device supports `INDIRECT_FIRST_INSTANCE`.
+    pub culler: Option<GpuCuller<PbrMaterial>>,
 }
 
 impl PbrRoutine {
+    /// Format of the target the prepass routines render into. It holds the
+    /// octahedral encoded view space normal in xy, the perceptual roughness in
+    /// z and the metallic value in w.
+    pub const PREPASS_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
     pub fn new(
         renderer: &Arc<Renderer>,
         data_core: &mut RendererDataCore,
@@ -45,7 +85,7 @@ impl PbrRoutine {
             source: ShaderSource::Wgsl(Cow::Owned(
                 spp.render_shader(
                     "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: true },
+                    &BlendModeWrapper { profile: renderer.profile, discard: true, features: interfaces.features },
                     Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
                 )
                 .unwrap(),
@@ -57,7 +97,7 @@ impl PbrRoutine {
             source: ShaderSource::Wgsl(Cow::Owned(
                 spp.render_shader(
                     "rend3-routine/depth.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: false },
+                    &BlendModeWrapper { profile: renderer.profile, discard: false, features: interfaces.features },
                     Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
                 )
                 .unwrap(),
@@ -69,7 +109,7 @@ impl PbrRoutine {
             source: ShaderSource::Wgsl(Cow::Owned(
                 spp.render_shader(
                     "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: true },
+                    &BlendModeWrapper { profile: renderer.profile, discard: true, features: interfaces.features },
                     Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
                 )
                 .unwrap(),
@@ -81,16 +121,31 @@ impl PbrRoutine {
             source: ShaderSource::Wgsl(Cow::Owned(
                 spp.render_shader(
                     "rend3-routine/opaque.wgsl",
-                    &BlendModeWrapper { profile: renderer.profile, discard: false },
+                    &BlendModeWrapper { profile: renderer.profile, discard: false, features: interfaces.features },
                     Some(&ShaderVertexBufferConfig::from_material::<PbrMaterial>()),
                 )
                 .unwrap(),
             )),
         });
 
-        let mut inner = |routine_type, module, transparency| {
+        let mut inner = |routine_type, module, transparency, depth_prepassed: bool| {
+            // The prepass and G-buffer routines render the surface of the forward shaders instead of shading it.
+            let fs_entry = match routine_type {
+                RoutineType::Prepass => "fs_prepass",
+                RoutineType::GBuffer => "fs_gbuffer",
+                RoutineType::ForwardVelocity => "fs_velocity",
+                RoutineType::ForwardWeightedBlended => "fs_weighted_blended",
+                RoutineType::Depth | RoutineType::DepthPrepass | RoutineType::Forward => "fs_main",
+            };
+            let name = if depth_prepassed {
+                format!("pbr {routine_type:?} {transparency:?} prepassed")
+            } else {
+                format!("pbr {routine_type:?} {transparency:?}")
+            };
+            // The weighted blended targets already have their own blending.
+            let alpha_blended = matches!(routine_type, RoutineType::Forward);
             ForwardRoutine::new(ForwardRoutineCreateArgs {
-                name: &format!("pbr {routine_type:?} {transparency:?}"),
+                name: &name,
                 renderer,
                 data_core,
                 spp,
@@ -98,29 +153,61 @@ impl PbrRoutine {
                 per_material: &per_material,
                 material_key: transparency as u64,
                 routine_type,
-                shaders: ShaderModulePair {
-                    vs_entry: "vs_main",
-                    vs_module: module,
-                    fs_entry: "fs_main",
-                    fs_module: module,
-                },
+                shaders: ShaderModulePair { vs_entry: "vs_main", vs_module: module, fs_entry, fs_module: module },
                 extra_bgls: &[],
                 descriptor_callback: Some(&|desc, targets| {
+                    // The depth prepass already wrote the final depth, only the visible fragments need shading.
+                    if depth_prepassed {
+                        let depth_stencil = desc.depth_stencil.as_mut().unwrap();
+                        depth_stencil.depth_write_enabled = false;
+                        depth_stencil.depth_compare = CompareFunction::Equal;
+                    }
                     if transparency == TransparencyType::Blend {
                         desc.depth_stencil.as_mut().unwrap().depth_write_enabled = false;
-                        targets[0].as_mut().unwrap().blend = Some(BlendState::ALPHA_BLENDING)
+                        if alpha_blended {
+                            targets[0].as_mut().unwrap().blend = Some(BlendState::ALPHA_BLENDING)
+                        }
                     }
                 }),
             })
         };
 
         Self {
-            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque),
-            cutout_depth: inner(RoutineType::Depth, &pbr_depth_cutout, TransparencyType::Cutout),
-            opaque_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque),
-            cutout_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout),
-            blend_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend),
+            opaque_depth: inner(RoutineType::Depth, &pbr_depth, TransparencyType::Opaque, false),
+            cutout_depth: inner(RoutineType::Depth, &pbr_depth_cutout, TransparencyType::Cutout, false),
+            opaque_depth_prepass: inner(RoutineType::DepthPrepass, &pbr_depth, TransparencyType::Opaque, false),
+            cutout_depth_prepass: inner(RoutineType::DepthPrepass, &pbr_depth_cutout, TransparencyType::Cutout, false),
+            opaque_prepass: inner(RoutineType::Prepass, &pbr_forward, TransparencyType::Opaque, false),
+            cutout_prepass: inner(RoutineType::Prepass, &pbr_cutout, TransparencyType::Cutout, false),
+            opaque_gbuffer: inner(RoutineType::GBuffer, &pbr_forward, TransparencyType::Opaque, false),
+            cutout_gbuffer: inner(RoutineType::GBuffer, &pbr_cutout, TransparencyType::Cutout, false),
+            opaque_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque, false),
+            cutout_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout, false),
+            opaque_prepassed_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Opaque, true),
+            cutout_prepassed_routine: inner(RoutineType::Forward, &pbr_cutout, TransparencyType::Cutout, true),
+            opaque_velocity_routine: inner(RoutineType::ForwardVelocity, &pbr_forward, TransparencyType::Opaque, false),
+            cutout_velocity_routine: inner(RoutineType::ForwardVelocity, &pbr_cutout, TransparencyType::Cutout, false),
+            opaque_prepassed_velocity_routine: inner(
+                RoutineType::ForwardVelocity,
+                &pbr_forward,
+                TransparencyType::Opaque,
+                true,
+            ),
+            cutout_prepassed_velocity_routine: inner(
+                RoutineType::ForwardVelocity,
+                &pbr_cutout,
+                TransparencyType::Cutout,
+                true,
+            ),
+            blend_routine: inner(RoutineType::Forward, &pbr_forward, TransparencyType::Blend, false),
+            blend_weighted_routine: inner(
+                RoutineType::ForwardWeightedBlended,
+                &pbr_forward,
+                TransparencyType::Blend,
+                false,
+            ),
             per_material,
+            culler: GpuCuller::<PbrMaterial>::supported(renderer).then(|| GpuCuller::new(renderer, spp)),
         }
     }
 }
@@ -0,0 +1,511 @@
+//! Local reflection probes with parallax corrected environments.
+//!
+//! A [`ReflectionProbe`](rend3::types::ReflectionProbe) replaces the global environment of the
+//! [`IblRoutine`](crate::ibl::IblRoutine) in the reflections of everything
+//! inside its box. Reflections are projected onto the box before the
+//! environment is looked up, so the reflections of the walls of a room line up
+//! with the walls themselves. Within the blend distance of the sides of their
+//! boxes, probes fade into the overlapping probes and the global environment.
+//!
+//! The forward shaders only read the probes if the graph was created with
+//! [`ForwardFeatures::reflection_probes`](crate::common::ForwardFeatures::reflection_probes)
+//! set, as they need more bindings than the required limits guarantee.
+//!
+//! The environment of every probe is prefiltered for increasing roughness like
+//! the specular cube of the image based lighting, and stored as an octahedral
+//! map in a layer of a texture array. Filtering isn't continuous over the
+//! folded edges of the octahedral maps, which can show as faint seams in very
+//! rough reflections.
+//!
+//! # Environments
+//!
+//! The environment of a probe is either uploaded as a cube texture, or captured
+//! by rendering the scene from the position of the probe once for every face
+//! of the cube:
+//!
+//! 1. Call [`ReflectionProbeRoutine::begin_capture`].
+//! 2. For every face, set the camera to [`capture_camera`] with an aspect ratio
+//!    of 1, evaluate the instructions, and render the scene into the target
+//!    from [`ReflectionProbeRoutine::add_capture_target`], for example with the
+//!    [`BaseRenderGraph`](crate::base::BaseRenderGraph). The faces need to stay
+//!    linear, so tonemap them into [`ReflectionProbeRoutine::CAPTURE_FORMAT`]
+//!    with the default [`TonemappingSettings`](crate::tonemapping::TonemappingSettings).
+//! 3. Call [`ReflectionProbeRoutine::end_capture`].
+//!
+//! Probes without a texture are ignored until they are captured. Environments
+//! are prefiltered right away on the GPU when evaluating, so avoid changing
+//! them every frame.
+
+use encase::{ArrayLength, ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec3};
+use rend3::{
+    graph::{RenderGraph, RenderTargetHandle, ViewportRect},
+    types::{
+        Camera, CameraProjection, Handedness, RawReflectionProbeHandle, RawTextureCubeHandle, ReflectionProbeHandle,
+    },
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        buffer::WrappedPotBuffer,
+        math::div_round_up,
+    },
+    Renderer, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, Device, Extent3d, FilterMode, Sampler,
+    SamplerBindingType, SamplerDescriptor, ShaderStages, StorageTextureAccess, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor,
+    TextureViewDimension,
+};
+
+use crate::ibl::create_pipeline;
+
+const PROBE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+const PROBE_SIZE: u32 = 256;
+const PROBE_MIP_COUNT: u32 = 7;
+const PROBE_SAMPLE_COUNT: u32 = 512;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Near plane of the capture cameras. Geometry closer than this to the probe is not captured.
+const CAPTURE_NEAR_PLANE: f32 = 0.05;
+
+#[derive(ShaderType)]
+struct ProbeBakeParameters {
+    perceptual_roughness: f32,
+    sample_count: u32,
+    direction_scale: Vec3,
+}
+
+#[derive(Debug, Clone, ShaderType)]
+struct ShaderReflectionProbeBuffer {
+    count: ArrayLength,
+    #[size(runtime)]
+    array: Vec<ShaderReflectionProbe>,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ShaderReflectionProbe {
+    position: Vec3,
+    box_min: Vec3,
+    box_max: Vec3,
+    blend_distance: f32,
+}
+
+/// Camera which renders the given face of the environment of a probe at
+/// `position`, in the face order of cube textures.
+///
+/// Cube textures are laid out for left handed coordinates. On right handed
+/// renderers the captured environment is mirrored along the x axis, which is
+/// undone when it is prefiltered.
+pub fn capture_camera(handedness: Handedness, position: Vec3, face: u32) -> Camera {
+    let (forward, up) = match face {
+        0 => (Vec3::X, Vec3::Y),
+        1 => (Vec3::NEG_X, Vec3::Y),
+        2 => (Vec3::Y, Vec3::NEG_Z),
+        3 => (Vec3::NEG_Y, Vec3::Z),
+        4 => (Vec3::Z, Vec3::Y),
+        5 => (Vec3::NEG_Z, Vec3::Y),
+        _ => panic!("Cube face {face} out of range"),
+    };
+
+    let view = match handedness {
+        Handedness::Left => Mat4::look_at_lh(position, position + forward, up),
+        Handedness::Right => {
+            let mirror = Vec3::new(-1.0, 1.0, 1.0);
+            Mat4::look_at_rh(position, position + forward * mirror, up * mirror)
+        }
+    };
+
+    Camera { projection: CameraProjection::Perspective { vfov: 90.0, near: CAPTURE_NEAR_PLANE }, view }
+}
+
+/// Buffers and textures the PBR shaders read reflection probes from.
+pub struct ReflectionProbeBindings {
+    probes: WrappedPotBuffer<ShaderReflectionProbeBuffer>,
+    maps: TextureView,
+}
+
+impl ReflectionProbeBindings {
+    /// No probes, everything reflects the global environment.
+    pub fn empty(device: &Device) -> Self {
+        // wgpu zero initializes buffers, so this has a probe count of zero.
+        let probes = WrappedPotBuffer::new(device, BufferUsages::STORAGE, "reflection probe buffer");
+        let maps = create_maps(device, "empty reflection probe maps", 1, 1, 1, TextureUsages::TEXTURE_BINDING);
+
+        Self { probes, maps: create_maps_view(&maps) }
+    }
+
+    /// Add the probes to the given bind group layout builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: Some(ShaderReflectionProbeBuffer::min_size()),
+            },
+            None,
+        )
+        .append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            None,
+        );
+    }
+
+    /// Add the probes to the given bind group builder.
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_buffer(&self.probes).append_texture_view(&self.maps);
+    }
+}
+
+/// Where the environment of a baked layer came from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ProbeSource {
+    Texture(RawTextureCubeHandle),
+    Capture { probe: RawReflectionProbeHandle, generation: u64 },
+}
+
+struct Capture {
+    texture: Texture,
+    view: TextureView,
+    /// Incremented every time a capture is finished, zero if it never was.
+    generation: u64,
+}
+
+/// Reflection probe routine.
+///
+/// See module for documentation.
+pub struct ReflectionProbeRoutine {
+    bake_bgl: BindGroupLayout,
+    prefilter_pipeline: ComputePipeline,
+    sampler: Sampler,
+    maps: Texture,
+    baked: Vec<ProbeSource>,
+    captures: Vec<Option<Capture>>,
+    bindings: ReflectionProbeBindings,
+}
+
+impl ReflectionProbeRoutine {
+    /// Resolution of the faces of captured environments.
+    pub const CAPTURE_SIZE: u32 = 256;
+    /// Format of the faces of captured environments.
+    pub const CAPTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("ReflectionProbeRoutine::new");
+
+        let device = &renderer.device;
+
+        let bake_bgl = BindGroupLayoutBuilder::new()
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(ShaderStages::COMPUTE, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: PROBE_FORMAT,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                None,
+            )
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Uniform,
+                false,
+                ProbeBakeParameters::SHADER_SIZE.get(),
+            )
+            .build(device, Some("reflection probe bake bgl"));
+
+        let prefilter_pipeline = create_pipeline(
+            device,
+            spp,
+            "rend3-routine/ibl/probe.wgsl",
+            "cs_prefilter",
+            &bake_bgl,
+            "reflection probe prefilter",
+        );
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("reflection probe bake sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let maps = create_probe_maps(device, 1);
+        let bindings = ReflectionProbeBindings::empty(device);
+
+        Self { bake_bgl, prefilter_pipeline, sampler, maps, baked: Vec::new(), captures: Vec::new(), bindings }
+    }
+
+    /// Prepare capturing the environment of `probe`. The previous capture
+    /// stays in use until [`Self::end_capture`] is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probe has a texture. Probes which were added since the
+    /// last evaluation of the instructions can't be checked yet, their
+    /// captures are dropped by [`Self::evaluate`] instead.
+    pub fn begin_capture(&mut self, renderer: &Renderer, probe: &ReflectionProbeHandle) {
+        let idx = probe.get_raw().idx;
+
+        let has_texture = renderer
+            .data_core
+            .lock()
+            .reflection_probe_manager
+            .get(probe.get_raw())
+            .is_some_and(|probe| probe.texture.is_some());
+        assert!(!has_texture, "Reflection probe {idx} has a texture, so it can't be captured");
+
+        if idx >= self.captures.len() {
+            self.captures.resize_with(idx + 1, || None);
+        }
+
+        self.captures[idx].get_or_insert_with(|| {
+            let texture = renderer.device.create_texture(&TextureDescriptor {
+                label: Some("reflection probe capture"),
+                size: Extent3d { width: Self::CAPTURE_SIZE, height: Self::CAPTURE_SIZE, depth_or_array_layers: 6 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: Self::CAPTURE_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                ..Default::default()
+            });
+
+            Capture { texture, view, generation: 0 }
+        });
+    }
+
+    /// Import the given face of the capture of `probe` into the graph, to be
+    /// rendered with the [`capture_camera`] of the face.
+    ///
+    /// [`Self::begin_capture`] must have been called for the probe.
+    pub fn add_capture_target<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        probe: &ReflectionProbeHandle,
+        face: u32,
+    ) -> RenderTargetHandle {
+        let capture = self.captures[probe.get_raw().idx].as_ref().expect("Capture of the probe was never begun");
+
+        graph.add_imported_render_target(
+            &capture.texture,
+            face..face + 1,
+            0..1,
+            ViewportRect::from_size(UVec2::splat(Self::CAPTURE_SIZE)),
+        )
+    }
+
+    /// Finish capturing `probe`, its environment is prefiltered on the next
+    /// call to [`Self::evaluate`].
+    pub fn end_capture(&mut self, probe: &ReflectionProbeHandle) {
+        let capture = self.captures[probe.get_raw().idx].as_mut().expect("Capture of the probe was never begun");
+        capture.generation += 1;
+    }
+
+    /// Prefilter the environments of all probes which changed since the last
+    /// call and upload the probes.
+    pub fn evaluate(&mut self, renderer: &Renderer) {
+        profiling::scope!("ReflectionProbeRoutine::evaluate");
+
+        let device = &renderer.device;
+
+        let data_core = renderer.data_core.lock();
+        let manager = &data_core.reflection_probe_manager;
+
+        // Forget the captures of removed probes and of probes which have a texture.
+        for (idx, capture) in self.captures.iter_mut().enumerate() {
+            match manager.get(RawReflectionProbeHandle::new(idx)) {
+                Some(probe) if probe.texture.is_none() => {}
+                _ => *capture = None,
+            }
+        }
+
+        // Probes are stored densely, every probe in the buffer has its map in the layer of its index.
+        let mut sources = Vec::new();
+        let mut array = Vec::new();
+        for (handle, probe) in manager.probes() {
+            let source = match probe.texture {
+                Some(ref texture) => ProbeSource::Texture(texture.get_raw()),
+                None => match self.captures.get(handle.idx) {
+                    Some(Some(capture)) if capture.generation != 0 => {
+                        ProbeSource::Capture { probe: handle, generation: capture.generation }
+                    }
+                    // Not captured yet.
+                    _ => continue,
+                },
+            };
+
+            sources.push(source);
+            array.push(ShaderReflectionProbe {
+                position: probe.position,
+                box_min: probe.box_min.min(probe.box_max),
+                box_max: probe.box_min.max(probe.box_max),
+                blend_distance: probe.blend_distance,
+            });
+        }
+
+        if self.maps.depth_or_array_layers() < sources.len() as u32 {
+            let layers = sources.len().next_power_of_two() as u32;
+            self.maps = create_probe_maps(device, layers);
+            self.bindings.maps = create_maps_view(&self.maps);
+            self.baked.clear();
+        }
+
+        // Captures of right handed renderers are mirrored, see capture_camera.
+        let capture_scale = match renderer.handedness {
+            Handedness::Left => Vec3::ONE,
+            Handedness::Right => Vec3::new(-1.0, 1.0, 1.0),
+        };
+
+        let mut encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor { label: Some("reflection probe bake") });
+        let mut any_baked = false;
+
+        for (layer, source) in sources.iter().enumerate() {
+            if self.baked.get(layer) == Some(source) {
+                continue;
+            }
+            any_baked = true;
+
+            let (environment, direction_scale) = match *source {
+                ProbeSource::Texture(handle) => (data_core.d2c_texture_manager.get_view(handle), Vec3::ONE),
+                ProbeSource::Capture { probe, .. } => (&self.captures[probe.idx].as_ref().unwrap().view, capture_scale),
+            };
+
+            for mip in 0..PROBE_MIP_COUNT {
+                self.bake(
+                    device,
+                    &mut encoder,
+                    environment,
+                    layer as u32,
+                    mip,
+                    ProbeBakeParameters {
+                        perceptual_roughness: mip as f32 / (PROBE_MIP_COUNT - 1) as f32,
+                        sample_count: PROBE_SAMPLE_COUNT,
+                        direction_scale,
+                    },
+                );
+            }
+        }
+
+        drop(data_core);
+
+        if any_baked {
+            renderer.queue.submit([encoder.finish()]);
+        }
+        self.baked = sources;
+
+        self.bindings.probes.write_to_buffer(
+            device,
+            &renderer.queue,
+            &ShaderReflectionProbeBuffer { count: ArrayLength, array },
+        );
+    }
+
+    /// The probes as of the last call to [`Self::evaluate`].
+    pub fn bindings(&self) -> &ReflectionProbeBindings {
+        &self.bindings
+    }
+
+    fn bake(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        environment: &TextureView,
+        layer: u32,
+        mip: u32,
+        params: ProbeBakeParameters,
+    ) {
+        let output_view = self.maps.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+
+        let params_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("reflection probe bake parameters"),
+            size: ProbeBakeParameters::SHADER_SIZE.get(),
+            usage: BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        let mut mapping = params_buffer.slice(..).get_mapped_range_mut();
+        UniformBuffer::new(&mut *mapping).write(&params).unwrap();
+        drop(mapping);
+        params_buffer.unmap();
+
+        let bg = BindGroupBuilder::new()
+            .append_texture_view(environment)
+            .append_sampler(&self.sampler)
+            .append_texture_view(&output_view)
+            .append_buffer(&params_buffer)
+            .build(device, Some("reflection probe bake bg"), &self.bake_bgl);
+
+        let size = (PROBE_SIZE >> mip).max(1);
+        let workgroups = div_round_up(size, WORKGROUP_SIZE);
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Reflection Probe Bake"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(&self.prefilter_pipeline);
+        cpass.set_bind_group(0, &bg, &[]);
+        cpass.dispatch_workgroups(workgroups, workgroups, 1);
+    }
+}
+
+fn create_maps(
+    device: &Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+    layers: u32,
+    usage: TextureUsages,
+) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d { width: size, height: size, depth_or_array_layers: layers },
+        mip_level_count,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: PROBE_FORMAT,
+        usage,
+        view_formats: &[],
+    })
+}
+
+fn create_probe_maps(device: &Device, layers: u32) -> Texture {
+    let usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+    create_maps(device, "reflection probe maps", PROBE_SIZE, PROBE_MIP_COUNT, layers, usage)
+}
+
+fn create_maps_view(texture: &Texture) -> TextureView {
+    texture.create_view(&TextureViewDescriptor { dimension: Some(TextureViewDimension::D2Array), ..Default::default() })
+}
@@ -82,6 +82,17 @@ mod tests {
                         "position_attribute_offset": 0,
                         "SAMPLES": 1,
                     }),
+                    json!({
+                        "profile": Some(RendererProfile::GpuDriven),
+                        "position_attribute_offset": 0,
+                        "SAMPLES": 1,
+                        "spot_lights": true,
+                        "light_clusters": true,
+                        "evsm": true,
+                        "ssao": true,
+                        "reflection_probes": true,
+                        "lightmaps": true,
+                    }),
                 ]
             } else {
                 vec![json!({
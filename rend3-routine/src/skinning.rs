@@ -1,12 +1,12 @@
-use std::{borrow::Cow, mem};
+use std::{borrow::Cow, mem, ops::Range};
 
 use encase::{ShaderSize, ShaderType};
 use glam::Mat4;
 use rend3::{
     graph::{NodeExecutionContext, RenderGraph},
     types::{
-        VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL,
-        VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+        VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL,
+        VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_PREVIOUS_POSITION, VERTEX_ATTRIBUTE_TANGENT,
     },
     util::{
         bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
@@ -38,17 +38,60 @@ pub struct GpuSkinningInput {
     updated_normal_offset: u32,
     /// Byte offset into vertex buffer of tangent attribute of skinned mesh.
     updated_tangent_offset: u32,
+    /// Byte offset into vertex buffer of position attribute of skinned mesh in
+    /// the previous frame.
+    updated_previous_position_offset: u32,
 
     /// Index into the matrix buffer that joint_indices is relative to.
     joint_matrix_base_offset: u32,
+    /// Index into the matrix buffer that joint_indices is relative to for the
+    /// joint matrices of the previous frame.
+    previous_joint_matrix_base_offset: u32,
     /// Count of vertices in this mesh.
     vertex_count: u32,
 }
 
-/// The two buffers uploaded to the GPU during pre-skinning.
+/// The per-skeleton data for morph target blending, as uploaded to the GPU compute shader.
+#[derive(Copy, Clone, ShaderType)]
+pub struct GpuMorphInput {
+    /// Byte offset into vertex buffer of position attribute of unmorphed mesh.
+    base_position_offset: u32,
+    /// Byte offset into vertex buffer of normal attribute of unmorphed mesh.
+    base_normal_offset: u32,
+    /// Byte offset into vertex buffer of tangent attribute of unmorphed mesh.
+    base_tangent_offset: u32,
+    /// Byte offset into vertex buffer of position offsets of the first morph target.
+    position_offsets_offset: u32,
+    /// Byte offset into vertex buffer of normal offsets of the first morph target.
+    normal_offsets_offset: u32,
+    /// Byte offset into vertex buffer of tangent offsets of the first morph target.
+    tangent_offsets_offset: u32,
+    /// Byte offset into vertex buffer of position attribute of morphed mesh.
+    updated_position_offset: u32,
+    /// Byte offset into vertex buffer of normal attribute of morphed mesh.
+    updated_normal_offset: u32,
+    /// Byte offset into vertex buffer of tangent attribute of morphed mesh.
+    updated_tangent_offset: u32,
+
+    /// Index into the weight buffer of the weight of the first morph target.
+    weight_base_offset: u32,
+    /// Count of morph targets in this mesh.
+    target_count: u32,
+    /// Count of vertices in this mesh.
+    vertex_count: u32,
+}
+
+/// The buffers uploaded to the GPU during pre-skinning.
 pub struct PreSkinningBuffers {
     gpu_skinning_inputs: Buffer,
     joint_matrices: Buffer,
+    gpu_morph_inputs: Buffer,
+    morph_weights: Buffer,
+}
+
+/// Returns the start of the range for the given attribute as a u32, or u32::MAX if there is no such range.
+fn find_offset(ranges: &[(VertexAttributeId, Range<u64>)], attribute: &VertexAttributeId) -> u32 {
+    ranges.iter().find_map(|(id, range)| (*id == *attribute).then_some(range.start as u32)).unwrap_or(u32::MAX)
 }
 
 fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBuffers {
@@ -63,9 +106,25 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
         mapped_at_creation: true,
     });
 
+    // The joint matrices of the previous frame follow the current ones.
+    let global_joint_count = ctx.data_core.skeleton_manager.global_joint_count();
     let joint_matrices = ctx.renderer.device.create_buffer(&BufferDescriptor {
         label: Some("joint matrices"),
-        size: (ctx.data_core.skeleton_manager.global_joint_count() * mem::size_of::<Mat4>()) as u64,
+        size: (global_joint_count * 2 * mem::size_of::<Mat4>()) as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: true,
+    });
+
+    let gpu_morph_inputs = ctx.renderer.device.create_buffer(&BufferDescriptor {
+        label: Some("morph inputs"),
+        size: ctx.data_core.skeleton_manager.skeletons().len() as u64 * GpuMorphInput::SHADER_SIZE.get(),
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: true,
+    });
+
+    let morph_weights = ctx.renderer.device.create_buffer(&BufferDescriptor {
+        label: Some("morph weights"),
+        size: (ctx.data_core.skeleton_manager.global_morph_weight_count() * mem::size_of::<f32>()) as u64,
         usage: BufferUsages::STORAGE,
         mapped_at_creation: true,
     });
@@ -73,10 +132,14 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
     let mut skinning_input_range = gpu_skinning_inputs.slice(..).get_mapped_range_mut();
     let mut skinning_input_data = encase::DynamicStorageBuffer::new(&mut *skinning_input_range);
     let mut joint_matrices_data = joint_matrices.slice(..).get_mapped_range_mut();
+    let mut morph_input_range = gpu_morph_inputs.slice(..).get_mapped_range_mut();
+    let mut morph_input_data = encase::DynamicStorageBuffer::new(&mut *morph_input_range);
+    let mut morph_weights_data = morph_weights.slice(..).get_mapped_range_mut();
 
-    // Skeletons have a variable number of joints, so we need to keep track of
-    // the global index here.
+    // Skeletons have a variable number of joints and morph targets, so we need
+    // to keep track of the global indices here.
     let mut joint_matrix_idx = 0;
+    let mut morph_weight_idx = 0;
 
     // Iterate over the skeletons, fill the buffers
     for skeleton in ctx.data_core.skeleton_manager.skeletons() {
@@ -92,7 +155,9 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
                 updated_position_offset: u32::MAX,
                 updated_normal_offset: u32::MAX,
                 updated_tangent_offset: u32::MAX,
+                updated_previous_position_offset: u32::MAX,
                 joint_matrix_base_offset: joint_matrix_idx,
+                previous_joint_matrix_base_offset: global_joint_count as u32 + joint_matrix_idx,
                 vertex_count: skeleton.vertex_count,
             };
 
@@ -112,19 +177,63 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
                     a if *a == *VERTEX_ATTRIBUTE_POSITION => input.updated_position_offset = range.start as u32,
                     a if *a == *VERTEX_ATTRIBUTE_NORMAL => input.updated_normal_offset = range.start as u32,
                     a if *a == *VERTEX_ATTRIBUTE_TANGENT => input.updated_tangent_offset = range.start as u32,
+                    a if *a == *VERTEX_ATTRIBUTE_PREVIOUS_POSITION => {
+                        input.updated_previous_position_offset = range.start as u32
+                    }
                     a => unreachable!("Unknown skinning output attribute {a:?}"),
                 }
             }
 
+            // Morph targets are blended into the skeleton's copy of the mesh first, so skinning
+            // has to read from there instead of the original mesh.
+            if skeleton.is_morphed() {
+                input.base_position_offset = input.updated_position_offset;
+                input.base_normal_offset = input.updated_normal_offset;
+                input.base_tangent_offset = input.updated_tangent_offset;
+            }
+
             skinning_input_data.write(&input).unwrap();
 
+            let source = &skeleton.source_attribute_ranges;
+            let morph_targets = &skeleton.morph_target_ranges;
+            let updated = &skeleton.overridden_attribute_ranges;
+            morph_input_data
+                .write(&GpuMorphInput {
+                    base_position_offset: find_offset(source, &VERTEX_ATTRIBUTE_POSITION),
+                    base_normal_offset: find_offset(source, &VERTEX_ATTRIBUTE_NORMAL),
+                    base_tangent_offset: find_offset(source, &VERTEX_ATTRIBUTE_TANGENT),
+                    position_offsets_offset: find_offset(morph_targets, &VERTEX_ATTRIBUTE_POSITION),
+                    normal_offsets_offset: find_offset(morph_targets, &VERTEX_ATTRIBUTE_NORMAL),
+                    tangent_offsets_offset: find_offset(morph_targets, &VERTEX_ATTRIBUTE_TANGENT),
+                    updated_position_offset: find_offset(updated, &VERTEX_ATTRIBUTE_POSITION),
+                    updated_normal_offset: find_offset(updated, &VERTEX_ATTRIBUTE_NORMAL),
+                    updated_tangent_offset: find_offset(updated, &VERTEX_ATTRIBUTE_TANGENT),
+                    weight_base_offset: morph_weight_idx,
+                    target_count: skeleton.morph_weights.len() as u32,
+                    vertex_count: skeleton.vertex_count,
+                })
+                .unwrap();
+
+            let morph_weights_ptr = morph_weights_data.as_mut_ptr() as *mut f32;
+            for &weight in &skeleton.morph_weights {
+                // Same as the joint matrices below, the sum of all weight vector lengths is the
+                // length of the buffer.
+                morph_weights_ptr.add(morph_weight_idx as usize).write_unaligned(weight);
+                morph_weight_idx += 1;
+            }
+
             let joint_matrices_ptr = joint_matrices_data.as_mut_ptr() as *mut [[f32; 4]; 4];
-            for joint_matrix in &skeleton.joint_matrices {
+            for (joint_matrix, previous_joint_matrix) in
+                skeleton.joint_matrices.iter().zip(&skeleton.previous_joint_matrices)
+            {
                 // Here, the access can't be OOB either: The joint_matrix_idx
                 // will get incremented once for every joint matrix, and the
-                // length of the buffer is exactly the sum of all joint matrix
-                // vector lengths.
+                // length of the buffer is exactly twice the sum of all joint
+                // matrix vector lengths.
                 joint_matrices_ptr.add(joint_matrix_idx as usize).write_unaligned(joint_matrix.to_cols_array_2d());
+                joint_matrices_ptr
+                    .add(global_joint_count + joint_matrix_idx as usize)
+                    .write_unaligned(previous_joint_matrix.to_cols_array_2d());
                 joint_matrix_idx += 1;
             }
         }
@@ -132,16 +241,23 @@ fn build_gpu_skinning_input_buffers(ctx: &NodeExecutionContext) -> PreSkinningBu
 
     drop(skinning_input_range);
     drop(joint_matrices_data);
+    drop(morph_input_range);
+    drop(morph_weights_data);
     gpu_skinning_inputs.unmap();
     joint_matrices.unmap();
+    gpu_morph_inputs.unmap();
+    morph_weights.unmap();
 
-    PreSkinningBuffers { gpu_skinning_inputs, joint_matrices }
+    PreSkinningBuffers { gpu_skinning_inputs, joint_matrices, gpu_morph_inputs, morph_weights }
 }
 
-/// Holds the necessary wgpu data structures for the GPU skinning compute pass
+/// Holds the necessary wgpu data structures for the GPU skinning and morph
+/// target compute passes
 pub struct GpuSkinner {
     pub pipeline: ComputePipeline,
     pub bgl: BindGroupLayout,
+    pub morph_pipeline: ComputePipeline,
+    pub morph_bgl: BindGroupLayout,
 }
 
 impl GpuSkinner {
@@ -175,7 +291,62 @@ impl GpuSkinner {
             entry_point: "main",
         });
 
-        Self { bgl, pipeline }
+        let morph_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, 4) // Vertices
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, true, GpuMorphInput::SHADER_SIZE.get()) // Inputs
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: true }, false, 4) // Weights
+            .build(device, Some("Gpu morph target mesh data"));
+
+        let morph_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&morph_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let morph_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Gpu morph target compute shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/morph.wgsl", &(), None).unwrap(),
+            )),
+        });
+
+        let morph_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Gpu morph target pipeline"),
+            layout: Some(&morph_layout),
+            module: &morph_module,
+            entry_point: "main",
+        });
+
+        Self { bgl, pipeline, morph_bgl, morph_pipeline }
+    }
+
+    /// Blends the morph targets of every morphed skeleton into the skeleton's copy of the mesh.
+    pub fn execute_morph_pass(
+        &self,
+        ctx: &NodeExecutionContext,
+        encoder: &mut CommandEncoder,
+        buffers: &PreSkinningBuffers,
+    ) {
+        let bg = BindGroupBuilder::new()
+            .append_buffer(&ctx.eval_output.mesh_buffer)
+            .append_buffer_with_size(&buffers.gpu_morph_inputs, GpuMorphInput::SHADER_SIZE.get())
+            .append_buffer(&buffers.morph_weights)
+            .build(&ctx.renderer.device, Some("GPU morph target inputs"), &self.morph_bgl);
+
+        let mut cpass = encoder
+            .begin_compute_pass(&ComputePassDescriptor { label: Some("GPU Morph Targets"), timestamp_writes: None });
+        cpass.set_pipeline(&self.morph_pipeline);
+        for (i, skel) in ctx.data_core.skeleton_manager.skeletons().enumerate() {
+            if !skel.is_morphed() {
+                continue;
+            }
+
+            let offset = (i as u64 * GpuMorphInput::SHADER_SIZE.get()) as u32;
+            cpass.set_bind_group(0, &bg, &[offset]);
+
+            let num_workgroups = div_round_up(skel.vertex_count, Self::WORKGROUP_SIZE);
+            cpass.dispatch_workgroups(num_workgroups, 1, 1);
+        }
     }
 
     pub fn execute_pass(&self, ctx: &NodeExecutionContext, encoder: &mut CommandEncoder, buffers: &PreSkinningBuffers) {
@@ -189,6 +360,10 @@ impl GpuSkinner {
             encoder.begin_compute_pass(&ComputePassDescriptor { label: Some("GPU Skinning"), timestamp_writes: None });
         cpass.set_pipeline(&self.pipeline);
         for (i, skel) in ctx.data_core.skeleton_manager.skeletons().enumerate() {
+            if !skel.is_skinned() {
+                continue;
+            }
+
             let offset = (i as u64 * GpuSkinningInput::SHADER_SIZE.get()) as u32;
             cpass.set_bind_group(0, &bg, &[offset]);
 
@@ -207,7 +382,7 @@ impl GpuSkinner {
 /// render graph (before any culling happens).
 pub struct SkinningOutput;
 
-/// Performs skinning on the GPU.
+/// Performs morph target blending and skinning on the GPU.
 pub fn add_skinning_to_graph<'node>(graph: &mut RenderGraph<'node>, gpu_skinner: &'node GpuSkinner) {
     let mut builder = graph.add_node("skinning");
     builder.add_side_effect();
@@ -217,9 +392,13 @@ pub fn add_skinning_to_graph<'node>(graph: &mut RenderGraph<'node>, gpu_skinner:
 
         let skinning_input = build_gpu_skinning_input_buffers(&ctx);
 
-        // Avoid running the compute pass if there are no skeletons. This
-        // prevents binding an empty buffer
-        if ctx.data_core.skeleton_manager.skeletons().len() > 0 {
+        // Avoid running the compute passes if there is nothing to do. This
+        // prevents binding an empty buffer. Morph targets need to be applied
+        // before skinning.
+        if ctx.data_core.skeleton_manager.global_morph_weight_count() > 0 {
+            gpu_skinner.execute_morph_pass(&ctx, encoder, &skinning_input);
+        }
+        if ctx.data_core.skeleton_manager.global_joint_count() > 0 {
             gpu_skinner.execute_pass(&ctx, encoder, &skinning_input);
         }
     });
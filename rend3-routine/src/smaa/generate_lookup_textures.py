@@ -0,0 +1,306 @@
+#!/usr/bin/env python3
+"""Generates the lookup textures of the SMAA routine.
+
+Port of AreaTex.py and SearchTex.py from the reference implementation of SMAA
+(https://github.com/iryoku/smaa, MIT licensed), writing raw texels instead of
+headers:
+
+  area.bin    160x560 Rg8Unorm, precomputed coverage areas of edge patterns.
+  search.bin   64x16  R8Unorm, distances to add to the last step of searches.
+
+Run it from this directory. It only needs the standard library.
+"""
+
+from math import copysign, modf, sqrt
+
+# Subsample offsets for orthogonal and diagonal areas, only the first one is used by SMAA 1x:
+SUBSAMPLE_OFFSETS_ORTHO = [0.0, -0.25, 0.25, -0.125, 0.125, -0.375, 0.375]
+SUBSAMPLE_OFFSETS_DIAG = [(0.0, 0.0), (0.25, -0.25), (-0.25, 0.25), (0.125, -0.125), (-0.125, 0.125)]
+
+# Size of the texture for every pattern, there are 5x5 orthogonal and 4x4 diagonal slots:
+SIZE_ORTHO = 16
+SIZE_DIAG = 20
+
+# Diagonal areas are calculated by brute force sampling:
+SAMPLES_DIAG = 30
+
+# Maximum distance for smoothing u-shapes:
+SMOOTH_MAX_DISTANCE = 32
+
+AREA_TEX_SIZE = (2 * 5 * SIZE_ORTHO, len(SUBSAMPLE_OFFSETS_ORTHO) * 5 * SIZE_ORTHO)
+
+# Coordinates of the top-left corner of every edge pattern, in slots:
+EDGES_ORTHO = [(0, 0), (3, 0), (0, 3), (3, 3), (1, 0), (4, 0), (1, 3), (4, 3),
+               (0, 1), (3, 1), (0, 4), (3, 4), (1, 1), (4, 1), (1, 4), (4, 4)]
+EDGES_DIAG = [(0, 0), (1, 0), (0, 2), (1, 2), (2, 0), (3, 0), (2, 2), (3, 2),
+              (0, 1), (1, 1), (0, 3), (1, 3), (2, 1), (3, 1), (2, 3), (3, 3)]
+
+
+def lerp(a, b, p):
+    return a + (b - a) * p
+
+
+def saturate(x):
+    return min(max(x, 0.0), 1.0)
+
+
+def add(a, b):
+    return (a[0] + b[0], a[1] + b[1])
+
+
+def scale(a, s):
+    return (a[0] * s, a[1] * s)
+
+
+# Smoothing function for small u-patterns:
+def smooth_area(d, a1, a2):
+    b1 = tuple(sqrt(x * 2.0) * 0.5 for x in a1)
+    b2 = tuple(sqrt(x * 2.0) * 0.5 for x in a2)
+    p = saturate(d / float(SMOOTH_MAX_DISTANCE))
+    return tuple(lerp(b, a, p) for b, a in zip(b1, a1)), tuple(lerp(b, a, p) for b, a in zip(b2, a2))
+
+
+# Calculates the area under the line p1->p2, for the pixel x:
+def area_line(p1, p2, x):
+    d = (p2[0] - p1[0], p2[1] - p1[1])
+    x1 = float(x)
+    x2 = x + 1.0
+    y1 = p1[1] + d[1] * (x1 - p1[0]) / d[0]
+    y2 = p1[1] + d[1] * (x2 - p1[0]) / d[0]
+
+    inside = (p1[0] <= x1 < p2[0]) or (p1[0] < x2 <= p2[0])
+    if not inside:
+        return (0.0, 0.0)
+
+    is_trapezoid = copysign(1.0, y1) == copysign(1.0, y2) or abs(y1) < 1e-4 or abs(y2) < 1e-4
+    if is_trapezoid:
+        a = (y1 + y2) / 2.0
+        return (abs(a), 0.0) if a < 0.0 else (0.0, abs(a))
+
+    # Two triangles:
+    x = -p1[1] * d[0] / d[1] + p1[0]
+    a1 = y1 * modf(x)[0] / 2.0 if x > p1[0] else 0.0
+    a2 = y2 * (1.0 - modf(x)[0]) / 2.0 if x < p2[0] else 0.0
+    a = a1 if abs(a1) > abs(a2) else -a2
+    return (abs(a1), abs(a2)) if a < 0.0 else (abs(a2), abs(a1))
+
+
+# Calculates the area for a given pattern and distances to the left and to the right, biased by an offset:
+def area_ortho(pattern, left, right, offset):
+    d = left + right + 1
+
+    o1 = 0.5 + offset
+    o2 = 0.5 + offset - 1.0
+
+    if pattern == 0:
+        return (0.0, 0.0)
+    elif pattern == 1:
+        # L patterns are only offset on the side of the crossing edge, to converge with pattern 0.
+        return area_line((0.0, o2), (d / 2.0, 0.0), left) if left <= right else (0.0, 0.0)
+    elif pattern == 2:
+        return area_line((d / 2.0, 0.0), (d, o2), left) if left >= right else (0.0, 0.0)
+    elif pattern == 3:
+        a1 = area_line((0.0, o2), (d / 2.0, 0.0), left)
+        a2 = area_line((d / 2.0, 0.0), (d, o2), left)
+        a1, a2 = smooth_area(d, a1, a2)
+        return add(a1, a2)
+    elif pattern == 4:
+        return area_line((0.0, o1), (d / 2.0, 0.0), left) if left <= right else (0.0, 0.0)
+    elif pattern == 5:
+        return (0.0, 0.0)
+    elif pattern == 6:
+        # Blend the full offset Z with partially offset L patterns, to avoid discontinuities in the middle of Z
+        # patterns that are longer than the search distance.
+        if abs(offset) > 0.0:
+            a1 = area_line((0.0, o1), (d, o2), left)
+            a2 = add(area_line((0.0, o1), (d / 2.0, 0.0), left), area_line((d / 2.0, 0.0), (d, o2), left))
+            return scale(add(a1, a2), 0.5)
+        return area_line((0.0, o1), (d, o2), left)
+    elif pattern == 7:
+        return area_line((0.0, o1), (d, o2), left)
+    elif pattern == 8:
+        return area_line((d / 2.0, 0.0), (d, o1), left) if left >= right else (0.0, 0.0)
+    elif pattern == 9:
+        if abs(offset) > 0.0:
+            a1 = area_line((0.0, o2), (d, o1), left)
+            a2 = add(area_line((0.0, o2), (d / 2.0, 0.0), left), area_line((d / 2.0, 0.0), (d, o1), left))
+            return scale(add(a1, a2), 0.5)
+        return area_line((0.0, o2), (d, o1), left)
+    elif pattern == 10:
+        return (0.0, 0.0)
+    elif pattern == 11:
+        return area_line((0.0, o2), (d, o1), left)
+    elif pattern == 12:
+        a1 = area_line((0.0, o1), (d / 2.0, 0.0), left)
+        a2 = area_line((d / 2.0, 0.0), (d, o1), left)
+        a1, a2 = smooth_area(d, a1, a2)
+        return add(a1, a2)
+    elif pattern == 13:
+        return area_line((0.0, o2), (d, o1), left)
+    elif pattern == 14:
+        return area_line((0.0, o1), (d, o2), left)
+    else:
+        return (0.0, 0.0)
+
+
+# Calculates the area under the line p1->p2 for the pixel p by brute force sampling:
+def area_pixel(p1, p2, p):
+    xm, ym = (p1[0] + p2[0]) / 2.0, (p1[1] + p2[1]) / 2.0
+    a = p2[1] - p1[1]
+    b = p1[0] - p2[0]
+
+    count = 0
+    for x in range(SAMPLES_DIAG):
+        for y in range(SAMPLES_DIAG):
+            sx = p[0] + float(x) / (SAMPLES_DIAG - 1)
+            sy = p[1] + float(y) / (SAMPLES_DIAG - 1)
+            if p1 == p2 or a * (sx - xm) + b * (sy - ym) > 0:
+                count += 1
+    return count / float(SAMPLES_DIAG * SAMPLES_DIAG)
+
+
+def area_diag(pattern, left, right, offset):
+    # Area under the line p1->p2, including the pixel and its opposite:
+    def area(p1, p2):
+        p1 = add(p1, offset)
+        p2 = add(p2, offset)
+        a1 = area_pixel(p1, p2, (1.0 + left, 0.0 + left))
+        a2 = area_pixel(p1, p2, (1.0 + left, 1.0 + left))
+        return (1.0 - a1, a2)
+
+    def blend(a1, a2):
+        return scale(add(a1, a2), 0.5)
+
+    d = left + right + 1
+
+    # Unlike orthogonal patterns, the pattern without crossing edges must be filtered, and the ends of the null
+    # and L patterns aren't known. Both possibilities are blended.
+    if pattern == 0:
+        return blend(area((1.0, 1.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 1:
+        return blend(area((1.0, 0.0), (0.0 + d, 0.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 2:
+        return blend(area((0.0, 0.0), (1.0 + d, 0.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 3:
+        return area((1.0, 0.0), (1.0 + d, 0.0 + d))
+    elif pattern == 4:
+        return blend(area((1.0, 1.0), (0.0 + d, 0.0 + d)), area((1.0, 1.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 5:
+        return blend(area((1.0, 1.0), (0.0 + d, 0.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 6:
+        return area((1.0, 1.0), (1.0 + d, 0.0 + d))
+    elif pattern == 7:
+        return blend(area((1.0, 1.0), (1.0 + d, 0.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 8:
+        return blend(area((0.0, 0.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 1.0 + d)))
+    elif pattern == 9:
+        return area((1.0, 0.0), (1.0 + d, 1.0 + d))
+    elif pattern == 10:
+        return blend(area((0.0, 0.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 11:
+        return blend(area((1.0, 0.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+    elif pattern == 12:
+        return area((1.0, 1.0), (1.0 + d, 1.0 + d))
+    elif pattern == 13:
+        return blend(area((1.0, 1.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 1.0 + d)))
+    elif pattern == 14:
+        return blend(area((1.0, 1.0), (1.0 + d, 1.0 + d)), area((1.0, 1.0), (1.0 + d, 0.0 + d)))
+    else:
+        return blend(area((1.0, 1.0), (1.0 + d, 1.0 + d)), area((1.0, 0.0), (1.0 + d, 0.0 + d)))
+
+
+def to_unorm8(x):
+    return int(round(saturate(x) * 255.0))
+
+
+def generate_area_texture():
+    width, height = AREA_TEX_SIZE
+    texels = bytearray(width * height * 2)
+
+    def put(x, y, value):
+        index = (y * width + x) * 2
+        texels[index] = to_unorm8(value[0])
+        texels[index + 1] = to_unorm8(value[1])
+
+    # Orthogonal areas on the left half, distances are compressed quadratically.
+    for subsample, offset in enumerate(SUBSAMPLE_OFFSETS_ORTHO):
+        for pattern, (slot_x, slot_y) in enumerate(EDGES_ORTHO):
+            for left in range(SIZE_ORTHO):
+                for right in range(SIZE_ORTHO):
+                    value = area_ortho(pattern, left * left, right * right, offset)
+                    x = slot_x * SIZE_ORTHO + left
+                    y = subsample * 5 * SIZE_ORTHO + slot_y * SIZE_ORTHO + right
+                    put(x, y, value)
+
+    # Diagonal areas on the right half.
+    for subsample, offset in enumerate(SUBSAMPLE_OFFSETS_DIAG):
+        for pattern, (slot_x, slot_y) in enumerate(EDGES_DIAG):
+            for left in range(SIZE_DIAG):
+                for right in range(SIZE_DIAG):
+                    value = area_diag(pattern, left, right, offset)
+                    x = width // 2 + slot_x * SIZE_DIAG + left
+                    y = subsample * 4 * SIZE_DIAG + slot_y * SIZE_DIAG + right
+                    put(x, y, value)
+
+    return texels
+
+
+# Bilinear fetch of four edges at an offset of (0.25, 0.125) from the last texel, see SMAASearchLength:
+def bilinear(e):
+    a = lerp(e[0], e[1], 1.0 - 0.25)
+    b = lerp(e[2], e[3], 1.0 - 0.25)
+    return lerp(a, b, 1.0 - 0.125)
+
+
+# Reverse lookup of the bilinear fetch, every combination of edges gives a different multiple of 1/32:
+EDGE = {}
+for bits in range(16):
+    e = [(bits >> 3) & 1, (bits >> 2) & 1, (bits >> 1) & 1, bits & 1]
+    EDGE[int(round(bilinear(e) * 32.0))] = e
+
+
+# Delta distance to add in the last step of searches to the left:
+def delta_left(left, top):
+    d = 0
+    # If there is an edge, continue:
+    if top[3] == 1:
+        d += 1
+    # If we previously found an edge, there is another edge and no crossing edges, continue:
+    if d == 1 and top[2] == 1 and left[1] != 1 and left[3] != 1:
+        d += 1
+    return d
+
+
+# Delta distance to add in the last step of searches to the right:
+def delta_right(left, top):
+    d = 0
+    # If there is an edge, and no crossing edges, continue:
+    if top[3] == 1 and left[1] != 1 and left[3] != 1:
+        d += 1
+    # If we previously found an edge, there is another edge and no crossing edges, continue:
+    if d == 1 and top[2] == 1 and left[0] != 1 and left[2] != 1:
+        d += 1
+    return d
+
+
+def generate_search_texture():
+    # The full table is 66x33, left searches on the left half and right searches on the right one.
+    full = [[0] * 66 for _ in range(33)]
+    for x in range(33):
+        for y in range(33):
+            if x in EDGE and y in EDGE:
+                full[y][x] = 127 * delta_left(EDGE[x], EDGE[y])
+                full[y][x + 33] = 127 * delta_right(EDGE[x], EDGE[y])
+
+    # Everything outside of 64x16 is zero, crop it and flip it vertically.
+    texels = bytearray()
+    for y in reversed(range(17, 33)):
+        texels.extend(full[y][:64])
+    return texels
+
+
+if __name__ == "__main__":
+    with open("search.bin", "wb") as f:
+        f.write(generate_search_texture())
+    with open("area.bin", "wb") as f:
+        f.write(generate_area_texture())
@@ -0,0 +1,409 @@
+//! Enhanced subpixel morphological anti-aliasing.
+//!
+//! A port of SMAA 1x with the high quality preset, from "SMAA: Enhanced
+//! Subpixel Morphological Antialiasing" by Jimenez et al. It runs in three
+//! passes:
+//!
+//! - Edges are detected from the luma of the image.
+//! - The edges are followed to find the lines they form and the shape of their
+//!   ends. The coverage of every pixel by the lines is looked up in a
+//!   precomputed area texture, giving the blending weights.
+//! - Every pixel is blended with its neighbors by the weights.
+//!
+//! Unlike FXAA, only the pixels along actual geometric lines are blended, so
+//! textures stay sharp, at the cost of a few more passes.
+//!
+//! Edges are detected on the displayed image, so this must run after
+//! tonemapping. As with the [`TonemappingRoutine`], each instance only has
+//! pipelines for the output format it was created with.
+//!
+//! The lookup textures next to this module are generated by
+//! `generate_lookup_textures.py`, a port of the generators of the reference
+//! implementation, which is MIT licensed.
+//!
+//! [`TonemappingRoutine`]: crate::tonemapping::TonemappingRoutine
+
+use std::borrow::Cow;
+
+use glam::{UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use serde::Serialize;
+use wgpu::{
+    util::{DeviceExt, TextureDataOrder},
+    AddressMode, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, Device, Extent3d, FilterMode,
+    FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModule, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Precomputed coverage of every edge pattern, 160x560 `Rg8Unorm`.
+const AREA_TEXTURE: &[u8] = include_bytes!("area.bin");
+const AREA_TEXTURE_SIZE: UVec2 = UVec2::new(160, 560);
+/// Distances to add to the last step of the searches along edges, 64x16
+/// `R8Unorm`.
+const SEARCH_TEXTURE: &[u8] = include_bytes!("search.bin");
+const SEARCH_TEXTURE_SIZE: UVec2 = UVec2::new(64, 16);
+
+#[derive(Serialize)]
+struct SmaaPreprocessingArguments {
+    #[serde(rename = "SRGB")]
+    srgb: bool,
+}
+
+fn texture_binding() -> BindingType {
+    BindingType::Texture {
+        sample_type: TextureSampleType::Float { filterable: true },
+        view_dimension: TextureViewDimension::D2,
+        multisampled: false,
+    }
+}
+
+fn create_lookup_texture(
+    renderer: &Renderer,
+    label: &str,
+    size: UVec2,
+    format: TextureFormat,
+    data: &[u8],
+) -> TextureView {
+    renderer
+        .device
+        .create_texture_with_data(
+            &renderer.queue,
+            &TextureDescriptor {
+                label: Some(label),
+                size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            data,
+        )
+        .create_view(&TextureViewDescriptor::default())
+}
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    bgl: &BindGroupLayout,
+    label: &str,
+    format: TextureFormat,
+) -> RenderPipeline {
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState { format, blend: None, write_mask: ColorWrites::all() })],
+        }),
+        multiview: None,
+    })
+}
+
+/// SMAA post-process routine.
+///
+/// See module for documentation.
+pub struct SmaaRoutine {
+    sampler: Sampler,
+    area_texture: TextureView,
+    search_texture: TextureView,
+    edge_detection_bgl: BindGroupLayout,
+    edge_detection_pipeline: RenderPipeline,
+    blending_weights_bgl: BindGroupLayout,
+    blending_weights_pipeline: RenderPipeline,
+    neighborhood_blending_bgl: BindGroupLayout,
+    neighborhood_blending_pipeline: RenderPipeline,
+    output_format: TextureFormat,
+}
+
+impl SmaaRoutine {
+    pub const EDGES_FORMAT: TextureFormat = TextureFormat::Rg8Unorm;
+    pub const BLENDING_WEIGHTS_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor, output_format: TextureFormat) -> Self {
+        profiling::scope!("SmaaRoutine::new");
+
+        let device = &renderer.device;
+
+        // The searches along edges must not wrap around to the other side of the screen.
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("smaa sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let area_texture =
+            create_lookup_texture(renderer, "smaa area", AREA_TEXTURE_SIZE, TextureFormat::Rg8Unorm, AREA_TEXTURE);
+        let search_texture =
+            create_lookup_texture(renderer, "smaa search", SEARCH_TEXTURE_SIZE, TextureFormat::R8Unorm, SEARCH_TEXTURE);
+
+        let edge_detection_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .build(device, Some("smaa edge detection bgl"));
+        let blending_weights_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .build(device, Some("smaa blending weights bgl"));
+        let neighborhood_blending_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .append(ShaderStages::FRAGMENT, texture_binding(), None)
+            .build(device, Some("smaa neighborhood blending bgl"));
+
+        let edge_detection_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("smaa edge detection"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader(
+                    "rend3-routine/smaa/edge_detection.wgsl",
+                    &SmaaPreprocessingArguments { srgb: output_format.is_srgb() },
+                    None,
+                )
+                .unwrap(),
+            )),
+        });
+        let blending_weights_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("smaa blending weights"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/smaa/blending_weights.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+        let neighborhood_blending_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("smaa neighborhood blending"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/smaa/neighborhood_blending.wgsl", &ShaderConfig::default(), None)
+                    .unwrap(),
+            )),
+        });
+
+        let edge_detection_pipeline = create_pipeline(
+            device,
+            &edge_detection_module,
+            &edge_detection_bgl,
+            "smaa edge detection",
+            Self::EDGES_FORMAT,
+        );
+        let blending_weights_pipeline = create_pipeline(
+            device,
+            &blending_weights_module,
+            &blending_weights_bgl,
+            "smaa blending weights",
+            Self::BLENDING_WEIGHTS_FORMAT,
+        );
+        let neighborhood_blending_pipeline = create_pipeline(
+            device,
+            &neighborhood_blending_module,
+            &neighborhood_blending_bgl,
+            "smaa neighborhood blending",
+            output_format,
+        );
+
+        Self {
+            sampler,
+            area_texture,
+            search_texture,
+            edge_detection_bgl,
+            edge_detection_pipeline,
+            blending_weights_bgl,
+            blending_weights_pipeline,
+            neighborhood_blending_bgl,
+            neighborhood_blending_pipeline,
+            output_format,
+        }
+    }
+
+    /// Format of the targets this routine reads from and renders to.
+    pub fn output_format(&self) -> TextureFormat {
+        self.output_format
+    }
+
+    /// Anti-aliases `src` into `dst`. Both must be single sampled targets of
+    /// the output format with the given resolution.
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        resolution: UVec2,
+    ) {
+        let edges = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("smaa edges".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: Self::EDGES_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let blending_weights = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("smaa blending weights".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: Self::BLENDING_WEIGHTS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        self.add_edge_detection_to_graph(graph, src, edges);
+        self.add_blending_weights_to_graph(graph, edges, blending_weights);
+        self.add_neighborhood_blending_to_graph(graph, src, blending_weights, dst);
+    }
+
+    fn add_edge_detection_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        edges: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("SMAA Edge Detection");
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        // Pixels without edges are discarded, so they keep the clear color.
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: edges, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            profiling::scope!("smaa edge detection");
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(src_view).build(
+                &ctx.renderer.device,
+                Some("smaa edge detection bg"),
+                &self.edge_detection_bgl,
+            ));
+
+            rpass.set_pipeline(&self.edge_detection_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    fn add_blending_weights_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        edges: RenderTargetHandle,
+        blending_weights: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("SMAA Blending Weights");
+
+        let edges_handle = builder.add_render_target(edges, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: blending_weights, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let edges_view = ctx.graph_data.get_render_target(edges_handle);
+
+            profiling::scope!("smaa blending weights");
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_sampler(&self.sampler)
+                    .append_texture_view(edges_view)
+                    .append_texture_view(&self.area_texture)
+                    .append_texture_view(&self.search_texture)
+                    .build(&ctx.renderer.device, Some("smaa blending weights bg"), &self.blending_weights_bgl),
+            );
+
+            rpass.set_pipeline(&self.blending_weights_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    fn add_neighborhood_blending_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        blending_weights: RenderTargetHandle,
+        dst: RenderTargetHandle,
+    ) {
+        let mut builder = graph.add_node("SMAA Neighborhood Blending");
+
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+        let blending_weights_handle = builder.add_render_target(blending_weights, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+            let blending_weights_view = ctx.graph_data.get_render_target(blending_weights_handle);
+
+            profiling::scope!("smaa neighborhood blending");
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_sampler(&self.sampler)
+                    .append_texture_view(src_view)
+                    .append_texture_view(blending_weights_view)
+                    .build(
+                        &ctx.renderer.device,
+                        Some("smaa neighborhood blending bg"),
+                        &self.neighborhood_blending_bgl,
+                    ),
+            );
+
+            rpass.set_pipeline(&self.neighborhood_blending_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
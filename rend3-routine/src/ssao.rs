@@ -0,0 +1,262 @@
+//! Screen space ambient occlusion.
+//!
+//! Darkens the ambient lighting of creases and of surfaces close to other
+//! geometry, which keeps dynamic objects from looking like they float above
+//! the ground. For every pixel, points in a hemisphere around the surface
+//! normal are projected onto the depth buffer and counted as occluded if they
+//! end up behind it. The hemisphere is randomly rotated per pixel and the
+//! resulting noise is removed by a depth aware blur.
+//!
+//! The routine needs the depth and view space normals of the scene before it
+//! is shaded, which the PBR prepass routines render. The resulting ambient
+//! occlusion texture is bound to the forward shaders, where it scales the
+//! ambient and image based lighting.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+    Device, FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+/// Per-frame settings of the [`SsaoRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsaoSettings {
+    /// Radius (in world units) around a surface in which geometry occludes
+    /// it.
+    pub radius: f32,
+    /// Strength of the occlusion. 1 is the measured occlusion, larger values
+    /// darken it further.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self { radius: 0.5, intensity: 1.0 }
+    }
+}
+
+#[derive(ShaderType)]
+struct SsaoUniform {
+    proj: Mat4,
+    inv_proj: Mat4,
+    resolution: UVec2,
+    radius: f32,
+    intensity: f32,
+}
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    bgl: &BindGroupLayout,
+    label: &str,
+    entry_point: &str,
+) -> RenderPipeline {
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module,
+            entry_point,
+            targets: &[Some(ColorTargetState {
+                format: SsaoRoutine::AO_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::all(),
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Screen space ambient occlusion routine.
+///
+/// See module for documentation.
+pub struct SsaoRoutine {
+    bgl: BindGroupLayout,
+    ssao_pipeline: RenderPipeline,
+    denoise_pipeline: RenderPipeline,
+}
+
+impl SsaoRoutine {
+    /// Format of the ambient occlusion texture.
+    pub const AO_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("SsaoRoutine::new");
+
+        let device = &renderer.device;
+
+        // The textures are only ever loaded, so they don't need to be filterable.
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, SsaoUniform::SHADER_SIZE.get())
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(device, Some("ssao bgl"));
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("ssao"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/ssao.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let ssao_pipeline = create_pipeline(device, &module, &bgl, "ssao", "fs_ssao");
+        let denoise_pipeline = create_pipeline(device, &module, &bgl, "ssao denoise", "fs_denoise");
+
+        Self { bgl, ssao_pipeline, denoise_pipeline }
+    }
+
+    /// Add the ambient occlusion texture to the given bind group layout
+    /// builder.
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            None,
+        );
+    }
+
+    /// Renders the ambient occlusion of the viewport camera into `output`.
+    ///
+    /// `depth` must be a single sampled depth target and `surface` the
+    /// [`PbrRoutine::PREPASS_FORMAT`](crate::pbr::PbrRoutine::PREPASS_FORMAT)
+    /// target rendered by the PBR prepass routines. All of them must be of the
+    /// given resolution, and `output` must be of [`Self::AO_FORMAT`].
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        surface: RenderTargetHandle,
+        output: RenderTargetHandle,
+        resolution: UVec2,
+        settings: SsaoSettings,
+    ) {
+        let raw = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("ssao raw".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: Self::AO_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        self.add_pass_to_graph(graph, "SSAO", &self.ssao_pipeline, depth, surface, raw, resolution, settings);
+        self.add_pass_to_graph(graph, "SSAO Denoise", &self.denoise_pipeline, depth, raw, output, resolution, settings);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_pass_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        label: &str,
+        pipeline: &'node RenderPipeline,
+        depth: RenderTargetHandle,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        resolution: UVec2,
+        settings: SsaoSettings,
+    ) {
+        let mut builder = graph.add_node(label);
+
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let src_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: dst, clear: Vec4::ONE, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let depth_view = ctx.graph_data.get_render_target(depth_handle);
+            let src_view = ctx.graph_data.get_render_target(src_handle);
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let uniform_values = SsaoUniform {
+                proj: camera.proj(),
+                inv_proj: camera.proj().inverse(),
+                resolution,
+                radius: settings.radius,
+                intensity: settings.intensity,
+            };
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("ssao uniform"),
+                size: SsaoUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform_values).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+            let uniform_buffer = ctx.temps.add(uniform_buffer);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_buffer(uniform_buffer)
+                    .append_texture_view(depth_view)
+                    .append_texture_view(src_view)
+                    .build(&ctx.renderer.device, Some("ssao bg"), &self.bgl),
+            );
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,365 @@
+//! Screen space reflections.
+//!
+//! Reflects the already shaded scene in smooth surfaces. For every pixel, the
+//! view ray is reflected around the surface normal and traced through a
+//! hierarchical-z buffer of the closest depths, built by
+//! [`HiZRoutine`](crate::hi_z::HiZRoutine) with
+//! [`HiZReduction::Closest`](crate::hi_z::HiZReduction::Closest). Rays skip
+//! over large empty regions of the screen in a few steps, and only refine
+//! where they may hit something. Where a ray hits, the HDR color at the hit is
+//! added onto the surface, weighted by a fresnel term of the roughness and
+//! metallic values of the surface.
+//!
+//! Only what is on screen can be reflected. Rays which miss, leave the screen,
+//! hit the back of a surface or start on a surface rougher than
+//! [`SsrSettings::max_roughness`] add nothing, leaving the regular lighting in
+//! place. The reflections fade out towards all of these cases, so there are no
+//! hard edges where they give up.
+//!
+//! The routine needs the depth and surface of the scene rendered by the PBR
+//! prepass routines, and must run after the opaque geometry is shaded but
+//! before transparent geometry is rendered.
+
+use std::borrow::Cow;
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec4};
+use rend3::{
+    graph::{
+        NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle,
+    },
+    types::{SampleCount, TextureFormat, TextureUsages},
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    BindGroupLayout, BindingType, BlendComponent, BlendFactor, BlendOperation, BlendState, BufferBindingType,
+    BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, StencilState, TextureSampleType, TextureViewDimension, VertexState,
+};
+
+use crate::hi_z::HiZRoutine;
+
+/// Per-frame settings of the [`SsrRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsrSettings {
+    /// Maximum amount of steps a ray takes through the hierarchical-z
+    /// buffer.
+    pub max_steps: u32,
+    /// Distance (in world units) a ray travels before it gives up.
+    pub max_distance: f32,
+    /// Thickness (in world units) assumed for everything in the depth buffer.
+    /// Rays which pass further behind a surface than this don't hit it.
+    pub thickness: f32,
+    /// Perceptual roughness at which the reflections are completely faded
+    /// out.
+    pub max_roughness: f32,
+    /// Multiplier of the reflections.
+    pub intensity: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        Self { max_steps: 64, max_distance: 20.0, thickness: 0.5, max_roughness: 0.6, intensity: 1.0 }
+    }
+}
+
+#[derive(ShaderType)]
+struct SsrUniform {
+    proj: Mat4,
+    inv_proj: Mat4,
+    resolution: UVec2,
+    max_level: u32,
+    max_steps: u32,
+    max_distance: f32,
+    thickness: f32,
+    max_roughness: f32,
+    intensity: f32,
+}
+
+const ADDITIVE: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    // Keep the alpha of the image we're adding onto.
+    alpha: BlendComponent {
+        src_factor: BlendFactor::Zero,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+fn create_pipeline(
+    device: &Device,
+    module: &ShaderModule,
+    bgl: &BindGroupLayout,
+    label: &str,
+    entry_point: &str,
+    samples: SampleCount,
+    (target, depth_stencil): (ColorTargetState, Option<DepthStencilState>),
+) -> RenderPipeline {
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pll),
+        vertex: VertexState { module, entry_point: "vs_main", buffers: &[] },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil,
+        multisample: MultisampleState { count: samples as u32, ..Default::default() },
+        fragment: Some(FragmentState { module, entry_point, targets: &[Some(target)] }),
+        multiview: None,
+    })
+}
+
+/// Screen space reflections routine.
+///
+/// See module for documentation.
+pub struct SsrRoutine {
+    trace_bgl: BindGroupLayout,
+    trace_pipeline: RenderPipeline,
+    composite_bgl: BindGroupLayout,
+    composite_pipeline_s1: RenderPipeline,
+    composite_pipeline_s4: RenderPipeline,
+}
+
+impl SsrRoutine {
+    /// Format of the traced reflections, before they are added onto the HDR
+    /// color.
+    pub const REFLECTIONS_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(renderer: &Renderer, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("SsrRoutine::new");
+
+        let device = &renderer.device;
+
+        let texture = |sample_type| BindingType::Texture {
+            sample_type,
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        };
+
+        // The textures are only ever loaded, so they don't need to be filterable.
+        let trace_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, SsrUniform::SHADER_SIZE.get())
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Depth), None)
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Float { filterable: false }), None)
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Float { filterable: false }), None)
+            .build(device, Some("ssr trace bgl"));
+
+        let composite_bgl = BindGroupLayoutBuilder::new()
+            .append(ShaderStages::FRAGMENT, texture(TextureSampleType::Float { filterable: false }), None)
+            .build(device, Some("ssr composite bgl"));
+
+        let trace_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("ssr trace"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/ssr.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+        let composite_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("ssr composite"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/ssr_composite.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        // The trace renders into its own target.
+        let trace_target =
+            (ColorTargetState { format: Self::REFLECTIONS_FORMAT, blend: None, write_mask: ColorWrites::all() }, None);
+        // The composite adds onto the primary renderpass, which has a depth target it must not touch.
+        let composite_target = (
+            ColorTargetState {
+                format: TextureFormat::Rgba16Float,
+                blend: Some(ADDITIVE),
+                write_mask: ColorWrites::all(),
+            },
+            Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: Default::default(),
+            }),
+        );
+
+        let trace_pipeline =
+            create_pipeline(device, &trace_module, &trace_bgl, "ssr trace", "fs_trace", SampleCount::One, trace_target);
+        let composite_pipeline_s1 = create_pipeline(
+            device,
+            &composite_module,
+            &composite_bgl,
+            "ssr composite",
+            "fs_main",
+            SampleCount::One,
+            composite_target.clone(),
+        );
+        let composite_pipeline_s4 = create_pipeline(
+            device,
+            &composite_module,
+            &composite_bgl,
+            "ssr composite",
+            "fs_main",
+            SampleCount::Four,
+            composite_target,
+        );
+
+        Self { trace_bgl, trace_pipeline, composite_bgl, composite_pipeline_s1, composite_pipeline_s4 }
+    }
+
+    /// Traces the reflections of the viewport camera and adds them onto the
+    /// color of `renderpass`.
+    ///
+    /// `depth` must be a single sampled depth target with all of its mips
+    /// filled by [`HiZRoutine`] with
+    /// [`HiZReduction::Closest`](crate::hi_z::HiZReduction::Closest), `surface`
+    /// the [`PbrRoutine::PREPASS_FORMAT`](crate::pbr::PbrRoutine::PREPASS_FORMAT)
+    /// target rendered by the PBR prepass routines and `color` the resolved
+    /// HDR color of `renderpass`. All of them must be of the given resolution.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        surface: RenderTargetHandle,
+        color: RenderTargetHandle,
+        renderpass: RenderPassTargets,
+        resolution: UVec2,
+        samples: SampleCount,
+        settings: SsrSettings,
+    ) {
+        let reflections = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("ssr reflections".into()),
+            resolution,
+            depth: 1,
+            mip_levels: Some(1),
+            samples: SampleCount::One,
+            format: Self::REFLECTIONS_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+
+        self.add_trace_to_graph(graph, depth, surface, color, reflections, resolution, settings);
+        self.add_composite_to_graph(graph, reflections, renderpass, samples);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_trace_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        depth: RenderTargetHandle,
+        surface: RenderTargetHandle,
+        color: RenderTargetHandle,
+        reflections: RenderTargetHandle,
+        resolution: UVec2,
+        settings: SsrSettings,
+    ) {
+        let mut builder = graph.add_node("SSR Trace");
+
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let surface_handle = builder.add_render_target(surface, NodeResourceUsage::Input);
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(
+            RenderPassTargets {
+                targets: vec![RenderPassTarget { color: reflections, clear: Vec4::ZERO, resolve: None }],
+                depth_stencil: None,
+            },
+            NodeResourceUsage::InputOutput,
+        );
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let depth_view = ctx.graph_data.get_render_target(depth_handle);
+            let surface_view = ctx.graph_data.get_render_target(surface_handle);
+            let color_view = ctx.graph_data.get_render_target(color_handle);
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let uniform_values = SsrUniform {
+                proj: camera.proj(),
+                inv_proj: camera.proj().inverse(),
+                resolution,
+                max_level: HiZRoutine::mip_count(resolution) as u32 - 1,
+                max_steps: settings.max_steps,
+                max_distance: settings.max_distance,
+                thickness: settings.thickness,
+                max_roughness: settings.max_roughness,
+                intensity: settings.intensity,
+            };
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("ssr uniform"),
+                size: SsrUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform_values).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+            let uniform_buffer = ctx.temps.add(uniform_buffer);
+
+            let bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_buffer(uniform_buffer)
+                    .append_texture_view(depth_view)
+                    .append_texture_view(surface_view)
+                    .append_texture_view(color_view)
+                    .build(&ctx.renderer.device, Some("ssr trace bg"), &self.trace_bgl),
+            );
+
+            rpass.set_pipeline(&self.trace_pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+
+    fn add_composite_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        reflections: RenderTargetHandle,
+        renderpass: RenderPassTargets,
+        samples: SampleCount,
+    ) {
+        let mut builder = graph.add_node("SSR Composite");
+
+        let reflections_handle = builder.add_render_target(reflections, NodeResourceUsage::Input);
+
+        let rpass_handle = builder.add_renderpass(renderpass, NodeResourceUsage::InputOutput);
+
+        builder.build(move |mut ctx| {
+            let rpass = ctx.encoder_or_pass.take_rpass(rpass_handle);
+            let reflections_view = ctx.graph_data.get_render_target(reflections_handle);
+
+            let bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(reflections_view).build(
+                &ctx.renderer.device,
+                Some("ssr composite bg"),
+                &self.composite_bgl,
+            ));
+
+            let pipeline = match samples {
+                SampleCount::One => &self.composite_pipeline_s1,
+                SampleCount::Four => &self.composite_pipeline_s4,
+            };
+
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bg, &[]);
+            rpass.draw(0..3, 0..1);
+        });
+    }
+}
@@ -0,0 +1,393 @@
+//! Temporal anti-aliasing.
+//!
+//! The viewport camera is jittered by a different sub-pixel offset every
+//! frame, see [`TaaRoutine::jitter`], so the pixels of consecutive frames
+//! sample different points of the scene. The resolve blends the current frame
+//! into a history of the previous frames, which converges towards a
+//! supersampled image. This also removes the shader aliasing of thin specular
+//! highlights, which multisampling does nothing about.
+//!
+//! To find where a pixel was in the history, the forward pass writes a motion
+//! vector per pixel. It is computed from the previous transform of the object
+//! and, for skinned meshes, the previous joint matrices. Pixels without
+//! geometry, like the skybox, are reprojected with the previous camera. The
+//! history is clamped to the colors around the pixel in the current frame,
+//! which rejects history that became disoccluded or changed its lighting.
+//!
+//! The routine owns the history, so use one routine per view you render.
+
+use std::{borrow::Cow, sync::Arc};
+
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{Mat4, UVec2, Vec2};
+use rend3::{
+    graph::{NodeResourceUsage, RenderGraph, RenderTargetHandle},
+    types::GraphDataHandle,
+    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    Renderer, ShaderConfig, ShaderPreProcessor,
+};
+use wgpu::{
+    AddressMode, BindGroupLayout, BindingType, BufferBindingType, BufferDescriptor, BufferUsages, Color,
+    ColorTargetState, ColorWrites, Device, Extent3d, FilterMode, FragmentState, FrontFace, LoadOp, MultisampleState,
+    Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+/// Per-frame settings of the [`TaaRoutine`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TaaSettings {
+    /// How much the current frame contributes to the result. Lower values
+    /// converge to a smoother image but take longer to react to changes.
+    pub current_frame_weight: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self { current_frame_weight: 0.1 }
+    }
+}
+
+#[derive(ShaderType)]
+struct TaaUniform {
+    reprojection: Mat4,
+    resolution: UVec2,
+    current_frame_weight: f32,
+    reset: u32,
+}
+
+/// Resolved images of the previous frames. Persists across graph executions.
+struct TaaHistory {
+    /// Two textures, as the history is read from one while writing the other.
+    views: Vec<TextureView>,
+    resolution: UVec2,
+    /// Index of the view holding the latest history.
+    current: usize,
+    /// False until a frame has been written into the history.
+    valid: bool,
+}
+
+impl TaaHistory {
+    fn new(device: &Device, resolution: UVec2) -> Self {
+        let views = (0..2)
+            .map(|_| {
+                device
+                    .create_texture(&TextureDescriptor {
+                        label: Some("taa history"),
+                        size: Extent3d { width: resolution.x, height: resolution.y, depth_or_array_layers: 1 },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: TextureDimension::D2,
+                        format: TaaRoutine::HISTORY_FORMAT,
+                        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    })
+                    .create_view(&TextureViewDescriptor::default())
+            })
+            .collect();
+
+        Self { views, resolution, current: 0, valid: false }
+    }
+}
+
+/// Radical inverse of `index` in the given base.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing routine.
+///
+/// See module for documentation.
+pub struct TaaRoutine {
+    bgl: BindGroupLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    history: GraphDataHandle<TaaHistory>,
+}
+
+impl TaaRoutine {
+    /// Format of the motion vectors written by the velocity routines. They
+    /// hold the offset from each pixel to where it was in the previous frame,
+    /// in UV coordinates.
+    pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+    /// Format of the resolved image and its history.
+    pub const HISTORY_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+    /// Amount of jitter offsets before the sequence repeats.
+    const JITTER_SEQUENCE_LENGTH: u32 = 8;
+
+    pub fn new(renderer: &Arc<Renderer>, spp: &ShaderPreProcessor) -> Self {
+        profiling::scope!("TaaRoutine::new");
+
+        let device = &renderer.device;
+
+        let bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Uniform, false, TaaUniform::SHADER_SIZE.get())
+            .append(ShaderStages::FRAGMENT, BindingType::Sampler(SamplerBindingType::Filtering), None)
+            // Current frame
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            // History
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            // Velocity
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .append(
+                ShaderStages::FRAGMENT,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(device, Some("taa bgl"));
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("taa sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("taa"),
+            source: ShaderSource::Wgsl(Cow::Owned(
+                spp.render_shader("rend3-routine/taa.wgsl", &ShaderConfig::default(), None).unwrap(),
+            )),
+        });
+
+        let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("taa resolve"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        // The resolved image goes both to the output and into the history.
+        let target =
+            Some(ColorTargetState { format: Self::HISTORY_FORMAT, blend: None, write_mask: ColorWrites::all() });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("taa resolve"),
+            layout: Some(&pll),
+            vertex: VertexState { module: &module, entry_point: "vs_main", buffers: &[] },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Cw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[target.clone(), target],
+            }),
+            multiview: None,
+        });
+
+        // The textures are created on the first frame, once the resolution is known.
+        let history = renderer.add_graph_data(TaaHistory {
+            views: Vec::new(),
+            resolution: UVec2::ZERO,
+            current: 0,
+            valid: false,
+        });
+
+        Self { bgl, pipeline, sampler, history }
+    }
+
+    /// Sub-pixel offset of the viewport camera for the given frame, in
+    /// normalized device coordinates. Pass it to
+    /// [`Renderer::set_camera_jitter`] before rendering every frame.
+    ///
+    /// The offsets follow the Halton (2, 3) sequence, which covers the pixel
+    /// evenly with few samples.
+    pub fn jitter(frame: u32, resolution: UVec2) -> Vec2 {
+        let index = frame % Self::JITTER_SEQUENCE_LENGTH + 1;
+        let offset = Vec2::new(halton(index, 2), halton(index, 3)) - 0.5;
+        offset * 2.0 / resolution.as_vec2()
+    }
+
+    /// Blends `color` into the history and writes the result into `output`.
+    ///
+    /// `velocity` must be of [`Self::VELOCITY_FORMAT`], as rendered by the
+    /// velocity routines of the PBR routine, and `depth` a single sampled
+    /// depth target. All of them must be of the given resolution, and
+    /// `output` must be of [`Self::HISTORY_FORMAT`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        color: RenderTargetHandle,
+        velocity: RenderTargetHandle,
+        depth: RenderTargetHandle,
+        output: RenderTargetHandle,
+        resolution: UVec2,
+        settings: TaaSettings,
+    ) {
+        let mut builder = graph.add_node("TAA Resolve");
+
+        let color_handle = builder.add_render_target(color, NodeResourceUsage::Input);
+        let velocity_handle = builder.add_render_target(velocity, NodeResourceUsage::Input);
+        let depth_handle = builder.add_render_target(depth, NodeResourceUsage::Input);
+        let output_handle = builder.add_render_target(output, NodeResourceUsage::Output);
+        // The history lives outside of the graph.
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let color_view = ctx.graph_data.get_render_target(color_handle);
+            let velocity_view = ctx.graph_data.get_render_target(velocity_handle);
+            let depth_view = ctx.graph_data.get_render_target(depth_handle);
+            let output_view = ctx.graph_data.get_render_target(output_handle);
+
+            profiling::scope!("taa resolve");
+
+            let mut history = ctx.data_core.graph_storage.get_mut(&self.history);
+            if history.resolution != resolution {
+                *history = TaaHistory::new(&ctx.renderer.device, resolution);
+            }
+
+            let camera = &ctx.data_core.viewport_camera_state;
+            let uniform_values = TaaUniform {
+                // Moves a point from the clip space of this frame into the clip space of the previous one.
+                reprojection: camera.previous_view_proj() * camera.unjittered_view_proj().inverse(),
+                resolution,
+                current_frame_weight: settings.current_frame_weight,
+                reset: !history.valid as u32,
+            };
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("taa uniform"),
+                size: TaaUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform_values).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+
+            let read = history.current;
+            let write = 1 - read;
+
+            let bg = BindGroupBuilder::new()
+                .append_buffer(&uniform_buffer)
+                .append_sampler(&self.sampler)
+                .append_texture_view(color_view)
+                .append_texture_view(&history.views[read])
+                .append_texture_view(velocity_view)
+                .append_texture_view(depth_view)
+                .build(&ctx.renderer.device, Some("taa bg"), &self.bgl);
+
+            let ops = Operations { load: LoadOp::Clear(Color::BLACK), store: StoreOp::Store };
+            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("TAA Resolve"),
+                color_attachments: &[
+                    Some(RenderPassColorAttachment { view: output_view, resolve_target: None, ops }),
+                    Some(RenderPassColorAttachment { view: &history.views[write], resolve_target: None, ops }),
+                ],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &bg, &[]);
+            rpass.draw(0..3, 0..1);
+            drop(rpass);
+
+            history.current = write;
+            history.valid = true;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+
+    use super::{halton, TaaRoutine};
+
+    #[test]
+    fn halton_sequence() {
+        assert_eq!(halton(0, 2), 0.0);
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(2, 2), 0.25);
+        assert_eq!(halton(3, 2), 0.75);
+        assert!((halton(1, 3) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+        assert!((halton(3, 3) - 1.0 / 9.0).abs() < 1e-6);
+
+        for index in 0..64 {
+            for base in [2, 3] {
+                let value = halton(index, base);
+                assert!((0.0..1.0).contains(&value), "halton({index}, {base}) = {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_within_a_pixel() {
+        for resolution in [UVec2::new(1, 1), UVec2::new(1920, 1080), UVec2::new(7, 3000)] {
+            let pixel = 2.0 / resolution.as_vec2();
+            for frame in 0..TaaRoutine::JITTER_SEQUENCE_LENGTH {
+                let jitter = TaaRoutine::jitter(frame, resolution);
+                assert!(jitter.abs().cmple(pixel).all(), "{jitter} exceeds a pixel at {resolution}");
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_repeats() {
+        let resolution = UVec2::new(1280, 720);
+        let length = TaaRoutine::JITTER_SEQUENCE_LENGTH;
+        for frame in 0..length {
+            assert_eq!(TaaRoutine::jitter(frame, resolution), TaaRoutine::jitter(frame + length, resolution));
+            assert_eq!(TaaRoutine::jitter(frame, resolution), TaaRoutine::jitter(frame + 5 * length, resolution));
+        }
+
+        // Every offset within the sequence differs.
+        for a in 0..length {
+            for b in a + 1..length {
+                assert_ne!(TaaRoutine::jitter(a, resolution), TaaRoutine::jitter(b, resolution));
+            }
+        }
+    }
+}
@@ -1,31 +1,138 @@
-//! Tonemapper which blits an image while applying a tonemapping operator.
+//! Tonemapper which blits an image while applying exposure and a tonemapping
+//! operator.
 //!
-//! As of right now there is no tonemapping applied as we don't have
-//! auto-exposure yet. Once we have auto-exposure, we can do proper tonemapping,
-//! and will offer a variety of tonemapping operators.
+//! The exposure is either set manually or measured from the scene. Automatic
+//! exposure builds a histogram of the log luminance of the HDR image every
+//! frame, and slowly adapts towards the average of it, the same way an eye
+//! adjusts when walking from a dark room outside. The average luminance is then
+//! mapped to middle grey. In both cases the exposure can be shifted with
+//! [`TonemappingSettings::exposure_compensation`].
+//!
+//! After exposure, the selected [`TonemappingOperator`] maps the HDR values
+//! into displayable range. The default is [`TonemappingOperator::None`] with a
+//! manual exposure of 1, which copies the image straight to the output.
 //!
 //! When creating the tonemapping, ensure you use the correct format for the
 //! output. Each TonemappingRoutine instance only has a single pipeline, so if
 //! you need to render to two different formats potentially, use two different
-//! routines.
+//! routines. Each instance also owns the adapted luminance of the automatic
+//! exposure, so use one routine per view you render.
 
 use std::borrow::Cow;
 
-use glam::Vec4;
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::{UVec2, Vec4};
 use rend3::{
     graph::{DataHandle, NodeResourceUsage, RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle},
-    util::bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        math::div_round_up,
+    },
     Renderer, ShaderConfig, ShaderPreProcessor,
 };
 use wgpu::{
-    BindGroup, BindGroupLayout, BindingType, ColorTargetState, ColorWrites, Device, FragmentState, FrontFace,
-    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline,
-    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat, TextureSampleType,
-    TextureViewDimension, VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupLayout, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device,
+    FragmentState, FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    TextureFormat, TextureSampleType, TextureViewDimension, VertexState,
 };
 
 use crate::common::WholeFrameInterfaces;
 
+/// Curve used to map exposed HDR values into displayable range.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TonemappingOperator {
+    /// Values are passed through and anything above 1 clips.
+    #[default]
+    None,
+    /// `x / (1 + x)` per channel. Desaturates highlights.
+    Reinhard,
+    /// John Hable's filmic curve from Uncharted 2.
+    Uncharted2,
+    /// Stephen Hill's fit of the ACES reference rendering and output transforms.
+    Aces,
+    /// Polynomial approximation of Troy Sobotka's AgX.
+    AgX,
+}
+
+impl TonemappingOperator {
+    fn shader_index(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Reinhard => 1,
+            Self::Uncharted2 => 2,
+            Self::Aces => 3,
+            Self::AgX => 4,
+        }
+    }
+}
+
+/// Settings of the histogram based automatic exposure.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoExposure {
+    /// log2 of the darkest luminance that is measured. Darker pixels are
+    /// ignored when averaging.
+    pub min_log_luminance: f32,
+    /// log2 of the brightest luminance that is measured. Brighter pixels are
+    /// treated as having this luminance.
+    pub max_log_luminance: f32,
+    /// How quickly the exposure adapts to a change in luminance. Higher is
+    /// faster, 0 never adapts.
+    pub adaptation_speed: f32,
+    /// Time since the last frame in seconds, used to make adaptation
+    /// independent of framerate.
+    pub delta_time: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self { min_log_luminance: -8.0, max_log_luminance: 4.0, adaptation_speed: 1.5, delta_time: 1.0 / 60.0 }
+    }
+}
+
+/// How the exposure of the HDR image is determined.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exposure {
+    /// The HDR image is multiplied by the given value.
+    Manual(f32),
+    /// The exposure is measured from the HDR image.
+    Automatic(AutoExposure),
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self::Manual(1.0)
+    }
+}
+
+/// Per-frame settings of the [`TonemappingRoutine`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TonemappingSettings {
+    pub operator: TonemappingOperator,
+    pub exposure: Exposure,
+    /// Exposure compensation in stops (EV). Every stop doubles the
+    /// brightness, negative values darken the image.
+    pub exposure_compensation: f32,
+}
+
+#[derive(ShaderType)]
+struct TonemappingUniform {
+    manual_exposure: f32,
+    compensation: f32,
+    automatic: u32,
+    operator_index: u32,
+}
+
+#[derive(ShaderType)]
+struct ExposureParameters {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    pixel_count: u32,
+}
+
 fn create_pipeline(
     device: &Device,
     spp: &ShaderPreProcessor,
@@ -73,15 +180,58 @@ fn create_pipeline(
     })
 }
 
+fn create_exposure_pipelines(
+    device: &Device,
+    spp: &ShaderPreProcessor,
+    bgl: &BindGroupLayout,
+) -> (ComputePipeline, ComputePipeline) {
+    profiling::scope!("ExposurePass::new");
+    let module = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("auto exposure"),
+        source: ShaderSource::Wgsl(Cow::Owned(
+            spp.render_shader("rend3-routine/exposure.wgsl", &ShaderConfig::default(), None).unwrap(),
+        )),
+    });
+
+    let pll = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("auto exposure pass"),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    let histogram = device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("luminance histogram"),
+        layout: Some(&pll),
+        module: &module,
+        entry_point: "cs_histogram",
+    });
+    let average = device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("luminance average"),
+        layout: Some(&pll),
+        module: &module,
+        entry_point: "cs_average",
+    });
+
+    (histogram, average)
+}
+
 /// HDR tonemapping routine.
 ///
 /// See module for documentation.
 pub struct TonemappingRoutine {
     bgl: BindGroupLayout,
     pipeline: RenderPipeline,
+    exposure_bgl: BindGroupLayout,
+    histogram_pipeline: ComputePipeline,
+    average_pipeline: ComputePipeline,
+    /// Adapted luminance of the automatic exposure. Persists across frames.
+    exposure_state: Buffer,
 }
 
 impl TonemappingRoutine {
+    const HISTOGRAM_BIN_COUNT: u64 = 256;
+    const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
     pub fn new(
         renderer: &Renderer,
         spp: &ShaderPreProcessor,
@@ -98,20 +248,70 @@ impl TonemappingRoutine {
                 },
                 None,
             )
+            .append_buffer(
+                ShaderStages::FRAGMENT,
+                BufferBindingType::Uniform,
+                false,
+                TonemappingUniform::SHADER_SIZE.get(),
+            )
+            .append_buffer(ShaderStages::FRAGMENT, BufferBindingType::Storage { read_only: true }, false, 4)
             .build(&renderer.device, Some("bind bgl"));
 
+        let exposure_bgl = BindGroupLayoutBuilder::new()
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Uniform,
+                false,
+                ExposureParameters::SHADER_SIZE.get(),
+            )
+            .append_buffer(
+                ShaderStages::COMPUTE,
+                BufferBindingType::Storage { read_only: false },
+                false,
+                Self::HISTOGRAM_BIN_COUNT * 4,
+            )
+            .append_buffer(ShaderStages::COMPUTE, BufferBindingType::Storage { read_only: false }, false, 4)
+            .append(
+                ShaderStages::COMPUTE,
+                BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                None,
+            )
+            .build(&renderer.device, Some("auto exposure bgl"));
+
         let pipeline = create_pipeline(&renderer.device, spp, interfaces, &bgl, output_format);
+        let (histogram_pipeline, average_pipeline) = create_exposure_pipelines(&renderer.device, spp, &exposure_bgl);
 
-        Self { bgl, pipeline }
+        // A negative luminance tells the shader to skip adaptation on the first frame.
+        let exposure_state = renderer.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("auto exposure state"),
+            contents: bytemuck::bytes_of(&-1.0_f32),
+            usage: BufferUsages::STORAGE,
+        });
+
+        Self { bgl, pipeline, exposure_bgl, histogram_pipeline, average_pipeline, exposure_state }
     }
 
+    /// Tonemap `src`, which has the given resolution, into `dst`.
+    ///
+    /// If the settings use automatic exposure, this also measures the
+    /// luminance of `src` and adapts the exposure towards it.
     pub fn add_to_graph<'node>(
         &'node self,
         graph: &mut RenderGraph<'node>,
         src: RenderTargetHandle,
         dst: RenderTargetHandle,
         forward_uniform_bg: DataHandle<BindGroup>,
+        resolution: UVec2,
+        settings: TonemappingSettings,
     ) {
+        if let Exposure::Automatic(auto_exposure) = settings.exposure {
+            self.add_auto_exposure_to_graph(graph, src, resolution, auto_exposure);
+        }
+
         let mut builder = graph.add_node("Tonemapping");
 
         let input_handle = builder.add_render_target(src, NodeResourceUsage::Input);
@@ -133,11 +333,36 @@ impl TonemappingRoutine {
 
             profiling::scope!("tonemapping");
 
-            let blit_src_bg = ctx.temps.add(BindGroupBuilder::new().append_texture_view(hdr_color).build(
-                &ctx.renderer.device,
-                Some("blit src bg"),
-                &self.bgl,
-            ));
+            let (manual_exposure, automatic) = match settings.exposure {
+                Exposure::Manual(exposure) => (exposure, false),
+                Exposure::Automatic(_) => (1.0, true),
+            };
+            let uniform_values = TonemappingUniform {
+                manual_exposure,
+                compensation: settings.exposure_compensation.exp2(),
+                automatic: automatic as u32,
+                operator_index: settings.operator.shader_index(),
+            };
+
+            let uniform_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("tonemapping uniform"),
+                size: TonemappingUniform::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = uniform_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&uniform_values).unwrap();
+            drop(mapping);
+            uniform_buffer.unmap();
+            let uniform_buffer = ctx.temps.add(uniform_buffer);
+
+            let blit_src_bg = ctx.temps.add(
+                BindGroupBuilder::new()
+                    .append_texture_view(hdr_color)
+                    .append_buffer(uniform_buffer)
+                    .append_buffer(&self.exposure_state)
+                    .build(&ctx.renderer.device, Some("blit src bg"), &self.bgl),
+            );
 
             rpass.set_pipeline(&self.pipeline);
             rpass.set_bind_group(0, forward_uniform_bg, &[]);
@@ -145,4 +370,71 @@ impl TonemappingRoutine {
             rpass.draw(0..3, 0..1);
         });
     }
+
+    fn add_auto_exposure_to_graph<'node>(
+        &'node self,
+        graph: &mut RenderGraph<'node>,
+        src: RenderTargetHandle,
+        resolution: UVec2,
+        settings: AutoExposure,
+    ) {
+        let mut builder = graph.add_node("Auto Exposure");
+
+        let input_handle = builder.add_render_target(src, NodeResourceUsage::Input);
+        // The adapted luminance lives outside of the graph.
+        builder.add_side_effect();
+
+        builder.build(move |mut ctx| {
+            let encoder = ctx.encoder_or_pass.take_encoder();
+            let hdr_color = ctx.graph_data.get_render_target(input_handle);
+
+            profiling::scope!("auto exposure");
+
+            let params = ExposureParameters {
+                min_log_luminance: settings.min_log_luminance,
+                log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(f32::EPSILON),
+                // Exponential decay so the adaptation is independent of the framerate.
+                adaptation: 1.0 - (-settings.delta_time * settings.adaptation_speed).exp(),
+                pixel_count: resolution.x * resolution.y,
+            };
+
+            let params_buffer = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("auto exposure parameters"),
+                size: ExposureParameters::SHADER_SIZE.get(),
+                usage: BufferUsages::UNIFORM,
+                mapped_at_creation: true,
+            });
+            let mut mapping = params_buffer.slice(..).get_mapped_range_mut();
+            UniformBuffer::new(&mut *mapping).write(&params).unwrap();
+            drop(mapping);
+            params_buffer.unmap();
+
+            // wgpu zero initializes buffers, so the histogram starts empty.
+            let histogram = ctx.renderer.device.create_buffer(&BufferDescriptor {
+                label: Some("luminance histogram"),
+                size: Self::HISTOGRAM_BIN_COUNT * 4,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            });
+
+            let bg = BindGroupBuilder::new()
+                .append_buffer(&params_buffer)
+                .append_buffer(&histogram)
+                .append_buffer(&self.exposure_state)
+                .append_texture_view(hdr_color)
+                .build(&ctx.renderer.device, Some("auto exposure bg"), &self.exposure_bgl);
+
+            let mut cpass = encoder
+                .begin_compute_pass(&ComputePassDescriptor { label: Some("Auto Exposure"), timestamp_writes: None });
+            cpass.set_pipeline(&self.histogram_pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups(
+                div_round_up(resolution.x, Self::HISTOGRAM_WORKGROUP_SIZE),
+                div_round_up(resolution.y, Self::HISTOGRAM_WORKGROUP_SIZE),
+                1,
+            );
+            cpass.set_pipeline(&self.average_pipeline);
+            cpass.dispatch_workgroups(1, 1, 1);
+        });
+    }
 }
@@ -10,7 +10,13 @@ use rend3::{
 };
 use wgpu::{BindGroup, BufferUsages};
 
-use crate::common::{Samplers, WholeFrameInterfaces};
+use crate::{
+    clustering::LightClusterRoutine,
+    common::{Samplers, WholeFrameInterfaces},
+    fog::FogSettings,
+    ibl::IblTextures,
+    reflection_probe::ReflectionProbeBindings,
+};
 
 #[derive(ShaderType)]
 pub struct PerCameraUniform {
@@ -31,9 +37,14 @@ pub struct FrameUniforms {
     pub inv_view: Mat4,
     pub inv_view_proj: Mat4,
     pub inv_origin_view_proj: Mat4,
+    /// View projection without the jitter of temporal anti-aliasing.
+    pub unjittered_view_proj: Mat4,
+    /// Unjittered view projection of the previous frame.
+    pub previous_view_proj: Mat4,
     pub frustum: Frustum,
     pub ambient: Vec4,
     pub resolution: UVec2,
+    pub fog: FogSettings,
 }
 impl FrameUniforms {
     /// Use the given camera to generate these uniforms.
@@ -51,9 +62,12 @@ impl FrameUniforms {
             inv_view: view.inverse(),
             inv_view_proj: view_proj.inverse(),
             inv_origin_view_proj: origin_view_proj.inverse(),
+            unjittered_view_proj: camera.unjittered_view_proj(),
+            previous_view_proj: camera.previous_view_proj(),
             frustum: Frustum::from_matrix(camera.proj()),
             ambient: info.ambient,
             resolution: info.resolution,
+            fog: info.fog.unwrap_or(FogSettings::NONE),
         }
     }
 }
@@ -66,6 +80,15 @@ pub struct UniformInformation<'node> {
     pub ambient: Vec4,
     /// Resolution of the viewport.
     pub resolution: UVec2,
+    /// Height fog, there is no fog if this is `None`.
+    pub fog: Option<FogSettings>,
+    /// Image based lighting textures.
+    pub ibl: &'node IblTextures,
+    /// Reflection probes which replace the image based lighting locally. Only
+    /// bound if the interfaces include them, like the other optional features.
+    pub reflection_probes: &'node ReflectionProbeBindings,
+    /// Point lights binned into clusters of the viewport camera.
+    pub light_clusters: &'node LightClusterRoutine,
 }
 
 pub struct UniformBindingHandles<'node> {
@@ -75,7 +98,8 @@ pub struct UniformBindingHandles<'node> {
     /// include the shadow map texture, preventing a cycle.
     pub shadow_uniform_bg: DataHandle<BindGroup>,
     /// The output bind group handle for the forward uniform data. This does
-    /// include the shadow map texture.
+    /// include the shadow map textures, the image based lighting textures, the
+    /// ambient occlusion and the optional reflection probes.
     pub forward_uniform_bg: DataHandle<BindGroup>,
 }
 
@@ -83,6 +107,8 @@ pub struct UniformBindingHandles<'node> {
 pub fn add_to_graph<'node>(
     graph: &mut RenderGraph<'node>,
     shadow_target: RenderTargetHandle,
+    shadow_moments: RenderTargetHandle,
+    ambient_occlusion: RenderTargetHandle,
     binding_handles: UniformBindingHandles<'node>,
     info: UniformInformation<'node>,
 ) {
@@ -90,12 +116,19 @@ pub fn add_to_graph<'node>(
     let shadow_handle = builder.add_data(binding_handles.shadow_uniform_bg, NodeResourceUsage::Output);
     let forward_handle = builder.add_data(binding_handles.forward_uniform_bg, NodeResourceUsage::Output);
 
-    // Get the shadow target and declare it a dependency of the forward_uniform_bg
+    // Get the shadow and ambient occlusion targets and declare them a dependency of the forward_uniform_bg
     let shadow_target_handle = builder.add_render_target(shadow_target, NodeResourceUsage::Reference);
-    builder.add_dependencies_to_render_targets(binding_handles.forward_uniform_bg, [shadow_target]);
+    let shadow_moments_handle = builder.add_render_target(shadow_moments, NodeResourceUsage::Reference);
+    let ambient_occlusion_handle = builder.add_render_target(ambient_occlusion, NodeResourceUsage::Reference);
+    builder.add_dependencies_to_render_targets(
+        binding_handles.forward_uniform_bg,
+        [shadow_target, shadow_moments, ambient_occlusion],
+    );
 
     builder.build(move |ctx| {
         let shadow_target = ctx.graph_data.get_render_target(shadow_target_handle);
+        let shadow_moments = ctx.graph_data.get_render_target(shadow_moments_handle);
+        let ambient_occlusion = ctx.graph_data.get_render_target(ambient_occlusion_handle);
 
         let mut bgb = BindGroupBuilder::new();
 
@@ -117,11 +150,37 @@ pub fn add_to_graph<'node>(
 
         ctx.data_core.directional_light_manager.add_to_bg(&mut bgb);
         ctx.data_core.point_light_manager.add_to_bg(&mut bgb);
+        // Must skip the same bindings as the layout.
+        let features = binding_handles.interfaces.features;
+        if features.spot_lights {
+            ctx.data_core.spot_light_manager.add_to_bg(&mut bgb);
+        } else {
+            bgb.skip(1);
+        }
 
         let shadow_uniform_bg =
             bgb.build(&ctx.renderer.device, Some("shadow uniform bg"), &binding_handles.interfaces.depth_uniform_bgl);
 
         bgb.append_texture_view(shadow_target);
+        info.ibl.add_to_bg(&mut bgb);
+        if features.light_clusters {
+            info.light_clusters.add_to_bg(&mut bgb);
+        } else {
+            bgb.skip(2);
+        }
+        if features.evsm {
+            bgb.append_texture_view(shadow_moments);
+        } else {
+            bgb.skip(1);
+        }
+        if features.ssao {
+            bgb.append_texture_view(ambient_occlusion);
+        } else {
+            bgb.skip(1);
+        }
+        if features.reflection_probes {
+            info.reflection_probes.add_to_bg(&mut bgb);
+        }
 
         let forward_uniform_bg = bgb.build(
             &ctx.renderer.device,
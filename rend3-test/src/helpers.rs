@@ -32,6 +32,12 @@ impl TestRunner {
             distance: 5.0,
             intensity: 1.0,
             direction,
+            cascade_count: 1,
+            cascade_split_lambda: 0.5,
+            shadow_bias: 0.0,
+            shadow_normal_offset: 0.0,
+            shadow_filter: rend3::types::ShadowFilter::Pcf,
+            baked: false,
         })
     }
 
@@ -79,6 +85,8 @@ impl TestRunner {
             mesh_kind: rend3::types::ObjectMeshKind::Static(self.add_mesh(mesh).unwrap()),
             material,
             transform,
+            cast_shadows: true,
+            receive_shadows: true,
         })
     }
 
@@ -135,6 +143,8 @@ impl TestRunner {
             mesh_kind: rend3::types::ObjectMeshKind::Static(self.add_mesh(mesh).unwrap()),
             material,
             transform,
+            cast_shadows: true,
+            receive_shadows: true,
         })
     }
 }
@@ -143,7 +143,12 @@ impl TestRunner {
                 routines: rend3_routine::base::BaseRenderGraphRoutines {
                     pbr: &self.pbr,
                     skybox: None,
+                    atmosphere: None,
+                    ibl: None,
+                    reflection_probes: None,
                     tonemapping: &self.tonemapping,
+                    fxaa: None,
+                    smaa: None,
                 },
                 target: rend3_routine::base::OutputRenderTarget {
                     handle: frame_handle,
@@ -154,6 +159,7 @@ impl TestRunner {
             rend3_routine::base::BaseRenderGraphSettings {
                 ambient_color: glam::Vec4::ZERO,
                 clear_color: glam::Vec4::ZERO,
+                ..Default::default()
             },
         );
 
@@ -0,0 +1,55 @@
+use anyhow::{ensure, Context};
+use glam::{Mat4, Vec3, Vec4};
+use rend3::types::{Camera, CameraProjection, Handedness};
+use rend3_routine::culling::GpuCuller;
+use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner};
+
+/// GPU culling must draw exactly what the CPU path draws, including once the
+/// culling buffers had to grow and when objects are outside of the frustum.
+#[test_attr]
+pub async fn gpu_matches_cpu() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(mut runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    if !GpuCuller::<rend3_routine::pbr::PbrMaterial>::supported(&runner.renderer) {
+        return Ok(());
+    }
+    ensure!(runner.pbr.culler.is_some());
+
+    let rows = 48;
+    runner.set_camera_data(Camera {
+        projection: CameraProjection::Raw(Mat4::orthographic_lh(0.0, 2.0, rows as f32, 0.0, 0.0, 1.0)),
+        view: Mat4::IDENTITY,
+    });
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let base_matrix = Mat4::from_translation(Vec3::new(0.5, 0.5, 0.0)) * Mat4::from_scale(Vec3::new(0.25, 0.5, 1.0));
+
+    let mut planes = Vec::new();
+    let mut gpu_frames = Vec::new();
+    // Each column holds `rows` objects in view and one outside of it, so the second column makes the buffers grow.
+    for x in 0..2 {
+        for y in 0..rows {
+            let translation = Mat4::from_translation(Vec3::new(x as f32, y as f32, 0.0));
+            planes.push(runner.plane(material.clone(), translation * base_matrix));
+        }
+        let outside = Mat4::from_translation(Vec3::new(x as f32 + 4.0, 0.0, 0.0));
+        planes.push(runner.plane(material.clone(), outside * base_matrix));
+
+        gpu_frames.push(runner.render_frame(FrameRenderSettings::new()).await?);
+    }
+
+    // Same objects, drawn from the CPU.
+    let culler = runner.pbr.culler.take();
+    let cpu_frame = runner.render_frame(FrameRenderSettings::new()).await?;
+    runner.pbr.culler = culler;
+
+    ensure!(gpu_frames[1] == cpu_frame, "GPU culled frame differs from the CPU culled frame");
+    ensure!(gpu_frames[0] != gpu_frames[1], "Second column wasn't drawn");
+
+    Ok(())
+}
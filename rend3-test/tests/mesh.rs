@@ -0,0 +1,176 @@
+use anyhow::Context;
+use glam::{Mat4, Vec3, Vec4};
+use rend3::{
+    managers::MeshUpdateError,
+    types::{
+        Camera, CameraProjection, Handedness, Mesh, MeshBuilder, MeshValidationError, Object, ObjectMeshKind,
+        VERTEX_ATTRIBUTE_POSITION,
+    },
+};
+use rend3_test::{test_attr, FrameRenderSettings, TestRunner};
+
+fn quad(size: f32) -> Mesh {
+    MeshBuilder::new(
+        vec![
+            Vec3::new(-size, -size, 0.0),
+            Vec3::new(-size, size, 0.0),
+            Vec3::new(size, size, 0.0),
+            Vec3::new(size, -size, 0.0),
+        ],
+        Handedness::Left,
+    )
+    .with_indices(vec![0, 2, 1, 0, 3, 2])
+    .build()
+    .unwrap()
+}
+
+async fn runner() -> anyhow::Result<Option<TestRunner>> {
+    let iad = match rend3::create_iad(None, None, None, None).await {
+        Err(rend3::RendererInitializationError::MissingAdapter) => {
+            eprintln!("No adapter found, skipping test");
+            return Ok(None);
+        }
+        iad => iad.context("InstanceAdapterDevice creation failed")?,
+    };
+
+    let Ok(runner) = TestRunner::builder().iad(iad).handedness(Handedness::Left).build().await else {
+        return Ok(None);
+    };
+
+    runner.set_camera_data(Camera { projection: CameraProjection::Raw(Mat4::IDENTITY), view: Mat4::IDENTITY });
+
+    Ok(Some(runner))
+}
+
+/// Updating a mesh in place must look the same as creating it with the new data.
+#[test_attr]
+pub async fn update() -> anyhow::Result<()> {
+    let Some(runner) = runner().await? else {
+        return Ok(());
+    };
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let mesh = runner.add_mesh(quad(0.5)).unwrap();
+    let object = runner.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(mesh.clone()),
+        material: material.clone(),
+        transform: Mat4::IDENTITY,
+        cast_shadows: false,
+        receive_shadows: false,
+    });
+    let before = runner.render_frame(FrameRenderSettings::new()).await?;
+
+    runner.update_mesh(&mesh, quad(0.25)).unwrap();
+    let updated = runner.render_frame(FrameRenderSettings::new()).await?;
+    assert!(before != updated, "Update didn't change the mesh");
+
+    drop(object);
+    let _object = runner.plane(material, Mat4::from_scale(Vec3::splat(0.25)));
+    let expected = runner.render_frame(FrameRenderSettings::new()).await?;
+    assert!(updated == expected, "Updated mesh differs from a new mesh");
+
+    Ok(())
+}
+
+#[test_attr]
+pub async fn update_errors() -> anyhow::Result<()> {
+    let Some(runner) = runner().await? else {
+        return Ok(());
+    };
+
+    let mesh = runner.add_mesh(quad(0.5)).unwrap();
+
+    let triangle = MeshBuilder::new(
+        vec![Vec3::new(-0.5, -0.5, 0.0), Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.5, -0.5, 0.0)],
+        Handedness::Left,
+    )
+    .build()
+    .unwrap();
+    assert!(matches!(
+        runner.update_mesh(&mesh, triangle),
+        Err(MeshUpdateError::MismatchedVertexCount { expected: 4, actual: 3 })
+    ));
+
+    let mut fewer_indices = quad(0.5);
+    fewer_indices.indices.truncate(3);
+    assert!(matches!(
+        runner.update_mesh(&mesh, fewer_indices),
+        Err(MeshUpdateError::MismatchedIndexCount { expected: 6, actual: 3 })
+    ));
+
+    // Meshes built outside of the builder are validated too.
+    let mut out_of_bounds = quad(0.5);
+    out_of_bounds.indices[0] = 4;
+    assert!(matches!(
+        runner.update_mesh(&mesh, out_of_bounds),
+        Err(MeshUpdateError::InvalidMesh { inner: MeshValidationError::IndexOutOfBounds { index: 0, value: 4, .. } })
+    ));
+
+    let mut short_attribute = quad(0.5);
+    short_attribute.vertex_count = 3;
+    assert!(matches!(
+        runner.update_mesh(&mesh, short_attribute),
+        Err(MeshUpdateError::InvalidMesh { inner: MeshValidationError::MismatchedVertexCount { .. } })
+    ));
+
+    Ok(())
+}
+
+/// Writing a part of an attribute must look the same as creating the mesh with the new data.
+#[test_attr]
+pub async fn write_attribute() -> anyhow::Result<()> {
+    let Some(runner) = runner().await? else {
+        return Ok(());
+    };
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let mesh = runner.add_mesh(quad(0.5)).unwrap();
+    let object = runner.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(mesh.clone()),
+        material: material.clone(),
+        transform: Mat4::IDENTITY,
+        cast_shadows: false,
+        receive_shadows: false,
+    });
+
+    // Only the last two vertices, the right half of the quad.
+    runner
+        .write_mesh_attribute(
+            &mesh,
+            &VERTEX_ATTRIBUTE_POSITION,
+            2,
+            &[Vec3::new(0.25, 0.25, 0.0), Vec3::new(0.25, -0.25, 0.0)],
+        )
+        .unwrap();
+    let written = runner.render_frame(FrameRenderSettings::new()).await?;
+
+    drop(object);
+    let expected_mesh = MeshBuilder::new(
+        vec![
+            Vec3::new(-0.5, -0.5, 0.0),
+            Vec3::new(-0.5, 0.5, 0.0),
+            Vec3::new(0.25, 0.25, 0.0),
+            Vec3::new(0.25, -0.25, 0.0),
+        ],
+        Handedness::Left,
+    )
+    .with_indices(vec![0, 2, 1, 0, 3, 2])
+    .build()
+    .unwrap();
+    let _object = runner.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(runner.add_mesh(expected_mesh).unwrap()),
+        material,
+        transform: Mat4::IDENTITY,
+        cast_shadows: false,
+        receive_shadows: false,
+    });
+    let expected = runner.render_frame(FrameRenderSettings::new()).await?;
+    assert!(written == expected, "Written mesh differs from a new mesh");
+
+    assert!(matches!(
+        runner.write_mesh_attribute(&mesh, &VERTEX_ATTRIBUTE_POSITION, 3, &[Vec3::ZERO, Vec3::ZERO]),
+        Err(MeshUpdateError::WriteOutOfBounds { start: 3, end: 5, vertex_count: 4 })
+    ));
+
+    Ok(())
+}
@@ -1,9 +1,11 @@
 use anyhow::Context;
-use glam::{Mat4, Quat, Vec3, Vec4};
+use glam::{Mat4, Quat, UVec2, Vec3, Vec4};
 use rend3::{
-    types::{Camera, Handedness, ObjectChange},
+    managers::{CameraSpecifier, CameraState, ShadowDesc, ShadowMap, ShadowSource},
+    types::{Camera, CameraProjection, Handedness, ObjectChange, RawSpotLightHandle, ShadowFilter},
     util::freelist::FreelistDerivedBuffer,
 };
+use rend3_routine::pbr::PbrMaterial;
 use rend3_test::{no_gpu_return, test_attr, FrameRenderSettings, TestRunner, Threshold};
 
 /// Ensure that duplicate_object doesn't retain the object for an extra frame.
@@ -107,3 +109,69 @@ pub async fn multi_frame_add() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn ortho_camera(left: f32, right: f32, top: f32, bottom: f32) -> Camera {
+    Camera {
+        projection: CameraProjection::Raw(Mat4::orthographic_lh(left, right, bottom, top, -1.0, 1.0)),
+        view: Mat4::IDENTITY,
+    }
+}
+
+/// Culling is tracked per camera, and the visible objects of each camera
+/// are only the ones inside of its frustum.
+#[test_attr]
+pub async fn cull_per_camera() -> anyhow::Result<()> {
+    let iad = no_gpu_return!(rend3::create_iad(None, None, None, None).await)
+        .context("InstanceAdapterDevice creation failed")?;
+
+    let Ok(runner) = TestRunner::builder().iad(iad.clone()).handedness(Handedness::Left).build().await else {
+        return Ok(());
+    };
+
+    runner.set_camera_data(ortho_camera(0.0, 2.0, 0.0, 2.0));
+
+    let material = runner.add_unlit_material(Vec4::ONE);
+    let scale = Mat4::from_scale(Vec3::splat(0.25));
+    let inside = runner.plane(material.clone(), Mat4::from_translation(Vec3::new(1.0, 1.0, 0.0)) * scale);
+    let outside = runner.plane(material, Mat4::from_translation(Vec3::new(10.0, 10.0, 0.0)) * scale);
+
+    // Rendering evaluates the objects and culls them against the viewport.
+    runner.render_frame(FrameRenderSettings::new()).await?;
+
+    let mut data_core = runner.data_core.lock();
+    let object_manager = &data_core.object_manager;
+
+    assert_eq!(object_manager.enumerated_objects::<PbrMaterial>().unwrap().len(), 2);
+    let visible: Vec<_> = object_manager
+        .enumerated_visible_objects::<PbrMaterial>(CameraSpecifier::Viewport)
+        .unwrap()
+        .map(|(handle, _)| handle)
+        .collect();
+    assert_eq!(visible, [inside.get_raw()]);
+
+    // A shadow camera which only sees the other object.
+    let shadow = ShadowDesc {
+        map: ShadowMap {
+            offset: UVec2::ZERO,
+            size: 64,
+            source: ShadowSource::Spot { handle: RawSpotLightHandle::new(0) },
+        },
+        camera: CameraState::new(ortho_camera(9.0, 11.0, 9.0, 11.0), Handedness::Left, None),
+        filter: ShadowFilter::default(),
+        dirty: true,
+    };
+    let viewport = data_core.viewport_camera_state.clone();
+    let culled = data_core.object_manager.cull(&viewport, &[shadow]);
+    assert_eq!(culled, [1, 1]);
+
+    let object_manager = &data_core.object_manager;
+    let visible_to = |camera| -> Vec<_> {
+        object_manager.enumerated_visible_objects::<PbrMaterial>(camera).unwrap().map(|(handle, _)| handle).collect()
+    };
+    assert_eq!(visible_to(CameraSpecifier::Viewport), [inside.get_raw()]);
+    assert_eq!(visible_to(CameraSpecifier::Shadow(0)), [outside.get_raw()]);
+    // Cameras which weren't culled against see everything.
+    assert_eq!(visible_to(CameraSpecifier::Shadow(1)).len(), 2);
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+mod culling;
+mod mesh;
 mod msaa;
 mod object;
 mod shadow;
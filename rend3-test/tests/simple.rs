@@ -59,8 +59,13 @@ pub async fn triangle() -> anyhow::Result<()> {
 
         let mesh_hdl = runner.add_mesh(mesh).unwrap();
         let material_hdl = runner.add_unlit_material(Vec4::new(0.25, 0.5, 0.75, 1.0));
-        let object =
-            Object { mesh_kind: ObjectMeshKind::Static(mesh_hdl), material: material_hdl, transform: Mat4::IDENTITY };
+        let object = Object {
+            mesh_kind: ObjectMeshKind::Static(mesh_hdl),
+            material: material_hdl,
+            transform: Mat4::IDENTITY,
+            cast_shadows: true,
+            receive_shadows: true,
+        };
         let _object_hdl = runner.add_object(object);
 
         runner.set_camera_data(Camera {
@@ -120,8 +125,13 @@ pub async fn coordinate_space() -> anyhow::Result<()> {
 
         let mesh_hdl = runner.add_mesh(mesh).unwrap();
         let material_hdl = runner.add_unlit_material(color.extend(1.0));
-        let object =
-            Object { mesh_kind: ObjectMeshKind::Static(mesh_hdl), material: material_hdl, transform: Mat4::IDENTITY };
+        let object = Object {
+            mesh_kind: ObjectMeshKind::Static(mesh_hdl),
+            material: material_hdl,
+            transform: Mat4::IDENTITY,
+            cast_shadows: true,
+            receive_shadows: true,
+        };
         runner.add_object(object)
     });
 
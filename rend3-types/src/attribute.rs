@@ -133,3 +133,7 @@ pub static VERTEX_ATTRIBUTE_COLOR_0: VertexAttribute<[u8; 4]> = VertexAttribute:
 pub static VERTEX_ATTRIBUTE_COLOR_1: VertexAttribute<[u8; 4]> = VertexAttribute::new("color_1", Some("vec4<f32>(1.0)"));
 pub static VERTEX_ATTRIBUTE_JOINT_INDICES: VertexAttribute<[u16; 4]> = VertexAttribute::new("joint_indices", None);
 pub static VERTEX_ATTRIBUTE_JOINT_WEIGHTS: VertexAttribute<glam::Vec4> = VertexAttribute::new("joint_weights", None);
+/// Position of the vertex in the previous frame, used for motion vectors. It is only written by
+/// skinning, objects whose mesh lacks it read the current position instead.
+pub static VERTEX_ATTRIBUTE_PREVIOUS_POSITION: VertexAttribute<glam::Vec3> =
+    VertexAttribute::new("previous_position", None);
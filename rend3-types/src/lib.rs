@@ -179,6 +179,10 @@ pub type ObjectHandle = ResourceHandle<Object>;
 pub type DirectionalLightHandle = ResourceHandle<DirectionalLight>;
 /// Refcounted handle to a PointLight
 pub type PointLightHandle = ResourceHandle<PointLight>;
+/// Refcounted handle to a SpotLight
+pub type SpotLightHandle = ResourceHandle<SpotLight>;
+/// Refcounted handle to a ReflectionProbe
+pub type ReflectionProbeHandle = ResourceHandle<ReflectionProbe>;
 /// Refcounted handle to a Skeleton
 pub type SkeletonHandle = ResourceHandle<Skeleton>;
 /// Refcounted handle to an instance of GraphData with the type erased
@@ -212,6 +216,10 @@ pub type RawObjectHandle = RawResourceHandle<Object>;
 pub type RawDirectionalLightHandle = RawResourceHandle<DirectionalLight>;
 /// Internal non-owning handle to a PointLight
 pub type RawPointLightHandle = RawResourceHandle<PointLight>;
+/// Internal non-owning handle to a SpotLight
+pub type RawSpotLightHandle = RawResourceHandle<SpotLight>;
+/// Internal non-owning handle to a ReflectionProbe
+pub type RawReflectionProbeHandle = RawResourceHandle<ReflectionProbe>;
 /// Internal non-owning handle to a Skeleton
 pub type RawSkeletonHandle = RawResourceHandle<Skeleton>;
 /// Internal non-owning handle to an instance of GraphData with the type erased
@@ -279,6 +287,8 @@ pub enum MeshValidationError {
         "Index at position {index} has the value {value} which is out of bounds for vertex buffers of {max} length"
     )]
     IndexOutOfBounds { index: usize, value: u32, max: u32 },
+    #[error("Mesh's morph target {target} has {actual} vertices but the position buffer has {expected}")]
+    MismatchedMorphTargetVertexCount { target: usize, expected: usize, actual: usize },
 }
 
 #[derive(Debug)]
@@ -331,12 +341,37 @@ impl StoredVertexAttributeData {
 unsafe impl Send for StoredVertexAttributeData {}
 unsafe impl Sync for StoredVertexAttributeData {}
 
+/// A set of per-vertex offsets which can be blended onto a [`Mesh`].
+///
+/// Each array, if present, must be the same length as the vertex count of the
+/// mesh. The offsets are multiplied by the weight of the target and added to
+/// the base mesh before skinning. Weights are set through a [`Skeleton`].
+#[derive(Debug, Default, Clone)]
+pub struct MorphTarget {
+    pub position_offsets: Option<Vec<Vec3>>,
+    pub normal_offsets: Option<Vec<Vec3>>,
+    pub tangent_offsets: Option<Vec<Vec3>>,
+}
+
+impl MorphTarget {
+    /// Returns the offsets for the given attribute, if the attribute can be morphed and this target has them.
+    pub fn offsets(&self, attribute: &VertexAttributeId) -> Option<&[Vec3]> {
+        match attribute {
+            a if *a == *VERTEX_ATTRIBUTE_POSITION => self.position_offsets.as_deref(),
+            a if *a == *VERTEX_ATTRIBUTE_NORMAL => self.normal_offsets.as_deref(),
+            a if *a == *VERTEX_ATTRIBUTE_TANGENT => self.tangent_offsets.as_deref(),
+            _ => None,
+        }
+    }
+}
+
 /// Easy to use builder for a [`Mesh`] that deals with common operations for
 /// you.
 #[derive(Debug, Default)]
 pub struct MeshBuilder {
     vertex_attributes: Vec<StoredVertexAttributeData>,
     vertex_count: usize,
+    morph_targets: Vec<MorphTarget>,
 
     indices: Option<Vec<u32>>,
     without_validation: bool,
@@ -426,6 +461,15 @@ impl MeshBuilder {
         self.with_attribute(&VERTEX_ATTRIBUTE_JOINT_WEIGHTS, joint_weights)
     }
 
+    /// Add a morph target to the given mesh.
+    ///
+    /// [`Self::build`] returns [`MeshValidationError::MismatchedMorphTargetVertexCount`] if the length of any
+    /// offset array is different from the position buffer length.
+    pub fn with_morph_target(mut self, target: MorphTarget) -> Self {
+        self.morph_targets.push(target);
+        self
+    }
+
     /// Add indices to the given mesh.
     ///
     /// # Panic
@@ -479,6 +523,7 @@ impl MeshBuilder {
             attributes: self.vertex_attributes,
             vertex_count: self.vertex_count,
             indices: self.indices.unwrap_or_else(|| (0..self.vertex_count as u32).collect()),
+            morph_targets: self.morph_targets,
         };
 
         if self.double_sided {
@@ -526,6 +571,8 @@ pub struct Mesh {
     pub vertex_count: usize,
 
     pub indices: Vec<u32>,
+
+    pub morph_targets: Vec<MorphTarget>,
 }
 
 impl Mesh {
@@ -549,6 +596,19 @@ impl Mesh {
             }
         }
 
+        for (target, morph_target) in self.morph_targets.iter().enumerate() {
+            let offsets = [&morph_target.position_offsets, &morph_target.normal_offsets, &morph_target.tangent_offsets];
+            for offsets in IntoIterator::into_iter(offsets).flatten() {
+                if offsets.len() != position_length {
+                    return Err(MeshValidationError::MismatchedMorphTargetVertexCount {
+                        target,
+                        actual: offsets.len(),
+                        expected: position_length,
+                    });
+                }
+            }
+        }
+
         if indices_length % 3 != 0 {
             return Err(MeshValidationError::IndexCountNotMultipleOfThree { count: indices_length });
         }
@@ -1070,6 +1130,10 @@ changeable_struct! {
         pub mesh_kind: ObjectMeshKind,
         pub material: MaterialHandle,
         pub transform: Mat4,
+        /// Whether the object is drawn into shadow maps.
+        pub cast_shadows: bool,
+        /// Whether shadows are applied when shading the object.
+        pub receive_shadows: bool,
     }
 }
 
@@ -1116,9 +1180,49 @@ changeable_struct! {
         pub direction: Vec3,
         /// Distance from the camera that shadows should be calculated.
         pub distance: f32,
+        /// Amount of shadow cascades to split `distance` into. Clamped to [1, 4].
+        pub cascade_count: u8,
+        /// Blend between a linear (0.0) and logarithmic (1.0) distribution of the
+        /// cascade split distances.
+        pub cascade_split_lambda: f32,
+        /// Distance (in world units) receivers are moved towards the light before
+        /// being compared against the shadow map. Raise this to fight shadow acne.
+        pub shadow_bias: f32,
+        /// Distance (in shadow map texels) receivers are moved along their surface
+        /// normal before being compared against the shadow map. Fights acne on
+        /// surfaces at a grazing angle to the light with less peter-panning than
+        /// `shadow_bias`.
+        pub shadow_normal_offset: f32,
+        /// How the edges of the shadow are filtered.
+        pub shadow_filter: ShadowFilter,
+        /// Whether the light is part of the baked lightmaps. Baked lights
+        /// still light everything else, but are skipped when shading
+        /// lightmapped materials so they aren't applied twice.
+        pub baked: bool,
     }
 }
 
+/// How the shadow map of a light is filtered when it is sampled.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum ShadowFilter {
+    /// A single comparison. Edges are hard and aliased.
+    Hard,
+    /// Percentage closer filtering over a small fixed kernel.
+    #[default]
+    Pcf,
+    /// Percentage closer soft shadows. Penumbras grow with the distance between
+    /// the occluder and the receiver, like those of a real light source.
+    Pcss {
+        /// Angular diameter (in degrees) of the light source as seen from the
+        /// receivers. The sun is about 0.5 degrees.
+        angular_diameter: f32,
+    },
+    /// Exponential variance shadow maps. The shadow map is converted into
+    /// exponential moments and blurred, giving smooth, cheap to sample edges at
+    /// the cost of some light bleeding where occluders overlap.
+    Evsm,
+}
+
 changeable_struct! {
     /// Describes how point lights and their shadows should be processed.
     pub struct PointLight <- PointLightChange {
@@ -1133,6 +1237,78 @@ changeable_struct! {
 
         /// Constant multiplier for the light.
         pub intensity: f32,
+
+        /// Resolution of each face of the shadow cube (in pix). Must be a power of two.
+        /// If `None`, the light does not cast shadows.
+        pub shadow_resolution: Option<u16>,
+
+        /// Whether the light is part of the baked lightmaps. Baked lights
+        /// still light everything else, but are skipped when shading
+        /// lightmapped materials so they aren't applied twice.
+        pub baked: bool,
+    }
+}
+
+changeable_struct! {
+    /// Describes how spot lights and their shadows should be processed.
+    pub struct SpotLight <- SpotLightChange {
+        /// The position of the light in the world.
+        pub position: Vec3,
+
+        /// The direction the light is pointing in. A zero direction is
+        /// replaced with straight down.
+        pub direction: Vec3,
+
+        /// The color of the light.
+        pub color: Vec3,
+
+        /// The radius of the light.
+        pub radius: f32,
+
+        /// Constant multiplier for the light.
+        pub intensity: f32,
+
+        /// Angle from the direction (in degrees) at which the light starts to fall off.
+        /// Clamped to be less than `outer_angle`.
+        pub inner_angle: f32,
+
+        /// Angle from the direction (in degrees) at which the light has fully fallen off.
+        /// Clamped between 0.1 and 89 degrees.
+        pub outer_angle: f32,
+
+        /// Resolution of the shadow map (in pix). Must be a power of two.
+        /// If `None`, the light does not cast shadows.
+        pub shadow_resolution: Option<u16>,
+
+        /// Whether the light is part of the baked lightmaps. Baked lights
+        /// still light everything else, but are skipped when shading
+        /// lightmapped materials so they aren't applied twice.
+        pub baked: bool,
+    }
+}
+
+changeable_struct! {
+    /// Describes a local environment which replaces the global environment in
+    /// the reflections of everything inside a box.
+    pub struct ReflectionProbe <- ReflectionProbeChange {
+        /// The position the environment is captured from.
+        pub position: Vec3,
+
+        /// Minimum corner of the box the probe applies to. Reflections are
+        /// projected onto the box, so it should match the walls of the room the
+        /// probe is in.
+        pub box_min: Vec3,
+
+        /// Maximum corner of the box the probe applies to.
+        pub box_max: Vec3,
+
+        /// Distance (in world units) inside the box over which the probe fades
+        /// into the surrounding probes and the global environment.
+        pub blend_distance: f32,
+
+        /// Environment of the probe. If `None`, the environment needs to be
+        /// captured by rendering the scene from `position`.
+        pub texture: Option<TextureCubeHandle>,
     }
 }
 
@@ -1202,7 +1378,8 @@ impl Default for Handedness {
     }
 }
 
-/// A Skeleton stores the necessary data to do vertex skinning for an [Object].
+/// A Skeleton stores the necessary data to do vertex skinning and morph
+/// target blending for an [Object].
 #[derive(Debug, Clone)]
 pub struct Skeleton {
     /// Stores one transformation matrix for each joint. These are the
@@ -1212,7 +1389,13 @@ pub struct Skeleton {
     ///
     /// The `Skeleton::form_joint_transforms` constructor can be used to create
     /// a Skeleton with the joint transform matrices instead.
+    ///
+    /// May be empty if the mesh has no joints and is only deformed by morph
+    /// targets.
     pub joint_matrices: Vec<Mat4>,
+    /// One weight per morph target of the mesh. Missing weights are treated as
+    /// zero.
+    pub morph_weights: Vec<f32>,
     pub mesh: MeshHandle,
 }
 
@@ -1225,7 +1408,7 @@ impl Skeleton {
         inverse_bind_transforms: &[Mat4],
     ) -> Skeleton {
         let joint_matrices = Self::compute_joint_matrices(joint_global_transforms, inverse_bind_transforms);
-        Skeleton { joint_matrices, mesh }
+        Skeleton { joint_matrices, morph_weights: Vec::new(), mesh }
     }
 
     /// Given a list of joint global positions and another one with inverse bind
@@ -1264,3 +1447,35 @@ macro_rules! trait_supertrait_alias {
         impl<T: $($supertraits)*> $name for T {}
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{Handedness, MeshBuilder, MeshValidationError, MorphTarget};
+
+    fn triangle() -> MeshBuilder {
+        MeshBuilder::new(vec![Vec3::ZERO, Vec3::X, Vec3::Y], Handedness::Left)
+    }
+
+    #[test]
+    fn morph_target_matching_vertex_count() {
+        let target = MorphTarget { position_offsets: Some(vec![Vec3::Z; 3]), ..Default::default() };
+        let mesh = triangle().with_morph_target(target).build().unwrap();
+        assert_eq!(mesh.morph_targets.len(), 1);
+    }
+
+    #[test]
+    fn morph_target_mismatched_vertex_count() {
+        let target = MorphTarget {
+            position_offsets: Some(vec![Vec3::Z; 3]),
+            normal_offsets: Some(vec![Vec3::Z; 2]),
+            ..Default::default()
+        };
+        let error = triangle().with_morph_target(MorphTarget::default()).with_morph_target(target).build().unwrap_err();
+        assert!(matches!(
+            error,
+            MeshValidationError::MismatchedMorphTargetVertexCount { target: 1, expected: 3, actual: 2 }
+        ));
+    }
+}
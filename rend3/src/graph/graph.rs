@@ -22,7 +22,7 @@ use crate::{
         RenderPassTargets, RenderTargetDescriptor, RenderTargetHandle, RpassTemporaryPool, TextureRegion,
     },
     managers::{ShadowDesc, TextureManagerEvaluateOutput},
-    util::typedefs::{FastHashMap, FastHashSet, RendererStatistics, SsoString},
+    util::typedefs::{FastHashMap, FastHashSet, SsoString},
     Renderer,
 };
 
@@ -32,8 +32,26 @@ pub struct InstructionEvaluationOutput {
     pub d2_texture: TextureManagerEvaluateOutput,
     pub d2c_texture: TextureManagerEvaluateOutput,
     pub shadow_target_size: UVec2,
+    /// Texture the shadow maps live in, which keeps its contents between
+    /// frames. Only the shadow maps in `shadows` which are dirty need to be
+    /// rendered.
+    pub shadow_atlas: Arc<Texture>,
     pub shadows: Vec<ShadowDesc>,
     pub mesh_buffer: Arc<Buffer>,
+    /// Amount of objects frustum culled on the CPU for every camera. The
+    /// viewport comes first, followed by the cameras in `shadows`.
+    pub culled_objects: Vec<usize>,
+}
+
+/// Statistics about a single execution of the render graph.
+#[derive(Debug, Clone)]
+pub struct RendererStatistics {
+    /// Timings of every node, if timestamp queries are supported. As the GPU
+    /// runs behind the CPU, these are for a frame a few frames in the past.
+    pub gpu_timers: Option<Vec<wgpu_profiler::GpuTimerQueryResult>>,
+    /// Amount of objects frustum culled on the CPU for every camera. The
+    /// viewport comes first, followed by the cameras in `shadows`.
+    pub culled_objects: Vec<usize>,
 }
 
 pub trait AsTextureReference {
@@ -52,6 +70,21 @@ impl AsTextureReference for SurfaceTexture {
     }
 }
 
+/// A texture which lives outside of the graph.
+pub(super) enum ImportedTexture<'node> {
+    Borrowed(&'node dyn AsTextureReference),
+    Shared(Arc<Texture>),
+}
+
+impl AsTextureReference for ImportedTexture<'_> {
+    fn as_texture_ref(&self) -> &Texture {
+        match self {
+            Self::Borrowed(texture) => texture.as_texture_ref(),
+            Self::Shared(texture) => texture,
+        }
+    }
+}
+
 pub(super) struct DataContents {
     // Any is RefCell<Option<T>> where T is the stored data
     pub(super) inner: Box<dyn Any>,
@@ -74,7 +107,7 @@ struct ResourceSpan {
 /// Implementation of a rendergraph. See module docs for details.
 pub struct RenderGraph<'node> {
     pub(super) targets: Vec<RenderTargetDescriptor>,
-    pub(super) imported_targets: Vec<&'node dyn AsTextureReference>,
+    pub(super) imported_targets: Vec<ImportedTexture<'node>>,
     pub(super) data: Vec<DataContents>,
     pub(super) nodes: Vec<RenderGraphNode<'node>>,
 }
@@ -124,6 +157,28 @@ impl<'node> RenderGraph<'node> {
         layers: Range<u32>,
         mips: Range<u8>,
         viewport: ViewportRect,
+    ) -> RenderTargetHandle {
+        self.import_render_target(ImportedTexture::Borrowed(texture), layers, mips, viewport)
+    }
+
+    /// Same as [`Self::add_imported_render_target`], but shares ownership of
+    /// the texture with the graph instead of borrowing it.
+    pub fn add_shared_render_target(
+        &mut self,
+        texture: Arc<Texture>,
+        layers: Range<u32>,
+        mips: Range<u8>,
+        viewport: ViewportRect,
+    ) -> RenderTargetHandle {
+        self.import_render_target(ImportedTexture::Shared(texture), layers, mips, viewport)
+    }
+
+    fn import_render_target(
+        &mut self,
+        texture: ImportedTexture<'node>,
+        layers: Range<u32>,
+        mips: Range<u8>,
+        viewport: ViewportRect,
     ) -> RenderTargetHandle {
         let idx = self.imported_targets.len();
         self.imported_targets.push(texture);
@@ -163,7 +218,7 @@ impl<'node> RenderGraph<'node> {
         mut self,
         renderer: &'node Arc<Renderer>,
         eval_output: &'node mut InstructionEvaluationOutput,
-    ) -> Option<RendererStatistics> {
+    ) -> RendererStatistics {
         profiling::scope!("RenderGraph::execute");
 
         // Because data handles have dependencies, we flatten the inputs and outputs ahead of time to simplify things.
@@ -330,6 +385,7 @@ impl<'node> RenderGraph<'node> {
                             array_layer_count: Some(region.layer_end - region.layer_start),
                             base_mip_level: region.mip_start as u32,
                             mip_level_count: Some((region.mip_end - region.mip_start) as u32),
+                            dimension: region.view_dimension(),
                             ..TextureViewDescriptor::default()
                         });
                         vacant.insert(view);
@@ -343,6 +399,7 @@ impl<'node> RenderGraph<'node> {
                                 array_layer_count: Some(region.layer_end - region.layer_start),
                                 base_mip_level: region.mip_start as u32,
                                 mip_level_count: Some((region.mip_end - region.mip_start) as u32),
+                                dimension: region.view_dimension(),
                                 ..TextureViewDescriptor::default()
                             });
                         vacant.insert(view);
@@ -430,14 +487,11 @@ impl<'node> RenderGraph<'node> {
                     Some(ref mut rpass) => {
                         let rpass_desc = node.rpass.unwrap();
 
-                        let viewport = rpass_desc
-                            .targets
-                            .first()
-                            .map_or_else(
-                                || rpass_desc.depth_stencil.as_ref().unwrap().target.to_region(),
-                                |t| t.color.to_region(),
-                            )
-                            .viewport;
+                        let region = rpass_desc.targets.first().map_or_else(
+                            || rpass_desc.depth_stencil.as_ref().unwrap().target.to_region(),
+                            |t| t.color.to_region(),
+                        );
+                        let viewport = region.viewport.mip(region.mip_start);
 
                         rpass.set_viewport(
                             viewport.offset.x as f32,
@@ -508,14 +562,15 @@ impl<'node> RenderGraph<'node> {
         eval_output.cmd_bufs.push(resolve_encoder.finish());
 
         renderer.queue.submit(eval_output.cmd_bufs.drain(..));
+        data_core.shadow_manager.submitted();
 
         data_core.profiler.try_lock().unwrap().end_frame().unwrap();
 
         // This variable seems superfluous, but solves borrow checker issues with the borrow of data_core.
-        let timers =
+        let gpu_timers =
             data_core.profiler.try_lock().unwrap().process_finished_frame(renderer.queue.get_timestamp_period());
 
-        timers
+        RendererStatistics { gpu_timers, culled_objects: eval_output.culled_objects.clone() }
     }
 
     #[allow(clippy::too_many_arguments)]
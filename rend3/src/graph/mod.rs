@@ -47,7 +47,7 @@ use std::ops::Range;
 
 use glam::{UVec2, Vec4};
 use rend3_types::{SampleCount, TextureFormat, TextureUsages};
-use wgpu::{Extent3d, TextureDimension, TextureView};
+use wgpu::{Extent3d, TextureDimension, TextureView, TextureViewDimension};
 
 use crate::util::typedefs::SsoString;
 
@@ -146,6 +146,14 @@ pub(super) struct TextureRegion {
     viewport: ViewportRect,
 }
 
+impl TextureRegion {
+    /// Single layers are viewed as plain 2D textures, even if the texture has
+    /// more layers, so they can be used as render attachments.
+    pub(super) fn view_dimension(&self) -> Option<TextureViewDimension> {
+        (self.layer_end - self.layer_start == 1).then_some(TextureViewDimension::D2)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 enum GraphSubResource {
     ImportedTexture(TextureRegion),
@@ -179,6 +187,12 @@ impl ViewportRect {
     pub fn from_size(size: UVec2) -> Self {
         Self::new(UVec2::ZERO, size)
     }
+
+    /// The same rect on a smaller mip of the texture. Viewports are always
+    /// specified in terms of mip 0.
+    pub fn mip(&self, mip: u8) -> Self {
+        Self::new(self.offset >> mip as u32, (self.size >> mip as u32).max(UVec2::ONE))
+    }
 }
 
 /// Handle to a graph-stored render target.
@@ -320,3 +334,35 @@ pub struct RenderPassDepthTarget {
     /// use.
     pub stencil_clear: Option<u32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::UVec2;
+    use wgpu::TextureViewDimension;
+
+    use super::{TextureRegion, ViewportRect};
+
+    fn region(layer_start: u32, layer_end: u32) -> TextureRegion {
+        TextureRegion {
+            idx: 0,
+            layer_start,
+            layer_end,
+            mip_start: 0,
+            mip_end: 1,
+            viewport: ViewportRect::from_size(UVec2::splat(256)),
+        }
+    }
+
+    #[test]
+    fn single_layer_viewed_as_2d() {
+        assert_eq!(region(0, 1).view_dimension(), Some(TextureViewDimension::D2));
+        // Layers of cube textures, like the faces of reflection probe captures.
+        assert_eq!(region(5, 6).view_dimension(), Some(TextureViewDimension::D2));
+    }
+
+    #[test]
+    fn multiple_layers_keep_texture_dimension() {
+        assert_eq!(region(0, 2).view_dimension(), None);
+        assert_eq!(region(0, 6).view_dimension(), None);
+    }
+}
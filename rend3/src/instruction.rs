@@ -1,17 +1,19 @@
 use std::{mem, panic::Location};
 
-use glam::Mat4;
+use glam::{Mat4, Vec2};
 use parking_lot::Mutex;
 use rend3_types::{
     trait_supertrait_alias, ObjectChange, PointLight, PointLightChange, RawDirectionalLightHandle,
-    RawGraphDataHandleUntyped, RawMaterialHandle, RawMeshHandle, RawPointLightHandle, RawSkeletonHandle,
-    RawTexture2DHandle, RawTextureCubeHandle, TextureFromTexture, WasmNotSend, WasmNotSync,
+    RawGraphDataHandleUntyped, RawMaterialHandle, RawMeshHandle, RawPointLightHandle, RawReflectionProbeHandle,
+    RawSkeletonHandle, RawSpotLightHandle, RawTexture2DHandle, RawTextureCubeHandle, ReflectionProbe,
+    ReflectionProbeChange, SpotLight, SpotLightChange, TextureFromTexture, WasmNotSend, WasmNotSync,
 };
 use wgpu::{CommandBuffer, Device};
 
 use crate::{
     managers::{GraphStorage, InternalSkeleton, InternalTexture, MaterialManager, TextureManager},
     types::{Camera, DirectionalLight, DirectionalLightChange, Object, RawObjectHandle},
+    util::frustum::BoundingSphere,
     RendererProfile,
 };
 
@@ -62,6 +64,14 @@ pub enum InstructionKind {
         handle: RawPointLightHandle,
         light: PointLight,
     },
+    AddSpotLight {
+        handle: RawSpotLightHandle,
+        light: SpotLight,
+    },
+    AddReflectionProbe {
+        handle: RawReflectionProbeHandle,
+        probe: ReflectionProbe,
+    },
     AddGraphData {
         add_invoke: Box<dyn AddGraphDataAddInvoke>,
     },
@@ -77,6 +87,14 @@ pub enum InstructionKind {
         handle: RawPointLightHandle,
         change: PointLightChange,
     },
+    ChangeSpotLight {
+        handle: RawSpotLightHandle,
+        change: SpotLightChange,
+    },
+    ChangeReflectionProbe {
+        handle: RawReflectionProbeHandle,
+        change: ReflectionProbeChange,
+    },
     DeleteMesh {
         handle: RawMeshHandle,
     },
@@ -101,6 +119,12 @@ pub enum InstructionKind {
     DeletePointLight {
         handle: RawPointLightHandle,
     },
+    DeleteSpotLight {
+        handle: RawSpotLightHandle,
+    },
+    DeleteReflectionProbe {
+        handle: RawReflectionProbeHandle,
+    },
     DeleteGraphData {
         handle: RawGraphDataHandleUntyped,
     },
@@ -108,16 +132,32 @@ pub enum InstructionKind {
         handle: RawObjectHandle,
         transform: Mat4,
     },
+    SetObjectShadows {
+        handle: RawObjectHandle,
+        cast_shadows: bool,
+        receive_shadows: bool,
+    },
+    SetMeshBoundingSphere {
+        handle: RawMeshHandle,
+        bounding_sphere: BoundingSphere,
+    },
     SetSkeletonJointDeltas {
         handle: RawSkeletonHandle,
         joint_matrices: Vec<Mat4>,
     },
+    SetSkeletonMorphWeights {
+        handle: RawSkeletonHandle,
+        morph_weights: Vec<f32>,
+    },
     SetAspectRatio {
         ratio: f32,
     },
     SetCameraData {
         data: Camera,
     },
+    SetCameraJitter {
+        jitter: Vec2,
+    },
     DuplicateObject {
         src_handle: RawObjectHandle,
         dst_handle: RawObjectHandle,
@@ -199,6 +239,18 @@ impl DeletableRawResourceHandle for RawPointLightHandle {
     }
 }
 
+impl DeletableRawResourceHandle for RawSpotLightHandle {
+    fn into_delete_instruction_kind(self) -> InstructionKind {
+        InstructionKind::DeleteSpotLight { handle: self }
+    }
+}
+
+impl DeletableRawResourceHandle for RawReflectionProbeHandle {
+    fn into_delete_instruction_kind(self) -> InstructionKind {
+        InstructionKind::DeleteReflectionProbe { handle: self }
+    }
+}
+
 impl DeletableRawResourceHandle for RawGraphDataHandleUntyped {
     fn into_delete_instruction_kind(self) -> InstructionKind {
         InstructionKind::DeleteGraphData { handle: self }
@@ -105,7 +105,10 @@ pub mod managers {
     mod mesh;
     mod object;
     mod point;
+    mod reflection_probe;
+    mod shadow;
     mod skeleton;
+    mod spot;
     mod texture;
 
     pub use camera::*;
@@ -116,7 +119,10 @@ pub mod managers {
     pub use mesh::*;
     pub use object::*;
     pub use point::*;
+    pub use reflection_probe::*;
+    pub use shadow::*;
     pub use skeleton::*;
+    pub use spot::*;
     pub use texture::*;
 }
 
@@ -1,4 +1,4 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use rend3_types::Handedness;
 
 use crate::{
@@ -11,11 +11,16 @@ use crate::{
 pub struct CameraState {
     handedness: Handedness,
     orig_view: Mat4,
+    /// Projection without the jitter.
     proj: Mat4,
     inv_view: Mat4,
     world_frustum: Frustum,
     data: Camera,
     aspect_ratio: f32,
+    /// Offset of the projection in normalized device coordinates.
+    jitter: Vec2,
+    /// Unjittered view projection of the previous frame.
+    previous_view_proj: Mat4,
 }
 impl CameraState {
     /// Builds a new camera, using the given aspect ratio. If no aspect ratio is
@@ -29,7 +34,17 @@ impl CameraState {
 
         let frustum = Frustum::from_matrix(proj * data.view);
 
-        Self { handedness, orig_view, proj, inv_view: data.view.inverse(), world_frustum: frustum, data, aspect_ratio }
+        Self {
+            handedness,
+            orig_view,
+            proj,
+            inv_view: data.view.inverse(),
+            world_frustum: frustum,
+            data,
+            aspect_ratio,
+            jitter: Vec2::ZERO,
+            previous_view_proj: proj * data.view,
+        }
     }
 
     /// Sets the camera data, rebuilding the using the given aspect ratio. If no
@@ -52,6 +67,20 @@ impl CameraState {
         self.aspect_ratio = aspect_ratio;
     }
 
+    /// Offsets the projection by the given amount in normalized device
+    /// coordinates, moving the samples of the pixels for temporal
+    /// anti-aliasing. The frustum isn't affected.
+    pub fn set_jitter(&mut self, jitter: Vec2) {
+        self.jitter = jitter;
+    }
+
+    /// Remembers the current view projection as the one of the previous
+    /// frame. Called once per frame, before the changes of the frame are
+    /// applied.
+    pub fn store_previous_frame(&mut self) {
+        self.previous_view_proj = self.unjittered_view_proj();
+    }
+
     pub fn get_data(&self) -> Camera {
         self.data
     }
@@ -65,15 +94,32 @@ impl CameraState {
     }
 
     pub fn view_proj(&self) -> Mat4 {
-        self.proj * self.data.view
+        self.proj() * self.data.view
     }
 
     pub fn origin_view_proj(&self) -> Mat4 {
-        self.proj * self.orig_view
+        self.proj() * self.orig_view
     }
 
+    /// The projection, including the jitter.
     pub fn proj(&self) -> Mat4 {
-        self.proj
+        // Offsetting clip space by the jitter times w moves every point by the jitter after the divide.
+        Mat4::from_translation(self.jitter.extend(0.0)) * self.proj
+    }
+
+    /// Same as [`Self::view_proj`], without the jitter.
+    pub fn unjittered_view_proj(&self) -> Mat4 {
+        self.proj * self.data.view
+    }
+
+    /// Unjittered view projection of the previous frame, see
+    /// [`Self::store_previous_frame`].
+    pub fn previous_view_proj(&self) -> Mat4 {
+        self.previous_view_proj
+    }
+
+    pub fn jitter(&self) -> Vec2 {
+        self.jitter
     }
 
     pub fn world_frustum(&self) -> Frustum {
@@ -85,6 +131,43 @@ impl CameraState {
     }
 }
 
+/// Specifier representing which camera we're referring to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CameraSpecifier {
+    Viewport,
+    Shadow(u32),
+}
+
+impl CameraSpecifier {
+    /// Returns `true` if the camera specifier is [`Viewport`].
+    ///
+    /// [`Viewport`]: CameraSpecifier::Viewport
+    #[must_use]
+    pub fn is_viewport(&self) -> bool {
+        matches!(self, Self::Viewport)
+    }
+
+    /// Returns `true` if the camera specifier is [`Shadow`].
+    ///
+    /// [`Shadow`]: CameraSpecifier::Shadow
+    #[must_use]
+    pub fn is_shadow(&self) -> bool {
+        matches!(self, Self::Shadow(..))
+    }
+
+    /// Returns a shader compatible index for the camera, using u32::MAX for the viewport camera.
+    #[must_use]
+    pub fn to_shader_index(&self) -> u32 {
+        match *self {
+            Self::Viewport => u32::MAX,
+            Self::Shadow(index) => {
+                assert_ne!(index, u32::MAX, "Shadow camera index cannot be 0xFFFF_FFFF");
+                index
+            }
+        }
+    }
+}
+
 fn compute_projection_matrix(data: Camera, handedness: Handedness, aspect_ratio: f32) -> Mat4 {
     match data.projection {
         CameraProjection::Orthographic { size } => {
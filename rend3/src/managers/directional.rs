@@ -1,33 +1,54 @@
 use encase::{ArrayLength, ShaderType};
-use glam::{Mat4, UVec2, Vec2, Vec3};
-use rend3_types::{DirectionalLightChange, RawDirectionalLightHandle};
-use wgpu::{
-    BindingType, BufferBindingType, BufferUsages, Device, Extent3d, ShaderStages, TextureDescriptor, TextureDimension,
-    TextureUsages, TextureView, TextureViewDescriptor,
-};
+use glam::{UVec2, Vec2, Vec3};
+use rend3_types::{DirectionalLightChange, RawDirectionalLightHandle, ShadowFilter};
+use wgpu::{BindingType, BufferBindingType, BufferUsages, Device, ShaderStages};
 
 use crate::{
-    managers::CameraState,
+    managers::{CameraState, ShaderShadowMap, ShadowDesc, ShadowSource},
     types::DirectionalLight,
     util::{
         bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
         buffer::WrappedPotBuffer,
     },
-    Renderer, INTERNAL_SHADOW_DEPTH_FORMAT,
+    Renderer,
 };
 
-mod shadow_alloc;
 mod shadow_camera;
 
-pub use shadow_alloc::ShadowMap;
-
-const MINIMUM_SHADOW_MAP_SIZE: UVec2 = UVec2::splat(32);
+/// Maximum amount of shadow cascades per light. Must match the size of the
+/// cascade array in `structures.wgsl`.
+pub const MAX_SHADOW_CASCADES: usize = 4;
 
 /// Internal representation of a directional light.
 pub struct InternalDirectionalLight {
     pub inner: DirectionalLight,
 }
 
+/// Index of the filter in the shader. Must match the filter selection in
+/// `opaque.wgsl`.
+fn shadow_filter_index(filter: ShadowFilter) -> u32 {
+    match filter {
+        ShadowFilter::Hard => 0,
+        ShadowFilter::Pcf => 1,
+        ShadowFilter::Pcss { .. } => 2,
+        ShadowFilter::Evsm => 3,
+    }
+}
+
+impl InternalDirectionalLight {
+    /// The amount of cascades this light uses, clamped to the supported range.
+    pub fn cascade_count(&self) -> u32 {
+        (self.inner.cascade_count as u32).clamp(1, MAX_SHADOW_CASCADES as u32)
+    }
+
+    fn light_size(&self) -> f32 {
+        match self.inner.shadow_filter {
+            ShadowFilter::Pcss { angular_diameter } => angular_diameter.to_radians().tan(),
+            _ => 0.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, ShaderType)]
 struct ShaderDirectionalLightBuffer {
     count: ArrayLength,
@@ -37,47 +58,40 @@ struct ShaderDirectionalLightBuffer {
 
 #[derive(Debug, Copy, Clone, ShaderType)]
 struct ShaderDirectionalLight {
-    /// View/Projection of directional light. Shadow rendering uses viewports
-    /// so this always outputs [-1, 1] no matter where in the atlast the shadow is.
-    pub view_proj: Mat4,
     /// Color/intensity of the light
     pub color: Vec3,
     /// Direction of the light
     pub direction: Vec3,
     /// 1 / resolution of whole shadow map
     pub inv_resolution: Vec2,
-    /// [0, 1] offset of the shadow map in the atlas.
-    pub atlas_offset: Vec2,
-    /// [0, 1] size of the shadow map in the atlas.
-    pub atlas_size: Vec2,
-}
-
-#[derive(Debug, Clone)]
-pub struct ShadowDesc {
-    pub map: ShadowMap,
-    pub camera: CameraState,
+    /// Amount of valid cascades in `cascades`.
+    pub cascade_count: u32,
+    /// Distance receivers are moved towards the light, in world units.
+    pub shadow_bias: f32,
+    /// Distance receivers are moved along their normal, in shadow map texels.
+    pub shadow_normal_offset: f32,
+    /// Filter used when sampling the shadow, see [`shadow_filter_index`].
+    pub shadow_filter: u32,
+    /// Tangent of the angular diameter of the light, used by PCSS.
+    pub light_size: f32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    pub baked: u32,
+    /// Cascades, ordered from the smallest to the largest.
+    pub cascades: [ShaderShadowMap; MAX_SHADOW_CASCADES],
 }
 
 /// Manages directional lights and their associated shadow maps.
 pub struct DirectionalLightManager {
     data: Vec<Option<InternalDirectionalLight>>,
     data_buffer: WrappedPotBuffer<ShaderDirectionalLightBuffer>,
-
-    texture_size: UVec2,
-    texture_view: TextureView,
 }
 impl DirectionalLightManager {
     pub fn new(device: &Device) -> Self {
         profiling::scope!("DirectionalLightManager::new");
 
-        let texture_size = MINIMUM_SHADOW_MAP_SIZE;
-        let texture_view = create_shadow_texture(device, texture_size);
-
         Self {
             data: Vec::new(),
             data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "shadow data buffer"),
-            texture_size,
-            texture_view,
         }
     }
 
@@ -96,64 +110,72 @@ impl DirectionalLightManager {
         self.data[handle.idx].take().unwrap();
     }
 
-    pub fn evaluate(&mut self, renderer: &Renderer, user_camera: &CameraState) -> (UVec2, Vec<ShadowDesc>) {
-        profiling::scope!("DirectionalLightManager::evaluate");
+    /// The light with the given handle, `None` if it has been removed.
+    pub fn get(&self, handle: RawDirectionalLightHandle) -> Option<&DirectionalLight> {
+        Some(&self.data.get(handle.idx)?.as_ref()?.inner)
+    }
 
-        let shadow_maps: Vec<_> = self
-            .data
+    /// All shadow maps, one per cascade, which need to be allocated in the shadow atlas.
+    pub(super) fn shadow_maps(&self) -> impl Iterator<Item = (ShadowSource, u16)> + '_ {
+        self.data
             .iter()
             .enumerate()
-            .filter_map(|(idx, light)| Some((RawDirectionalLightHandle::new(idx), light.as_ref()?.inner.resolution)))
-            .collect();
-        let shadow_atlas = shadow_alloc::allocate_shadow_atlas(shadow_maps, renderer.limits.max_texture_dimension_2d);
-
-        let new_shadow_map_size = match shadow_atlas {
-            Some(ref m) => m.texture_dimensions.max(MINIMUM_SHADOW_MAP_SIZE),
-            None => MINIMUM_SHADOW_MAP_SIZE,
-        };
-        let new_shadow_map_size_f32 = new_shadow_map_size.as_vec2();
-
-        if new_shadow_map_size != self.texture_size {
-            self.texture_size = new_shadow_map_size;
-            self.texture_view = create_shadow_texture(&renderer.device, self.texture_size);
-        }
+            .filter_map(|(idx, light)| Some((RawDirectionalLightHandle::new(idx), light.as_ref()?)))
+            .flat_map(|(handle, light)| {
+                (0..light.cascade_count())
+                    .map(move |cascade| (ShadowSource::Directional { handle, cascade }, light.inner.resolution))
+            })
+    }
 
-        let coordinates = match shadow_atlas {
-            Some(m) => m.maps,
-            None => return (new_shadow_map_size, Vec::new()),
-        };
+    pub(super) fn shadow_filter(&self, handle: RawDirectionalLightHandle) -> ShadowFilter {
+        self.data[handle.idx].as_ref().unwrap().inner.shadow_filter
+    }
 
-        let shadow_data: Vec<_> = coordinates
-            .into_iter()
-            .map(|map| {
-                let camera = shadow_camera::shadow_camera(self.data[map.handle.idx].as_ref().unwrap(), user_camera);
+    pub(super) fn shadow_camera(
+        &self,
+        handle: RawDirectionalLightHandle,
+        cascade: u32,
+        user_camera: &CameraState,
+    ) -> CameraState {
+        let light = self.data[handle.idx].as_ref().unwrap();
+        let distances = shadow_camera::cascade_distances(light, user_camera);
 
-                ShadowDesc { map, camera }
-            })
-            .collect();
-
-        let buffer = ShaderDirectionalLightBuffer {
-            count: ArrayLength,
-            array: shadow_data
-                .iter()
-                .map(|desc| {
-                    let light = &self.data[desc.map.handle.idx].as_ref().unwrap().inner;
-
-                    ShaderDirectionalLight {
-                        view_proj: desc.camera.view_proj(),
-                        color: light.color * light.intensity,
-                        direction: light.direction,
-                        inv_resolution: 1.0 / new_shadow_map_size_f32,
-                        atlas_offset: desc.map.offset.as_vec2() / new_shadow_map_size_f32,
-                        atlas_size: desc.map.size as f32 / new_shadow_map_size_f32,
-                    }
-                })
-                .collect(),
-        };
+        shadow_camera::shadow_camera(light, user_camera, distances[cascade as usize])
+    }
 
-        self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+    pub fn evaluate(&mut self, renderer: &Renderer, shadow_target_size: UVec2, shadows: &[ShadowDesc]) {
+        profiling::scope!("DirectionalLightManager::evaluate");
+
+        // Lights are stored densely in the buffer, so we need to know where each light ended up.
+        let mut buffer_indices = vec![usize::MAX; self.data.len()];
+        let mut array = Vec::with_capacity(self.data.len());
+        for (idx, light) in self.data.iter().enumerate() {
+            if let Some(light) = light {
+                buffer_indices[idx] = array.len();
+                array.push(ShaderDirectionalLight {
+                    color: light.inner.color * light.inner.intensity,
+                    direction: light.inner.direction,
+                    inv_resolution: 1.0 / shadow_target_size.as_vec2(),
+                    cascade_count: light.cascade_count(),
+                    shadow_bias: light.inner.shadow_bias,
+                    shadow_normal_offset: light.inner.shadow_normal_offset,
+                    shadow_filter: shadow_filter_index(light.inner.shadow_filter),
+                    light_size: light.light_size(),
+                    baked: light.inner.baked as u32,
+                    cascades: [ShaderShadowMap::default(); MAX_SHADOW_CASCADES],
+                });
+            }
+        }
+        for desc in shadows {
+            if let ShadowSource::Directional { handle, cascade } = desc.map.source {
+                array[buffer_indices[handle.idx]].cascades[cascade as usize] =
+                    ShaderShadowMap::new(desc, shadow_target_size);
+            }
+        }
 
-        (new_shadow_map_size, shadow_data)
+        let buffer = ShaderDirectionalLightBuffer { count: ArrayLength, array };
+
+        self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
     }
 
     pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
@@ -172,23 +194,3 @@ impl DirectionalLightManager {
         bgb.append_buffer(&self.data_buffer);
     }
 }
-
-fn create_shadow_texture(device: &Device, size: UVec2) -> TextureView {
-    profiling::scope!("shadow texture creation");
-
-    let texture = device.create_texture(&TextureDescriptor {
-        label: Some("rend3 shadow texture"),
-        size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: TextureDimension::D2,
-        format: INTERNAL_SHADOW_DEPTH_FORMAT,
-        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
-        view_formats: &[],
-    });
-
-    texture.create_view(&TextureViewDescriptor {
-        label: Some("rend3 shadow texture view"),
-        ..TextureViewDescriptor::default()
-    })
-}
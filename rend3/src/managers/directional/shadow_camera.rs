@@ -1,12 +1,45 @@
+use std::array;
+
 use glam::{Mat4, Vec3, Vec3A};
 use rend3_types::{Camera, CameraProjection, Handedness};
 
-use crate::managers::{CameraState, InternalDirectionalLight};
+use crate::managers::{CameraState, InternalDirectionalLight, MAX_SHADOW_CASCADES};
+
+/// Near distance used for the logarithmic split when the user camera has no near plane.
+const DEFAULT_CASCADE_NEAR: f32 = 0.1;
+
+/// Computes the size of each of the light's shadow cascades. The last used cascade
+/// always covers the full shadow distance.
+///
+/// Uses the "practical split scheme" which blends between a logarithmic and a linear
+/// distribution of the splits based on `cascade_split_lambda`.
+pub(super) fn cascade_distances(l: &InternalDirectionalLight, user_camera: &CameraState) -> [f32; MAX_SHADOW_CASCADES] {
+    let count = l.cascade_count();
+    let far = l.inner.distance;
+    let near = match user_camera.get_data().projection {
+        CameraProjection::Perspective { near, .. } => near,
+        _ => DEFAULT_CASCADE_NEAR,
+    }
+    .max(f32::EPSILON);
+    let lambda = l.inner.cascade_split_lambda.clamp(0.0, 1.0);
+
+    array::from_fn(|idx| {
+        let idx = idx as u32 + 1;
+        if idx >= count {
+            return far;
+        }
+        let fraction = idx as f32 / count as f32;
+        let log = near * (far / near).powf(fraction);
+        let linear = near + (far - near) * fraction;
 
-pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraState) -> CameraState {
+        lambda * log + (1.0 - lambda) * linear
+    })
+}
+
+pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraState, distance: f32) -> CameraState {
     let camera_location = user_camera.location();
 
-    let shadow_texel_size = l.inner.distance / l.inner.resolution as f32;
+    let shadow_texel_size = distance / l.inner.resolution as f32;
 
     let look_at = match user_camera.handedness() {
         Handedness::Left => Mat4::look_at_lh,
@@ -16,15 +49,22 @@ pub(super) fn shadow_camera(l: &InternalDirectionalLight, user_camera: &CameraSt
     let origin_view = look_at(Vec3::ZERO, l.inner.direction, Vec3::Y);
     let camera_origin_view = origin_view.transform_point3(camera_location);
 
-    let offset = camera_origin_view.truncate() % shadow_texel_size;
-    let shadow_location = camera_origin_view - Vec3::from((offset, 0.0));
+    // Snap to whole texels, so the shadow doesn't shimmer when the camera moves. The depth
+    // is snapped as well, so the shadow camera stays exactly the same while the camera moves
+    // within a texel, and the shadow map doesn't need to be rendered again.
+    let offset = camera_origin_view % shadow_texel_size;
+    let shadow_location = camera_origin_view - offset;
 
     let inv_origin_view = origin_view.inverse();
     let new_shadow_location = inv_origin_view.transform_point3(shadow_location);
 
+    // Every cascade keeps the full depth range so casters outside of the smaller
+    // cascades still cast shadows into them.
+    let size = Vec3A::new(distance, distance, l.inner.distance);
+
     CameraState::new(
         Camera {
-            projection: CameraProjection::Orthographic { size: Vec3A::splat(l.inner.distance) },
+            projection: CameraProjection::Orthographic { size },
             view: look_at(new_shadow_location, new_shadow_location + l.inner.direction, Vec3::Y),
         },
         user_camera.handedness(),
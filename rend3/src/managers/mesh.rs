@@ -4,9 +4,13 @@ use std::{
     sync::Arc,
 };
 
+use glam::Vec3;
 use parking_lot::{Mutex, MutexGuard};
 use range_alloc::RangeAllocator;
-use rend3_types::{RawMeshHandle, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_POSITION};
+use rend3_types::{
+    MeshValidationError, RawMeshHandle, VertexAttribute, VertexAttributeId, VertexFormat,
+    VERTEX_ATTRIBUTE_JOINT_INDICES, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+};
 use thiserror::Error;
 use wgpu::{
     Buffer, BufferAddress, BufferDescriptor, BufferUsages, CommandBuffer, CommandEncoder, CommandEncoderDescriptor,
@@ -38,6 +42,12 @@ pub struct InternalMesh {
     pub required_joint_count: Option<u16>,
     /// The bounding sphere of this mesh. Used for culling.
     pub bounding_sphere: BoundingSphere,
+    /// Amount of morph targets in this mesh.
+    pub morph_target_count: u32,
+    /// Location in the vertex buffer of the morph target offsets for each morphed attribute.
+    ///
+    /// The offsets of all targets are stored back to back, target by target.
+    pub morph_target_ranges: Vec<(VertexAttributeId, Range<u64>)>,
 }
 
 impl InternalMesh {
@@ -49,12 +59,38 @@ impl InternalMesh {
             index_range: 0..0,
             required_joint_count: None,
             bounding_sphere: BoundingSphere::from_mesh(&[]),
+            morph_target_count: 0,
+            morph_target_ranges: Vec::new(),
         }
     }
 
     pub fn get_attribute(&self, attribute: &VertexAttributeId) -> Option<Range<u64>> {
         self.vertex_attribute_ranges.iter().find_map(|(id, range)| (*id == *attribute).then_some(range.clone()))
     }
+
+    pub fn get_morph_target_attribute(&self, attribute: &VertexAttributeId) -> Option<Range<u64>> {
+        self.morph_target_ranges.iter().find_map(|(id, range)| (*id == *attribute).then_some(range.clone()))
+    }
+}
+
+/// Gathers the offsets of all morph targets of a mesh into one array per morphed attribute.
+///
+/// Targets which don't have offsets for an attribute some other target has get zeros.
+fn gather_morph_target_offsets(mesh: &Mesh) -> Vec<(VertexAttributeId, Vec<Vec3>)> {
+    [&VERTEX_ATTRIBUTE_POSITION, &VERTEX_ATTRIBUTE_NORMAL, &VERTEX_ATTRIBUTE_TANGENT]
+        .into_iter()
+        .filter(|attribute| mesh.morph_targets.iter().any(|target| target.offsets(attribute).is_some()))
+        .map(|attribute| {
+            let mut offsets = Vec::with_capacity(mesh.morph_targets.len() * mesh.vertex_count);
+            for target in &mesh.morph_targets {
+                match target.offsets(attribute) {
+                    Some(target_offsets) => offsets.extend_from_slice(target_offsets),
+                    None => offsets.resize(offsets.len() + mesh.vertex_count, Vec3::ZERO),
+                }
+            }
+            (*attribute.id(), offsets)
+        })
+        .collect()
 }
 
 #[derive(Debug, Error)]
@@ -74,6 +110,36 @@ pub enum MeshCreationError {
     },
 }
 
+#[derive(Debug, Error)]
+pub enum MeshUpdateError {
+    #[error("New mesh has {actual} vertices but the mesh being updated has {expected}")]
+    MismatchedVertexCount { expected: usize, actual: usize },
+    #[error("New mesh has {actual} indices but the mesh being updated has {expected}")]
+    MismatchedIndexCount { expected: usize, actual: usize },
+    #[error("New mesh failed validation")]
+    InvalidMesh {
+        #[source]
+        inner: MeshValidationError,
+    },
+    #[error("Mesh being updated does not have a {:?} attribute", .attribute_id.name())]
+    MissingAttribute { attribute_id: VertexAttributeId },
+    #[error("New mesh's {:?} attribute is {actual} bytes but the mesh being updated stores {expected}", .attribute_id.name())]
+    MismatchedAttributeSize { attribute_id: VertexAttributeId, expected: u64, actual: u64 },
+    #[error("New mesh has {actual} attributes but the mesh being updated has {expected}")]
+    MismatchedAttributeCount { expected: usize, actual: usize },
+    #[error("New mesh has {actual} morph targets but the mesh being updated has {expected}")]
+    MismatchedMorphTargetCount { expected: usize, actual: usize },
+    #[error("Mesh being updated does not have morph target offsets for the {:?} attribute", .attribute_id.name())]
+    MissingMorphTargetAttribute { attribute_id: VertexAttributeId },
+    #[error("Tried to write vertices {start}..{end} of a mesh with {vertex_count} vertices")]
+    WriteOutOfBounds { start: usize, end: usize, vertex_count: usize },
+    #[error("Failed to write new mesh data to buffer. Failed to allocate staging buffer.")]
+    BufferWriteFailed {
+        #[source]
+        inner: wgpu::Error,
+    },
+}
+
 /// Contains all the state for the mesh buffer.
 ///
 /// As the mesh manager is multithreaded, this needs to be wrapped in a single mutex
@@ -139,6 +205,8 @@ impl MeshManager {
             required_joint_count = Some(joint_indices.iter().flatten().max().map_or(0, |v| v + 1));
         }
 
+        let morph_target_offsets = gather_morph_target_offsets(&mesh);
+
         let mut vertex_attribute_ranges = Vec::with_capacity(mesh.attributes.len());
         let mut upload = UploadChainer::new();
 
@@ -152,6 +220,13 @@ impl MeshManager {
             vertex_attribute_ranges.push((*attribute.id(), range));
         }
 
+        let mut morph_target_ranges = Vec::with_capacity(morph_target_offsets.len());
+        for (attribute_id, offsets) in &morph_target_offsets {
+            let range = self.allocate_range_impl(device, buffer_state, mem::size_of_val(&offsets[..]) as u64)?;
+            upload.add(range.start, bytemuck::cast_slice(offsets));
+            morph_target_ranges.push((*attribute_id, range));
+        }
+
         let index_range = self.allocate_range_impl(device, buffer_state, index_count as u64 * 4)?;
         upload.add(index_range.start, bytemuck::cast_slice(&mesh.indices));
         upload.create_staging_buffer(device).map_err(|e| MeshCreationError::BufferWriteFailed { inner: e })?;
@@ -165,14 +240,7 @@ impl MeshManager {
         upload.stage();
         drop(staging_guard);
 
-        // We can cheat here as we know vertex positions are always the first attribute as they must exist.
-        let bounding_sphere = BoundingSphere::from_mesh(
-            mesh.attributes
-                .first()
-                .expect("Meshes first attributes must always exist")
-                .typed_data(&VERTEX_ATTRIBUTE_POSITION)
-                .expect("Meshes must have positions"),
-        );
+        let bounding_sphere = mesh_bounding_sphere(&mesh);
 
         Ok(InternalMesh {
             vertex_attribute_ranges,
@@ -180,6 +248,8 @@ impl MeshManager {
             index_range,
             required_joint_count,
             bounding_sphere,
+            morph_target_count: mesh.morph_targets.len() as u32,
+            morph_target_ranges,
         })
     }
 
@@ -194,11 +264,181 @@ impl MeshManager {
         drop(data_guard);
     }
 
+    /// Overwrites the vertex and index data of an existing mesh.
+    ///
+    /// The new mesh must be valid and have the same vertex count, index count, and attributes
+    /// as the mesh it replaces, so the data can be written in place without touching the
+    /// allocator. Returns the recomputed bounding sphere of the mesh.
+    pub fn update(
+        &self,
+        device: &Device,
+        handle: RawMeshHandle,
+        mesh: Mesh,
+    ) -> Result<BoundingSphere, MeshUpdateError> {
+        profiling::scope!("MeshManager::update");
+
+        mesh.validate().map_err(|inner| MeshUpdateError::InvalidMesh { inner })?;
+
+        let data_guard = self.data.lock();
+        let internal_mesh = data_guard[handle.idx].as_ref().unwrap();
+
+        // Empty meshes are stored without any attributes, so the vertex count needs to be checked against
+        // what we would have stored.
+        let vertex_count = if mesh.indices.is_empty() { 0 } else { mesh.vertex_count };
+        if vertex_count != internal_mesh.vertex_count as usize {
+            return Err(MeshUpdateError::MismatchedVertexCount {
+                expected: internal_mesh.vertex_count as usize,
+                actual: vertex_count,
+            });
+        }
+        if vertex_count == 0 {
+            return Ok(internal_mesh.bounding_sphere);
+        }
+        let index_count = (internal_mesh.index_range.end - internal_mesh.index_range.start) as usize / 4;
+        if mesh.indices.len() != index_count {
+            return Err(MeshUpdateError::MismatchedIndexCount { expected: index_count, actual: mesh.indices.len() });
+        }
+        if mesh.attributes.len() != internal_mesh.vertex_attribute_ranges.len() {
+            return Err(MeshUpdateError::MismatchedAttributeCount {
+                expected: internal_mesh.vertex_attribute_ranges.len(),
+                actual: mesh.attributes.len(),
+            });
+        }
+        if mesh.morph_targets.len() != internal_mesh.morph_target_count as usize {
+            return Err(MeshUpdateError::MismatchedMorphTargetCount {
+                expected: internal_mesh.morph_target_count as usize,
+                actual: mesh.morph_targets.len(),
+            });
+        }
+
+        let morph_target_offsets = gather_morph_target_offsets(&mesh);
+
+        let mut upload = UploadChainer::new();
+        for attribute in &mesh.attributes {
+            let range = internal_mesh
+                .get_attribute(attribute.id())
+                .ok_or(MeshUpdateError::MissingAttribute { attribute_id: *attribute.id() })?;
+            let data = attribute.untyped_data();
+            // Validation keeps this in line for well formed meshes, but the write must never leave the range.
+            if data.len() as u64 != range.end - range.start {
+                return Err(MeshUpdateError::MismatchedAttributeSize {
+                    attribute_id: *attribute.id(),
+                    expected: range.end - range.start,
+                    actual: data.len() as u64,
+                });
+            }
+            upload.add(range.start, data);
+        }
+        for (attribute_id, offsets) in &morph_target_offsets {
+            let range = internal_mesh
+                .get_morph_target_attribute(attribute_id)
+                .ok_or(MeshUpdateError::MissingMorphTargetAttribute { attribute_id: *attribute_id })?;
+            let data: &[u8] = bytemuck::cast_slice(offsets);
+            if data.len() as u64 != range.end - range.start {
+                return Err(MeshUpdateError::MismatchedAttributeSize {
+                    attribute_id: *attribute_id,
+                    expected: range.end - range.start,
+                    actual: data.len() as u64,
+                });
+            }
+            upload.add(range.start, data);
+        }
+        upload.add(internal_mesh.index_range.start, bytemuck::cast_slice(&mesh.indices));
+        drop(data_guard);
+
+        self.upload(device, upload)?;
+
+        let required_joint_count = mesh
+            .attributes
+            .iter()
+            .find_map(|attribute| attribute.typed_data(&VERTEX_ATTRIBUTE_JOINT_INDICES))
+            .map(|joint_indices| joint_indices.iter().flatten().max().map_or(0, |v| v + 1));
+
+        let bounding_sphere = mesh_bounding_sphere(&mesh);
+
+        let mut data_guard = self.data.lock();
+        let internal_mesh = data_guard[handle.idx].as_mut().unwrap();
+        internal_mesh.required_joint_count = required_joint_count;
+        internal_mesh.bounding_sphere = bounding_sphere;
+
+        Ok(bounding_sphere)
+    }
+
+    /// Overwrites `data.len()` vertices of a single attribute of a mesh, starting at vertex `offset`.
+    ///
+    /// If positions are written, returns the new bounding sphere of the mesh. As the rest of the
+    /// positions are not known, the bounding sphere only ever grows to contain the new positions.
+    pub fn write_attribute<T: VertexFormat>(
+        &self,
+        device: &Device,
+        handle: RawMeshHandle,
+        attribute: &'static VertexAttribute<T>,
+        offset: usize,
+        data: &[T],
+    ) -> Result<Option<BoundingSphere>, MeshUpdateError> {
+        profiling::scope!("MeshManager::write_attribute");
+
+        let data_guard = self.data.lock();
+        let internal_mesh = data_guard[handle.idx].as_ref().unwrap();
+
+        let end = offset + data.len();
+        if end > internal_mesh.vertex_count as usize {
+            return Err(MeshUpdateError::WriteOutOfBounds {
+                start: offset,
+                end,
+                vertex_count: internal_mesh.vertex_count as usize,
+            });
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let range = internal_mesh
+            .get_attribute(attribute)
+            .ok_or(MeshUpdateError::MissingAttribute { attribute_id: *attribute.id() })?;
+        drop(data_guard);
+
+        let mut upload = UploadChainer::new();
+        upload.add(range.start + offset as u64 * T::METADATA.size as u64, bytemuck::cast_slice(data));
+        self.upload(device, upload)?;
+
+        if *attribute.id() != *VERTEX_ATTRIBUTE_POSITION.id() {
+            return Ok(None);
+        }
+
+        // Positions are always Vec3, so this cast is a no-op.
+        let written_sphere = BoundingSphere::from_mesh(bytemuck::cast_slice::<T, Vec3>(data));
+
+        let mut data_guard = self.data.lock();
+        let internal_mesh = data_guard[handle.idx].as_mut().unwrap();
+        internal_mesh.bounding_sphere = internal_mesh.bounding_sphere.union(written_sphere);
+
+        Ok(Some(internal_mesh.bounding_sphere))
+    }
+
+    fn upload(&self, device: &Device, mut upload: UploadChainer<'_>) -> Result<(), MeshUpdateError> {
+        upload.create_staging_buffer(device).map_err(|e| MeshUpdateError::BufferWriteFailed { inner: e })?;
+
+        // Need to deref to allow split borrows
+        let mut buffer_state_guard = self.buffer_state.lock();
+        let buffer_state = &mut *buffer_state_guard;
+        // The buffer may have been reallocated since the ranges were looked up, but
+        // reallocation preserves offsets, so writing into the current buffer is correct.
+        upload.encode_upload(&mut buffer_state.encoder, &buffer_state.buffer);
+        let staging_guard = buffer_state.wait_group.increment();
+        drop(buffer_state_guard);
+
+        upload.stage();
+        drop(staging_guard);
+
+        Ok(())
+    }
+
     pub fn remove(&self, object_id: RawMeshHandle) {
         let mesh = self.data.lock()[object_id.idx].take().unwrap();
 
         let mut buffer_state = self.buffer_state.lock();
-        for (_id, range) in mesh.vertex_attribute_ranges {
+        for (_id, range) in mesh.vertex_attribute_ranges.into_iter().chain(mesh.morph_target_ranges) {
             if range.is_empty() {
                 continue;
             }
@@ -308,6 +548,28 @@ impl MeshManager {
     }
 }
 
+/// Computes the bounding sphere of a mesh, including every vertex it could reach through its morph targets.
+fn mesh_bounding_sphere(mesh: &Mesh) -> BoundingSphere {
+    // We can cheat here as we know vertex positions are always the first attribute as they must exist.
+    let mut bounding_sphere = BoundingSphere::from_mesh(
+        mesh.attributes
+            .first()
+            .expect("Meshes first attributes must always exist")
+            .typed_data(&VERTEX_ATTRIBUTE_POSITION)
+            .expect("Meshes must have positions"),
+    );
+
+    // Assume weights stay within 0..=1, so each target can move a vertex by at most its largest offset.
+    bounding_sphere.radius += mesh
+        .morph_targets
+        .iter()
+        .filter_map(|target| target.position_offsets.as_ref())
+        .map(|offsets| offsets.iter().fold(0.0_f32, |max, offset| max.max(offset.length())))
+        .sum::<f32>();
+
+    bounding_sphere
+}
+
 pub struct LockedInternalMeshDataArray<'a>(MutexGuard<'a, Vec<Option<InternalMesh>>>);
 
 impl<'a> Index<RawMeshHandle> for LockedInternalMeshDataArray<'a> {
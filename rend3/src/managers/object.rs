@@ -1,20 +1,23 @@
-use std::{any::TypeId, ops::Range};
+use std::{any::TypeId, iter, ops::Range};
 
 use bytemuck::Zeroable;
 use encase::ShaderType;
 use glam::{Mat4, Vec3A};
 use rend3_types::{
-    Material, MaterialArray, MaterialHandle, ObjectChange, ObjectMeshKind, RawObjectHandle, VertexAttributeId,
-    WasmVecAny,
+    Material, MaterialArray, MaterialHandle, ObjectChange, ObjectMeshKind, RawMeshHandle, RawObjectHandle,
+    VertexAttributeId, WasmVecAny, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_PREVIOUS_POSITION,
 };
 use wgpu::{Buffer, CommandEncoder, Device};
 
 use super::SkeletonManager;
 use crate::{
-    managers::{InternalMesh, MaterialManager, MeshManager},
+    managers::{CameraSpecifier, CameraState, InternalMesh, MaterialManager, MeshManager, ShadowDesc},
     types::Object,
     util::{
-        freelist::FreelistDerivedBuffer, frustum::BoundingSphere, iter::ExactSizerIterator, scatter_copy::ScatterCopy,
+        freelist::FreelistDerivedBuffer,
+        frustum::{BoundingSphere, Frustum},
+        iter::ExactSizerIterator,
+        scatter_copy::ScatterCopy,
         typedefs::FastHashMap,
     },
 };
@@ -24,11 +27,15 @@ use crate::{
 pub struct ShaderObject<M: Material> {
     /// Model -> World matrix
     pub transform: Mat4,
+    /// Model -> World matrix of the previous frame, for motion vectors.
+    pub previous_transform: Mat4,
     /// Bounding sphere in world space.
     pub bounding_sphere: BoundingSphere,
     pub first_index: u32,
     pub index_count: u32,
     pub material_index: u32,
+    /// 1 if shadows are applied when shading the object, 0 otherwise.
+    pub receive_shadows: u32,
     pub vertex_attribute_start_offsets:
         <M::SupportedAttributeArrayType as MaterialArray<&'static VertexAttributeId>>::U32Array,
 }
@@ -37,10 +44,12 @@ impl<M: Material> Default for ShaderObject<M> {
     fn default() -> Self {
         Self {
             transform: Default::default(),
+            previous_transform: Default::default(),
             bounding_sphere: Default::default(),
             first_index: Default::default(),
             index_count: Default::default(),
             material_index: Default::default(),
+            receive_shadows: Default::default(),
             vertex_attribute_start_offsets: Zeroable::zeroed(),
         }
     }
@@ -60,6 +69,8 @@ impl<M: Material> Clone for ShaderObject<M> {
 pub struct InternalObject<M: Material> {
     pub mesh_kind: ObjectMeshKind,
     pub material_handle: MaterialHandle,
+    /// If false, the object is skipped when rendering shadow maps.
+    pub cast_shadows: bool,
 
     /// World space
     pub location: Vec3A,
@@ -74,6 +85,7 @@ impl<M: Material> Clone for InternalObject<M> {
             mesh_kind: self.mesh_kind.clone(),
             mesh_bounding_sphere: self.mesh_bounding_sphere,
             material_handle: self.material_handle.clone(),
+            cast_shadows: self.cast_shadows,
             location: self.location,
             inner: self.inner,
         }
@@ -85,10 +97,30 @@ struct ObjectArchetype {
     data_vec: WasmVecAny,
     object_count: usize,
     buffer: FreelistDerivedBuffer,
-    set_object_transform: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, usize, Mat4),
+    /// Per-object visibility for each camera culled against by [`ObjectManager::cull`].
+    /// The viewport camera comes first, followed by the shadow cameras.
+    visibility: Vec<Vec<bool>>,
+    /// World space bounds of the shadow casters which changed since the last call to
+    /// [`ObjectManager::take_shadow_changes`], both before and after the change.
+    shadow_changes: Vec<BoundingSphere>,
+    /// Objects whose transform changed since the last call to [`ObjectManager::store_previous_frame`].
+    moved: Vec<usize>,
+    set_object_transform: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, &mut Vec<BoundingSphere>, usize, Mat4),
+    set_object_shadows: fn(&mut WasmVecAny, &mut FreelistDerivedBuffer, &mut Vec<BoundingSphere>, usize, bool, bool),
+    set_mesh_bounding_sphere: fn(
+        &mut WasmVecAny,
+        &mut FreelistDerivedBuffer,
+        &mut Vec<BoundingSphere>,
+        &SkeletonManager,
+        RawMeshHandle,
+        BoundingSphere,
+    ),
     duplicate_object: fn(&WasmVecAny, usize, ObjectChange) -> Object,
     remove: fn(&mut ObjectArchetype, usize),
+    store_previous_transforms: fn(&mut ObjectArchetype),
     evaluate: fn(&mut ObjectArchetype, &Device, &mut CommandEncoder, &ScatterCopy),
+    cull: fn(&WasmVecAny, &Frustum, &mut Vec<bool>) -> usize,
+    animated_shadow_casters: fn(&WasmVecAny, &mut Vec<BoundingSphere>),
 }
 
 /// Manages objects. That's it. ¯\\\_(ツ)\_/¯
@@ -109,10 +141,18 @@ impl ObjectManager {
             data_vec: WasmVecAny::new::<Option<InternalObject<M>>>(),
             object_count: 0,
             buffer: FreelistDerivedBuffer::new::<ShaderObject<M>>(device),
+            visibility: Vec::new(),
+            shadow_changes: Vec::new(),
+            moved: Vec::new(),
             set_object_transform: set_object_transform::<M>,
+            set_object_shadows: set_object_shadows::<M>,
+            set_mesh_bounding_sphere: set_mesh_bounding_sphere::<M>,
             duplicate_object: duplicate_object::<M>,
             remove: remove::<M>,
+            store_previous_transforms: store_previous_transforms::<M>,
             evaluate: evaluate::<M>,
+            cull: cull::<M>,
+            animated_shadow_casters: animated_shadow_casters::<M>,
         })
     }
 
@@ -149,7 +189,48 @@ impl ObjectManager {
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
-        (archetype.set_object_transform)(&mut archetype.data_vec, &mut archetype.buffer, handle.idx, transform);
+        (archetype.set_object_transform)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            &mut archetype.shadow_changes,
+            handle.idx,
+            transform,
+        );
+        archetype.moved.push(handle.idx);
+    }
+
+    pub fn set_object_shadows(&mut self, handle: RawObjectHandle, cast_shadows: bool, receive_shadows: bool) {
+        let type_id = self.handle_to_typeid[&handle];
+
+        let archetype = self.archetype.get_mut(&type_id).unwrap();
+
+        (archetype.set_object_shadows)(
+            &mut archetype.data_vec,
+            &mut archetype.buffer,
+            &mut archetype.shadow_changes,
+            handle.idx,
+            cast_shadows,
+            receive_shadows,
+        );
+    }
+
+    /// Updates the bounding sphere of every object using the given mesh, either directly or through a skeleton.
+    pub fn set_mesh_bounding_sphere(
+        &mut self,
+        skeleton_manager: &SkeletonManager,
+        handle: RawMeshHandle,
+        bounding_sphere: BoundingSphere,
+    ) {
+        for archetype in self.archetype.values_mut() {
+            (archetype.set_mesh_bounding_sphere)(
+                &mut archetype.data_vec,
+                &mut archetype.buffer,
+                &mut archetype.shadow_changes,
+                skeleton_manager,
+                handle,
+                bounding_sphere,
+            );
+        }
     }
 
     pub fn remove(&mut self, handle: RawObjectHandle) {
@@ -160,12 +241,57 @@ impl ObjectManager {
         (archetype.remove)(archetype, handle.idx);
     }
 
+    /// Makes the current transforms of the objects which moved the previous
+    /// transforms. Called once per frame, before the changes of the frame are
+    /// applied.
+    pub fn store_previous_frame(&mut self) {
+        for archetype in self.archetype.values_mut() {
+            (archetype.store_previous_transforms)(archetype);
+        }
+    }
+
     pub fn evaluate(&mut self, device: &Device, encoder: &mut CommandEncoder, scatter: &ScatterCopy) {
         for archetype in self.archetype.values_mut() {
             (archetype.evaluate)(archetype, device, encoder, scatter);
         }
     }
 
+    /// World space bounds of the shadow casters which were added, removed or changed since the
+    /// last call, both before and after the change. Shadow maps which contain any of them need
+    /// to be rendered again.
+    ///
+    /// The pose of animated objects isn't tracked, so they are always included.
+    pub fn take_shadow_changes(&mut self) -> Vec<BoundingSphere> {
+        let mut changes = Vec::new();
+        for archetype in self.archetype.values_mut() {
+            changes.append(&mut archetype.shadow_changes);
+            (archetype.animated_shadow_casters)(&archetype.data_vec, &mut changes);
+        }
+        changes
+    }
+
+    /// Frustum culls every object against the viewport camera and the given shadow cameras.
+    ///
+    /// The results are used by [`Self::enumerated_visible_objects`] until the next call.
+    /// Returns the amount of objects culled for every camera, the viewport first, followed by
+    /// the shadow cameras in the given order.
+    pub fn cull(&mut self, viewport: &CameraState, shadows: &[ShadowDesc]) -> Vec<usize> {
+        profiling::scope!("ObjectManager::cull");
+
+        let frustums: Vec<Frustum> = iter::once(viewport.world_frustum())
+            .chain(shadows.iter().map(|desc| desc.camera.world_frustum()))
+            .collect();
+
+        let mut culled = vec![0; frustums.len()];
+        for archetype in self.archetype.values_mut() {
+            archetype.visibility.resize_with(frustums.len(), Vec::new);
+            for ((visibility, frustum), culled) in archetype.visibility.iter_mut().zip(&frustums).zip(&mut culled) {
+                *culled += (archetype.cull)(&archetype.data_vec, frustum, visibility);
+            }
+        }
+        culled
+    }
+
     pub fn buffer<M: Material>(&self) -> Option<&Buffer> {
         Some(&self.archetype.get(&TypeId::of::<M>())?.buffer)
     }
@@ -188,6 +314,28 @@ impl ObjectManager {
         Some(ExactSizerIterator::new(iter, archetype.object_count))
     }
 
+    /// Same as [`Self::enumerated_objects`], but skips the objects which were culled for the
+    /// given camera in the last call to [`Self::cull`]. Shadow cameras also skip the objects
+    /// which don't cast shadows.
+    ///
+    /// If the camera wasn't culled against, all objects which can be seen by it are returned.
+    pub fn enumerated_visible_objects<M: Material>(
+        &self,
+        camera: CameraSpecifier,
+    ) -> Option<impl Iterator<Item = (RawObjectHandle, &InternalObject<M>)> + '_> {
+        let visibility = self.archetype.get(&TypeId::of::<M>())?.visibility.get(match camera {
+            CameraSpecifier::Viewport => 0,
+            CameraSpecifier::Shadow(idx) => idx as usize + 1,
+        });
+
+        let iter = self.enumerated_objects::<M>()?;
+
+        Some(iter.filter(move |(handle, object)| {
+            (object.cast_shadows || !camera.is_shadow())
+                && visibility.map_or(true, |v| v.get(handle.idx).copied().unwrap_or(true))
+        }))
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn duplicate_object(
         &mut self,
@@ -203,6 +351,8 @@ impl ObjectManager {
 
         let archetype = self.archetype.get_mut(&type_id).unwrap();
 
+        // Only the current transform is copied, so the new object starts with a matching previous transform in
+        // `object_add_callback` instead of the motion of the source object.
         let dst_obj = (archetype.duplicate_object)(&mut archetype.data_vec, src_handle.idx, change);
 
         self.add(device, dst_handle, dst_obj, mesh_manager, skeleton_manager, material_manager);
@@ -238,18 +388,22 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
         assert!(found_in_supported);
     }
 
-    let vertex_attribute_start_offsets = M::supported_attributes().map_to_u32(|&supported_attribute| {
+    let start_offset = |attribute: &VertexAttributeId| {
         // We first check the skeleton for the attribute's base offset.
         let found_start_offset =
-            args.skeleton_ranges.iter().find_map(|(id, range)| (*id == supported_attribute).then_some(range.start));
-
-        if let Some(start_offset) = found_start_offset {
-            return start_offset as u32;
-        }
+            args.skeleton_ranges.iter().find_map(|(id, range)| (id == attribute).then_some(range.start));
 
         // After the skeleton, check the mesh for non-overriden attributes.
-        match args.internal_mesh.get_attribute(&supported_attribute) {
-            Some(range) => range.start as u32,
+        found_start_offset.or_else(|| args.internal_mesh.get_attribute(attribute).map(|range| range.start))
+    };
+
+    let vertex_attribute_start_offsets = M::supported_attributes().map_to_u32(|&supported_attribute| {
+        match start_offset(&supported_attribute) {
+            Some(start_offset) => start_offset as u32,
+            // Only skinning writes the previous positions, every other mesh didn't move within itself.
+            None if supported_attribute == *VERTEX_ATTRIBUTE_PREVIOUS_POSITION.id() => {
+                start_offset(VERTEX_ATTRIBUTE_POSITION.id()).map_or(u32::MAX, |start_offset| start_offset as u32)
+            }
             // If the attribute isn't there, push u32::MAX.
             None => u32::MAX,
         }
@@ -265,13 +419,16 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
         mesh_bounding_sphere,
         inner: ShaderObject {
             material_index: args.object.material.idx as u32,
+            receive_shadows: args.object.receive_shadows as u32,
             transform: args.object.transform,
+            previous_transform: args.object.transform,
             bounding_sphere,
             first_index: (index_range.start / 4) as u32,
             index_count: ((index_range.end - index_range.start) / 4) as u32,
             vertex_attribute_start_offsets,
         },
         material_handle: args.object.material,
+        cast_shadows: args.object.cast_shadows,
         mesh_kind: args.object.mesh_kind,
     };
 
@@ -280,6 +437,10 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
     args.manager.handle_to_typeid.insert(args.handle, type_id);
     let archetype = args.manager.ensure_archetype::<M>(args.device);
 
+    if internal_object.cast_shadows {
+        archetype.shadow_changes.push(bounding_sphere);
+    }
+
     let mut data_vec = archetype.data_vec.downcast_mut::<Option<InternalObject<M>>>().unwrap();
     if args.handle.idx >= data_vec.len() {
         data_vec.resize_with((args.handle.idx + 1).next_power_of_two(), || None);
@@ -292,19 +453,81 @@ pub(super) fn object_add_callback<M: Material>(_material: &M, args: ObjectAddCal
 fn set_object_transform<M: Material>(
     data: &mut WasmVecAny,
     buffer: &mut FreelistDerivedBuffer,
+    shadow_changes: &mut Vec<BoundingSphere>,
     idx: usize,
     transform: Mat4,
 ) {
     let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
 
     let object = data_vec[idx].as_mut().unwrap();
+    let previous_bounding_sphere = object.inner.bounding_sphere;
     object.inner.transform = transform;
     object.inner.bounding_sphere = object.mesh_bounding_sphere.apply_transform(transform);
     object.location = transform.transform_point3a(Vec3A::ZERO);
 
+    if object.cast_shadows {
+        shadow_changes.extend([previous_bounding_sphere, object.inner.bounding_sphere]);
+    }
+
     buffer.use_index(idx);
 }
 
+fn set_object_shadows<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    shadow_changes: &mut Vec<BoundingSphere>,
+    idx: usize,
+    cast_shadows: bool,
+    receive_shadows: bool,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    let object = data_vec[idx].as_mut().unwrap();
+    if object.cast_shadows != cast_shadows {
+        shadow_changes.push(object.inner.bounding_sphere);
+    }
+    object.cast_shadows = cast_shadows;
+    object.inner.receive_shadows = receive_shadows as u32;
+
+    buffer.use_index(idx);
+}
+
+fn set_mesh_bounding_sphere<M: Material>(
+    data: &mut WasmVecAny,
+    buffer: &mut FreelistDerivedBuffer,
+    shadow_changes: &mut Vec<BoundingSphere>,
+    skeleton_manager: &SkeletonManager,
+    handle: RawMeshHandle,
+    bounding_sphere: BoundingSphere,
+) {
+    let data_vec = data.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    for (idx, object) in data_vec.iter_mut().enumerate() {
+        let Some(object) = object else {
+            continue;
+        };
+
+        let mesh_handle = match &object.mesh_kind {
+            ObjectMeshKind::Animated(skeleton) => *skeleton_manager.internal_data(**skeleton).mesh_handle,
+            ObjectMeshKind::Static(mesh) => **mesh,
+        };
+        if mesh_handle != handle {
+            continue;
+        }
+
+        let previous_bounding_sphere = object.inner.bounding_sphere;
+        object.mesh_bounding_sphere = bounding_sphere;
+        object.inner.bounding_sphere = bounding_sphere.apply_transform(object.inner.transform);
+        object.location = object.inner.bounding_sphere.center.into();
+
+        if object.cast_shadows {
+            shadow_changes.extend([previous_bounding_sphere, object.inner.bounding_sphere]);
+        }
+
+        buffer.use_index(idx);
+    }
+}
+
 fn duplicate_object<M: Material>(data: &WasmVecAny, idx: usize, change: ObjectChange) -> Object {
     let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
 
@@ -314,9 +537,27 @@ fn duplicate_object<M: Material>(data: &WasmVecAny, idx: usize, change: ObjectCh
         mesh_kind: change.mesh_kind.unwrap_or_else(|| src_obj.mesh_kind.clone()),
         material: change.material.unwrap_or_else(|| src_obj.material_handle.clone()),
         transform: change.transform.unwrap_or(src_obj.inner.transform),
+        cast_shadows: change.cast_shadows.unwrap_or(src_obj.cast_shadows),
+        receive_shadows: change.receive_shadows.unwrap_or(src_obj.inner.receive_shadows != 0),
     }
 }
 
+fn cull<M: Material>(data: &WasmVecAny, frustum: &Frustum, visibility: &mut Vec<bool>) -> usize {
+    let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
+
+    let mut culled = 0;
+    visibility.clear();
+    visibility.extend(data_vec.iter().map(|object| match object {
+        Some(object) => {
+            let visible = frustum.contains_sphere(object.inner.bounding_sphere);
+            culled += !visible as usize;
+            visible
+        }
+        None => false,
+    }));
+    culled
+}
+
 fn remove<M: Material>(archetype: &mut ObjectArchetype, idx: usize) {
     let data_vec = archetype.data_vec.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
 
@@ -324,11 +565,38 @@ fn remove<M: Material>(archetype: &mut ObjectArchetype, idx: usize) {
     // so if we have it, we can be sure it's ours.
     let removed_obj = Option::take(&mut data_vec[idx]);
 
-    if removed_obj.is_some() {
+    if let Some(removed_obj) = removed_obj {
         archetype.object_count -= 1;
+
+        if removed_obj.cast_shadows {
+            archetype.shadow_changes.push(removed_obj.inner.bounding_sphere);
+        }
     }
 }
 
+fn store_previous_transforms<M: Material>(archetype: &mut ObjectArchetype) {
+    let data_vec = archetype.data_vec.downcast_slice_mut::<Option<InternalObject<M>>>().unwrap();
+
+    // Objects which moved in the last frame still need their previous transform updated, even if they
+    // don't move again. The others already have matching transforms.
+    for idx in archetype.moved.drain(..) {
+        // The object may have been removed since.
+        if let Some(object) = &mut data_vec[idx] {
+            object.inner.previous_transform = object.inner.transform;
+            archetype.buffer.use_index(idx);
+        }
+    }
+}
+
+fn animated_shadow_casters<M: Material>(data: &WasmVecAny, shadow_changes: &mut Vec<BoundingSphere>) {
+    let data_vec = data.downcast_slice::<Option<InternalObject<M>>>().unwrap();
+
+    shadow_changes.extend(data_vec.iter().flatten().filter_map(|object| {
+        let animated = matches!(object.mesh_kind, ObjectMeshKind::Animated(_));
+        (animated && object.cast_shadows).then_some(object.inner.bounding_sphere)
+    }));
+}
+
 fn evaluate<M: Material>(
     archetype: &mut ObjectArchetype,
     device: &Device,
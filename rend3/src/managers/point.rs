@@ -1,9 +1,10 @@
 use encase::{ArrayLength, ShaderType};
-use glam::{Vec3, Vec4};
-use rend3_types::{PointLight, PointLightChange, RawPointLightHandle};
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
+use rend3_types::{Camera, CameraProjection, Handedness, PointLight, PointLightChange, RawPointLightHandle};
 use wgpu::{BufferUsages, Device, ShaderStages};
 
 use crate::{
+    managers::{CameraState, ShaderShadowMap, ShadowDesc, ShadowSource},
     util::{
         bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
         buffer::WrappedPotBuffer,
@@ -18,11 +19,36 @@ struct ShaderPointLightBuffer {
     array: Vec<ShaderPointLight>,
 }
 
+/// Amount of faces of a point light's shadow cube.
+const CUBE_FACE_COUNT: usize = 6;
+
+/// Forward and up directions of each of the cube faces, in the order +X, -X, +Y, -Y, +Z, -Z.
+/// Must match the face selection in `opaque.wgsl`.
+const CUBE_FACES: [(Vec3, Vec3); CUBE_FACE_COUNT] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+/// Near plane of the shadow cameras. Geometry closer than this to the light will not cast shadows.
+const SHADOW_NEAR_PLANE: f32 = 0.05;
+
 #[derive(Debug, Copy, Clone, ShaderType)]
 struct ShaderPointLight {
     pub position: Vec4,
     pub color: Vec3,
     pub radius: f32,
+    /// 1 / resolution of whole shadow map
+    pub inv_resolution: Vec2,
+    /// 1 if the light has a shadow, 0 otherwise.
+    pub shadowed: u32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    pub baked: u32,
+    /// Faces of the shadow cube, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub shadow_maps: [ShaderShadowMap; CUBE_FACE_COUNT],
 }
 
 /// Manages point lights and their associated shadow maps.
@@ -55,20 +81,64 @@ impl PointLightManager {
         self.data[handle.idx].take().unwrap();
     }
 
-    pub fn evaluate(&mut self, renderer: &Renderer) {
-        let buffer = ShaderPointLightBuffer {
-            count: ArrayLength,
-            array: self
-                .data
-                .iter()
-                .flatten()
-                .map(|light| ShaderPointLight {
+    /// All shadow maps, six per shadowed light, which need to be allocated in the shadow atlas.
+    pub(super) fn shadow_maps(&self) -> impl Iterator<Item = (ShadowSource, u16)> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, light)| Some((RawPointLightHandle::new(idx), light.as_ref()?.shadow_resolution?)))
+            .flat_map(|(handle, resolution)| {
+                (0..CUBE_FACE_COUNT as u32).map(move |face| (ShadowSource::Point { handle, face }, resolution))
+            })
+    }
+
+    pub(super) fn shadow_camera(&self, handle: RawPointLightHandle, face: u32, handedness: Handedness) -> CameraState {
+        let light = self.data[handle.idx].as_ref().unwrap();
+        let (forward, up) = CUBE_FACES[face as usize];
+
+        let look_at = match handedness {
+            Handedness::Left => Mat4::look_at_lh,
+            Handedness::Right => Mat4::look_at_rh,
+        };
+
+        CameraState::new(
+            Camera {
+                projection: CameraProjection::Perspective { vfov: 90.0, near: SHADOW_NEAR_PLANE },
+                view: look_at(light.position, light.position + forward, up),
+            },
+            handedness,
+            None,
+        )
+    }
+
+    pub fn evaluate(&mut self, renderer: &Renderer, shadow_target_size: UVec2, shadows: &[ShadowDesc]) {
+        profiling::scope!("PointLightManager::evaluate");
+
+        // Lights are stored densely in the buffer, so we need to know where each light ended up.
+        let mut buffer_indices = vec![usize::MAX; self.data.len()];
+        let mut array = Vec::with_capacity(self.data.len());
+        for (idx, light) in self.data.iter().enumerate() {
+            if let Some(light) = light {
+                buffer_indices[idx] = array.len();
+                array.push(ShaderPointLight {
                     position: light.position.extend(1.0),
                     color: light.color * light.intensity,
                     radius: light.radius,
-                })
-                .collect(),
-        };
+                    inv_resolution: 1.0 / shadow_target_size.as_vec2(),
+                    shadowed: light.shadow_resolution.is_some() as u32,
+                    baked: light.baked as u32,
+                    shadow_maps: [ShaderShadowMap::default(); CUBE_FACE_COUNT],
+                });
+            }
+        }
+        for desc in shadows {
+            if let ShadowSource::Point { handle, face } = desc.map.source {
+                array[buffer_indices[handle.idx]].shadow_maps[face as usize] =
+                    ShaderShadowMap::new(desc, shadow_target_size);
+            }
+        }
+
+        let buffer = ShaderPointLightBuffer { count: ArrayLength, array };
 
         self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
     }
@@ -0,0 +1,109 @@
+use rend3_types::{RawReflectionProbeHandle, ReflectionProbe, ReflectionProbeChange};
+
+/// Manages reflection probes.
+///
+/// The probes are only stored here, rendering their environments and binding
+/// them to the shaders is left to the render routines.
+#[derive(Default)]
+pub struct ReflectionProbeManager {
+    data: Vec<Option<ReflectionProbe>>,
+}
+
+impl ReflectionProbeManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, handle: RawReflectionProbeHandle, probe: ReflectionProbe) {
+        if handle.idx >= self.data.len() {
+            self.data.resize(handle.idx + 1, None);
+        }
+
+        self.data[handle.idx] = Some(probe);
+    }
+
+    pub fn update(&mut self, handle: RawReflectionProbeHandle, change: ReflectionProbeChange) {
+        self.data[handle.idx].as_mut().unwrap().update_from_changes(change);
+    }
+
+    pub fn remove(&mut self, handle: RawReflectionProbeHandle) {
+        self.data[handle.idx].take().unwrap();
+    }
+
+    /// The probe with the given handle, `None` if it has been removed.
+    pub fn get(&self, handle: RawReflectionProbeHandle) -> Option<&ReflectionProbe> {
+        self.data.get(handle.idx)?.as_ref()
+    }
+
+    /// All probes, in the order of their handles.
+    pub fn probes(&self) -> impl Iterator<Item = (RawReflectionProbeHandle, &ReflectionProbe)> + '_ {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, probe)| Some((RawReflectionProbeHandle::new(idx), probe.as_ref()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rend3_types::{RawReflectionProbeHandle, ReflectionProbe, ReflectionProbeChange};
+
+    use super::ReflectionProbeManager;
+
+    fn probe(position: Vec3) -> ReflectionProbe {
+        ReflectionProbe {
+            position,
+            box_min: Vec3::splat(-1.0),
+            box_max: Vec3::splat(1.0),
+            blend_distance: 0.5,
+            texture: None,
+        }
+    }
+
+    #[test]
+    fn add_out_of_order() {
+        let mut manager = ReflectionProbeManager::new();
+        manager.add(RawReflectionProbeHandle::new(2), probe(Vec3::X));
+        manager.add(RawReflectionProbeHandle::new(0), probe(Vec3::Y));
+
+        assert_eq!(manager.get(RawReflectionProbeHandle::new(0)).unwrap().position, Vec3::Y);
+        assert!(manager.get(RawReflectionProbeHandle::new(1)).is_none());
+        assert_eq!(manager.get(RawReflectionProbeHandle::new(2)).unwrap().position, Vec3::X);
+        assert!(manager.get(RawReflectionProbeHandle::new(3)).is_none());
+
+        let indices: Vec<_> = manager.probes().map(|(handle, _)| handle.idx).collect();
+        assert_eq!(indices, [0, 2]);
+    }
+
+    #[test]
+    fn update_only_changes_given_fields() {
+        let mut manager = ReflectionProbeManager::new();
+        let handle = RawReflectionProbeHandle::new(0);
+        manager.add(handle, probe(Vec3::X));
+
+        manager.update(handle, ReflectionProbeChange { blend_distance: Some(2.0), ..Default::default() });
+
+        let updated = manager.get(handle).unwrap();
+        assert_eq!(updated.blend_distance, 2.0);
+        assert_eq!(updated.position, Vec3::X);
+        assert_eq!(updated.box_max, Vec3::splat(1.0));
+    }
+
+    #[test]
+    fn remove_leaves_other_probes() {
+        let mut manager = ReflectionProbeManager::new();
+        manager.add(RawReflectionProbeHandle::new(0), probe(Vec3::X));
+        manager.add(RawReflectionProbeHandle::new(1), probe(Vec3::Y));
+
+        manager.remove(RawReflectionProbeHandle::new(0));
+
+        assert!(manager.get(RawReflectionProbeHandle::new(0)).is_none());
+        let positions: Vec<_> = manager.probes().map(|(_, probe)| probe.position).collect();
+        assert_eq!(positions, [Vec3::Y]);
+
+        // The slot can be reused by a new probe.
+        manager.add(RawReflectionProbeHandle::new(0), probe(Vec3::Z));
+        assert_eq!(manager.get(RawReflectionProbeHandle::new(0)).unwrap().position, Vec3::Z);
+    }
+}
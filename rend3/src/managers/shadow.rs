@@ -0,0 +1,305 @@
+use std::sync::Arc;
+
+use encase::ShaderType;
+use glam::{Mat4, UVec2, Vec2};
+use rend3_types::{RawDirectionalLightHandle, RawPointLightHandle, RawSpotLightHandle, ShadowFilter};
+use wgpu::{Extent3d, Texture, TextureDescriptor, TextureDimension, TextureUsages};
+
+use crate::{
+    managers::{CameraState, DirectionalLightManager, PointLightManager, SpotLightManager},
+    util::{frustum::BoundingSphere, typedefs::FastHashMap},
+    Renderer, INTERNAL_SHADOW_DEPTH_FORMAT,
+};
+
+mod shadow_alloc;
+
+pub use shadow_alloc::ShadowMap;
+
+const MINIMUM_SHADOW_MAP_SIZE: UVec2 = UVec2::splat(32);
+
+/// Identifies which light, and which part of that light, a shadow map belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ShadowSource {
+    /// A single cascade of a directional light.
+    Directional { handle: RawDirectionalLightHandle, cascade: u32 },
+    /// A single face of a point light's shadow cube.
+    Point { handle: RawPointLightHandle, face: u32 },
+    /// The shadow map of a spot light.
+    Spot { handle: RawSpotLightHandle },
+}
+
+#[derive(Debug, Clone)]
+pub struct ShadowDesc {
+    pub map: ShadowMap,
+    pub camera: CameraState,
+    /// Filter the shadow map is sampled with. Some filters need extra passes
+    /// after the shadow map is rendered.
+    pub filter: ShadowFilter,
+    /// If the shadow map needs to be rendered this frame. Shadow maps which
+    /// aren't dirty still hold what was rendered into them on a previous frame.
+    pub dirty: bool,
+}
+
+/// Location and projection of a single shadow map in the atlas.
+#[derive(Debug, Default, Copy, Clone, ShaderType)]
+pub(crate) struct ShaderShadowMap {
+    /// View/Projection of the shadow map. Shadow rendering uses viewports
+    /// so this always outputs [-1, 1] no matter where in the atlast the shadow is.
+    pub view_proj: Mat4,
+    /// [0, 1] offset of the shadow map in the atlas.
+    pub atlas_offset: Vec2,
+    /// [0, 1] size of the shadow map in the atlas.
+    pub atlas_size: Vec2,
+}
+
+impl ShaderShadowMap {
+    pub(crate) fn new(desc: &ShadowDesc, shadow_target_size: UVec2) -> Self {
+        let shadow_target_size_f32 = shadow_target_size.as_vec2();
+
+        Self {
+            view_proj: desc.camera.view_proj(),
+            atlas_offset: desc.map.offset.as_vec2() / shadow_target_size_f32,
+            atlas_size: desc.map.size as f32 / shadow_target_size_f32,
+        }
+    }
+}
+
+/// Allocates the shadow atlas shared between all shadow casting lights, and
+/// tracks which shadow maps need to be rendered again.
+///
+/// The atlas texture persists between frames, so a shadow map only needs to be
+/// rendered if it moved in the atlas, its camera moved, or a shadow caster
+/// inside of it was added, removed or changed. The atlas is only allocated
+/// again if the set of shadow maps or their resolutions change.
+#[derive(Default)]
+pub struct ShadowManager {
+    /// Shadow maps and their resolution the atlas was allocated for.
+    requested: Vec<(ShadowSource, u16)>,
+    atlas: Option<ShadowAtlasTexture>,
+    cache: ShadowCache,
+}
+
+/// Tracks what every shadow map in the atlas was last rendered with.
+#[derive(Default)]
+struct ShadowCache {
+    /// View/Projection every shadow map was last rendered with.
+    rendered: FastHashMap<ShadowSource, Mat4>,
+    /// View/Projection of the shadow maps which were dirty in the last
+    /// evaluation. They only count as rendered once the frame is submitted.
+    pending: FastHashMap<ShadowSource, Mat4>,
+    invalidated: bool,
+}
+
+impl ShadowCache {
+    /// Checks if the shadow map of `source` needs to be rendered with `camera`.
+    fn update(&mut self, source: ShadowSource, camera: &CameraState, shadow_changes: &[BoundingSphere]) -> bool {
+        let view_proj = camera.view_proj();
+        let dirty = self.invalidated || self.rendered.get(&source) != Some(&view_proj) || {
+            let frustum = camera.world_frustum();
+            shadow_changes.iter().any(|&sphere| frustum.contains_sphere(sphere))
+        };
+
+        if dirty {
+            // Forget the shadow map until it's rendered, so it stays dirty if this frame is never submitted.
+            self.rendered.remove(&source);
+            self.pending.insert(source, view_proj);
+        }
+
+        dirty
+    }
+
+    fn submitted(&mut self) {
+        self.rendered.extend(self.pending.drain());
+    }
+
+    fn clear(&mut self) {
+        self.rendered.clear();
+        self.pending.clear();
+    }
+}
+
+struct ShadowAtlasTexture {
+    texture: Arc<Texture>,
+    size: UVec2,
+    maps: Vec<ShadowMap>,
+}
+
+impl ShadowManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render every shadow map again on the next call to [`Self::evaluate`].
+    pub fn invalidate(&mut self) {
+        self.cache.invalidated = true;
+    }
+
+    /// Marks the shadow maps which were dirty in the last call to
+    /// [`Self::evaluate`] as rendered. Called once the frame is submitted, so
+    /// shadow maps of frames which were evaluated but never rendered stay
+    /// dirty.
+    pub fn submitted(&mut self) {
+        self.cache.submitted();
+    }
+
+    /// The texture the shadow maps are rendered into. Only valid after the first call to [`Self::evaluate`].
+    pub fn atlas_texture(&self) -> &Arc<Texture> {
+        &self.atlas.as_ref().expect("shadow atlas used before the first evaluation").texture
+    }
+
+    pub fn evaluate(
+        &mut self,
+        renderer: &Renderer,
+        user_camera: &CameraState,
+        directional: &DirectionalLightManager,
+        point: &PointLightManager,
+        spot: &SpotLightManager,
+        shadow_changes: &[BoundingSphere],
+    ) -> (UVec2, Vec<ShadowDesc>) {
+        profiling::scope!("ShadowManager::evaluate");
+
+        let requested: Vec<_> =
+            directional.shadow_maps().chain(point.shadow_maps()).chain(spot.shadow_maps()).collect();
+        if self.atlas.is_none() || requested != self.requested {
+            self.allocate(renderer, requested);
+        }
+        let atlas = self.atlas.as_ref().unwrap();
+        let cache = &mut self.cache;
+        cache.pending.clear();
+
+        let shadows = atlas
+            .maps
+            .iter()
+            .map(|&map| {
+                let (camera, filter) = match map.source {
+                    ShadowSource::Directional { handle, cascade } => {
+                        (directional.shadow_camera(handle, cascade, user_camera), directional.shadow_filter(handle))
+                    }
+                    ShadowSource::Point { handle, face } => {
+                        (point.shadow_camera(handle, face, user_camera.handedness()), ShadowFilter::Pcf)
+                    }
+                    ShadowSource::Spot { handle } => {
+                        (spot.shadow_camera(handle, user_camera.handedness()), ShadowFilter::Pcf)
+                    }
+                };
+
+                let dirty = cache.update(map.source, &camera, shadow_changes);
+
+                ShadowDesc { map, camera, filter, dirty }
+            })
+            .collect();
+
+        cache.invalidated = false;
+
+        (atlas.size, shadows)
+    }
+
+    fn allocate(&mut self, renderer: &Renderer, requested: Vec<(ShadowSource, u16)>) {
+        let shadow_atlas =
+            shadow_alloc::allocate_shadow_atlas(requested.clone(), renderer.limits.max_texture_dimension_2d);
+
+        let (size, maps) = match shadow_atlas {
+            Some(atlas) => (atlas.texture_dimensions.max(MINIMUM_SHADOW_MAP_SIZE), atlas.maps),
+            None => (MINIMUM_SHADOW_MAP_SIZE, Vec::new()),
+        };
+
+        let texture = match self.atlas.take() {
+            Some(atlas) if atlas.size == size => atlas.texture,
+            _ => Arc::new(renderer.device.create_texture(&TextureDescriptor {
+                label: Some("shadow atlas"),
+                size: Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: INTERNAL_SHADOW_DEPTH_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })),
+        };
+
+        // The maps may have moved within the atlas, so they all need to be rendered again.
+        self.cache.clear();
+        self.requested = requested;
+        self.atlas = Some(ShadowAtlasTexture { texture, size, maps });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3, Vec3A};
+    use rend3_types::{Camera, CameraProjection, Handedness, RawSpotLightHandle};
+
+    use super::{ShadowCache, ShadowSource};
+    use crate::{managers::CameraState, util::frustum::BoundingSphere};
+
+    const SOURCE: ShadowSource = ShadowSource::Spot { handle: RawSpotLightHandle::new(0) };
+
+    fn camera(x: f32) -> CameraState {
+        CameraState::new(
+            Camera {
+                projection: CameraProjection::Orthographic { size: Vec3A::splat(10.0) },
+                view: Mat4::from_translation(Vec3::new(-x, 0.0, 0.0)),
+            },
+            Handedness::Left,
+            None,
+        )
+    }
+
+    fn sphere(x: f32) -> BoundingSphere {
+        BoundingSphere { center: Vec3::new(x, 0.0, 0.0), radius: 1.0 }
+    }
+
+    /// Renders the shadow map once, so it's clean for `camera(0.0)`.
+    fn rendered_cache() -> ShadowCache {
+        let mut cache = ShadowCache::default();
+        assert!(cache.update(SOURCE, &camera(0.0), &[]));
+        cache.submitted();
+        assert!(!cache.update(SOURCE, &camera(0.0), &[]));
+        cache
+    }
+
+    #[test]
+    fn camera_moved() {
+        let mut cache = rendered_cache();
+        assert!(cache.update(SOURCE, &camera(1.0), &[]));
+        cache.submitted();
+        assert!(!cache.update(SOURCE, &camera(1.0), &[]));
+    }
+
+    #[test]
+    fn caster_changed() {
+        let mut cache = rendered_cache();
+        // Outside of the shadow camera.
+        assert!(!cache.update(SOURCE, &camera(0.0), &[sphere(100.0)]));
+        assert!(cache.update(SOURCE, &camera(0.0), &[sphere(0.0)]));
+        cache.submitted();
+        assert!(!cache.update(SOURCE, &camera(0.0), &[]));
+    }
+
+    #[test]
+    fn caster_removed() {
+        // Removing a caster reports the bounds it had before the removal.
+        let mut cache = rendered_cache();
+        assert!(cache.update(SOURCE, &camera(0.0), &[sphere(2.0)]));
+        cache.submitted();
+        assert!(!cache.update(SOURCE, &camera(0.0), &[]));
+    }
+
+    #[test]
+    fn skipped_frame_stays_dirty() {
+        let mut cache = rendered_cache();
+        assert!(cache.update(SOURCE, &camera(0.0), &[sphere(0.0)]));
+        // The frame is never submitted, so the change isn't rendered yet.
+        cache.pending.clear();
+        assert!(cache.update(SOURCE, &camera(0.0), &[]));
+        cache.submitted();
+        assert!(!cache.update(SOURCE, &camera(0.0), &[]));
+    }
+
+    #[test]
+    fn invalidated() {
+        let mut cache = rendered_cache();
+        cache.invalidated = true;
+        assert!(cache.update(SOURCE, &camera(0.0), &[]));
+    }
+}
@@ -1,27 +1,23 @@
 use std::{array, cmp::Reverse, collections::VecDeque};
 
 use glam::UVec2;
-use rend3_types::RawDirectionalLightHandle;
+
+use crate::managers::ShadowSource;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum ShadowNode {
     Vacant,
-    Leaf(RawDirectionalLightHandle),
+    Leaf(ShadowSource),
     Children([usize; 4]),
 }
 
 impl ShadowNode {
-    fn try_alloc(
-        nodes: &mut Vec<ShadowNode>,
-        node_idx: usize,
-        relative_order: u32,
-        handle: RawDirectionalLightHandle,
-    ) -> bool {
+    fn try_alloc(nodes: &mut Vec<ShadowNode>, node_idx: usize, relative_order: u32, source: ShadowSource) -> bool {
         let this = &mut nodes[node_idx];
         match *this {
             ShadowNode::Vacant => {
                 if relative_order == 0 {
-                    *this = ShadowNode::Leaf(handle);
+                    *this = ShadowNode::Leaf(source);
 
                     true
                 } else {
@@ -29,7 +25,7 @@ impl ShadowNode {
                     nodes[node_idx] = ShadowNode::Children(array::from_fn(|idx| base_idx + idx));
                     nodes.resize_with(base_idx + 4, || ShadowNode::Vacant);
 
-                    ShadowNode::try_alloc(nodes, node_idx, relative_order, handle)
+                    ShadowNode::try_alloc(nodes, node_idx, relative_order, source)
                 }
             }
             ShadowNode::Leaf(_) => false,
@@ -38,7 +34,7 @@ impl ShadowNode {
                     return false;
                 }
 
-                children.into_iter().any(|child| ShadowNode::try_alloc(nodes, child, relative_order - 1, handle))
+                children.into_iter().any(|child| ShadowNode::try_alloc(nodes, child, relative_order - 1, source))
             }
         }
     }
@@ -53,13 +49,10 @@ pub(super) struct ShadowAtlas {
 pub struct ShadowMap {
     pub offset: UVec2,
     pub size: u32,
-    pub handle: RawDirectionalLightHandle,
+    pub source: ShadowSource,
 }
 
-pub(super) fn allocate_shadow_atlas(
-    mut maps: Vec<(RawDirectionalLightHandle, u16)>,
-    max_dimension: u32,
-) -> Option<ShadowAtlas> {
+pub(super) fn allocate_shadow_atlas(mut maps: Vec<(ShadowSource, u16)>, max_dimension: u32) -> Option<ShadowAtlas> {
     if maps.is_empty() {
         return None;
     }
@@ -67,7 +60,7 @@ pub(super) fn allocate_shadow_atlas(
         return None;
     }
 
-    maps.sort_by_key(|(_idx, res)| Reverse(*res));
+    maps.sort_by_key(|(_source, res)| Reverse(*res));
 
     let root_size = maps.first().unwrap().1 as u32;
     let min_leading_zeros = (root_size as u16).leading_zeros();
@@ -78,13 +71,13 @@ pub(super) fn allocate_shadow_atlas(
     nodes.push(ShadowNode::Vacant);
     roots.push(0);
 
-    for (handle, resolution) in maps {
+    for (source, resolution) in maps {
         debug_assert!(resolution.is_power_of_two());
         debug_assert_ne!(resolution, 0);
         let order = resolution.leading_zeros() - min_leading_zeros;
 
         loop {
-            if ShadowNode::try_alloc(&mut nodes, *roots.last().unwrap(), order, handle) {
+            if ShadowNode::try_alloc(&mut nodes, *roots.last().unwrap(), order, source) {
                 break;
             }
 
@@ -118,7 +111,7 @@ pub(super) fn allocate_shadow_atlas(
 
         match nodes[node_idx] {
             ShadowNode::Vacant => {}
-            ShadowNode::Leaf(handle) => output_maps.push(ShadowMap { offset, size, handle }),
+            ShadowNode::Leaf(source) => output_maps.push(ShadowMap { offset, size, source }),
             ShadowNode::Children(children) => {
                 let child_divisor = root_divisor * 2;
                 nodes_to_visit.extend(children.into_iter().enumerate().map(|(child_idx, node_idx)| {
@@ -138,40 +131,47 @@ pub(super) fn allocate_shadow_atlas(
 #[cfg(test)]
 mod tests {
     use glam::UVec2;
-    use rend3_types::RawDirectionalLightHandle as RDLH;
+    use rend3_types::{RawDirectionalLightHandle, RawPointLightHandle};
 
     use super::ShadowNode;
-    use crate::managers::directional::shadow_alloc::{allocate_shadow_atlas, ShadowMap};
+    use crate::managers::{
+        shadow::shadow_alloc::{allocate_shadow_atlas, ShadowMap},
+        ShadowSource,
+    };
+
+    fn dir(idx: usize) -> ShadowSource {
+        ShadowSource::Directional { handle: RawDirectionalLightHandle::new(idx), cascade: 0 }
+    }
 
     #[test]
     fn chunk_subdivision_single() {
         let mut nodes = vec![ShadowNode::Vacant];
 
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 0, RDLH::new(0)));
-        assert_eq!(&nodes, &[ShadowNode::Leaf(RDLH::new(0))]);
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 0, dir(0)));
+        assert_eq!(&nodes, &[ShadowNode::Leaf(dir(0))]);
     }
 
     #[test]
     fn chunk_subdivision_single_failure() {
         let mut nodes = vec![ShadowNode::Vacant];
 
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 0, RDLH::new(0)));
-        assert!(!ShadowNode::try_alloc(&mut nodes, 0, 0, RDLH::new(1)));
-        assert_eq!(&nodes, &[ShadowNode::Leaf(RDLH::new(0))]);
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 0, dir(0)));
+        assert!(!ShadowNode::try_alloc(&mut nodes, 0, 0, dir(1)));
+        assert_eq!(&nodes, &[ShadowNode::Leaf(dir(0))]);
     }
 
     #[test]
     fn chunk_subdivision_multiple() {
         let mut nodes = vec![ShadowNode::Vacant];
 
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(0)));
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(1)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(0)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(1)));
         assert_eq!(
             &nodes,
             &[
                 ShadowNode::Children([1, 2, 3, 4]),
-                ShadowNode::Leaf(RDLH::new(0)),
-                ShadowNode::Leaf(RDLH::new(1)),
+                ShadowNode::Leaf(dir(0)),
+                ShadowNode::Leaf(dir(1)),
                 ShadowNode::Vacant,
                 ShadowNode::Vacant
             ]
@@ -183,17 +183,17 @@ mod tests {
         let mut nodes = vec![ShadowNode::Vacant];
 
         for i in 0..4 {
-            assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(i)));
+            assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(i)));
         }
-        assert!(!ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(5)));
+        assert!(!ShadowNode::try_alloc(&mut nodes, 0, 1, dir(5)));
         assert_eq!(
             &nodes,
             &[
                 ShadowNode::Children([1, 2, 3, 4]),
-                ShadowNode::Leaf(RDLH::new(0)),
-                ShadowNode::Leaf(RDLH::new(1)),
-                ShadowNode::Leaf(RDLH::new(2)),
-                ShadowNode::Leaf(RDLH::new(3)),
+                ShadowNode::Leaf(dir(0)),
+                ShadowNode::Leaf(dir(1)),
+                ShadowNode::Leaf(dir(2)),
+                ShadowNode::Leaf(dir(3)),
             ]
         );
     }
@@ -202,21 +202,21 @@ mod tests {
     fn chunk_subdivision_multiple_nested() {
         let mut nodes = vec![ShadowNode::Vacant];
 
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(0)));
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(1)));
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 2, RDLH::new(2)));
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, RDLH::new(3)));
-        assert!(ShadowNode::try_alloc(&mut nodes, 0, 2, RDLH::new(4)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(0)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(1)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 2, dir(2)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 1, dir(3)));
+        assert!(ShadowNode::try_alloc(&mut nodes, 0, 2, dir(4)));
         assert_eq!(
             &nodes,
             &[
                 ShadowNode::Children([1, 2, 3, 4]),
-                ShadowNode::Leaf(RDLH::new(0)),
-                ShadowNode::Leaf(RDLH::new(1)),
+                ShadowNode::Leaf(dir(0)),
+                ShadowNode::Leaf(dir(1)),
                 ShadowNode::Children([5, 6, 7, 8]),
-                ShadowNode::Leaf(RDLH::new(3)),
-                ShadowNode::Leaf(RDLH::new(2)),
-                ShadowNode::Leaf(RDLH::new(4)),
+                ShadowNode::Leaf(dir(3)),
+                ShadowNode::Leaf(dir(2)),
+                ShadowNode::Leaf(dir(4)),
                 ShadowNode::Vacant,
                 ShadowNode::Vacant,
             ]
@@ -225,60 +225,59 @@ mod tests {
 
     #[test]
     fn allocate_single() {
-        let maps = vec![(RDLH::new(0), 16)];
+        let maps = vec![(dir(0), 16)];
 
         let res = allocate_shadow_atlas(maps, 16).unwrap();
         assert_eq!(res.texture_dimensions, UVec2::splat(16));
-        assert_eq!(res.maps, &[ShadowMap { offset: UVec2::splat(0), size: 16, handle: RDLH::new(0) }]);
+        assert_eq!(res.maps, &[ShadowMap { offset: UVec2::splat(0), size: 16, source: dir(0) }]);
     }
 
     #[test]
     fn allocate_single_level_single_row() {
-        let maps = vec![(RDLH::new(0), 16), (RDLH::new(1), 16), (RDLH::new(2), 16)];
+        let maps = vec![(dir(0), 16), (dir(1), 16), (dir(2), 16)];
 
         let res = allocate_shadow_atlas(maps, 48).unwrap();
         assert_eq!(res.texture_dimensions, UVec2::new(48, 16));
         assert_eq!(
             res.maps,
             &[
-                ShadowMap { offset: UVec2::splat(0), size: 16, handle: RDLH::new(0) },
-                ShadowMap { offset: UVec2::new(16, 0), size: 16, handle: RDLH::new(1) },
-                ShadowMap { offset: UVec2::new(32, 0), size: 16, handle: RDLH::new(2) }
+                ShadowMap { offset: UVec2::splat(0), size: 16, source: dir(0) },
+                ShadowMap { offset: UVec2::new(16, 0), size: 16, source: dir(1) },
+                ShadowMap { offset: UVec2::new(32, 0), size: 16, source: dir(2) }
             ]
         );
     }
 
     #[test]
     fn allocate_single_level_double_row() {
-        let maps = vec![(RDLH::new(0), 16), (RDLH::new(1), 16), (RDLH::new(2), 16)];
+        let maps = vec![(dir(0), 16), (dir(1), 16), (dir(2), 16)];
 
         let res = allocate_shadow_atlas(maps, 32).unwrap();
         assert_eq!(res.texture_dimensions, UVec2::new(32, 32));
         assert_eq!(
             res.maps,
             &[
-                ShadowMap { offset: UVec2::splat(0), size: 16, handle: RDLH::new(0) },
-                ShadowMap { offset: UVec2::new(16, 0), size: 16, handle: RDLH::new(1) },
-                ShadowMap { offset: UVec2::new(0, 16), size: 16, handle: RDLH::new(2) }
+                ShadowMap { offset: UVec2::splat(0), size: 16, source: dir(0) },
+                ShadowMap { offset: UVec2::new(16, 0), size: 16, source: dir(1) },
+                ShadowMap { offset: UVec2::new(0, 16), size: 16, source: dir(2) }
             ]
         );
     }
 
     #[test]
     fn allocate_single_level_double_row_extra_space() {
-        let maps =
-            vec![(RDLH::new(0), 16), (RDLH::new(1), 16), (RDLH::new(2), 16), (RDLH::new(3), 16), (RDLH::new(4), 16)];
+        let maps = vec![(dir(0), 16), (dir(1), 16), (dir(2), 16), (dir(3), 16), (dir(4), 16)];
 
         let res = allocate_shadow_atlas(maps, 64).unwrap();
         assert_eq!(res.texture_dimensions, UVec2::new(48, 32));
         assert_eq!(
             res.maps,
             &[
-                ShadowMap { offset: UVec2::splat(0), size: 16, handle: RDLH::new(0) },
-                ShadowMap { offset: UVec2::new(16, 0), size: 16, handle: RDLH::new(1) },
-                ShadowMap { offset: UVec2::new(32, 0), size: 16, handle: RDLH::new(2) },
-                ShadowMap { offset: UVec2::new(0, 16), size: 16, handle: RDLH::new(3) },
-                ShadowMap { offset: UVec2::new(16, 16), size: 16, handle: RDLH::new(4) }
+                ShadowMap { offset: UVec2::splat(0), size: 16, source: dir(0) },
+                ShadowMap { offset: UVec2::new(16, 0), size: 16, source: dir(1) },
+                ShadowMap { offset: UVec2::new(32, 0), size: 16, source: dir(2) },
+                ShadowMap { offset: UVec2::new(0, 16), size: 16, source: dir(3) },
+                ShadowMap { offset: UVec2::new(16, 16), size: 16, source: dir(4) }
             ]
         );
     }
@@ -294,26 +293,39 @@ mod tests {
     /// └───────────────┴───┘
     #[test]
     fn allocate_multiple_level() {
-        let maps = vec![
-            (RDLH::new(0), 16),
-            (RDLH::new(1), 8),
-            (RDLH::new(2), 8),
-            (RDLH::new(3), 4),
-            (RDLH::new(4), 4),
-            (RDLH::new(5), 4),
-        ];
+        let maps = vec![(dir(0), 16), (dir(1), 8), (dir(2), 8), (dir(3), 4), (dir(4), 4), (dir(5), 4)];
 
         let res = allocate_shadow_atlas(maps, 32).unwrap();
         assert_eq!(res.texture_dimensions, UVec2::new(32, 16));
         assert_eq!(
             res.maps,
             &[
-                ShadowMap { offset: UVec2::splat(0), size: 16, handle: RDLH::new(0) },
-                ShadowMap { offset: UVec2::new(16, 0), size: 8, handle: RDLH::new(1) },
-                ShadowMap { offset: UVec2::new(24, 0), size: 8, handle: RDLH::new(2) },
-                ShadowMap { offset: UVec2::new(16, 8), size: 4, handle: RDLH::new(3) },
-                ShadowMap { offset: UVec2::new(20, 8), size: 4, handle: RDLH::new(4) },
-                ShadowMap { offset: UVec2::new(16, 12), size: 4, handle: RDLH::new(5) },
+                ShadowMap { offset: UVec2::splat(0), size: 16, source: dir(0) },
+                ShadowMap { offset: UVec2::new(16, 0), size: 8, source: dir(1) },
+                ShadowMap { offset: UVec2::new(24, 0), size: 8, source: dir(2) },
+                ShadowMap { offset: UVec2::new(16, 8), size: 4, source: dir(3) },
+                ShadowMap { offset: UVec2::new(20, 8), size: 4, source: dir(4) },
+                ShadowMap { offset: UVec2::new(16, 12), size: 4, source: dir(5) },
+            ]
+        );
+    }
+
+    #[test]
+    fn allocate_cascades_and_faces() {
+        let cascade = |cascade| ShadowSource::Directional { handle: RawDirectionalLightHandle::new(0), cascade };
+        let face = |face| ShadowSource::Point { handle: RawPointLightHandle::new(0), face };
+        let maps = vec![(cascade(0), 16), (cascade(1), 16), (face(0), 8), (face(1), 8), (cascade(2), 16)];
+
+        let res = allocate_shadow_atlas(maps, 32).unwrap();
+        assert_eq!(res.texture_dimensions, UVec2::new(32, 32));
+        assert_eq!(
+            res.maps,
+            &[
+                ShadowMap { offset: UVec2::splat(0), size: 16, source: cascade(0) },
+                ShadowMap { offset: UVec2::new(16, 0), size: 16, source: cascade(1) },
+                ShadowMap { offset: UVec2::new(0, 16), size: 16, source: cascade(2) },
+                ShadowMap { offset: UVec2::new(16, 16), size: 8, source: face(0) },
+                ShadowMap { offset: UVec2::new(24, 16), size: 8, source: face(1) },
             ]
         );
     }
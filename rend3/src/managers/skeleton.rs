@@ -4,7 +4,8 @@ use arrayvec::ArrayVec;
 use glam::Mat4;
 use rend3_types::{
     MeshHandle, RawSkeletonHandle, Skeleton, VertexAttributeId, VERTEX_ATTRIBUTE_JOINT_INDICES,
-    VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION, VERTEX_ATTRIBUTE_TANGENT,
+    VERTEX_ATTRIBUTE_JOINT_WEIGHTS, VERTEX_ATTRIBUTE_NORMAL, VERTEX_ATTRIBUTE_POSITION,
+    VERTEX_ATTRIBUTE_PREVIOUS_POSITION, VERTEX_ATTRIBUTE_TANGENT,
 };
 use thiserror::Error;
 use wgpu::Device;
@@ -22,21 +23,41 @@ pub struct InternalSkeleton {
     /// The list of per-joint transformation matrices that will be applied to
     /// vertices.
     pub joint_matrices: Vec<Mat4>,
+    /// The joint matrices of the previous frame, used to skin the previous
+    /// position of the vertices for motion vectors.
+    pub previous_joint_matrices: Vec<Mat4>,
     /// There are 5 different ranges we need to store here:
     /// Position, Normals, Tangent, Joint Index, Joint Weight
     pub source_attribute_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 5>,
     /// There are three attributes that we can possibly override here:
-    /// Position, Normals, and Tangent
-    pub overridden_attribute_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 3>,
+    /// Position, Normals, and Tangent. Skinned meshes also get a fourth range
+    /// for the previous position.
+    pub overridden_attribute_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 4>,
+    /// Morph target offsets of the mesh for Position, Normals, and Tangent.
+    pub morph_target_ranges: ArrayVec<(VertexAttributeId, Range<u64>), 3>,
+    /// One weight per morph target of the mesh.
+    pub morph_weights: Vec<f32>,
     /// Amount of vertices in the pointed to mesh
     pub vertex_count: u32,
 }
 
+impl InternalSkeleton {
+    /// Returns true if the mesh has joints, so skinning needs to run.
+    pub fn is_skinned(&self) -> bool {
+        !self.joint_matrices.is_empty()
+    }
+
+    /// Returns true if the mesh has morph targets, so they need to be blended before skinning.
+    pub fn is_morphed(&self) -> bool {
+        !self.morph_weights.is_empty()
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum SkeletonCreationError {
     #[error("Failed to create needed resources in the mesh manager")]
     MeshFailure(#[from] MeshCreationError),
-    #[error("Mesh must have joint indices to be used in a skeleton")]
+    #[error("Mesh must have joint indices or morph targets to be used in a skeleton")]
     MissingAttributesJointIndices,
     #[error("Mesh must have joint weights to be used in a skeleton")]
     MissingAttributesJointWeights,
@@ -56,12 +77,14 @@ pub struct SkeletonManager {
     skeleton_count: usize,
     /// The number of joints of all the skeletons in this manager
     global_joint_count: usize,
+    /// The number of morph weights of all the skeletons in this manager
+    global_morph_weight_count: usize,
 }
 impl SkeletonManager {
     pub fn new() -> Self {
         profiling::scope!("SkeletonManager::new");
 
-        Self { data: Vec::new(), skeleton_count: 0, global_joint_count: 0 }
+        Self { data: Vec::new(), skeleton_count: 0, global_joint_count: 0, global_morph_weight_count: 0 }
     }
 
     pub fn validate_skeleton(
@@ -70,63 +93,94 @@ impl SkeletonManager {
         skeleton: Skeleton,
     ) -> Result<InternalSkeleton, SkeletonCreationError> {
         let internal_mesh = &mesh_manager.lock_internal_data()[skeleton.mesh.get_raw()];
-        let required_joint_count =
-            internal_mesh.required_joint_count.ok_or(SkeletonCreationError::MissingAttributesJointIndices)?;
-
-        let joint_weight_range = internal_mesh
-            .get_attribute(&VERTEX_ATTRIBUTE_JOINT_WEIGHTS)
-            .ok_or(SkeletonCreationError::MissingAttributesJointWeights)?;
-        let joint_indices_range = internal_mesh
-            .get_attribute(&VERTEX_ATTRIBUTE_JOINT_INDICES)
-            .ok_or(SkeletonCreationError::MissingAttributesJointIndices)?;
-
-        // Converts the following assert to an error
-        if required_joint_count as usize > skeleton.joint_matrices.len() {
-            return Err(SkeletonCreationError::NotEnoughJoints {
-                mesh_joint_count: required_joint_count,
-                joint_matrix_count: skeleton.joint_matrices.len(),
-            });
-        }
-
-        let overridden_attributes = [&VERTEX_ATTRIBUTE_POSITION, &VERTEX_ATTRIBUTE_NORMAL, &VERTEX_ATTRIBUTE_TANGENT];
 
         let mut source_attribute_ranges: ArrayVec<_, 5> = ArrayVec::new();
-        source_attribute_ranges.push((*VERTEX_ATTRIBUTE_JOINT_WEIGHTS.id(), joint_weight_range));
-        source_attribute_ranges.push((*VERTEX_ATTRIBUTE_JOINT_INDICES.id(), joint_indices_range));
 
-        let mut overridden_attribute_ranges: ArrayVec<_, 3> = ArrayVec::new();
+        // Meshes which are only deformed by morph targets don't need any joints.
+        let required_joint_count = match internal_mesh.required_joint_count {
+            Some(required_joint_count) => {
+                let joint_weight_range = internal_mesh
+                    .get_attribute(&VERTEX_ATTRIBUTE_JOINT_WEIGHTS)
+                    .ok_or(SkeletonCreationError::MissingAttributesJointWeights)?;
+                let joint_indices_range = internal_mesh
+                    .get_attribute(&VERTEX_ATTRIBUTE_JOINT_INDICES)
+                    .ok_or(SkeletonCreationError::MissingAttributesJointIndices)?;
+
+                // Converts the following assert to an error
+                if required_joint_count as usize > skeleton.joint_matrices.len() {
+                    return Err(SkeletonCreationError::NotEnoughJoints {
+                        mesh_joint_count: required_joint_count,
+                        joint_matrix_count: skeleton.joint_matrices.len(),
+                    });
+                }
+
+                source_attribute_ranges.push((*VERTEX_ATTRIBUTE_JOINT_WEIGHTS.id(), joint_weight_range));
+                source_attribute_ranges.push((*VERTEX_ATTRIBUTE_JOINT_INDICES.id(), joint_indices_range));
+
+                required_joint_count
+            }
+            None if internal_mesh.morph_target_count != 0 => 0,
+            None => return Err(SkeletonCreationError::MissingAttributesJointIndices),
+        };
+        let joint_attribute_count = source_attribute_ranges.len();
+
+        let overridden_attributes = [&VERTEX_ATTRIBUTE_POSITION, &VERTEX_ATTRIBUTE_NORMAL, &VERTEX_ATTRIBUTE_TANGENT];
+
+        let mut overridden_attribute_ranges: ArrayVec<_, 4> = ArrayVec::new();
+        let mut morph_target_ranges: ArrayVec<_, 3> = ArrayVec::new();
         for attribute in overridden_attributes {
             let original_range = match internal_mesh.get_attribute(attribute) {
                 Some(a) => a,
                 None => continue,
             };
             source_attribute_ranges.push((*attribute.id(), original_range));
+
+            if let Some(morph_target_range) = internal_mesh.get_morph_target_attribute(attribute) {
+                morph_target_ranges.push((*attribute.id(), morph_target_range));
+            }
         }
 
         // We split this for loop into two parts so that because we need &mut on the mesh manager
         // the original loop needs & on the mesh manager to call get_attribute.
         //
-        // We skip the first two as those are always the joint* attributes.
-        for (attribute_id, original_range) in &source_attribute_ranges[2..] {
+        // We skip the joint* attributes, as those are never overridden.
+        for (attribute_id, original_range) in &source_attribute_ranges[joint_attribute_count..] {
             let skeleton_range = mesh_manager.allocate_range(device, original_range.end - original_range.start)?;
             overridden_attribute_ranges.push((*attribute_id, skeleton_range));
         }
 
+        // Skinned vertices move relative to the object, so their previous position is skinned too.
+        // Morphed only meshes use the current position instead.
+        if required_joint_count != 0 {
+            if let Some(position_range) = internal_mesh.get_attribute(&VERTEX_ATTRIBUTE_POSITION) {
+                let skeleton_range = mesh_manager.allocate_range(device, position_range.end - position_range.start)?;
+                overridden_attribute_ranges.push((*VERTEX_ATTRIBUTE_PREVIOUS_POSITION.id(), skeleton_range));
+            }
+        }
+
         // Ensure there will be exactly `num_joints` matrices.
         let mut joint_matrices = skeleton.joint_matrices;
         joint_matrices.truncate(required_joint_count as _);
 
+        // Ensure there will be exactly one weight per morph target.
+        let mut morph_weights = skeleton.morph_weights;
+        morph_weights.resize(internal_mesh.morph_target_count as usize, 0.0);
+
         Ok(InternalSkeleton {
+            previous_joint_matrices: joint_matrices.clone(),
             joint_matrices,
             mesh_handle: skeleton.mesh,
             source_attribute_ranges,
             overridden_attribute_ranges,
+            morph_target_ranges,
+            morph_weights,
             vertex_count: internal_mesh.vertex_count,
         })
     }
 
     pub fn add(&mut self, handle: RawSkeletonHandle, internal: InternalSkeleton) {
         self.global_joint_count += internal.joint_matrices.len();
+        self.global_morph_weight_count += internal.morph_weights.len();
 
         if handle.idx >= self.data.len() {
             self.data.resize_with(handle.idx + 1, || None);
@@ -139,6 +193,7 @@ impl SkeletonManager {
     pub fn remove(&mut self, mesh_manager: &MeshManager, handle: RawSkeletonHandle) {
         let skeleton = self.data[handle.idx].take().unwrap();
         self.global_joint_count -= skeleton.joint_matrices.len();
+        self.global_morph_weight_count -= skeleton.morph_weights.len();
 
         // Free the owned regions of the mesh data buffer
         for (_, range) in skeleton.overridden_attribute_ranges {
@@ -162,6 +217,21 @@ impl SkeletonManager {
         skeleton.joint_matrices = joint_matrices;
     }
 
+    /// Makes the current joint matrices the previous ones. Called once per
+    /// frame, before the changes of the frame are applied.
+    pub fn store_previous_frame(&mut self) {
+        for skeleton in self.data.iter_mut().flatten() {
+            skeleton.previous_joint_matrices.clone_from(&skeleton.joint_matrices);
+        }
+    }
+
+    pub fn set_morph_weights(&mut self, handle: RawSkeletonHandle, mut morph_weights: Vec<f32>) {
+        let skeleton = self.data[handle.idx].as_mut().unwrap();
+        // Missing weights are zero, extra weights are ignored.
+        morph_weights.resize(skeleton.morph_weights.len(), 0.0);
+        skeleton.morph_weights = morph_weights;
+    }
+
     pub fn internal_data(&self, handle: RawSkeletonHandle) -> &InternalSkeleton {
         self.data[handle.idx].as_ref().unwrap()
     }
@@ -174,6 +244,11 @@ impl SkeletonManager {
     pub fn global_joint_count(&self) -> usize {
         self.global_joint_count
     }
+
+    /// Get the skeleton manager's global morph weight count.
+    pub fn global_morph_weight_count(&self) -> usize {
+        self.global_morph_weight_count
+    }
 }
 
 impl Default for SkeletonManager {
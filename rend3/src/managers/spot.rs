@@ -0,0 +1,243 @@
+use encase::{ArrayLength, ShaderType};
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4};
+use rend3_types::{Camera, CameraProjection, Handedness, RawSpotLightHandle, SpotLight, SpotLightChange};
+use wgpu::{BufferUsages, Device, ShaderStages};
+
+use crate::{
+    managers::{CameraState, ShaderShadowMap, ShadowDesc, ShadowSource},
+    util::{
+        bind_merge::{BindGroupBuilder, BindGroupLayoutBuilder},
+        buffer::WrappedPotBuffer,
+    },
+    Renderer,
+};
+
+/// Near plane of the shadow cameras. Geometry closer than this to the light will not cast shadows.
+const SHADOW_NEAR_PLANE: f32 = 0.05;
+/// Range (in degrees) the outer angle is clamped to. The shadow camera covers twice the outer angle, so it must
+/// stay below 90 degrees.
+const OUTER_ANGLE_RANGE: (f32, f32) = (0.1, 89.0);
+/// Smallest difference (in degrees) between the inner and outer angle. The cone falloff is undefined if they are
+/// equal.
+const MIN_FALLOFF_ANGLE: f32 = 0.01;
+
+/// Clamps the cone of the light into a valid range and replaces a zero direction, which would otherwise turn the
+/// cone attenuation and the shadow camera into NaNs.
+fn sanitize(light: &mut SpotLight) {
+    let (min_outer, max_outer) = OUTER_ANGLE_RANGE;
+    light.outer_angle =
+        if light.outer_angle.is_finite() { light.outer_angle.clamp(min_outer, max_outer) } else { max_outer };
+    let max_inner = light.outer_angle - MIN_FALLOFF_ANGLE;
+    light.inner_angle = if light.inner_angle.is_finite() { light.inner_angle.clamp(0.0, max_inner) } else { 0.0 };
+    if !light.direction.is_finite() || light.direction.length_squared() == 0.0 {
+        light.direction = Vec3::NEG_Y;
+    }
+}
+
+#[derive(Debug, Clone, ShaderType)]
+struct ShaderSpotLightBuffer {
+    count: ArrayLength,
+    #[size(runtime)]
+    array: Vec<ShaderSpotLight>,
+}
+
+#[derive(Debug, Copy, Clone, ShaderType)]
+struct ShaderSpotLight {
+    pub position: Vec4,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub radius: f32,
+    /// Cosine of the inner cone angle.
+    pub cos_inner_angle: f32,
+    /// Cosine of the outer cone angle.
+    pub cos_outer_angle: f32,
+    /// 1 / resolution of whole shadow map
+    pub inv_resolution: Vec2,
+    /// 1 if the light has a shadow, 0 otherwise.
+    pub shadowed: u32,
+    /// 1 if the light is part of the baked lightmaps, 0 otherwise.
+    pub baked: u32,
+    pub shadow_map: ShaderShadowMap,
+}
+
+/// Manages spot lights and their associated shadow maps.
+pub struct SpotLightManager {
+    data: Vec<Option<SpotLight>>,
+    data_buffer: WrappedPotBuffer<ShaderSpotLightBuffer>,
+}
+
+impl SpotLightManager {
+    pub fn new(device: &Device) -> Self {
+        profiling::scope!("SpotLightManager::new");
+
+        Self {
+            data: Vec::new(),
+            data_buffer: WrappedPotBuffer::new(device, BufferUsages::STORAGE, "spot light buffer"),
+        }
+    }
+
+    pub fn add(&mut self, handle: RawSpotLightHandle, mut light: SpotLight) {
+        sanitize(&mut light);
+
+        if handle.idx >= self.data.len() {
+            self.data.resize(handle.idx + 1, None);
+        }
+
+        self.data[handle.idx] = Some(light);
+    }
+
+    pub fn update(&mut self, handle: RawSpotLightHandle, change: SpotLightChange) {
+        let light = self.data[handle.idx].as_mut().unwrap();
+        light.update_from_changes(change);
+        sanitize(light);
+    }
+
+    pub fn remove(&mut self, handle: RawSpotLightHandle) {
+        self.data[handle.idx].take().unwrap();
+    }
+
+    /// All shadow maps, one per shadowed light, which need to be allocated in the shadow atlas.
+    pub(super) fn shadow_maps(&self) -> impl Iterator<Item = (ShadowSource, u16)> + '_ {
+        self.data.iter().enumerate().filter_map(|(idx, light)| {
+            let resolution = light.as_ref()?.shadow_resolution?;
+
+            Some((ShadowSource::Spot { handle: RawSpotLightHandle::new(idx) }, resolution))
+        })
+    }
+
+    pub(super) fn shadow_camera(&self, handle: RawSpotLightHandle, handedness: Handedness) -> CameraState {
+        let light = self.data[handle.idx].as_ref().unwrap();
+        let direction = light.direction.normalize();
+
+        // Avoid a degenerate view matrix when the light points straight up or down.
+        let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+        let look_at = match handedness {
+            Handedness::Left => Mat4::look_at_lh,
+            Handedness::Right => Mat4::look_at_rh,
+        };
+
+        CameraState::new(
+            Camera {
+                projection: CameraProjection::Perspective { vfov: light.outer_angle * 2.0, near: SHADOW_NEAR_PLANE },
+                view: look_at(light.position, light.position + direction, up),
+            },
+            handedness,
+            None,
+        )
+    }
+
+    pub fn evaluate(&mut self, renderer: &Renderer, shadow_target_size: UVec2, shadows: &[ShadowDesc]) {
+        profiling::scope!("SpotLightManager::evaluate");
+
+        // Lights are stored densely in the buffer, so we need to know where each light ended up.
+        let mut buffer_indices = vec![usize::MAX; self.data.len()];
+        let mut array = Vec::with_capacity(self.data.len());
+        for (idx, light) in self.data.iter().enumerate() {
+            if let Some(light) = light {
+                buffer_indices[idx] = array.len();
+                array.push(ShaderSpotLight {
+                    position: light.position.extend(1.0),
+                    direction: light.direction.normalize(),
+                    color: light.color * light.intensity,
+                    radius: light.radius,
+                    cos_inner_angle: light.inner_angle.to_radians().cos(),
+                    cos_outer_angle: light.outer_angle.to_radians().cos(),
+                    inv_resolution: 1.0 / shadow_target_size.as_vec2(),
+                    shadowed: light.shadow_resolution.is_some() as u32,
+                    baked: light.baked as u32,
+                    shadow_map: ShaderShadowMap::default(),
+                });
+            }
+        }
+        for desc in shadows {
+            if let ShadowSource::Spot { handle } = desc.map.source {
+                array[buffer_indices[handle.idx]].shadow_map = ShaderShadowMap::new(desc, shadow_target_size);
+            }
+        }
+
+        let buffer = ShaderSpotLightBuffer { count: ArrayLength, array };
+
+        self.data_buffer.write_to_buffer(&renderer.device, &renderer.queue, &buffer);
+    }
+
+    pub fn add_to_bgl(bglb: &mut BindGroupLayoutBuilder) {
+        bglb.append(
+            ShaderStages::FRAGMENT,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: Some(ShaderSpotLightBuffer::min_size()),
+            },
+            None,
+        );
+    }
+
+    pub fn add_to_bg<'a>(&'a self, bgb: &mut BindGroupBuilder<'a>) {
+        bgb.append_buffer(&self.data_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rend3_types::SpotLight;
+
+    use super::{sanitize, MIN_FALLOFF_ANGLE, OUTER_ANGLE_RANGE};
+
+    fn light(direction: Vec3, inner_angle: f32, outer_angle: f32) -> SpotLight {
+        SpotLight {
+            position: Vec3::ZERO,
+            direction,
+            color: Vec3::ONE,
+            radius: 10.0,
+            intensity: 1.0,
+            inner_angle,
+            outer_angle,
+            shadow_resolution: None,
+            baked: false,
+        }
+    }
+
+    #[test]
+    fn valid_light_unchanged() {
+        let mut l = light(Vec3::X, 20.0, 30.0);
+        sanitize(&mut l);
+        assert_eq!(l.direction, Vec3::X);
+        assert_eq!(l.inner_angle, 20.0);
+        assert_eq!(l.outer_angle, 30.0);
+    }
+
+    #[test]
+    fn outer_angle_clamped() {
+        let mut l = light(Vec3::X, 0.0, 120.0);
+        sanitize(&mut l);
+        assert_eq!(l.outer_angle, OUTER_ANGLE_RANGE.1);
+
+        let mut l = light(Vec3::X, 0.0, -5.0);
+        sanitize(&mut l);
+        assert_eq!(l.outer_angle, OUTER_ANGLE_RANGE.0);
+
+        let mut l = light(Vec3::X, 0.0, f32::NAN);
+        sanitize(&mut l);
+        assert_eq!(l.outer_angle, OUTER_ANGLE_RANGE.1);
+    }
+
+    #[test]
+    fn inner_angle_below_outer() {
+        let mut l = light(Vec3::X, 45.0, 30.0);
+        sanitize(&mut l);
+        assert_eq!(l.inner_angle, 30.0 - MIN_FALLOFF_ANGLE);
+
+        let mut l = light(Vec3::X, 30.0, 30.0);
+        sanitize(&mut l);
+        assert!(l.inner_angle < l.outer_angle);
+    }
+
+    #[test]
+    fn zero_direction_replaced() {
+        let mut l = light(Vec3::ZERO, 20.0, 30.0);
+        sanitize(&mut l);
+        assert_ne!(l.direction.length_squared(), 0.0);
+    }
+}
@@ -278,6 +278,12 @@ impl<T: 'static> TextureManager<T> {
         self.data[handle.idx].as_ref().unwrap()
     }
 
+    /// Like [`Self::get_internal`], but returns None if the instruction adding
+    /// the texture hasn't been evaluated yet.
+    pub fn try_get_internal(&self, handle: RawResourceHandle<T>) -> Option<&InternalTexture> {
+        self.data.get(handle.idx)?.as_ref()
+    }
+
     pub fn get_view(&self, handle: RawResourceHandle<T>) -> &TextureView {
         &self.data[handle.idx].as_ref().unwrap().view
     }
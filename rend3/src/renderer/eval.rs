@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use wgpu::CommandEncoderDescriptor;
 
 use crate::{
@@ -20,6 +22,11 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
     let mut data_core = renderer.data_core.lock();
     let data_core = &mut *data_core;
 
+    // Keep the state of the previous frame around for motion vectors.
+    data_core.viewport_camera_state.store_previous_frame();
+    data_core.object_manager.store_previous_frame();
+    data_core.skeleton_manager.store_previous_frame();
+
     {
         profiling::scope!("Instruction Processing");
         for Instruction { kind, location: _ } in instructions.drain(..) {
@@ -67,6 +74,8 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                         &mut data_core.d2_texture_manager,
                         handle,
                     );
+                    // The material may change which parts of its objects cast shadows.
+                    data_core.shadow_manager.invalidate();
                 }
                 InstructionKind::AddObject { handle, object } => {
                     data_core.object_manager.add(
@@ -81,9 +90,22 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::SetObjectTransform { handle, transform } => {
                     data_core.object_manager.set_object_transform(handle, transform);
                 }
+                InstructionKind::SetObjectShadows { handle, cast_shadows, receive_shadows } => {
+                    data_core.object_manager.set_object_shadows(handle, cast_shadows, receive_shadows);
+                }
+                InstructionKind::SetMeshBoundingSphere { handle, bounding_sphere } => {
+                    data_core.object_manager.set_mesh_bounding_sphere(
+                        &data_core.skeleton_manager,
+                        handle,
+                        bounding_sphere,
+                    );
+                }
                 InstructionKind::SetSkeletonJointDeltas { handle, joint_matrices } => {
                     data_core.skeleton_manager.set_joint_matrices(handle, joint_matrices);
                 }
+                InstructionKind::SetSkeletonMorphWeights { handle, morph_weights } => {
+                    data_core.skeleton_manager.set_morph_weights(handle, morph_weights);
+                }
                 InstructionKind::AddDirectionalLight { handle, light } => {
                     data_core.directional_light_manager.add(handle, light);
                 }
@@ -96,12 +118,27 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                 InstructionKind::ChangePointLight { handle, change } => {
                     data_core.point_light_manager.update(handle, change);
                 }
+                InstructionKind::AddSpotLight { handle, light } => {
+                    data_core.spot_light_manager.add(handle, light);
+                }
+                InstructionKind::ChangeSpotLight { handle, change } => {
+                    data_core.spot_light_manager.update(handle, change);
+                }
+                InstructionKind::AddReflectionProbe { handle, probe } => {
+                    data_core.reflection_probe_manager.add(handle, probe);
+                }
+                InstructionKind::ChangeReflectionProbe { handle, change } => {
+                    data_core.reflection_probe_manager.update(handle, change);
+                }
                 InstructionKind::SetAspectRatio { ratio } => {
                     data_core.viewport_camera_state.set_aspect_ratio(Some(ratio))
                 }
                 InstructionKind::SetCameraData { data } => {
                     data_core.viewport_camera_state.set_data(data);
                 }
+                InstructionKind::SetCameraJitter { jitter } => {
+                    data_core.viewport_camera_state.set_jitter(jitter);
+                }
                 InstructionKind::DuplicateObject { src_handle, dst_handle, change } => {
                     data_core.object_manager.duplicate_object(
                         &renderer.device,
@@ -145,6 +182,14 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
                     renderer.resource_handle_allocators.point_light.deallocate(handle);
                     data_core.point_light_manager.remove(handle);
                 }
+                InstructionKind::DeleteSpotLight { handle } => {
+                    renderer.resource_handle_allocators.spot_light.deallocate(handle);
+                    data_core.spot_light_manager.remove(handle);
+                }
+                InstructionKind::DeleteReflectionProbe { handle } => {
+                    renderer.resource_handle_allocators.reflection_probe.deallocate(handle);
+                    data_core.reflection_probe_manager.remove(handle);
+                }
                 InstructionKind::DeleteGraphData { handle } => {
                     renderer.resource_handle_allocators.graph_storage.deallocate(handle);
                     data_core.graph_storage.remove(&handle);
@@ -173,13 +218,36 @@ pub fn evaluate_instructions(renderer: &Renderer) -> InstructionEvaluationOutput
 
     // Level 0
     let d2c_texture = data_core.d2c_texture_manager.evaluate(&renderer.device);
-    let (shadow_target_size, shadows) =
-        data_core.directional_light_manager.evaluate(renderer, &data_core.viewport_camera_state);
-    data_core.point_light_manager.evaluate(renderer);
+    let shadow_changes = data_core.object_manager.take_shadow_changes();
+    let (shadow_target_size, shadows) = data_core.shadow_manager.evaluate(
+        renderer,
+        &data_core.viewport_camera_state,
+        &data_core.directional_light_manager,
+        &data_core.point_light_manager,
+        &data_core.spot_light_manager,
+        &shadow_changes,
+    );
+    let shadow_atlas = Arc::clone(data_core.shadow_manager.atlas_texture());
+    data_core.directional_light_manager.evaluate(renderer, shadow_target_size, &shadows);
+    data_core.point_light_manager.evaluate(renderer, shadow_target_size, &shadows);
+    data_core.spot_light_manager.evaluate(renderer, shadow_target_size, &shadows);
     let (mesh_buffer, mesh_cmd_buf) = renderer.mesh_manager.evaluate(&renderer.device);
 
+    // Even on the GpuDriven profile, routines without a GPU culling source draw objects on the CPU,
+    // so every camera needs its visibility.
+    let culled_objects = data_core.object_manager.cull(&data_core.viewport_camera_state, &shadows);
+
     cmd_bufs.push(mesh_cmd_buf);
     cmd_bufs.push(encoder.finish());
 
-    InstructionEvaluationOutput { cmd_bufs, d2_texture, d2c_texture, shadow_target_size, shadows, mesh_buffer }
+    InstructionEvaluationOutput {
+        cmd_bufs,
+        d2_texture,
+        d2c_texture,
+        shadow_target_size,
+        shadow_atlas,
+        shadows,
+        mesh_buffer,
+        culled_objects,
+    }
 }
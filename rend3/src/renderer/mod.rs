@@ -1,11 +1,12 @@
 use std::{marker::PhantomData, panic::Location, sync::Arc};
 
-use glam::Mat4;
+use glam::{Mat4, Vec2};
 use parking_lot::Mutex;
 use rend3_types::{
     GraphDataHandle, GraphDataTag, Handedness, Material, MaterialTag, ObjectChange, PointLight, PointLightChange,
-    PointLightHandle, Skeleton, SkeletonHandle, Texture2DTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
-    WasmNotSend,
+    PointLightHandle, ReflectionProbe, ReflectionProbeChange, ReflectionProbeHandle, Skeleton, SkeletonHandle,
+    SpotLight, SpotLightChange, SpotLightHandle, Texture2DTag, TextureCubeHandle, TextureCubeTag, TextureFromTexture,
+    VertexAttribute, VertexFormat, WasmNotSend,
 };
 use wgpu::{Device, DownlevelCapabilities, Features, Limits, Queue};
 use wgpu_profiler::GpuProfiler;
@@ -15,8 +16,8 @@ use crate::{
     instruction::{InstructionKind, InstructionStreamPair},
     managers::{
         CameraState, DirectionalLightManager, GraphStorage, HandleAllocator, MaterialManager, MeshCreationError,
-        MeshManager, ObjectManager, PointLightManager, SkeletonCreationError, SkeletonManager, TextureCreationError,
-        TextureManager,
+        MeshManager, MeshUpdateError, ObjectManager, PointLightManager, ReflectionProbeManager, ShadowManager,
+        SkeletonCreationError, SkeletonManager, SpotLightManager, TextureCreationError, TextureManager,
     },
     types::{
         Camera, DirectionalLight, DirectionalLightChange, DirectionalLightHandle, MaterialHandle, Mesh, MeshHandle,
@@ -76,6 +77,8 @@ struct HandleAllocators {
     pub object: HandleAllocator<Object>,
     pub directional_light: HandleAllocator<DirectionalLight>,
     pub point_light: HandleAllocator<PointLight>,
+    pub spot_light: HandleAllocator<SpotLight>,
+    pub reflection_probe: HandleAllocator<ReflectionProbe>,
     pub graph_storage: HandleAllocator<GraphDataTag>,
 }
 
@@ -90,6 +93,8 @@ impl Default for HandleAllocators {
             object: HandleAllocator::new(),
             directional_light: HandleAllocator::new(),
             point_light: HandleAllocator::new(),
+            spot_light: HandleAllocator::new(),
+            reflection_probe: HandleAllocator::new(),
             graph_storage: HandleAllocator::new(),
         }
     }
@@ -111,6 +116,12 @@ pub struct RendererDataCore {
     pub directional_light_manager: DirectionalLightManager,
     /// Manages all point lights, including their shadow maps.
     pub point_light_manager: PointLightManager,
+    /// Manages all spot lights, including their shadow maps.
+    pub spot_light_manager: SpotLightManager,
+    /// Manages all reflection probes.
+    pub reflection_probe_manager: ReflectionProbeManager,
+    /// Manages the shadow atlas shared between all shadow casting lights.
+    pub shadow_manager: ShadowManager,
     /// Manages skeletons, and their owned portion of the MeshManager's buffers
     pub skeleton_manager: SkeletonManager,
     /// Managed long term storage of data for the graph and it's routines
@@ -156,6 +167,51 @@ impl Renderer {
         Ok(handle)
     }
 
+    /// Replaces the vertex and index data of a mesh.
+    ///
+    /// The new mesh must have the same vertex count, index count, and attributes
+    /// as the existing mesh. The data is overwritten in place, so all objects
+    /// and skeletons using the mesh will pick up the new data without needing
+    /// to be re-created.
+    #[track_caller]
+    pub fn update_mesh(&self, handle: &MeshHandle, mesh: Mesh) -> Result<(), MeshUpdateError> {
+        let bounding_sphere = self.mesh_manager.update(&self.device, handle.get_raw(), mesh)?;
+
+        self.instructions.push(
+            InstructionKind::SetMeshBoundingSphere { handle: handle.get_raw(), bounding_sphere },
+            *Location::caller(),
+        );
+
+        Ok(())
+    }
+
+    /// Overwrites part of a single vertex attribute of a mesh, starting at
+    /// the vertex `offset`.
+    ///
+    /// When writing positions, the bounding sphere of the mesh will grow to
+    /// contain the new positions, but will never shrink. Use
+    /// [Renderer::update_mesh] to get a tight bounding sphere again.
+    #[track_caller]
+    pub fn write_mesh_attribute<T: VertexFormat>(
+        &self,
+        handle: &MeshHandle,
+        attribute: &'static VertexAttribute<T>,
+        offset: usize,
+        data: &[T],
+    ) -> Result<(), MeshUpdateError> {
+        let bounding_sphere =
+            self.mesh_manager.write_attribute(&self.device, handle.get_raw(), attribute, offset, data)?;
+
+        if let Some(bounding_sphere) = bounding_sphere {
+            self.instructions.push(
+                InstructionKind::SetMeshBoundingSphere { handle: handle.get_raw(), bounding_sphere },
+                *Location::caller(),
+            );
+        }
+
+        Ok(())
+    }
+
     /// Adds a skeleton into the renderer. This combines a [`Mesh`] with a set
     /// of joints that can be used to animate that mesh.
     ///
@@ -299,6 +355,15 @@ impl Renderer {
             .push(InstructionKind::SetObjectTransform { handle: handle.get_raw(), transform }, *Location::caller());
     }
 
+    /// Changes whether the given object casts and receives shadows.
+    #[track_caller]
+    pub fn set_object_shadows(&self, handle: &ObjectHandle, cast_shadows: bool, receive_shadows: bool) {
+        self.instructions.push(
+            InstructionKind::SetObjectShadows { handle: handle.get_raw(), cast_shadows, receive_shadows },
+            *Location::caller(),
+        );
+    }
+
     /// Sets the joint positions for a skeleton. See
     /// [Renderer::set_skeleton_joint_matrices] to set the vertex
     /// transformations directly, without having to supply two separate
@@ -336,6 +401,17 @@ impl Renderer {
         )
     }
 
+    /// Sets the morph target weights for a skeleton. There is one weight per
+    /// morph target of the skeleton's mesh. Missing weights are treated as zero
+    /// and extra weights are ignored.
+    #[track_caller]
+    pub fn set_skeleton_morph_weights(&self, handle: &SkeletonHandle, morph_weights: Vec<f32>) {
+        self.instructions.push(
+            InstructionKind::SetSkeletonMorphWeights { handle: handle.get_raw(), morph_weights },
+            *Location::caller(),
+        )
+    }
+
     /// Add a sun-like light into the world.
     ///
     /// The handle will keep the light alive.
@@ -364,6 +440,33 @@ impl Renderer {
         handle
     }
 
+    /// Add a spot light into the world.
+    ///
+    /// **WARNING**: like point lights, every fragment in the forward pass is
+    /// shaded with every spot light in the world.
+    ///
+    /// The handle will keep the light alive.
+    #[track_caller]
+    pub fn add_spot_light(self: &Arc<Self>, light: SpotLight) -> SpotLightHandle {
+        let handle = self.resource_handle_allocators.spot_light.allocate(self);
+
+        self.instructions.push(InstructionKind::AddSpotLight { handle: *handle, light }, *Location::caller());
+
+        handle
+    }
+
+    /// Add a reflection probe into the world.
+    ///
+    /// The handle will keep the probe alive.
+    #[track_caller]
+    pub fn add_reflection_probe(self: &Arc<Self>, probe: ReflectionProbe) -> ReflectionProbeHandle {
+        let handle = self.resource_handle_allocators.reflection_probe.allocate(self);
+
+        self.instructions.push(InstructionKind::AddReflectionProbe { handle: *handle, probe }, *Location::caller());
+
+        handle
+    }
+
     /// Updates the settings for given directional light.
     #[track_caller]
     pub fn update_directional_light(&self, handle: &DirectionalLightHandle, change: DirectionalLightChange) {
@@ -378,6 +481,20 @@ impl Renderer {
             .push(InstructionKind::ChangePointLight { handle: handle.get_raw(), change }, *Location::caller())
     }
 
+    /// Updates the settings for given spot light.
+    #[track_caller]
+    pub fn update_spot_light(&self, handle: &SpotLightHandle, change: SpotLightChange) {
+        self.instructions
+            .push(InstructionKind::ChangeSpotLight { handle: handle.get_raw(), change }, *Location::caller())
+    }
+
+    /// Updates the settings for given reflection probe.
+    #[track_caller]
+    pub fn update_reflection_probe(&self, handle: &ReflectionProbeHandle, change: ReflectionProbeChange) {
+        self.instructions
+            .push(InstructionKind::ChangeReflectionProbe { handle: handle.get_raw(), change }, *Location::caller())
+    }
+
     /// Adds a piece of data for long term storage and convienient use in the RenderGraph
     ///
     /// The handle will keep the data alive.
@@ -405,6 +522,14 @@ impl Renderer {
         self.instructions.push(InstructionKind::SetCameraData { data }, *Location::caller())
     }
 
+    /// Offsets the projection of the camera by the given amount in normalized
+    /// device coordinates, for temporal anti-aliasing. Stays until it is set
+    /// again.
+    #[track_caller]
+    pub fn set_camera_jitter(&self, jitter: Vec2) {
+        self.instructions.push(InstructionKind::SetCameraJitter { jitter }, *Location::caller())
+    }
+
     /// Swaps the front and back instruction buffer. Any world-modifiying functions
     /// called after this will be recorded for the next frame.
     ///
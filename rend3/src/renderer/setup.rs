@@ -10,7 +10,7 @@ use crate::{
     instruction::InstructionStreamPair,
     managers::{
         CameraState, DirectionalLightManager, GraphStorage, MaterialManager, MeshManager, ObjectManager,
-        PointLightManager, SkeletonManager, TextureManager,
+        PointLightManager, ReflectionProbeManager, ShadowManager, SkeletonManager, SpotLightManager, TextureManager,
     },
     renderer::{HandleAllocators, RendererDataCore},
     util::{mipmap::MipmapGenerator, scatter_copy::ScatterCopy},
@@ -47,6 +47,9 @@ pub fn create_renderer(
     let object_manager = ObjectManager::new();
     let directional_light_manager = DirectionalLightManager::new(&iad.device);
     let point_light_manager = PointLightManager::new(&iad.device);
+    let spot_light_manager = SpotLightManager::new(&iad.device);
+    let reflection_probe_manager = ReflectionProbeManager::new();
+    let shadow_manager = ShadowManager::new();
     let skeleton_manager = SkeletonManager::new();
     let graph_storage = GraphStorage::new();
 
@@ -95,6 +98,9 @@ pub fn create_renderer(
             object_manager,
             directional_light_manager,
             point_light_manager,
+            spot_light_manager,
+            reflection_probe_manager,
+            shadow_manager,
             skeleton_manager,
             graph_storage,
             profiler,
@@ -42,6 +42,7 @@ pub const OPTIONAL_FEATURES: Features = Features::from_bits_truncate(
         | Features::TEXTURE_COMPRESSION_BC.bits()
         | Features::TEXTURE_COMPRESSION_ETC2.bits()
         | Features::TEXTURE_COMPRESSION_ASTC.bits()
+        | Features::INDIRECT_FIRST_INSTANCE.bits()
         | Features::TIMESTAMP_QUERY.bits()
         | Features::TIMESTAMP_QUERY_INSIDE_PASSES.bits(),
 );
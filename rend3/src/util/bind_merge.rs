@@ -11,18 +11,27 @@ use wgpu::{
 /// Builder for BindGroupLayouts.
 pub struct BindGroupLayoutBuilder {
     bgl_entries: Vec<BindGroupLayoutEntry>,
+    next_binding: u32,
 }
 impl BindGroupLayoutBuilder {
     pub fn new() -> Self {
-        Self { bgl_entries: Vec::with_capacity(16) }
+        Self { bgl_entries: Vec::with_capacity(16), next_binding: 0 }
     }
 
     pub fn append(&mut self, visibility: ShaderStages, ty: BindingType, count: Option<NonZeroU32>) -> &mut Self {
-        let binding = self.bgl_entries.len() as u32;
+        let binding = self.next_binding;
+        self.next_binding += 1;
         self.bgl_entries.push(BindGroupLayoutEntry { binding, visibility, ty, count });
         self
     }
 
+    /// Leaves the next `count` bindings empty, so optional bindings don't move
+    /// the bindings after them.
+    pub fn skip(&mut self, count: u32) -> &mut Self {
+        self.next_binding += count;
+        self
+    }
+
     pub fn append_buffer(
         &mut self,
         visibility: ShaderStages,
@@ -55,15 +64,24 @@ impl Default for BindGroupLayoutBuilder {
 /// Builder for BindGroups.
 pub struct BindGroupBuilder<'a> {
     bg_entries: Vec<BindGroupEntry<'a>>,
+    next_binding: u32,
 }
 impl<'a> BindGroupBuilder<'a> {
     pub fn new() -> Self {
-        Self { bg_entries: Vec::with_capacity(16) }
+        Self { bg_entries: Vec::with_capacity(16), next_binding: 0 }
     }
 
     pub fn append(&mut self, resource: BindingResource<'a>) -> &mut Self {
-        let index = self.bg_entries.len();
-        self.bg_entries.push(BindGroupEntry { binding: index as u32, resource });
+        let binding = self.next_binding;
+        self.next_binding += 1;
+        self.bg_entries.push(BindGroupEntry { binding, resource });
+        self
+    }
+
+    /// Leaves the next `count` bindings empty, matching
+    /// [`BindGroupLayoutBuilder::skip`].
+    pub fn skip(&mut self, count: u32) -> &mut Self {
+        self.next_binding += count;
         self
     }
 
@@ -30,6 +30,24 @@ impl BoundingSphere {
 
         Self { center: center.truncate(), radius: max_scale * self.radius }
     }
+
+    /// Returns the smallest sphere containing both spheres.
+    pub fn union(self, other: Self) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.length();
+
+        if distance + other.radius <= self.radius {
+            return self;
+        }
+        if distance + self.radius <= other.radius {
+            return other;
+        }
+
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+
+        Self { center, radius }
+    }
 }
 
 fn find_mesh_center(mesh: &[Vec3]) -> Vec3A {
@@ -160,3 +178,25 @@ impl Frustum {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::BoundingSphere;
+
+    #[test]
+    fn union() {
+        let a = BoundingSphere { center: Vec3::ZERO, radius: 1.0 };
+        let b = BoundingSphere { center: Vec3::new(4.0, 0.0, 0.0), radius: 1.0 };
+        let inner = BoundingSphere { center: Vec3::new(0.5, 0.0, 0.0), radius: 0.25 };
+
+        let union = a.union(b);
+        assert_eq!(union.center, Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(union.radius, 3.0);
+
+        // Contained spheres don't change the result.
+        assert_eq!(a.union(inner).center, a.center);
+        assert_eq!(inner.union(a).radius, a.radius);
+    }
+}
@@ -11,8 +11,6 @@ pub type FastHashSet<K> = rustc_hash::FxHashSet<K>;
 pub type FastHasher = rustc_hash::FxHasher;
 /// Build hasher designed for small keys.
 pub type FastBuildHasher = std::hash::BuildHasherDefault<FastHasher>;
-/// Output of wgpu_profiler's code.
-pub type RendererStatistics = Vec<wgpu_profiler::GpuTimerQueryResult>;
 
 #[macro_export]
 /// Similar to the [`format`] macro, but creates a [`SsoString`].